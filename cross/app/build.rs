@@ -40,4 +40,7 @@ fn main() {
 
     // Set the linker script to the one provided by cortex-m-rt.
     println!("cargo:rustc-link-arg=-Tlink.x");
+
+    // The defmt log frames need its linker script too.
+    println!("cargo:rustc-link-arg=-Tdefmt.x");
 }
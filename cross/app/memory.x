@@ -17,7 +17,8 @@ MEMORY
    section */
 /* This is required only on microcontrollers that store some configuration right
    after the vector table */
-/* _stext = ORIGIN(FLASH) + 0x400; */
+/* The sectors 1 and 2 (16K each) are the config store pages, see `board::config_flash` */
+_stext = ORIGIN(FLASH) + 48K;
 
 /* Example of putting non-initialized variables into custom RAM locations. */
 /* This assumes you have defined a region RAM2 above, and in the Rust
@@ -6,20 +6,17 @@ use core::panic::PanicInfo;
 use core::sync::atomic;
 use core::sync::atomic::Ordering;
 use cortex_m_rt::{exception, ExceptionFrame};
-use cortex_m_semihosting::hprintln;
+use logic::utils::logger::{self, Event};
 
 #[exception]
 unsafe  fn DefaultHandler(irqn: i16) {
-    hprintln!("irqn={}", irqn);
+    logger::log(Event::UnexpectedInterrupt(irqn));
     loop {}
 }
 
 #[exception]
 unsafe  fn HardFault(ef: &ExceptionFrame) -> ! {
-    /*if let Ok(mut hstdout) = hio::hstdout() {
-        writeln!(hstdout, "{:#?}", ef).ok();
-    }*/
-    hprintln!("ef={:#?}", ef);
+    logger::log(Event::HardFault { pc: ef.pc(), lr: ef.lr() });
 
     loop {}
 }
@@ -6,6 +6,7 @@
 //use panic_halt as _;
 
 mod handlers;
+mod rtt_logger;
 
 #[rtic::app(device = stm32f4xx_hal::pac, peripherals = true, dispatchers = [EXTI3])]
 mod app {
@@ -14,7 +15,8 @@ mod app {
     };
     use dwt_systick_monotonic::DwtSystick;
     use embedded_alloc::Heap;
-    use board::{ Board, ControllerLinkSlave1, InWork };
+    use board::{ Board, Hub, InWork, SLAVE1_PORT, SLAVE2_PORT, SLAVE6_PORT };
+    use logic::utils::logger::{self, DefmtLogger};
 
 
     #[global_allocator]
@@ -26,7 +28,7 @@ mod app {
     struct Shared {
         in_work: InWork,
         #[lock_free]
-        controller_link_slave1: ControllerLinkSlave1,
+        hub: Hub,
     }
 
     #[local]
@@ -39,6 +41,9 @@ mod app {
     #[init]
     fn init(mut ctx: init::Context) -> (Shared, Local, init::Monotonics) {
 
+        crate::rtt_logger::init();
+        logger::set_logger(&DefmtLogger).ok();
+
         {
             use core::mem::MaybeUninit;
             const HEAP_SIZE: usize = 1024;
@@ -46,14 +51,14 @@ mod app {
             unsafe { HEAP.init(HEAP_MEM.as_ptr() as usize, HEAP_SIZE) }
         }
 
-        let Board { controller_link_slave1, in_work } = Board::init(ctx.device, MONO_HZ);
+        let Board { hub, in_work } = Board::init(ctx.device, MONO_HZ);
 
         let mono = DwtSystick::new(&mut ctx.core.DCB, ctx.core.DWT, ctx.core.SYST, MONO_HZ);
 
         polling::spawn_after(1.secs()).ok();
 
         (
-            Shared { controller_link_slave1, in_work },
+            Shared { hub, in_work },
             Local {  },
             init::Monotonics(mono),
         )
@@ -74,10 +79,11 @@ mod app {
         });
     }
 
-    #[task(binds = TIM2, local = [], shared=[in_work])]
-    fn tim2(mut ctx: tim2::Context) {
-        ctx.shared.in_work.lock(|in_work: &mut InWork| {
-            in_work.on_tim2();
+    #[task(binds = TIM2, priority=1, local = [], shared=[hub, in_work])]
+    fn tim2(ctx: tim2::Context) {
+        let tim2::SharedResources { hub, mut in_work } = ctx.shared;
+        in_work.lock(|in_work: &mut InWork| {
+            in_work.on_tim2(hub);
         });
     }
 
@@ -88,44 +94,62 @@ mod app {
         });
     }
 
-    // Important! USART1 and DMA2_STREAM2 should the same interrupt priority!
-    #[task(binds = USART1, priority=1, local = [],shared = [controller_link_slave1, in_work])]
+    // Important! All the hub tasks should have the same interrupt priority!
+    #[task(binds = USART1, priority=1, local = [],shared = [hub, in_work])]
     fn usart1(mut ctx: usart1::Context) {
-        let usart1::SharedResources { mut controller_link_slave1, mut in_work } = ctx.shared;
+        let usart1::SharedResources { mut hub, mut in_work } = ctx.shared;
         in_work.lock(|in_work: &mut InWork| {
-            controller_link_slave1.on_get_command(&mut in_work.rtc)
+            hub.on_get_command(SLAVE1_PORT, &mut in_work.clock);
+            in_work.on_hub_events(hub);
         });
     }
 
-    #[task(binds = USART2, priority=1, local = [], shared = [in_work])]
+    #[task(binds = USART2, priority=1, local = [], shared = [hub, in_work])]
     fn usart2(mut ctx: usart2::Context) {
-        ctx.shared.in_work.lock(|in_work: &mut InWork| {
-            in_work.on_usart2();
+        let usart2::SharedResources { mut hub, mut in_work } = ctx.shared;
+        in_work.lock(|in_work: &mut InWork| {
+            hub.on_get_command(SLAVE2_PORT, &mut in_work.clock);
+            in_work.on_hub_events(hub);
         });
     }
 
-    #[task(binds = DMA2_STREAM2, priority=1, shared = [controller_link_slave1])]
+    #[task(binds = USART6, priority=1, local = [], shared = [hub, in_work])]
+    fn usart6(mut ctx: usart6::Context) {
+        let usart6::SharedResources { mut hub, mut in_work } = ctx.shared;
+        in_work.lock(|in_work: &mut InWork| {
+            hub.on_get_command(SLAVE6_PORT, &mut in_work.clock);
+            in_work.on_hub_events(hub);
+        });
+    }
+
+    #[task(binds = DMA2_STREAM2, priority=1, shared = [hub])]
     fn dma2_stream2(mut ctx: dma2_stream2::Context) {
-        ctx.shared.controller_link_slave1.on_rx_dma_interrupts();
+        ctx.shared.hub.on_rx_dma_interrupts(SLAVE1_PORT);
     }
 
-    #[task(binds = DMA2_STREAM7, priority=1, shared = [controller_link_slave1])]
+    #[task(binds = DMA2_STREAM7, priority=1, shared = [hub])]
     fn dma2_stream7(mut ctx: dma2_stream7::Context) {
-        ctx.shared.controller_link_slave1.on_tx_dma_interrupts();
+        ctx.shared.hub.on_tx_dma_interrupts(SLAVE1_PORT);
     }
 
-    #[task(binds = DMA1_STREAM5, priority=1, shared = [in_work])]
+    #[task(binds = DMA1_STREAM5, priority=1, shared = [hub])]
     fn dma1_stream5(mut ctx: dma1_stream5::Context) {
-        ctx.shared.in_work.lock(|in_work: &mut InWork| {
-            in_work.on_dma1_stream5();
-        });
+        ctx.shared.hub.on_rx_dma_interrupts(SLAVE2_PORT);
     }
 
-    #[task(binds = DMA1_STREAM6, priority=1, shared = [in_work])]
+    #[task(binds = DMA1_STREAM6, priority=1, shared = [hub])]
     fn dma1_stream6(mut ctx: dma1_stream6::Context) {
-        ctx.shared.in_work.lock(|in_work: &mut InWork| {
-            in_work.on_dma1_stream6();
-        });
+        ctx.shared.hub.on_tx_dma_interrupts(SLAVE2_PORT);
+    }
+
+    #[task(binds = DMA2_STREAM1, priority=1, shared = [hub])]
+    fn dma2_stream1(mut ctx: dma2_stream1::Context) {
+        ctx.shared.hub.on_rx_dma_interrupts(SLAVE6_PORT);
+    }
+
+    #[task(binds = DMA2_STREAM6, priority=1, shared = [hub])]
+    fn dma2_stream6(mut ctx: dma2_stream6::Context) {
+        ctx.shared.hub.on_tx_dma_interrupts(SLAVE6_PORT);
     }
 
     #[task(binds = DMA2_STREAM0, priority=1, shared = [in_work])]
@@ -135,18 +159,22 @@ mod app {
         });
     }
 
-    #[task(priority=1, shared = [in_work])]
-    fn polling(mut ctx: polling::Context) {
-        ctx.shared.in_work.lock(|in_work: &mut InWork| {
+    #[task(priority=1, shared = [in_work, hub])]
+    fn polling(ctx: polling::Context) {
+        let polling::SharedResources { mut hub, mut in_work } = ctx.shared;
+        in_work.lock(|in_work: &mut InWork| {
             in_work.on_polling();
+            hub.poll(&mut in_work.clock);
+            in_work.on_hub_events(hub);
         });
         polling::spawn_after(1.secs()).ok();
     }
 
-    #[task(binds=OTG_FS, shared=[in_work])]
-    fn usb_fs(mut cx: usb_fs::Context) {
-        cx.shared.in_work.lock(|in_work: &mut InWork| {
-            in_work.on_usb_otg_fs();
+    #[task(binds=OTG_FS, priority=1, shared=[in_work, hub])]
+    fn usb_fs(ctx: usb_fs::Context) {
+        let usb_fs::SharedResources { hub, mut in_work } = ctx.shared;
+        in_work.lock(|in_work: &mut InWork| {
+            in_work.on_usb_otg_fs(hub);
         });
     }
 
@@ -0,0 +1,68 @@
+#![allow(unsafe_code)]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use rtt_target::{rtt_init, UpChannel};
+
+/**
+The defmt global logger writing to the RTT up channel 0. Nothing waits for the probe: the frames which
+do not fit are dropped, defmt decoder skips to the next frame after a broken one.
+ */
+#[defmt::global_logger]
+struct RttLogger;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+// accessed in the critical section only
+static mut RESTORE_STATE: critical_section::RestoreState = critical_section::RestoreState::invalid();
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+static mut CHANNEL: Option<UpChannel> = None;
+
+/** Should be called once at the start of `init`, before the records are logged. */
+pub fn init() {
+    let channels = rtt_init! {
+        up: {
+            0: {
+                size: 1024
+                mode: NoBlockTrim
+                name: "defmt"
+            }
+        }
+    };
+    let channel = channels.up.0;
+    critical_section::with(|_| unsafe {
+        CHANNEL = Some(channel);
+    });
+}
+
+fn do_write(bytes: &[u8]) {
+    unsafe {
+        if let Some(channel) = (*core::ptr::addr_of_mut!(CHANNEL)).as_mut() {
+            channel.write(bytes);
+        }
+    }
+}
+
+unsafe impl defmt::Logger for RttLogger {
+    fn acquire() {
+        let restore_state = unsafe { critical_section::acquire() };
+        if TAKEN.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly")
+        }
+        TAKEN.store(true, Ordering::Relaxed);
+        unsafe {
+            RESTORE_STATE = restore_state;
+            (*core::ptr::addr_of_mut!(ENCODER)).start_frame(do_write);
+        }
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        (*core::ptr::addr_of_mut!(ENCODER)).end_frame(do_write);
+        TAKEN.store(false, Ordering::Relaxed);
+        critical_section::release(RESTORE_STATE);
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*core::ptr::addr_of_mut!(ENCODER)).write(bytes, do_write);
+    }
+}
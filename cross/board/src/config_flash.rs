@@ -0,0 +1,89 @@
+use embedded_storage::nor_flash::{check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError,
+                                  NorFlashErrorKind, ReadNorFlash};
+use stm32f4xx_hal::flash::{self, FlashExt, LockedFlash};
+use stm32f4xx_hal::pac::FLASH;
+
+/** The config store pages are the flash sectors 1 and 2, the code is placed after them, see `memory.x`. */
+pub const CONFIG_OFFSET: u32 = 0x4000;
+pub const CONFIG_PAGE_SIZE: u32 = 0x4000;
+const SECTOR_SIZE: usize = 0x4000;
+const SMALL_SECTORS_COUNT: usize = 4;
+
+#[derive(Debug, Copy, Clone)]
+pub enum ConfigFlashError {
+    Flash(flash::Error),
+    NotAligned,
+    OutOfBounds,
+}
+
+impl NorFlashError for ConfigFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            ConfigFlashError::Flash(error) => error.kind(),
+            ConfigFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            ConfigFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for ConfigFlashError {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => ConfigFlashError::NotAligned,
+            _ => ConfigFlashError::OutOfBounds,
+        }
+    }
+}
+
+/**
+On-chip flash erased by the 16K sectors, unlike `UnlockedFlash` erasing 128K at once. Only the first
+four sectors can be erased so. The flash is unlocked for the time of each write and erase.
+ */
+pub struct ConfigFlash {
+    flash: LockedFlash,
+}
+
+impl ConfigFlash {
+    pub fn new(flash: FLASH) -> Self {
+        Self { flash: LockedFlash::new(flash) }
+    }
+}
+
+impl ErrorType for ConfigFlash {
+    type Error = ConfigFlashError;
+}
+
+impl ReadNorFlash for ConfigFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        ReadNorFlash::read(&mut self.flash, offset, bytes).map_err(ConfigFlashError::Flash)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.len()
+    }
+}
+
+impl NorFlash for ConfigFlash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        if to as usize > SMALL_SECTORS_COUNT * SECTOR_SIZE {
+            return Err(ConfigFlashError::OutOfBounds);
+        }
+        let mut unlocked = self.flash.unlocked();
+        for offset in (from as usize..to as usize).step_by(SECTOR_SIZE) {
+            unlocked.erase((offset / SECTOR_SIZE) as u8).map_err(ConfigFlashError::Flash)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        NorFlash::write(&mut self.flash.unlocked(), offset, bytes).map_err(ConfigFlashError::Flash)
+    }
+}
@@ -1,15 +1,12 @@
 #![no_std]
 
 
+mod config_flash;
 mod custom_interrupt_class;
 
-use core::fmt::Display;
-use logic::services::slave_controller_link::parsers::ResponseData;
-use logic::services::slave_controller_link::domain::{DataInstructions, ErrorCode, SignalData, Version};
 use logic::errors::Errors;
 
 
-use cortex_m_semihosting::hprintln;
 use drivers::implementations::rtc::RtcWrapper;
 use stm32f4xx_hal::{
     gpio::{ self, Edge, Input },
@@ -25,18 +22,26 @@ use stm32f4xx_hal::{
 use stm32f4xx_hal::dma::{MemoryToPeripheral, PeripheralToMemory, Stream1, Stream5, Stream6, Stream7};
 use stm32f4xx_hal::gpio::{Output, Pin, PushPull};
 use stm32f4xx_hal::pac::{DMA1, USART2, USART6};
-use time::{Date, PrimitiveDateTime, Time};
-use time::Month;
+use time::{OffsetDateTime, PrimitiveDateTime};
 use drivers::services::adc_transfer::{ ADCTransfer};
-use logic::hal_ext::rtc_wrapper::{DateTimeSource};
+use logic::hal_ext::rtc_wrapper::{DateTimeSource, RelativeTimestampSource};
 use logic::services::led::Led;
-use logic::services::slave_controller_link::{init_slave_controllers, SlaveControllerLink};
+use logic::services::slave_controller_link::SlaveControllerLink;
+use logic::services::slave_hub::SlaveHub;
 use logic::hal_ext::serial_transfer::{Receiver, RxTransfer, Sender, SerialTransfer, TxTransfer};
 use logic::utils::write_to;
+use logic::utils::logger::{self, Event, UsbEndpoint, UsbErrorKind};
 use drivers::implementations::serial::{Buffers, RxBuffer, SerialTransferBuilderSTMF401x, Transfer};
-use logic::services::slave_controller_link::receiver_from_slave::ErrorHandler;
-use logic::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest};
-use logic::services::slave_controller_link::signals_controller::SignalsHandler;
+use logic::services::event_journal::{EventJournal, JournalCollector, JournalErrorForwarder, JournalEventsQueue,
+                                     JournalResponseForwarder, JournalSignalsForwarder, JournalSignalsQueue, NoSpill};
+use logic::services::host_protocol::{HostResponseForwarder, HostServer, HostSignalsForwarder, HostSink,
+                                     LinkAnswersQueue, LinkSignalsQueue};
+use logic::services::hub_config::{PersistentConfig, DEFAULT_RTC_BASE_DATE};
+use logic::services::wall_clock::WallClock;
+use heapless::spsc::Queue;
+use logic::services::relay_controller::{RelayAnswersQueue, RelayController, RelayResponseForwarder};
+use logic::services::relay_scheduler::RelayScheduler;
+use logic::services::rules::{RulesEngine, RulesSignalsForwarder, RulesSignalsQueue};
 use logic::utils::dma_read_buffer::{Buffer, BufferWriter};
 use stm32f4xx_hal::serial::{Rx, Tx};
 use stm32f4xx_hal::dma::traits::StreamISR;
@@ -46,6 +51,7 @@ use stm32f4xx_hal::otg_fs::{UsbBus, USB, UsbBusType};
 use usb_device::class_prelude::*;
 use usb_device::prelude::*;
 use usbd_serial::SerialPort;
+use crate::config_flash::{ConfigFlash, CONFIG_OFFSET, CONFIG_PAGE_SIZE};
 use crate::custom_interrupt_class::CustomInterruptClass;
 
 
@@ -68,60 +74,26 @@ type Tx6Transfer_ = Transfer<Stream6<DMA2>, 5, Tx<USART6>, MemoryToPeripheral, T
 type Serial6Transfer = SerialTransfer<crate::Tx6Transfer_, crate::Rx6Transfer_, TxBuffer, RxBuffer>;
 type Rx6Transfer = RxTransfer<crate::Rx6Transfer_, RxBuffer>;
 type Tx6Transfer = TxTransfer<crate::Tx6Transfer_, TxBuffer>;
-pub type ControllerLinkSlave1 = SlaveControllerLink<Tx1Transfer_, Rx1Transfer_, TxBuffer, RxBuffer, SignalHandlerImp, ResponseHandlerImp, ErrorHandlerImp>;
+type LinkResponseHandler = JournalResponseForwarder<'static, RelayResponseForwarder<'static, HostResponseForwarder<'static>>>;
+type LinkSignalsHandler = JournalSignalsForwarder<'static, RulesSignalsForwarder<'static, HostSignalsForwarder<'static>>>;
+type LinkErrorHandler = JournalErrorForwarder<'static>;
+pub type ControllerLinkSlave1 = SlaveControllerLink<Tx1Transfer_, Rx1Transfer_, TxBuffer, RxBuffer, LinkSignalsHandler, LinkResponseHandler, LinkErrorHandler>;
+pub type ControllerLinkSlave2 = SlaveControllerLink<Tx2Transfer_, Rx2Transfer_, TxBuffer, RxBuffer, LinkSignalsHandler, LinkResponseHandler, LinkErrorHandler>;
+pub type ControllerLinkSlave6 = SlaveControllerLink<Tx6Transfer_, Rx6Transfer_, TxBuffer, RxBuffer, LinkSignalsHandler, LinkResponseHandler, LinkErrorHandler>;
 
-pub struct SignalHandlerImp();
-
-impl SignalsHandler for SignalHandlerImp {
-    fn on_signal(&mut self, signal_data: SignalData, processed_successfully: bool) {
-        todo!()
-    }
-
-    fn on_signal_parse_error(&mut self, error: Errors, sent_to_slave_success: bool, data: &[u8]) {
-        todo!()
-    }
-
-    fn on_signal_process_error(&mut self, error: Errors, sent_to_slave_success: bool, data: SignalData) {
-        todo!()
-    }
-}
-
-pub struct ResponseHandlerImp();
-
-impl ResponseHandler for ResponseHandlerImp {
-    fn on_request_success(&mut self, request: SentRequest) {
-        todo!()
-    }
-
-    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
-        todo!()
-    }
-
-    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
-        todo!()
-    }
-
-    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
-        todo!()
-    }
-
-    fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
-        todo!()
-    }
-}
-
-pub struct ErrorHandlerImp();
-
-impl ErrorHandler for ErrorHandlerImp {
-    fn on_error(&mut self, error: Errors) {
-        todo!()
-    }
-}
+pub const SLAVES_COUNT: usize = 3;
+pub const SLAVE1_PORT: usize = 0;
+pub const SLAVE2_PORT: usize = 1;
+pub const SLAVE6_PORT: usize = 2;
 
+pub type Hub = SlaveHub<'static, SLAVES_COUNT>;
+pub type HubConfig = PersistentConfig<ConfigFlash, SLAVES_COUNT>;
 
+/** Write attempts of a frame to the host while the USB serial buffer is full. */
+const USB_WRITE_ATTEMPTS: u8 = 10;
 
 pub struct Board {
-    pub controller_link_slave1: ControllerLinkSlave1,
+    pub hub: Hub,
     pub in_work: InWork
 }
 
@@ -133,8 +105,6 @@ impl Board {
         static mut EP_MEMORY: [u32; 1024] = [0; 1024];
         static mut USB_BUS: Option<UsbBusAllocator<stm32f4xx_hal::otg_fs::UsbBusType>> = None;
 
-        init_slave_controllers();
-
         let rcc = dp.RCC.constrain();
         let clocks = rcc.cfgr
             .use_hse(25.MHz())
@@ -145,15 +115,20 @@ impl Board {
             .pclk2(42.MHz())
             .freeze();
 
+        let config: HubConfig = PersistentConfig::open(ConfigFlash::new(dp.FLASH), CONFIG_OFFSET, CONFIG_PAGE_SIZE).unwrap();
+
         let rtc_not_initialized = dp.RTC.isr.read().inits().is_not_initalized();
-        let mut rtc = DateTimeSource::new( RtcWrapper::new( Rtc::new(dp.RTC, &mut dp.PWR) ) );
+        let mut clock = WallClock::new(DateTimeSource::new( RtcWrapper::new( Rtc::new(dp.RTC, &mut dp.PWR) ) ));
 
         if rtc_not_initialized {
-            let date = Date::from_calendar_date(2023, Month::June, 13).unwrap();
-            let time = Time::from_hms(8, 26, 30).unwrap();
-            let date_time = PrimitiveDateTime::new(date, time);
-            rtc.set_datetime(date_time).unwrap();
+            // the stored date could be out of the RTC range
+            if clock.preset(config.config().rtc_base_date()).is_err() {
+                clock.preset(DEFAULT_RTC_BASE_DATE).unwrap();
+            }
         }
+        let slave1_config = config.config().slave(SLAVE1_PORT).unwrap();
+        let slave2_config = config.config().slave(SLAVE2_PORT).unwrap();
+        let slave6_config = config.config().slave(SLAVE6_PORT).unwrap();
 
         let gpioa = dp.GPIOA.split();
         let gpiob = dp.GPIOB.split();
@@ -187,7 +162,7 @@ impl Board {
         let serial1 = dp.USART1.serial(
             (gpioa.pa9.into_alternate(), gpioa.pa10),
             Config::default()
-                .baudrate(9600.bps())
+                .baudrate(slave1_config.baud_rate().bps())
                 .dma(config::DmaConfig::TxRx),
             &clocks,
         ).unwrap();
@@ -195,7 +170,15 @@ impl Board {
         let serial2 = dp.USART2.serial(
             (gpioa.pa2.into_alternate(), gpioa.pa3),
             Config::default()
-                .baudrate(9600.bps())
+                .baudrate(slave2_config.baud_rate().bps())
+                .dma(config::DmaConfig::TxRx),
+            &clocks,
+        ).unwrap();
+
+        let serial6 = dp.USART6.serial(
+            (gpioc.pc6.into_alternate(), gpioc.pc7),
+            Config::default()
+                .baudrate(slave6_config.baud_rate().bps())
                 .dma(config::DmaConfig::TxRx),
             &clocks,
         ).unwrap();
@@ -214,15 +197,89 @@ impl Board {
             cortex_m::singleton!(: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE]).unwrap()
         );
 
+        let buffers6 = Buffers::new(
+            cortex_m::singleton!(: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE]).unwrap(),
+            cortex_m::singleton!(: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE]).unwrap(),
+            cortex_m::singleton!(: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE]).unwrap(),
+            cortex_m::singleton!(: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE]).unwrap()
+        );
+
         let serial_transfer_1 = SerialTransferBuilderSTMF401x::create_serial_transfer(serial1, dma2.7, dma2.2, buffers1);
         let serial_transfer_2 = SerialTransferBuilderSTMF401x::create_serial_transfer(serial2, dma1.6, dma1.5, buffers2);
-        // let serial_transfer_6 = SerialTransferBuilderSTMF401x::create_serial_transfer(serial6, dma2.6, dma2.1, buffers6);
-
-        let signal_handler = SignalHandlerImp();
+        let serial_transfer_6 = SerialTransferBuilderSTMF401x::create_serial_transfer(serial6, dma2.6, dma2.1, buffers6);
+
+        let (answers1_tx, answers1_rx) = cortex_m::singleton!(: LinkAnswersQueue = Queue::new()).unwrap().split();
+        let (answers2_tx, answers2_rx) = cortex_m::singleton!(: LinkAnswersQueue = Queue::new()).unwrap().split();
+        let (answers6_tx, answers6_rx) = cortex_m::singleton!(: LinkAnswersQueue = Queue::new()).unwrap().split();
+        let (relays1_tx, relays1_rx) = cortex_m::singleton!(: RelayAnswersQueue = RelayAnswersQueue::new()).unwrap().split();
+        let (relays2_tx, relays2_rx) = cortex_m::singleton!(: RelayAnswersQueue = RelayAnswersQueue::new()).unwrap().split();
+        let (relays6_tx, relays6_rx) = cortex_m::singleton!(: RelayAnswersQueue = RelayAnswersQueue::new()).unwrap().split();
+        let (signals1_tx, signals1_rx) = cortex_m::singleton!(: LinkSignalsQueue = Queue::new()).unwrap().split();
+        let (signals2_tx, signals2_rx) = cortex_m::singleton!(: LinkSignalsQueue = Queue::new()).unwrap().split();
+        let (signals6_tx, signals6_rx) = cortex_m::singleton!(: LinkSignalsQueue = Queue::new()).unwrap().split();
+        let (rules1_tx, rules1_rx) = cortex_m::singleton!(: RulesSignalsQueue = Queue::new()).unwrap().split();
+        let (rules2_tx, rules2_rx) = cortex_m::singleton!(: RulesSignalsQueue = Queue::new()).unwrap().split();
+        let (rules6_tx, rules6_rx) = cortex_m::singleton!(: RulesSignalsQueue = Queue::new()).unwrap().split();
+        let (journal_signals1_tx, journal_signals1_rx) = cortex_m::singleton!(: JournalSignalsQueue = Queue::new()).unwrap().split();
+        let (journal_signals2_tx, journal_signals2_rx) = cortex_m::singleton!(: JournalSignalsQueue = Queue::new()).unwrap().split();
+        let (journal_signals6_tx, journal_signals6_rx) = cortex_m::singleton!(: JournalSignalsQueue = Queue::new()).unwrap().split();
+        let (journal_answers1_tx, journal_answers1_rx) = cortex_m::singleton!(: JournalEventsQueue = Queue::new()).unwrap().split();
+        let (journal_answers2_tx, journal_answers2_rx) = cortex_m::singleton!(: JournalEventsQueue = Queue::new()).unwrap().split();
+        let (journal_answers6_tx, journal_answers6_rx) = cortex_m::singleton!(: JournalEventsQueue = Queue::new()).unwrap().split();
+        let (journal_faults1_tx, journal_faults1_rx) = cortex_m::singleton!(: JournalEventsQueue = Queue::new()).unwrap().split();
+        let (journal_faults2_tx, journal_faults2_rx) = cortex_m::singleton!(: JournalEventsQueue = Queue::new()).unwrap().split();
+        let (journal_faults6_tx, journal_faults6_rx) = cortex_m::singleton!(: JournalEventsQueue = Queue::new()).unwrap().split();
+
+        let controller_link_slave1: ControllerLinkSlave1 =
+            SlaveControllerLink::create(serial_transfer_1,
+                 JournalSignalsForwarder::new(
+                     RulesSignalsForwarder::new(HostSignalsForwarder::new(signals1_tx), rules1_tx),
+                     journal_signals1_tx),
+                 JournalResponseForwarder::new(
+                     RelayResponseForwarder::new(HostResponseForwarder::new(answers1_tx), relays1_tx),
+                     journal_answers1_tx),
+                 JournalErrorForwarder::new(journal_faults1_tx), slave1_config.version()).unwrap();
+        let controller_link_slave2: ControllerLinkSlave2 =
+            SlaveControllerLink::create(serial_transfer_2,
+                 JournalSignalsForwarder::new(
+                     RulesSignalsForwarder::new(HostSignalsForwarder::new(signals2_tx), rules2_tx),
+                     journal_signals2_tx),
+                 JournalResponseForwarder::new(
+                     RelayResponseForwarder::new(HostResponseForwarder::new(answers2_tx), relays2_tx),
+                     journal_answers2_tx),
+                 JournalErrorForwarder::new(journal_faults2_tx), slave2_config.version()).unwrap();
+        let controller_link_slave6: ControllerLinkSlave6 =
+            SlaveControllerLink::create(serial_transfer_6,
+                 JournalSignalsForwarder::new(
+                     RulesSignalsForwarder::new(HostSignalsForwarder::new(signals6_tx), rules6_tx),
+                     journal_signals6_tx),
+                 JournalResponseForwarder::new(
+                     RelayResponseForwarder::new(HostResponseForwarder::new(answers6_tx), relays6_tx),
+                     journal_answers6_tx),
+                 JournalErrorForwarder::new(journal_faults6_tx), slave6_config.version()).unwrap();
+
+        // in the order of the ports
+        let host_server = HostServer::new(
+            [answers1_rx, answers2_rx, answers6_rx],
+            [signals1_rx, signals2_rx, signals6_rx],
+        );
+        let relays = RelayController::new([relays1_rx, relays2_rx, relays6_rx]);
+        let rules = RulesEngine::new([rules1_rx, rules2_rx, rules6_rx], config.config().rules());
+        let scheduler = RelayScheduler::new(config.config().schedules());
+        let journal_collector = JournalCollector::new(
+            [journal_signals1_rx, journal_signals2_rx, journal_signals6_rx],
+            [journal_answers1_rx, journal_answers2_rx, journal_answers6_rx],
+            [journal_faults1_rx, journal_faults2_rx, journal_faults6_rx],
+        );
+        // no flash sector is left for the spill, the config store takes the small ones after the vectors, so
+        // only the last records are kept, the host learns the lost ones by the first sequence number it gets
+        let journal = EventJournal::new(NoSpill);
 
-        let controller_link_slave1 =
-            SlaveControllerLink::create(serial_transfer_1, signal_handler, ResponseHandlerImp(),
-                 ErrorHandlerImp(), Version::V1).unwrap();
+        let hub = SlaveHub::new([
+            cortex_m::singleton!(: ControllerLinkSlave1 = controller_link_slave1).unwrap(),
+            cortex_m::singleton!(: ControllerLinkSlave2 = controller_link_slave2).unwrap(),
+            cortex_m::singleton!(: ControllerLinkSlave6 = controller_link_slave6).unwrap(),
+        ]);
 
         let led = Led::new(4, 2, true, gpioc.pc13.into_push_pull_output());
         let mut button = gpioa.pa0.into_pull_up_input();
@@ -246,14 +303,12 @@ impl Board {
         let adc_transfer =
             ADCTransfer::new(dma2.0, dp.ADC1, gpiob.pb1.into_analog());
 
-        let last_sent = rtc.get_relative_timestamp().value();
+        let last_sent = clock.get().value();
 
         let in_work = InWork {
-            serial_transfer_2,
-            // serial_transfer_6,
             led,
             adc_transfer,
-            rtc,
+            clock,
             button,
             counter,
             counter2, 
@@ -262,11 +317,17 @@ impl Board {
             usb_interrupt_device,
             measure_data: [0; 3],
             last_sent,
-            send_interrupts: false,
+            host_server,
+            relays,
+            rules,
+            scheduler,
+            journal_collector,
+            journal,
+            config,
         };
 
         Self {
-            controller_link_slave1,
+            hub,
             in_work
         }
     }
@@ -274,10 +335,9 @@ impl Board {
 
 
 pub struct InWork {
-    serial_transfer_2: Serial2Transfer,
     led: Led<Pin<'C', 13, Output<PushPull>>>,
     adc_transfer: ADCTransfer,
-    pub rtc: DateTimeSource<RtcWrapper>,
+    pub clock: WallClock<RtcWrapper>,
     button: gpio::PA0<Input>,
     counter: timer::CounterMs<TIM3>,
     counter2: timer::CounterMs<TIM2>,
@@ -286,7 +346,13 @@ pub struct InWork {
     usb_interrupt_device: CustomInterruptClass<'static, UsbBusType>,
     measure_data: [u8; 3], 
     last_sent: u32,
-    send_interrupts: bool,
+    host_server: HostServer<'static, SLAVES_COUNT>,
+    relays: RelayController<'static, SLAVES_COUNT>,
+    rules: RulesEngine<'static, SLAVES_COUNT>,
+    scheduler: RelayScheduler,
+    journal_collector: JournalCollector<'static, SLAVES_COUNT>,
+    journal: EventJournal<NoSpill>,
+    config: HubConfig,
 }
 
 impl InWork {
@@ -294,10 +360,10 @@ impl InWork {
     pub fn on_button_pressed(&mut self) {
         self.button.clear_interrupt_pending_bit();
 
-        self.last_sent = self.rtc.get_relative_timestamp().value();
+        self.last_sent = self.clock.get().value();
         match self.usb_interrupt_device.write(&mut self.measure_data) {
-            Ok(_) => hprintln!("Measured data sent to USB!"),
-            Err(err) => hprintln!("Error measured data to USB! {}", UsbErrorWrapper::from(err)),
+            Ok(_) => logger::log(Event::UsbWritten(UsbEndpoint::Interrupt)),
+            Err(err) => logger::log(Event::UsbWriteFailed(UsbEndpoint::Interrupt, usb_error_kind(err))),
         }
         
         self.led.update_periods(|prev_on_cylcles_count: u16, prev_off_cycles_count|{
@@ -305,16 +371,19 @@ impl InWork {
             if off_cylcles_count == 0 {
                 off_cylcles_count = 8;
             }
-            hprintln!("off_cylcles_count: {}", off_cylcles_count);
+            logger::log(Event::LedPeriodChanged(off_cylcles_count));
             (prev_on_cylcles_count, off_cylcles_count)
         });
     }
 
 
-    pub fn on_tim2(&mut self) {
+    /** Shows the time to the host and switches the scheduled relays. */
+    pub fn on_tim2(&mut self, hub: &mut Hub) {
         self.counter2.clear_all_flags();
         self.counter2.now().ticks();
-        let time: PrimitiveDateTime = self.rtc.get_datetime();
+        let time: OffsetDateTime = self.clock.now();
+        self.scheduler.poll(hub, &mut self.relays, PrimitiveDateTime::new(time.date(), time.time()),
+                            self.clock.get());
         let mut buf = [0u8; 64];
         let _s: &str = write_to::show(
             &mut buf,
@@ -322,63 +391,19 @@ impl InWork {
                          time.year(), time.hour(), time.minute(),
                          time.second())
         ).unwrap();
-        let tx: &mut Tx2Transfer = self.serial_transfer_2.tx();
 
         match self.usb_interrupt_device.write(_s.as_bytes()) {
-            Ok(_) => { hprintln!("usb interrupt sent!"); }
+            Ok(_) => { logger::log(Event::UsbWritten(UsbEndpoint::Interrupt)); }
             Err(err) => {
-                hprintln!("Error sending interrupu on usb! {}", UsbErrorWrapper::from(err));
+                logger::log(Event::UsbWriteFailed(UsbEndpoint::Interrupt, usb_error_kind(err)));
             }
         }
-        if self.send_interrupts {
-            match tx.start_transfer(|buf| {
-                buf.add_str(_s)
-            }) {
-                Ok(_) => { hprintln!("tx interrupt handled!"); }
-                Err(err) => { hprintln!("Error sending on tim 2! {}", err); }
-            };
-        }
 
     }
 
     pub fn on_tim3(&mut self) {
         self.counter.clear_all_flags();
         self.led.update().unwrap();
-        if self.send_interrupts && self.rtc.get_relative_timestamp().value() - self.last_sent > 5000 {
-            self.last_sent = self.rtc.get_relative_timestamp().value();
-            match self.usb_serial.write(&mut self.measure_data) {
-                Ok(_) => hprintln!("Measured data sent to USB!"),
-                Err(err) => hprintln!("Error measured data to USB! {}", UsbErrorWrapper::from(err)),
-            }
-        }
-    }
-
-
-    pub fn on_usart2(&mut self) {
-        let (tx, rx): ( &mut Tx2Transfer, &mut Rx2Transfer) = self.serial_transfer_2.split();
-        match rx.on_rx_transfer_interrupt(|data| {
-            hprintln!("rx got");
-            tx.start_transfer(|buffer| {
-                hprintln!("writng answer...");
-                buffer.add("bytes_: ".as_bytes()).unwrap();
-                buffer.add(data).unwrap();
-                hprintln!("answer wroten!");
-                Ok(())
-            })
-        }) {
-            Ok(_) => { hprintln!("rx interrupt handled!"); }
-            Err(err) => { hprintln!("Wrong UART2 on idle interrupt: {}!", err); }
-        };
-    }
-
-
-    pub fn on_dma1_stream5(&mut self) {
-        self.serial_transfer_2.rx().on_dma_interrupts();
-    }
-
-
-    pub fn on_dma1_stream6(&mut self) {
-        self.serial_transfer_2.tx().on_dma_interrupts();
     }
 
 
@@ -394,66 +419,87 @@ impl InWork {
         self.measure_data[0] = temperature as u8;
         self.measure_data[1] = (voltage & 0xFF) as u8;
         self.measure_data[2] = (voltage >> 8) as u8;
-        hprintln!("temperature: {}, voltage: {}", temperature, voltage);
+        logger::log(Event::Measurement { temperature, voltage });
     }
     
-    pub fn on_usb_otg_fs(&mut self) {
-        let serial: &mut SerialPort<UsbBusType> = &mut self.usb_serial;
-            if self.usb_dev.poll(&mut [serial]) {
-                let mut buf = [0u8; 64];
-
-                hprintln!("reading USB");
-                match serial.read(&mut buf) {
-                    Ok(count) if count > 0 => {
-                        hprintln!("read succes: {} bytes", count);
-                        for i in 0..count {
-                            buf[i] = buf[i] * 2 + 7;
-                        }
-                        self.send_interrupts = true;
-                        let mut write_offset = 0;
-                        while write_offset < count {
-                            match serial.write(&mut buf[write_offset..count]) {
-                                Ok(len) if len > 0 => {
-                                    write_offset += len;
-                                }
-                                _ => {}
-                            }
-                        }
-                    }, 
-                    Ok(i) => {
-                        hprintln!("read succes: {} bytes", i);
-                    }
-                    Err(e) => {
-                        hprintln!("read error: {:?}", e);
-                    }
+    pub fn on_usb_otg_fs(&mut self, hub: &mut Hub) {
+        if self.usb_dev.poll(&mut [&mut self.usb_serial]) {
+            let mut buf = [0u8; 64];
+
+            match self.usb_serial.read(&mut buf) {
+                Ok(count) => {
+                    logger::log(Event::UsbRead(count));
+                    let mut sink = UsbHostSink { serial: &mut self.usb_serial, usb_dev: &mut self.usb_dev };
+                    self.host_server.on_received(&buf[..count], hub, &mut self.clock, &mut self.config,
+                                                 &mut self.journal, &mut sink).ok();
+                    // the host could change the rules and the schedules
+                    self.rules.set_rules(self.config.config().rules());
+                    self.scheduler.set_schedules(self.config.config().schedules());
+                }
+                Err(e) => {
+                    logger::log(Event::UsbReadFailed(usb_error_kind(e)));
                 }
             }
+        }
+    }
+
+    /**
+    Pushes to the host the slaves answers and signals got by the hub, resolves the relay requests, runs the
+    rules, brings the relays to the desired states, records the slaves events and the relay alarms to the
+    journal.
+     */
+    pub fn on_hub_events(&mut self, hub: &mut Hub) {
+        let mut sink = UsbHostSink { serial: &mut self.usb_serial, usb_dev: &mut self.usb_dev };
+        self.host_server.poll(hub, &mut self.clock, &mut sink).ok();
+        self.relays.poll(self.clock.get());
+        self.rules.poll(hub, &mut self.relays, self.clock.get());
+        self.relays.reconcile(hub, self.clock.get());
+        self.journal_collector.poll(hub, &mut self.clock, &mut self.journal);
+        self.journal_collector.record_alarms(hub, &mut self.relays, &mut self.clock, &mut self.journal);
     }
 }
 
 
-struct UsbErrorWrapper {
-    error: UsbError
+struct UsbHostSink<'a> {
+    serial: &'a mut SerialPort<'static, UsbBusType>,
+    usb_dev: &'a mut UsbDevice<'static, UsbBusType>,
 }
 
-impl From<UsbError> for UsbErrorWrapper {
-    fn from(error: UsbError) -> Self {
-        UsbErrorWrapper { error }
+impl HostSink for UsbHostSink<'_> {
+    fn send(&mut self, frame: &[u8]) -> Result<(), Errors> {
+        let mut write_offset = 0;
+        let mut attempts = 0;
+        while write_offset < frame.len() {
+            match self.serial.write(&frame[write_offset..]) {
+                Ok(len) => {
+                    write_offset += len;
+                }
+                Err(UsbError::WouldBlock) if attempts < USB_WRITE_ATTEMPTS => {
+                    attempts += 1;
+                    self.usb_dev.poll(&mut [&mut *self.serial]);
+                }
+                Err(err) => {
+                    logger::log(Event::UsbWriteFailed(UsbEndpoint::Serial, usb_error_kind(err)));
+                    return Err(Errors::HostWriteFailed);
+                }
+            }
+        }
+        logger::log(Event::UsbWritten(UsbEndpoint::Serial));
+        Ok(())
     }
 }
 
-impl Display for UsbErrorWrapper {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        match self.error  { 
-            UsbError::BufferOverflow => write!(f, "BufferOverflow"),
-            UsbError::EndpointMemoryOverflow => write!(f, "EndpointMemoryOverflow"),
-            UsbError::InvalidEndpoint => write!(f, "InvalidEndpoint"),
-            UsbError::InvalidState => write!(f, "InvalidState"),
-            UsbError::WouldBlock => write!(f, "WouldBlock"),
-            UsbError::ParseError => write!(f, "ParseError"),
-            UsbError::EndpointOverflow => write!(f, "EndpointOverflow"),
-            UsbError::Unsupported => write!(f, "Unsupported"),
-        }
+
+fn usb_error_kind(error: UsbError) -> UsbErrorKind {
+    match error {
+        UsbError::BufferOverflow => UsbErrorKind::BufferOverflow,
+        UsbError::EndpointMemoryOverflow => UsbErrorKind::EndpointMemoryOverflow,
+        UsbError::InvalidEndpoint => UsbErrorKind::InvalidEndpoint,
+        UsbError::InvalidState => UsbErrorKind::InvalidState,
+        UsbError::WouldBlock => UsbErrorKind::WouldBlock,
+        UsbError::ParseError => UsbErrorKind::ParseError,
+        UsbError::EndpointOverflow => UsbErrorKind::EndpointOverflow,
+        UsbError::Unsupported => UsbErrorKind::Unsupported,
     }
 }
 
@@ -0,0 +1,798 @@
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use clap::{Parser, Subcommand, ValueEnum};
+use logic::hal_ext::rtc_wrapper::RelativeMillis;
+use logic::services::event_journal::{JournalEvent, JournalFilter, JournalRecord, LinkFault, RequestFailure};
+use logic::services::host_protocol::messages::{HostCommand, RelayTimes, SlaveAnswer};
+use logic::services::hub_config::{ConfigEntry, RelayName, RELAY_NAME_SIZE};
+use logic::services::relay_scheduler::{Schedule, ScheduleData, SCHEDULE_SIZE};
+use logic::services::rules::{Rule, RuleAction, RuleData, RuleTrigger, RULE_SIZE};
+use logic::services::slave_controller_link::domain::{AllData, Conversation, CyclesStatistics, DataInstructionCodes,
+                                                     DataInstructions, RelaySignalDataGetter, SignalData, Signals,
+                                                     StateFixSettings, SwitchCountingSettings, Version};
+use logic::services::slave_state_mirror::SlaveStateMirror;
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::connection::{JournalRead, TargetConn};
+use crate::output::{optional, yes_no, Table};
+
+/// Operates the relay controllers hub over its USB serial port
+#[derive(Parser, Debug)]
+#[command(name = "hub-cli")]
+pub struct Cli {
+    /// Serial port of the hub, found by its USB ids if omitted
+    #[arg(long, global = true)]
+    pub port: Option<String>,
+    /// Prints JSON instead of the tables
+    #[arg(long, global = true)]
+    pub json: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Lists the ports of the hub and the slaves on them
+    List,
+    /// Shows the pins and the state of the relays of a slave, with the times of their last fix tries and switches
+    Status { slave: u32 },
+    /// Switches a relay of a slave
+    Relay { slave: u32, index: u8, state: Switch },
+    /// Reads or writes the relays settings of a slave
+    Settings {
+        #[command(subcommand)]
+        action: SettingsAction,
+    },
+    /// Shows the main loop statistics of a slave
+    Stats { slave: u32 },
+    /// Streams the signals of the slaves
+    Watch {
+        /// Stops after this number of signals
+        #[arg(long)]
+        count: Option<usize>,
+    },
+    /// Operates the hub clock
+    Time {
+        #[command(subcommand)]
+        action: TimeAction,
+    },
+    /// Reads or writes the configuration stored in the hub, most of it takes effect after the restart
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Shows the events of the slaves recorded by the hub
+    Journal {
+        /// Only the events of this slave
+        #[arg(long)]
+        slave: Option<u32>,
+        /// Only the events of the relay with this index
+        #[arg(long)]
+        relay: Option<u8>,
+        /// Sequence number of the first record to show
+        #[arg(long, default_value_t = 0)]
+        from: u32,
+    },
+}
+
+#[derive(ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SettingsAction {
+    Get { slave: u32 },
+    /// Changes the given settings, the others are kept
+    Set {
+        slave: u32,
+        #[arg(long)]
+        switch_try_duration_ms: Option<u16>,
+        #[arg(long)]
+        switch_try_count: Option<u8>,
+        #[arg(long)]
+        wait_delay_s: Option<u8>,
+        #[arg(long)]
+        contact_ready_wait_delay_ms: Option<u16>,
+        #[arg(long)]
+        switch_limit_interval_s: Option<u16>,
+        #[arg(long)]
+        max_switch_count: Option<u8>,
+    },
+}
+
+/// The port argument is named apart from the global `--port` of the serial port.
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Lists the configuration entries
+    Show,
+    /// Sets the protocol version or the baud rate of the slave on a port
+    Port {
+        #[arg(id = "slave_port", value_name = "PORT")]
+        port: u8,
+        #[arg(long)]
+        version: Option<ProtocolVersion>,
+        #[arg(long)]
+        baud_rate: Option<u32>,
+    },
+    /// Names a relay of the slave on a port, an empty name removes it
+    RelayName {
+        #[arg(id = "slave_port", value_name = "PORT")]
+        port: u8,
+        relay: u8,
+        name: String,
+    },
+    /// Sets the state fix settings the slave on a port should have
+    StateFix {
+        #[arg(id = "slave_port", value_name = "PORT")]
+        port: u8,
+        switch_try_duration_ms: u16,
+        switch_try_count: u8,
+        wait_delay_s: u8,
+        contact_ready_wait_delay_ms: u16,
+    },
+    /// Sets the date the hub clock starts from after its power loss, in RFC 3339
+    RtcBaseDate { date: String },
+    /// Sets a rule: when a relay state of a slave gets the value, a relay state of a slave is set after the delay
+    Rule {
+        index: u8,
+        when_slave: u32,
+        when_relay: u8,
+        when: RuleSignal,
+        when_state: Switch,
+        slave: u32,
+        relay: u8,
+        action: RuleActionKind,
+        state: Switch,
+        /// Multiple of 100 ms
+        #[arg(long, default_value_t = 0)]
+        delay_ms: u32,
+    },
+    /// Removes a rule
+    ClearRule { index: u8 },
+    /// Sets a schedule: a relay of a slave is switched on and off at the times of the hub clock on the days
+    Schedule {
+        index: u8,
+        slave: u32,
+        relay: u8,
+        /// Comma separated, e.g. mon,tue,fri
+        days: String,
+        /// HH:MM
+        on: String,
+        /// HH:MM, the next day if it is not after the on time
+        off: String,
+    },
+    /// Removes a schedule
+    ClearSchedule { index: u8 },
+}
+
+#[derive(ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+const DAYS: [Day; 7] = [Day::Mon, Day::Tue, Day::Wed, Day::Thu, Day::Fri, Day::Sat, Day::Sun];
+
+/// The relay state, which change triggers a rule.
+#[derive(ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum RuleSignal {
+    Relay,
+    Monitoring,
+    Control,
+}
+
+impl From<RuleSignal> for Signals {
+    fn from(signal: RuleSignal) -> Self {
+        match signal {
+            RuleSignal::Relay => Signals::RelayStateChanged,
+            RuleSignal::Monitoring => Signals::MonitoringStateChanged,
+            RuleSignal::Control => Signals::ControlStateChanged,
+        }
+    }
+}
+
+/// The relay state a rule sets, `disable` is the temporary disabling.
+#[derive(ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum RuleActionKind {
+    Switch,
+    Disable,
+    Monitoring,
+    Control,
+}
+
+impl From<RuleActionKind> for DataInstructionCodes {
+    fn from(action: RuleActionKind) -> Self {
+        match action {
+            RuleActionKind::Switch => DataInstructionCodes::RelaySwitchedOn,
+            RuleActionKind::Disable => DataInstructionCodes::RelayDisabledTemp,
+            RuleActionKind::Monitoring => DataInstructionCodes::RelayMonitorOn,
+            RuleActionKind::Control => DataInstructionCodes::RelayControlOn,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum ProtocolVersion {
+    V1,
+    V2,
+    V3,
+}
+
+impl From<ProtocolVersion> for Version {
+    fn from(version: ProtocolVersion) -> Self {
+        match version {
+            ProtocolVersion::V1 => Version::V1,
+            ProtocolVersion::V2 => Version::V2,
+            ProtocolVersion::V3 => Version::V3,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TimeAction {
+    /// Sets the hub clock to the host clock
+    Sync,
+    /// Shows the hub clock and its drift against the host clock
+    Show,
+}
+
+/// Executes the command on the target, the result is written to `out`.
+pub fn run<P: Read + Write>(cli: &Cli, conn: &mut TargetConn<P>, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    match &cli.command {
+        Command::List => list(cli.json, conn, out),
+        Command::Status { slave } => status(cli.json, conn, out, *slave),
+        Command::Relay { slave, index, state } => {
+            let command = HostCommand::SetRelay { slave_id: *slave, relay_index: *index, on: *state == Switch::On };
+            expect_done(conn.execute(command)?)?;
+            print_done(cli.json, out)
+        }
+        Command::Settings { action: SettingsAction::Get { slave } } => {
+            let (state_fix, switch_counting) = read_settings(conn, *slave)?;
+            print_settings(cli.json, out, &state_fix, &switch_counting)
+        }
+        Command::Settings { action: SettingsAction::Set {
+            slave, switch_try_duration_ms, switch_try_count, wait_delay_s, contact_ready_wait_delay_ms,
+            switch_limit_interval_s, max_switch_count } } => {
+            let (state_fix, switch_counting) = read_settings(conn, *slave)?;
+            if switch_try_duration_ms.is_some() || switch_try_count.is_some() || wait_delay_s.is_some()
+                || contact_ready_wait_delay_ms.is_some() {
+                let settings = StateFixSettings::new(
+                    switch_try_duration_ms.unwrap_or(state_fix.switch_try_duration()),
+                    switch_try_count.unwrap_or(state_fix.switch_try_count()),
+                    wait_delay_s.unwrap_or(state_fix.wait_delay()),
+                    contact_ready_wait_delay_ms.unwrap_or(state_fix.contact_ready_wait_delay()));
+                expect_done(conn.execute(HostCommand::WriteStateFixSettings { slave_id: *slave, settings })?)?;
+            }
+            if switch_limit_interval_s.is_some() || max_switch_count.is_some() {
+                let settings = SwitchCountingSettings::new(
+                    switch_limit_interval_s.unwrap_or(switch_counting.switch_limit_interval_seconds()),
+                    max_switch_count.unwrap_or(switch_counting.max_switch_count));
+                expect_done(conn.execute(HostCommand::WriteSwitchCountingSettings { slave_id: *slave, settings })?)?;
+            }
+            let (state_fix, switch_counting) = read_settings(conn, *slave)?;
+            print_settings(cli.json, out, &state_fix, &switch_counting)
+        }
+        Command::Stats { slave } => match conn.execute(HostCommand::ReadCyclesStatistics { slave_id: *slave })? {
+            SlaveAnswer::CyclesStatistics(statistics) => print_statistics(cli.json, out, &statistics),
+            answer => Err(unexpected(answer)),
+        },
+        Command::Watch { count } => watch(cli.json, conn, out, *count),
+        Command::Time { action: TimeAction::Sync } => time_sync(cli.json, conn, out),
+        Command::Time { action: TimeAction::Show } => time_show(cli.json, conn, out),
+        Command::Config { action } => config(cli.json, conn, out, action),
+        Command::Journal { slave, relay, from } => {
+            let journal = conn.read_journal(*from, JournalFilter { slave_id: *slave, relay_index: *relay })?;
+            print_journal(cli.json, out, *from, &journal)
+        }
+    }
+}
+
+fn list<P: Read + Write>(json: bool, conn: &mut TargetConn<P>, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    let slaves = conn.list_slaves()?;
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&slaves)?)?;
+    } else {
+        let mut table = Table::new(&["PORT", "ID", "VERSION", "CONNECTION"]);
+        for slave in slaves {
+            table.add(vec![slave.port.to_string(), optional(slave.id),
+                           optional(slave.version.map(|version| format!("{:?}", version))),
+                           format!("{:?}", slave.connection)]);
+        }
+        write!(out, "{}", table)?;
+    }
+    Ok(())
+}
+
+fn status<P: Read + Write>(json: bool, conn: &mut TargetConn<P>, out: &mut dyn Write,
+                           slave: u32) -> Result<(), anyhow::Error> {
+    let all_data = match conn.execute(HostCommand::ReadAllData { slave_id: slave })? {
+        SlaveAnswer::AllData(all_data) => all_data,
+        answer => return Err(unexpected(answer)),
+    };
+    let fix_data = match conn.execute(HostCommand::ReadFixData { slave_id: slave })? {
+        SlaveAnswer::FixData(fix_data) => fix_data,
+        answer => return Err(unexpected(answer)),
+    };
+    let switch_data = match conn.execute(HostCommand::ReadSwitchData { slave_id: slave })? {
+        SlaveAnswer::SwitchData(switch_data) => switch_data,
+        answer => return Err(unexpected(answer)),
+    };
+    let mirror = mirror_of(all_data);
+    let mut relays = vec![];
+    for (index, relay) in mirror.relays().iter().take(mirror.relays_count() as usize).enumerate() {
+        relays.push((index, relay, relay_time(&fix_data, index)?, relay_time(&switch_data, index)?));
+    }
+    if json {
+        let relays: Vec<Value> = relays.into_iter().map(|(index, relay, fix_try, switch)| json!({
+            "index": index,
+            "set_pin": relay.settings().set_pin().data(),
+            "monitor_pin": relay.settings().monitor_pin().data(),
+            "control_pin": relay.settings().control_pin().data(),
+            "on": relay.is_on(),
+            "disabled": relay.is_disabled(),
+            "monitoring": relay.is_monitoring_on(),
+            "control": relay.is_control_on(),
+            "fix_tries": fix_try.value,
+            "last_fix_try": fix_try.date,
+            "last_fix_try_uncertainty_ms": fix_try.uncertainty_ms,
+            "last_switch": switch.date,
+            "last_switch_uncertainty_ms": switch.uncertainty_ms,
+        })).collect();
+        let status = json!({
+            "id": mirror.id(),
+            "interrupt_pin": mirror.interrupt_pin(),
+            "relays": relays,
+        });
+        writeln!(out, "{}", serde_json::to_string_pretty(&status)?)?;
+    } else {
+        writeln!(out, "slave {}, interrupt pin {}", optional(mirror.id()), optional(mirror.interrupt_pin()))?;
+        let mut table = Table::new(&["RELAY", "SET PIN", "MONITOR PIN", "CONTROL PIN", "ON", "DISABLED",
+            "MONITORING", "CONTROL", "FIX TRIES", "LAST FIX TRY", "LAST SWITCH"]);
+        for (index, relay, fix_try, switch) in relays {
+            table.add(vec![index.to_string(), relay.settings().set_pin().data().to_string(),
+                           relay.settings().monitor_pin().data().to_string(),
+                           relay.settings().control_pin().data().to_string(), yes_no(relay.is_on()),
+                           yes_no(relay.is_disabled()), yes_no(relay.is_monitoring_on()),
+                           yes_no(relay.is_control_on()), optional(fix_try.value), optional(fix_try.date),
+                           optional(switch.date)]);
+        }
+        write!(out, "{}", table)?;
+    }
+    Ok(())
+}
+
+/// A relay of the fix or switch data, the fields are `None` if the slave did not send the relay or its time.
+struct RelayTime {
+    value: Option<u8>,
+    date: Option<String>,
+    uncertainty_ms: Option<u32>,
+}
+
+fn relay_time(times: &RelayTimes, index: usize) -> Result<RelayTime, anyhow::Error> {
+    let sent = index < times.relays_count as usize;
+    let time = times.times.get(index).copied().flatten().filter(|_| sent);
+    Ok(RelayTime {
+        value: times.values.get(index).copied().filter(|_| sent),
+        date: time.map(|time| format_unix_millis(time.unix_millis())).transpose()?,
+        uncertainty_ms: time.map(|time| time.uncertainty_ms()),
+    })
+}
+
+/// The hub decodes the relay flags of `AllData` the same way.
+fn mirror_of(all_data: AllData) -> SlaveStateMirror {
+    let mut mirror = SlaveStateMirror::new();
+    mirror.apply_response(&DataInstructions::All(Conversation::Data(all_data)), RelativeMillis::new(0));
+    mirror
+}
+
+fn read_settings<P: Read + Write>(conn: &mut TargetConn<P>,
+                                  slave: u32) -> Result<(StateFixSettings, SwitchCountingSettings), anyhow::Error> {
+    let state_fix = match conn.execute(HostCommand::ReadStateFixSettings { slave_id: slave })? {
+        SlaveAnswer::StateFixSettings(settings) => settings,
+        answer => return Err(unexpected(answer)),
+    };
+    let switch_counting = match conn.execute(HostCommand::ReadSwitchCountingSettings { slave_id: slave })? {
+        SlaveAnswer::SwitchCountingSettings(settings) => settings,
+        answer => return Err(unexpected(answer)),
+    };
+    Ok((state_fix, switch_counting))
+}
+
+fn print_settings(json: bool, out: &mut dyn Write, state_fix: &StateFixSettings,
+                  switch_counting: &SwitchCountingSettings) -> Result<(), anyhow::Error> {
+    let settings = [
+        ("switch_try_duration_ms", state_fix.switch_try_duration() as u64),
+        ("switch_try_count", state_fix.switch_try_count() as u64),
+        ("wait_delay_s", state_fix.wait_delay() as u64),
+        ("contact_ready_wait_delay_ms", state_fix.contact_ready_wait_delay() as u64),
+        ("switch_limit_interval_s", switch_counting.switch_limit_interval_seconds() as u64),
+        ("max_switch_count", switch_counting.max_switch_count as u64),
+    ];
+    print_values(json, out, &settings)
+}
+
+fn print_statistics(json: bool, out: &mut dyn Write, statistics: &CyclesStatistics) -> Result<(), anyhow::Error> {
+    let values = [
+        ("min_cycle_duration_ms", statistics.min_cycle_duration() as u64),
+        ("max_cycle_duration_ms", statistics.max_cycle_duration() as u64),
+        ("avg_cycle_duration_ms", statistics.avg_cycle_duration() as u64),
+        ("cycles_count", statistics.cycles_count()),
+    ];
+    print_values(json, out, &values)
+}
+
+fn print_values(json: bool, out: &mut dyn Write, values: &[(&str, u64)]) -> Result<(), anyhow::Error> {
+    if json {
+        let object: serde_json::Map<String, Value> = values.iter()
+            .map(|(name, value)| (name.to_string(), json!(value)))
+            .collect();
+        writeln!(out, "{}", serde_json::to_string_pretty(&object)?)?;
+    } else {
+        let mut table = Table::new(&["NAME", "VALUE"]);
+        for (name, value) in values {
+            table.add(vec![name.to_string(), value.to_string()]);
+        }
+        write!(out, "{}", table)?;
+    }
+    Ok(())
+}
+
+fn watch<P: Read + Write>(json: bool, conn: &mut TargetConn<P>, out: &mut dyn Write,
+                          count: Option<usize>) -> Result<(), anyhow::Error> {
+    conn.subscribe_signals(true)?;
+    if !json {
+        write_signal_line(out, "SLAVE", "SIGNAL", "RELAY", "ON", "TIME")?;
+    }
+    let mut received = 0;
+    while count.is_none_or(|count| received < count) {
+        let (slave_id, signal, time) = conn.next_signal()?;
+        let date = time.map(|time| format_unix_millis(time.unix_millis())).transpose()?;
+        received += 1;
+        let (name, relay) = match &signal {
+            SignalData::GetTimeStamp => ("get_time_stamp", None),
+            SignalData::RelayStateChanged(data) => ("relay_state_changed",
+                Some((data.get_relay_idx(), data.is_on(), data.get_relative_timestamp().value()))),
+            SignalData::MonitoringStateChanged(data) => ("monitoring_state_changed",
+                Some((data.get_relay_idx(), data.is_on(), data.get_relative_timestamp().value()))),
+            SignalData::ControlStateChanged(data) => ("control_state_changed",
+                Some((data.get_relay_idx(), data.is_on(), data.get_relative_timestamp().value()))),
+            SignalData::StateFixTry(data) => ("state_fix_try",
+                Some((data.get_relay_idx(), data.is_on(), data.get_relative_timestamp().value()))),
+        };
+        if json {
+            // one object per line, so the stream can be piped
+            writeln!(out, "{}", json!({
+                "slave": slave_id,
+                "signal": name,
+                "relay": relay.map(|(index, _, _)| index),
+                "on": relay.map(|(_, on, _)| on),
+                "slave_time_s": relay.map(|(_, _, seconds)| seconds),
+                "time": date,
+                "time_uncertainty_ms": time.map(|time| time.uncertainty_ms()),
+            }))?;
+        } else {
+            // the slave seconds are shown while the hub does not know the date
+            let time = date.zip(time).map(|(date, time)| format!("{} ±{} ms", date, time.uncertainty_ms()))
+                .or_else(|| relay.map(|(_, _, seconds)| format!("{} s", seconds)));
+            write_signal_line(out, &optional(slave_id), name, &optional(relay.map(|(index, _, _)| index)),
+                              &optional(relay.map(|(_, on, _)| yes_no(on))), &optional(time))?;
+        }
+        out.flush()?;
+    }
+    conn.subscribe_signals(false)
+}
+
+/// The signals are printed as they come, so the columns have fixed widths.
+fn write_signal_line(out: &mut dyn Write, slave: &str, signal: &str, relay: &str, on: &str,
+                     slave_time: &str) -> Result<(), anyhow::Error> {
+    writeln!(out, "{:<10}  {:<24}  {:<5}  {:<3}  {}", slave, signal, relay, on, slave_time)?;
+    Ok(())
+}
+
+fn time_sync<P: Read + Write>(json: bool, conn: &mut TargetConn<P>,
+                              out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    let previous = conn.set_time(now)?;
+    let offset = previous - now;
+    let previous = format_unix_millis(previous)?;
+    let now = format_unix_millis(now)?;
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&json!({
+            "previous": previous,
+            "current": now,
+            "offset_ms": offset,
+        }))?)?;
+    } else {
+        writeln!(out, "hub clock set to {}, it was {} ({:+} ms)", now, previous, offset)?;
+    }
+    Ok(())
+}
+
+fn time_show<P: Read + Write>(json: bool, conn: &mut TargetConn<P>,
+                              out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    let (unix_millis, synced, drift_ppm) = conn.read_time()?;
+    let host = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    let time = format_unix_millis(unix_millis)?;
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&json!({
+            "time": time,
+            "synced": synced,
+            "offset_ms": unix_millis - host,
+            "drift_ppm": drift_ppm,
+        }))?)?;
+    } else {
+        let mut table = Table::new(&["NAME", "VALUE"]);
+        table.add(vec!["time".to_string(), time]);
+        table.add(vec!["synced".to_string(), yes_no(synced).to_string()]);
+        table.add(vec!["offset, ms".to_string(), format!("{:+}", unix_millis - host)]);
+        table.add(vec!["drift, ppm".to_string(), optional(drift_ppm)]);
+        write!(out, "{}", table)?;
+    }
+    Ok(())
+}
+
+fn format_unix_seconds(unix_seconds: i64) -> Result<String, anyhow::Error> {
+    Ok(OffsetDateTime::from_unix_timestamp(unix_seconds)?.format(&Rfc3339)?)
+}
+
+fn format_unix_millis(unix_millis: i64) -> Result<String, anyhow::Error> {
+    Ok(OffsetDateTime::from_unix_timestamp_nanos(unix_millis as i128 * 1_000_000)?.format(&Rfc3339)?)
+}
+
+fn print_journal(json: bool, out: &mut dyn Write, from: u32, journal: &JournalRead) -> Result<(), anyhow::Error> {
+    let mut rows = vec![];
+    for record in &journal.records {
+        let (event, relay, detail) = match record.event() {
+            JournalEvent::RelayChanged { relay_index, on } =>
+                ("relay_changed", Some(relay_index), on_off(on).to_string()),
+            JournalEvent::FixTry { relay_index, on } => ("fix_try", Some(relay_index), on_off(on).to_string()),
+            JournalEvent::RequestFailed { instruction, failure } => ("request_failed", None, format!("{:?} {}",
+                instruction, match failure {
+                    RequestFailure::Timeout => "timeout",
+                    RequestFailure::Corrupted => "corrupted",
+                })),
+            JournalEvent::SlaveError { instruction, error } =>
+                ("slave_error", None, format!("{:?} {:?}", instruction, error)),
+            JournalEvent::LinkFault(fault) => ("link_fault", None, match fault {
+                LinkFault::Dma => "dma",
+                LinkFault::Overflow => "overflow",
+                LinkFault::Corrupted => "corrupted",
+                LinkFault::Other => "other",
+            }.to_string()),
+            JournalEvent::Connection { state, cause } => ("connection", None, format!("{:?} {:?}", state, cause)),
+            JournalEvent::Diverged { relay_index, desired, cause } =>
+                ("diverged", Some(relay_index), format!("{} {:?}", on_off(desired), cause)),
+        };
+        rows.push((record, format_unix_millis(record.time().unix_millis())?, event, relay, detail));
+    }
+    if json {
+        let rows: Vec<Value> = rows.into_iter()
+            .map(|(record, time, event, relay, detail)| json!({
+                "seq": record.seq(),
+                "time": time,
+                "time_uncertainty_ms": record.time().uncertainty_ms(),
+                "port": record.port(),
+                "slave": record.slave_id(),
+                "event": event,
+                "relay": relay,
+                "detail": detail,
+            }))
+            .collect();
+        writeln!(out, "{}", serde_json::to_string_pretty(&json!({"first_seq": journal.first_seq, "records": rows}))?)?;
+    } else {
+        if from < journal.first_seq {
+            writeln!(out, "records {}-{} are lost on the hub", from, journal.first_seq - 1)?;
+        }
+        let mut table = Table::new(&["SEQ", "TIME", "PORT", "SLAVE", "EVENT", "RELAY", "DETAIL"]);
+        for (record, time, event, relay, detail) in rows {
+            table.add(vec![record.seq().to_string(), time, record.port().to_string(), optional(record.slave_id()),
+                           event.to_string(), optional(relay), detail]);
+        }
+        write!(out, "{}", table)?;
+    }
+    Ok(())
+}
+
+fn config<P: Read + Write>(json: bool, conn: &mut TargetConn<P>, out: &mut dyn Write,
+                           action: &ConfigAction) -> Result<(), anyhow::Error> {
+    let entries = match action {
+        ConfigAction::Show => return print_config(json, out, &conn.read_config()?),
+        ConfigAction::Port { port, version, baud_rate } => {
+            if version.is_none() && baud_rate.is_none() {
+                return Err(anyhow!("nothing to set, give --version or --baud-rate"));
+            }
+            version.map(|version| ConfigEntry::SlaveVersion { port: *port, version: version.into() }).into_iter()
+                .chain(baud_rate.map(|baud_rate| ConfigEntry::SlaveBaudRate { port: *port, baud_rate }))
+                .collect()
+        }
+        ConfigAction::RelayName { port, relay, name } =>
+            vec![ConfigEntry::RelayName { port: *port, relay_index: *relay, name: relay_name(name)? }],
+        ConfigAction::StateFix { port, switch_try_duration_ms, switch_try_count, wait_delay_s,
+            contact_ready_wait_delay_ms } => vec![ConfigEntry::StateFixSettings { port: *port,
+            settings: StateFixSettings::new(*switch_try_duration_ms, *switch_try_count, *wait_delay_s,
+                                            *contact_ready_wait_delay_ms) }],
+        ConfigAction::RtcBaseDate { date } => vec![ConfigEntry::RtcBaseDate {
+            unix_seconds: OffsetDateTime::parse(date, &Rfc3339)?.unix_timestamp() }],
+        ConfigAction::Rule { index, when_slave, when_relay, when, when_state, slave, relay, action, state,
+            delay_ms } => {
+            let trigger = RuleTrigger::new(*when_slave, *when_relay, (*when).into(), *when_state == Switch::On);
+            let action = RuleAction::new(*slave, *relay, (*action).into(), *state == Switch::On, *delay_ms);
+            let rule = Rule::new(trigger, action).map_err(|error| anyhow!("invalid rule: {}", error))?;
+            vec![ConfigEntry::Rule { index: *index, rule: rule.serialize() }]
+        }
+        ConfigAction::ClearRule { index } => vec![ConfigEntry::Rule { index: *index, rule: [0; RULE_SIZE] }],
+        ConfigAction::Schedule { index, slave, relay, days, on, off } => {
+            let weekdays = days.split(',')
+                .map(|day| Day::from_str(day, true).map_err(|_| anyhow!("unknown day {}", day)))
+                .try_fold(0, |mask, day| day.map(|day| mask | 1 << day as u8))?;
+            let schedule = Schedule::new(*slave, *relay, weekdays, minute_of_day(on)?, minute_of_day(off)?)
+                .map_err(|error| anyhow!("invalid schedule: {}", error))?;
+            vec![ConfigEntry::Schedule { index: *index, schedule: schedule.serialize() }]
+        }
+        ConfigAction::ClearSchedule { index } =>
+            vec![ConfigEntry::Schedule { index: *index, schedule: [0; SCHEDULE_SIZE] }],
+    };
+    for entry in entries {
+        conn.write_config(entry)?;
+    }
+    print_done(json, out)
+}
+
+fn minute_of_day(time: &str) -> Result<u16, anyhow::Error> {
+    let (hours, minutes) = time.split_once(':')
+        .and_then(|(hours, minutes)| Some((hours.parse::<u16>().ok()?, minutes.parse::<u16>().ok()?)))
+        .filter(|(hours, minutes)| *hours < 24 && *minutes < 60)
+        .ok_or_else(|| anyhow!("time {} is not HH:MM", time))?;
+    Ok(hours * 60 + minutes)
+}
+
+fn relay_name(name: &str) -> Result<RelayName, anyhow::Error> {
+    if name.len() > RELAY_NAME_SIZE {
+        return Err(anyhow!("relay name is longer than {} bytes", RELAY_NAME_SIZE));
+    }
+    let mut relay_name = [0; RELAY_NAME_SIZE];
+    relay_name[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(relay_name)
+}
+
+fn print_config(json: bool, out: &mut dyn Write, entries: &[ConfigEntry]) -> Result<(), anyhow::Error> {
+    let mut rows = vec![];
+    for entry in entries {
+        let (port, relay, setting, value) = match entry {
+            ConfigEntry::SlaveVersion { port, version } =>
+                (Some(*port), None, "version", json!(format!("{:?}", version))),
+            ConfigEntry::SlaveBaudRate { port, baud_rate } => (Some(*port), None, "baud_rate", json!(baud_rate)),
+            ConfigEntry::StateFixSettings { port, settings } => (Some(*port), None, "state_fix", json!({
+                "switch_try_duration_ms": settings.switch_try_duration(),
+                "switch_try_count": settings.switch_try_count(),
+                "wait_delay_s": settings.wait_delay(),
+                "contact_ready_wait_delay_ms": settings.contact_ready_wait_delay(),
+            })),
+            ConfigEntry::RelayName { port, relay_index, name } => {
+                let len = name.iter().position(|byte| *byte == 0).unwrap_or(RELAY_NAME_SIZE);
+                (Some(*port), Some(*relay_index), "name", json!(String::from_utf8_lossy(&name[..len])))
+            }
+            ConfigEntry::RtcBaseDate { unix_seconds } => (None, None, "rtc_base_date",
+                json!(format_unix_seconds(*unix_seconds)?)),
+            ConfigEntry::Rule { index, rule } => (None, None, "rule", rule_json(*index, rule)?),
+            ConfigEntry::Schedule { index, schedule } => (None, None, "schedule", schedule_json(*index, schedule)?),
+        };
+        rows.push((port, relay, setting, value));
+    }
+    if json {
+        let rows: Vec<Value> = rows.into_iter()
+            .map(|(port, relay, setting, value)| json!({
+                "port": port,
+                "relay": relay,
+                "setting": setting,
+                "value": value,
+            }))
+            .collect();
+        writeln!(out, "{}", serde_json::to_string_pretty(&rows)?)?;
+    } else {
+        let mut table = Table::new(&["PORT", "RELAY", "SETTING", "VALUE"]);
+        for (port, relay, setting, value) in rows {
+            let value = match value {
+                Value::String(text) => text,
+                Value::Object(fields) => fields.iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                value => value.to_string(),
+            };
+            table.add(vec![optional(port), optional(relay), setting.to_string(), value]);
+        }
+        write!(out, "{}", table)?;
+    }
+    Ok(())
+}
+
+fn rule_json(index: u8, rule: &RuleData) -> Result<Value, anyhow::Error> {
+    let rule = Rule::parse(rule).map_err(|error| anyhow!("invalid rule {}: {}", index, error))?
+        .ok_or_else(|| anyhow!("empty rule {}", index))?;
+    let (trigger, action) = (rule.trigger(), rule.action());
+    let when = match trigger.signal() {
+        Signals::MonitoringStateChanged => RuleSignal::Monitoring,
+        Signals::ControlStateChanged => RuleSignal::Control,
+        _ => RuleSignal::Relay,
+    };
+    let kind = match action.instruction() {
+        DataInstructionCodes::RelayDisabledTemp => RuleActionKind::Disable,
+        DataInstructionCodes::RelayMonitorOn => RuleActionKind::Monitoring,
+        DataInstructionCodes::RelayControlOn => RuleActionKind::Control,
+        _ => RuleActionKind::Switch,
+    };
+    Ok(json!({
+        "index": index,
+        "when_slave": trigger.slave_id(),
+        "when_relay": trigger.relay_index(),
+        "when": value_name(when),
+        "when_state": on_off(trigger.is_on()),
+        "slave": action.slave_id(),
+        "relay": action.relay_index(),
+        "action": value_name(kind),
+        "state": on_off(action.is_on()),
+        "delay_ms": action.delay_ms(),
+    }))
+}
+
+fn schedule_json(index: u8, schedule: &ScheduleData) -> Result<Value, anyhow::Error> {
+    let schedule = Schedule::parse(schedule).map_err(|error| anyhow!("invalid schedule {}: {}", index, error))?
+        .ok_or_else(|| anyhow!("empty schedule {}", index))?;
+    let days: Vec<String> = DAYS.iter()
+        .filter(|day| schedule.weekdays() & 1 << **day as u8 != 0)
+        .map(|day| value_name(*day))
+        .collect();
+    Ok(json!({
+        "index": index,
+        "slave": schedule.slave_id(),
+        "relay": schedule.relay_index(),
+        "days": days.join(","),
+        "on": format!("{:02}:{:02}", schedule.on_minute() / 60, schedule.on_minute() % 60),
+        "off": format!("{:02}:{:02}", schedule.off_minute() / 60, schedule.off_minute() % 60),
+    }))
+}
+
+fn value_name<V: ValueEnum>(value: V) -> String {
+    value.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+fn print_done(json: bool, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    if json {
+        writeln!(out, "{}", json!({ "done": true }))?;
+    } else {
+        writeln!(out, "done")?;
+    }
+    Ok(())
+}
+
+fn expect_done(answer: SlaveAnswer) -> Result<(), anyhow::Error> {
+    match answer {
+        SlaveAnswer::Done => Ok(()),
+        answer => Err(unexpected(answer)),
+    }
+}
+
+fn unexpected(answer: SlaveAnswer) -> anyhow::Error {
+    match answer {
+        SlaveAnswer::Error(code) => anyhow!("slave error: {:?}", code),
+        SlaveAnswer::Timeout => anyhow!("slave did not answer"),
+        SlaveAnswer::Corrupted => anyhow!("slave answer is corrupted"),
+        answer => anyhow!("unexpected slave answer: {:?}", answer),
+    }
+}
@@ -0,0 +1,239 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use logic::services::event_journal::{JournalFilter, JournalRecord};
+use logic::services::host_protocol::messages::{Host2Target, HostCommand, SlaveAnswer, SlaveInfo, Target2Host};
+use logic::services::hub_config::ConfigEntry;
+use logic::services::slave_controller_link::domain::SignalData;
+use logic::services::wall_clock::UtcTimestamp;
+use serialport::SerialPort;
+
+const BAUD_RATE: u32 = 115_200;
+const VID: u16 = 0x16c0;
+const PID: u16 = 0x27dd;
+/// How long to wait for a message from the target, longer than the hub waits for a slave.
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The journal records read from the target, the ones before `first_seq` are lost on the target.
+pub struct JournalRead {
+    pub first_seq: u32,
+    pub records: Vec<JournalRecord>,
+}
+
+/// A connection between the host and the target, over any byte stream.
+pub struct TargetConn<P: Read + Write> {
+    port: P,
+    rx_bytes: Vec<u8>,
+    next_tag: u16,
+}
+
+impl TargetConn<Box<dyn SerialPort>> {
+    /// Opens the serial port by its name or, if none is given, the first USB port of the hub
+    pub fn open(port_name: Option<&str>) -> Result<Self, anyhow::Error> {
+        let port_name = match port_name {
+            Some(port_name) => port_name.to_string(),
+            None => Self::find_port()?,
+        };
+        let port = serialport::new(port_name, BAUD_RATE)
+            .timeout(Duration::from_millis(100))
+            .open()?;
+        Ok(Self::new(port))
+    }
+
+    fn find_port() -> Result<String, anyhow::Error> {
+        for port in serialport::available_ports()? {
+            if let serialport::SerialPortType::UsbPort(info) = &port.port_type {
+                if info.vid == VID && info.pid == PID {
+                    return Ok(port.port_name);
+                }
+            }
+        }
+
+        Err(anyhow!("device {:04x}:{:04x} is not connected", VID, PID))
+    }
+}
+
+impl<P: Read + Write> TargetConn<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            rx_bytes: vec![],
+            next_tag: 0,
+        }
+    }
+
+    /// The ports of the hub with the slaves on them
+    pub fn list_slaves(&mut self) -> Result<Vec<SlaveInfo>, anyhow::Error> {
+        let tag = self.send(HostCommand::ListSlaves)?;
+        loop {
+            match self.receive(RESPONSE_TIMEOUT)? {
+                Target2Host::Slaves { tag: slaves_tag, slaves } if slaves_tag == tag =>
+                    return Ok(slaves.iter().flatten().copied().collect()),
+                message => self.check_unexpected(tag, message)?,
+            }
+        }
+    }
+
+    /// Sends a command to a slave and waits for the slave answer.
+    pub fn execute(&mut self, command: HostCommand) -> Result<SlaveAnswer, anyhow::Error> {
+        let tag = self.send(command)?;
+        loop {
+            match self.receive(RESPONSE_TIMEOUT)? {
+                Target2Host::Accepted { tag: accepted_tag } if accepted_tag == tag => {}
+                Target2Host::Answer { tag: answer_tag, answer } if answer_tag == tag => return Ok(answer),
+                message => self.check_unexpected(tag, message)?,
+            }
+        }
+    }
+
+    /// Syncs the target clock, returns its previous value in milliseconds.
+    pub fn set_time(&mut self, unix_millis: i64) -> Result<i64, anyhow::Error> {
+        let tag = self.send(HostCommand::SetTime { unix_millis })?;
+        loop {
+            match self.receive(RESPONSE_TIMEOUT)? {
+                Target2Host::TimeSet { tag: set_tag, previous_unix_millis } if set_tag == tag =>
+                    return Ok(previous_unix_millis),
+                message => self.check_unexpected(tag, message)?,
+            }
+        }
+    }
+
+    /// Reads the target clock: the time in milliseconds, whether it is synced, and its drift estimate.
+    pub fn read_time(&mut self) -> Result<(i64, bool, Option<i32>), anyhow::Error> {
+        let tag = self.send(HostCommand::ReadTime)?;
+        loop {
+            match self.receive(RESPONSE_TIMEOUT)? {
+                Target2Host::Time { tag: time_tag, unix_millis, synced, drift_ppm } if time_tag == tag =>
+                    return Ok((unix_millis, synced, drift_ppm)),
+                message => self.check_unexpected(tag, message)?,
+            }
+        }
+    }
+
+    pub fn subscribe_signals(&mut self, on: bool) -> Result<(), anyhow::Error> {
+        self.execute_on_target(HostCommand::SubscribeSignals { on })
+    }
+
+    /// Reads all the entries of the hub configuration.
+    pub fn read_config(&mut self) -> Result<Vec<ConfigEntry>, anyhow::Error> {
+        let mut entries = vec![];
+        loop {
+            let tag = self.send(HostCommand::ReadConfig { index: entries.len() as u16 })?;
+            let entry = loop {
+                match self.receive(RESPONSE_TIMEOUT)? {
+                    Target2Host::Config { tag: config_tag, entry, .. } if config_tag == tag => break entry,
+                    message => self.check_unexpected(tag, message)?,
+                }
+            };
+            match entry {
+                Some(entry) => entries.push(entry),
+                None => return Ok(entries),
+            }
+        }
+    }
+
+    pub fn write_config(&mut self, entry: ConfigEntry) -> Result<(), anyhow::Error> {
+        self.execute_on_target(HostCommand::WriteConfig { entry })
+    }
+
+    /// Reads the journal records matching the filter, from the cursor to the last one.
+    pub fn read_journal(&mut self, cursor: u32, filter: JournalFilter) -> Result<JournalRead, anyhow::Error> {
+        let mut records: Vec<JournalRecord> = vec![];
+        let mut first_seq = None;
+        loop {
+            let cursor = records.last().map(|record| record.seq() + 1).unwrap_or(cursor);
+            let tag = self.send(HostCommand::ReadJournal { cursor, filter })?;
+            let (seq, record) = loop {
+                match self.receive(RESPONSE_TIMEOUT)? {
+                    Target2Host::Journal { tag: journal_tag, first_seq, record } if journal_tag == tag =>
+                        break (first_seq, record),
+                    message => self.check_unexpected(tag, message)?,
+                }
+            };
+            let first_seq = *first_seq.get_or_insert(seq);
+            match record {
+                Some(record) => records.push(record),
+                None => return Ok(JournalRead { first_seq, records }),
+            }
+        }
+    }
+
+    /// Sends a command executed by the target itself and waits for it to be done.
+    fn execute_on_target(&mut self, command: HostCommand) -> Result<(), anyhow::Error> {
+        let tag = self.send(command)?;
+        loop {
+            match self.receive(RESPONSE_TIMEOUT)? {
+                Target2Host::Done { tag: done_tag } if done_tag == tag => return Ok(()),
+                message => self.check_unexpected(tag, message)?,
+            }
+        }
+    }
+
+    /// Waits for the next signal of the subscription, the other messages are skipped. The signal comes with
+    /// the time of its timestamp, if the hub knows the slave clock and the date.
+    pub fn next_signal(&mut self) -> Result<(Option<u32>, SignalData, Option<UtcTimestamp>), anyhow::Error> {
+        loop {
+            if let Target2Host::Signal { slave_id, signal, time } = self.receive(Duration::MAX)? {
+                return Ok((slave_id, signal, time));
+            }
+        }
+    }
+
+    /// Sends a command to the target, returns its tag.
+    pub fn send(&mut self, command: HostCommand) -> Result<u16, anyhow::Error> {
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1);
+        let tx_bytes = postcard::to_allocvec_cobs(&Host2Target { tag, command })
+            .map_err(|e| anyhow::Error::msg(e.to_string()))?;
+
+        self.port.write_all(&tx_bytes)?;
+        self.port.flush()?;
+
+        Ok(tag)
+    }
+
+    /// Waits for the next message from the target.
+    pub fn receive(&mut self, timeout: Duration) -> Result<Target2Host, anyhow::Error> {
+        let started = Instant::now();
+        let mut buffer = [0; 64];
+
+        let delimiter_pos = loop {
+            if let Some(pos) = self.rx_bytes.iter().position(|byte| *byte == 0) {
+                break pos;
+            }
+
+            match self.port.read(&mut buffer) {
+                Ok(0) => return Err(anyhow!("connection closed")),
+                Ok(bytes_read) => self.rx_bytes.extend_from_slice(&buffer[..bytes_read]),
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => {
+                    if started.elapsed() >= timeout {
+                        return Err(anyhow!("no answer from the target"));
+                    }
+                }
+                Err(error) => return Err(error.into()),
+            }
+        };
+
+        let endpos = delimiter_pos + 1;
+        let frame = &mut self.rx_bytes[..endpos];
+        let res = postcard::from_bytes_cobs::<Target2Host>(frame)
+            .map_err(|e| anyhow::Error::msg(e.to_string()));
+
+        // pop frame from RX buffer *before* raising any error
+        self.rx_bytes.drain(..endpos);
+
+        res
+    }
+
+    /// Fails on the messages which mean the command with the tag is not executed, others are skipped.
+    fn check_unexpected(&self, tag: u16, message: Target2Host) -> Result<(), anyhow::Error> {
+        match message {
+            Target2Host::Rejected { tag: rejected_tag, reason } if rejected_tag == tag =>
+                Err(anyhow!("command rejected: {:?}", reason)),
+            Target2Host::Malformed => Err(anyhow!("command is not understood by the target")),
+            // signals and late answers to the abandoned commands
+            _ => Ok(()),
+        }
+    }
+}
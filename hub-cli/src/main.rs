@@ -0,0 +1,9 @@
+use clap::Parser;
+use hub_cli::cli::{self, Cli};
+use hub_cli::connection::TargetConn;
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let mut conn = TargetConn::open(cli.port.as_deref())?;
+    cli::run(&cli, &mut conn, &mut std::io::stdout().lock())
+}
@@ -0,0 +1,446 @@
+//! The commands are run against a fake hub on the other side of a pseudo-terminal.
+
+use std::io::{Read, Write};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+use hub_cli::cli::{self, Cli};
+use hub_cli::connection::TargetConn;
+use logic::hal_ext::rtc_wrapper::RelativeSeconds;
+use logic::services::event_journal::{JournalEvent, JournalFilter, JournalRecord, LinkFault};
+use logic::services::hub_config::{ConfigEntry, RELAY_NAME_SIZE};
+use logic::services::host_protocol::messages::{Host2Target, HostCommand, Rejection, RelayTimes, SlaveAnswer,
+                                               SlaveInfo, Target2Host, HOST_FRAME_SIZE, MAX_LISTED_SLAVES};
+use logic::services::slave_controller_link::connection_monitor::ConnectionState;
+use logic::services::slave_controller_link::domain::{AllData, DataInstructionCodes, ErrorCode, RelaySettings,
+                                                     RelaySignalData, RelaySignalDataExt, SignalData, StateFixSettings,
+                                                     SwitchCountingSettings, Version};
+use logic::services::wall_clock::UtcTimestamp;
+use postcard::accumulator::{CobsAccumulator, FeedResult};
+use serialport::{SerialPort, TTYPort};
+
+#[test]
+fn list_prints_table_of_ports() {
+    let output = run_against(&["list"], |command| match command.command {
+        HostCommand::ListSlaves => {
+            let mut slaves = [None; MAX_LISTED_SLAVES];
+            slaves[0] = Some(SlaveInfo { port: 0, id: Some(305419896), version: Some(Version::V2),
+                connection: ConnectionState::Online });
+            slaves[1] = Some(SlaveInfo { port: 1, id: None, version: None, connection: ConnectionState::Offline });
+            vec![Target2Host::Slaves { tag: command.tag, slaves }]
+        }
+        _ => vec![],
+    });
+
+    assert_eq!("PORT  ID         VERSION  CONNECTION\n0     305419896  V2       Online\n1     -          -        Offline\n",
+               output.unwrap());
+}
+
+#[test]
+fn status_prints_relays_as_json() {
+    let output = run_against(&["--json", "status", "7"], |command| match command.command {
+        HostCommand::ReadAllData { slave_id: 7 } => {
+            let mut all_data = AllData::new(7, 2);
+            all_data.relays_count = 2;
+            all_data.relays_settings[0] = RelaySettings::create(10, 11, 12);
+            all_data.relays_settings[1] = RelaySettings::create(13, 14, 15);
+            // relay 0 is on and monitored, relay 1 is disabled
+            all_data.state_data.bits = 0x25;
+            answer(command.tag, SlaveAnswer::AllData(all_data))
+        }
+        HostCommand::ReadFixData { slave_id: 7 } => {
+            let mut fix_data = RelayTimes::new();
+            fix_data.add(2, Some(UtcTimestamp::new(1_686_644_910_500, 520))).unwrap();
+            // the slave clock is not known
+            fix_data.add(0, None).unwrap();
+            answer(command.tag, SlaveAnswer::FixData(fix_data))
+        }
+        HostCommand::ReadSwitchData { slave_id: 7 } => {
+            let mut switch_data = RelayTimes::new();
+            switch_data.add(1, Some(UtcTimestamp::new(1_686_644_790_500, 510))).unwrap();
+            answer(command.tag, SlaveAnswer::SwitchData(switch_data))
+        }
+        _ => vec![],
+    });
+
+    let status: serde_json::Value = serde_json::from_str(&output.unwrap()).unwrap();
+    assert_eq!(7, status["id"]);
+    assert_eq!(2, status["interrupt_pin"]);
+    assert_eq!(2, status["relays"].as_array().unwrap().len());
+    assert_eq!(serde_json::json!({"index": 0, "set_pin": 10, "monitor_pin": 11, "control_pin": 12, "on": true,
+        "disabled": false, "monitoring": true, "control": false, "fix_tries": 2,
+        "last_fix_try": "2023-06-13T08:28:30.5Z", "last_fix_try_uncertainty_ms": 520,
+        "last_switch": "2023-06-13T08:26:30.5Z", "last_switch_uncertainty_ms": 510}), status["relays"][0]);
+    assert_eq!(true, status["relays"][1]["disabled"]);
+    assert_eq!(false, status["relays"][1]["on"]);
+    assert_eq!(0, status["relays"][1]["fix_tries"]);
+    assert_eq!(serde_json::Value::Null, status["relays"][1]["last_fix_try"]);
+    assert_eq!(serde_json::Value::Null, status["relays"][1]["last_switch"]);
+}
+
+#[test]
+fn relay_is_switched() {
+    let received = Arc::new(std::sync::Mutex::new(None));
+    let received_by_device = received.clone();
+    let output = run_against(&["relay", "7", "3", "on"], move |command| match command.command {
+        HostCommand::SetRelay { .. } => {
+            *received_by_device.lock().unwrap() = Some(command.command.clone());
+            answer(command.tag, SlaveAnswer::Done)
+        }
+        _ => vec![],
+    });
+
+    assert_eq!("done\n", output.unwrap());
+    assert_eq!(Some(HostCommand::SetRelay { slave_id: 7, relay_index: 3, on: true }), *received.lock().unwrap());
+}
+
+#[test]
+fn settings_set_keeps_not_given_values() {
+    let state_fix = Arc::new(std::sync::Mutex::new(StateFixSettings::new(100, 3, 5, 20)));
+    let state_fix_of_device = state_fix.clone();
+    let output = run_against(&["--json", "settings", "set", "7", "--switch-try-count", "9"],
+                             move |command| match &command.command {
+        HostCommand::ReadStateFixSettings { slave_id: 7 } =>
+            answer(command.tag, SlaveAnswer::StateFixSettings(state_fix_of_device.lock().unwrap().clone())),
+        HostCommand::WriteStateFixSettings { slave_id: 7, settings } => {
+            *state_fix_of_device.lock().unwrap() = settings.clone();
+            answer(command.tag, SlaveAnswer::Done)
+        }
+        HostCommand::ReadSwitchCountingSettings { slave_id: 7 } =>
+            answer(command.tag, SlaveAnswer::SwitchCountingSettings(SwitchCountingSettings::new(60, 4))),
+        _ => vec![],
+    });
+
+    let settings: serde_json::Value = serde_json::from_str(&output.unwrap()).unwrap();
+    assert_eq!(StateFixSettings::new(100, 9, 5, 20), *state_fix.lock().unwrap());
+    assert_eq!(9, settings["switch_try_count"]);
+    assert_eq!(60, settings["switch_limit_interval_s"]);
+}
+
+#[test]
+fn stats_fails_on_slave_error() {
+    let output = run_against(&["stats", "7"], |command| match command.command {
+        HostCommand::ReadCyclesStatistics { slave_id: 7 } =>
+            answer(command.tag, SlaveAnswer::Error(ErrorCode::EInternalError)),
+        _ => vec![],
+    });
+
+    assert_eq!("slave error: EInternalError", output.unwrap_err().to_string());
+}
+
+#[test]
+fn command_for_unknown_slave_is_rejected() {
+    let output = run_against(&["status", "8"], |command|
+        vec![Target2Host::Rejected { tag: command.tag, reason: Rejection::SlaveNotFound }]);
+
+    assert_eq!("command rejected: SlaveNotFound", output.unwrap_err().to_string());
+}
+
+#[test]
+fn watch_streams_signals_and_unsubscribes() {
+    let subscriptions = Arc::new(std::sync::Mutex::new(vec![]));
+    let subscriptions_of_device = subscriptions.clone();
+    let output = run_against(&["--json", "watch", "--count", "2"], move |command| match command.command {
+        HostCommand::SubscribeSignals { on } => {
+            subscriptions_of_device.lock().unwrap().push(on);
+            let mut messages = vec![Target2Host::Done { tag: command.tag }];
+            if on {
+                messages.push(Target2Host::Signal { slave_id: Some(7), signal: SignalData::RelayStateChanged(
+                    RelaySignalDataExt::new(RelativeSeconds::new(120), 3, true, false)), time: Some(UtcTimestamp::new(1_686_644_910_500, 520)) });
+                messages.push(Target2Host::Signal { slave_id: None, signal: SignalData::ControlStateChanged(
+                    RelaySignalData::new(RelativeSeconds::new(121), 1, false)), time: None });
+            }
+            messages
+        }
+        _ => vec![],
+    });
+
+    let output = output.unwrap();
+    let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(vec![
+        serde_json::json!({"slave": 7, "signal": "relay_state_changed", "relay": 3, "on": true, "slave_time_s": 120,
+            "time": "2023-06-13T08:28:30.5Z", "time_uncertainty_ms": 520}),
+        serde_json::json!({"slave": null, "signal": "control_state_changed", "relay": 1, "on": false,
+            "slave_time_s": 121, "time": null, "time_uncertainty_ms": null}),
+    ], lines);
+    assert_eq!(vec![true, false], *subscriptions.lock().unwrap());
+}
+
+#[test]
+fn time_sync_sends_host_time() {
+    let sent = Arc::new(std::sync::Mutex::new(0));
+    let sent_to_device = sent.clone();
+    let output = run_against(&["time", "sync"], move |command| match command.command {
+        HostCommand::SetTime { unix_millis } => {
+            *sent_to_device.lock().unwrap() = unix_millis;
+            vec![Target2Host::TimeSet { tag: command.tag, previous_unix_millis: unix_millis - 90_250 }]
+        }
+        _ => vec![],
+    });
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+    assert!(now - *sent.lock().unwrap() <= 2000);
+    assert!(output.unwrap().ends_with("(-90250 ms)\n"));
+}
+
+#[test]
+fn time_show_prints_drift() {
+    let output = run_against(&["--json", "time", "show"], |command| match command.command {
+        HostCommand::ReadTime => vec![Target2Host::Time { tag: command.tag, unix_millis: 1_686_644_790_500,
+            synced: true, drift_ppm: Some(-12) }],
+        _ => vec![],
+    });
+
+    let time: serde_json::Value = serde_json::from_str(&output.unwrap()).unwrap();
+    assert_eq!("2023-06-13T08:26:30.5Z", time["time"]);
+    assert_eq!(true, time["synced"]);
+    assert_eq!(-12, time["drift_ppm"]);
+}
+
+#[test]
+fn config_show_reads_entries_until_none() {
+    let mut name = [0; RELAY_NAME_SIZE];
+    name[..4].copy_from_slice(b"pump");
+    let entries = [
+        ConfigEntry::RtcBaseDate { unix_seconds: 1_686_644_790 },
+        ConfigEntry::SlaveBaudRate { port: 0, baud_rate: 19_200 },
+        ConfigEntry::StateFixSettings { port: 0, settings: StateFixSettings::new(100, 3, 5, 20) },
+        ConfigEntry::RelayName { port: 1, relay_index: 4, name },
+    ];
+    let output = run_against(&["config", "show"], move |command| match command.command {
+        HostCommand::ReadConfig { index } => vec![Target2Host::Config {
+            tag: command.tag, index, entry: entries.get(index as usize).cloned() }],
+        _ => vec![],
+    });
+
+    assert_eq!("PORT  RELAY  SETTING        VALUE\n\
+                -     -      rtc_base_date  2023-06-13T08:26:30Z\n\
+                0     -      baud_rate      19200\n\
+                0     -      state_fix      contact_ready_wait_delay_ms=20 switch_try_count=3 \
+                switch_try_duration_ms=100 wait_delay_s=5\n\
+                1     4      name           pump\n", output.unwrap());
+}
+
+#[test]
+fn config_port_writes_given_entries() {
+    let written = Arc::new(std::sync::Mutex::new(vec![]));
+    let written_to_device = written.clone();
+    let output = run_against(&["config", "port", "2", "--version", "v3", "--baud-rate", "57600"],
+                             move |command| match &command.command {
+        HostCommand::WriteConfig { entry: ConfigEntry::SlaveBaudRate { .. } } =>
+            vec![Target2Host::Rejected { tag: command.tag, reason: Rejection::ConfigNotWritten }],
+        HostCommand::WriteConfig { entry } => {
+            written_to_device.lock().unwrap().push(entry.clone());
+            vec![Target2Host::Done { tag: command.tag }]
+        }
+        _ => vec![],
+    });
+
+    assert_eq!("command rejected: ConfigNotWritten", output.unwrap_err().to_string());
+    assert_eq!(vec![ConfigEntry::SlaveVersion { port: 2, version: Version::V3 }], *written.lock().unwrap());
+}
+
+#[test]
+fn config_rule_is_written_and_shown() {
+    let written = Arc::new(std::sync::Mutex::new(vec![]));
+    let written_to_device = written.clone();
+    let output = run_against(&["config", "rule", "3", "7", "1", "relay", "on", "8", "0", "disable", "on",
+                               "--delay-ms", "1500"], move |command| match &command.command {
+        HostCommand::WriteConfig { entry } => {
+            written_to_device.lock().unwrap().push(entry.clone());
+            vec![Target2Host::Done { tag: command.tag }]
+        }
+        _ => vec![],
+    });
+    output.unwrap();
+    let entries = written.lock().unwrap().clone();
+    assert_eq!(1, entries.len());
+    assert!(matches!(entries[0], ConfigEntry::Rule { index: 3, .. }));
+
+    let output = run_against(&["--json", "config", "show"], move |command| match command.command {
+        HostCommand::ReadConfig { index } => vec![Target2Host::Config {
+            tag: command.tag, index, entry: entries.get(index as usize).cloned() }],
+        _ => vec![],
+    });
+
+    let rules: serde_json::Value = serde_json::from_str(&output.unwrap()).unwrap();
+    assert_eq!("rule", rules[0]["setting"]);
+    assert_eq!(serde_json::json!({"index": 3, "when_slave": 7, "when_relay": 1, "when": "relay", "when_state": "on",
+        "slave": 8, "relay": 0, "action": "disable", "state": "on", "delay_ms": 1500}), rules[0]["value"]);
+}
+
+#[test]
+fn config_rule_with_inexact_delay_is_rejected() {
+    let output = run_against(&["config", "rule", "0", "7", "1", "relay", "on", "8", "0", "switch", "off",
+                               "--delay-ms", "150"], |_| vec![]);
+
+    assert_eq!("invalid rule: Out of range", output.unwrap_err().to_string());
+}
+
+#[test]
+fn config_schedule_is_written_and_shown() {
+    let written = Arc::new(std::sync::Mutex::new(vec![]));
+    let written_to_device = written.clone();
+    let output = run_against(&["config", "schedule", "2", "7", "1", "mon,tue,fri", "18:00", "23:30"],
+                             move |command| match &command.command {
+        HostCommand::WriteConfig { entry } => {
+            written_to_device.lock().unwrap().push(entry.clone());
+            vec![Target2Host::Done { tag: command.tag }]
+        }
+        _ => vec![],
+    });
+    output.unwrap();
+    let entries = written.lock().unwrap().clone();
+    assert_eq!(1, entries.len());
+    assert!(matches!(entries[0], ConfigEntry::Schedule { index: 2, .. }));
+
+    let output = run_against(&["--json", "config", "show"], move |command| match command.command {
+        HostCommand::ReadConfig { index } => vec![Target2Host::Config {
+            tag: command.tag, index, entry: entries.get(index as usize).cloned() }],
+        _ => vec![],
+    });
+
+    let schedules: serde_json::Value = serde_json::from_str(&output.unwrap()).unwrap();
+    assert_eq!("schedule", schedules[0]["setting"]);
+    assert_eq!(serde_json::json!({"index": 2, "slave": 7, "relay": 1, "days": "mon,tue,fri", "on": "18:00",
+        "off": "23:30"}), schedules[0]["value"]);
+}
+
+#[test]
+fn config_schedule_with_wrong_time_is_rejected() {
+    let output = run_against(&["config", "schedule", "0", "7", "1", "sat", "18:00", "24:00"], |_| vec![]);
+
+    assert_eq!("time 24:00 is not HH:MM", output.unwrap_err().to_string());
+}
+
+#[test]
+fn journal_is_read_by_cursor_with_filter() {
+    let time = UtcTimestamp::new(1_686_644_790_000, 0);
+    let records = [
+        JournalRecord::new(4, time, 0, Some(7), JournalEvent::RelayChanged { relay_index: 2, on: true }),
+        JournalRecord::new(9, time, 0, Some(7), JournalEvent::SlaveError {
+            instruction: DataInstructionCodes::RelaySwitchedOn, error: ErrorCode::ERelayIndexOutOfRange }),
+    ];
+    let cursors = Arc::new(std::sync::Mutex::new(vec![]));
+    let cursors_of_device = cursors.clone();
+    let output = run_against(&["journal", "--slave", "7", "--from", "3"], move |command| match command.command {
+        HostCommand::ReadJournal { cursor, filter } => {
+            assert_eq!(JournalFilter { slave_id: Some(7), relay_index: None }, filter);
+            cursors_of_device.lock().unwrap().push(cursor);
+            vec![Target2Host::Journal { tag: command.tag, first_seq: 2,
+                record: records.iter().find(|record| record.seq() >= cursor).copied() }]
+        }
+        _ => vec![],
+    });
+
+    assert_eq!("SEQ  TIME                  PORT  SLAVE  EVENT          RELAY  DETAIL\n\
+                4    2023-06-13T08:26:30Z  0     7      relay_changed  2      on\n\
+                9    2023-06-13T08:26:30Z  0     7      slave_error    -      RelaySwitchedOn ERelayIndexOutOfRange\n",
+               output.unwrap());
+    assert_eq!(vec![3, 5, 10], *cursors.lock().unwrap());
+}
+
+#[test]
+fn journal_shows_records_lost_on_hub() {
+    let record = JournalRecord::new(4, UtcTimestamp::new(1_686_644_790_000, 0), 1, None,
+                                    JournalEvent::LinkFault(LinkFault::Dma));
+    let output = run_against(&["journal"], move |command| match command.command {
+        HostCommand::ReadJournal { cursor, .. } => vec![Target2Host::Journal { tag: command.tag, first_seq: 4,
+            record: Some(record).filter(|record| record.seq() >= cursor) }],
+        _ => vec![],
+    });
+
+    assert_eq!("records 0-3 are lost on the hub\n\
+                SEQ  TIME                  PORT  SLAVE  EVENT       RELAY  DETAIL\n\
+                4    2023-06-13T08:26:30Z  1     -      link_fault  -      dma\n",
+               output.unwrap());
+}
+
+#[test]
+fn binary_opens_given_port() {
+    let (master, slave) = TTYPort::pair().unwrap();
+    let port_name = slave.name().unwrap();
+    let device = FakeDevice::start(master, |command| match command.command {
+        HostCommand::ListSlaves => vec![Target2Host::Slaves { tag: command.tag, slaves: [None; MAX_LISTED_SLAVES] }],
+        _ => vec![],
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hub-cli"))
+        .args(["--port", &port_name, "list", "--json"])
+        .output()
+        .unwrap();
+    device.stop();
+    drop(slave);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!("[]\n", String::from_utf8(output.stdout).unwrap());
+}
+
+fn answer(tag: u16, answer: SlaveAnswer) -> Vec<Target2Host> {
+    vec![Target2Host::Accepted { tag }, Target2Host::Answer { tag, answer }]
+}
+
+/// Runs the command line against the fake device answering with `respond`, returns the printed text.
+fn run_against<F>(args: &[&str], respond: F) -> Result<String, anyhow::Error>
+    where F: FnMut(&Host2Target) -> Vec<Target2Host> + Send + 'static
+{
+    let cli = Cli::try_parse_from(["hub-cli"].iter().chain(args)).unwrap();
+    let (master, slave) = TTYPort::pair().unwrap();
+    let device = FakeDevice::start(master, respond);
+    let mut conn = TargetConn::new(slave);
+    let mut output = Vec::new();
+
+    let result = cli::run(&cli, &mut conn, &mut output);
+    device.stop();
+
+    result.map(|()| String::from_utf8(output).unwrap())
+}
+
+/// Plays the hub on the master side of a pseudo-terminal.
+struct FakeDevice {
+    stopped: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl FakeDevice {
+    fn start<F>(mut port: TTYPort, mut respond: F) -> Self
+        where F: FnMut(&Host2Target) -> Vec<Target2Host> + Send + 'static
+    {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped_by_test = stopped.clone();
+        let thread = thread::spawn(move || {
+            let mut accumulator = CobsAccumulator::<HOST_FRAME_SIZE>::new();
+            let mut buffer = [0; 64];
+            while !stopped.load(Ordering::Relaxed) {
+                let count = match port.read(&mut buffer) {
+                    Ok(count) => count,
+                    Err(_) => continue,
+                };
+                let mut window = &buffer[..count];
+                while !window.is_empty() {
+                    window = match accumulator.feed::<Host2Target>(window) {
+                        FeedResult::Consumed => break,
+                        FeedResult::OverFull(remaining) | FeedResult::DeserError(remaining) => remaining,
+                        FeedResult::Success { data, remaining } => {
+                            for message in respond(&data) {
+                                let frame = postcard::to_allocvec_cobs(&message).unwrap();
+                                port.write_all(&frame).unwrap();
+                            }
+                            remaining
+                        }
+                    };
+                }
+            }
+        });
+        Self { stopped: stopped_by_test, thread }
+    }
+
+    fn stop(self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap();
+    }
+}
@@ -1,9 +1,11 @@
 #![deny(unsafe_code)]
 
 use core::fmt::Display;
+use embedded_storage::nor_flash::NorFlashErrorKind;
 use crate::services::slave_controller_link::domain::{ErrorCode, Operation};
 
 
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Errors {
     NoBufferAvailable,
@@ -23,7 +25,6 @@ pub enum Errors {
     SentRequestsQueueIsEmpty,
     RelayIndexOutOfRange,
     RelayCountOverflow,
-    SlaveControllersInstancesMaxCountReached,
     FromAfterTo,
     OutOfRange,
     SwitchesDataCountOverflow,
@@ -34,6 +35,13 @@ pub enum Errors {
     WrongIncomingOperation(Operation),
     DataOverflow,
     IndexOverflow,
+    FrameCrcMismatch,
+    SlaveNotFound(u32),
+    TxQueueOverflow,
+    LoggerAlreadySet,
+    HostWriteFailed,
+    FlashError(NorFlashErrorKind),
+    ConfigStoreFull,
 }
 
 impl Display for Errors {
@@ -56,7 +64,6 @@ impl Display for Errors {
             Errors::SentRequestsQueueIsEmpty => write!(f, "Sent requests queue is empty"),
             Errors::RelayIndexOutOfRange => write!(f, "Relay index out of range"),
             Errors::RelayCountOverflow => write!(f, "Relay count overflow"),
-            Errors::SlaveControllersInstancesMaxCountReached => write!(f, "Slave controllers instances max count reached"),
             Errors::FromAfterTo => write!(f, "From after to"),
             Errors::OutOfRange => write!(f, "Out of range"),
             Errors::SwitchesDataCountOverflow => write!(f, "Switches data count overflow"),
@@ -67,10 +74,18 @@ impl Display for Errors {
             Errors::WrongIncomingOperation(op) => write!(f, "Wrong incoming operation: {:?}", op),
             Errors::DataOverflow => write!(f, "Data overflow"),
             Errors::IndexOverflow => write!(f, "Index overflow"),
+            Errors::FrameCrcMismatch => write!(f, "Frame CRC mismatch"),
+            Errors::SlaveNotFound(id) => write!(f, "Slave not found: {}", id),
+            Errors::TxQueueOverflow => write!(f, "Transmit queue overflow"),
+            Errors::LoggerAlreadySet => write!(f, "Logger already set"),
+            Errors::HostWriteFailed => write!(f, "Host write failed"),
+            Errors::FlashError(kind) => write!(f, "Flash error: {:?}", kind),
+            Errors::ConfigStoreFull => write!(f, "Config store full"),
         }
     }
 }
 
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum DMAError<T> {
     /// DMA not ready to change buffers.
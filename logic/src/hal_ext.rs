@@ -2,4 +2,5 @@
 
 pub mod rtc_wrapper;
 pub mod serial_transfer;
+pub mod tx_queue;
 
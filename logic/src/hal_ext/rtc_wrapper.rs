@@ -2,11 +2,12 @@
 
 use time::PrimitiveDateTime;
 use time_core::convert::{ Millisecond, Second, Nanosecond};
+use serde_derive::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct RelativeMillis(u32);
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
 pub struct RelativeSeconds(u32);
 
 impl RelativeMillis {
@@ -49,6 +50,13 @@ pub trait RelativeTimestampSource {
     fn get(&mut self) -> RelativeMillis;
 }
 
+impl <TS: RelativeTimestampSource + ?Sized> RelativeTimestampSource for &mut TS {
+    #[inline(always)]
+    fn get(&mut self) -> RelativeMillis {
+        (**self).get()
+    }
+}
+
 pub struct DateTimeSource<RTC: Rtc> {
     rtc: RTC,
     base_date_time: Option<PrimitiveDateTime>,
@@ -70,12 +78,19 @@ impl<RTC: Rtc> DateTimeSource<RTC> {
         self.rtc.get_datetime()
     }
 
+    /**
+    Sets the date of the RTC and moves the base by the same step, so the relative timestamps go on
+    without a gap and the ones given before stay comparable with the next. Returns the previous base.
+     */
     pub fn set_datetime(&mut self, date: PrimitiveDateTime) -> Result<Option<PrimitiveDateTime>, RTC::Error> {
-        self.rtc.set_datetime(&date).map(|()| {
-            let old_base_date_time = self.base_date_time;
-            self.base_date_time = Some(date);
-            old_base_date_time
-        })
+        let elapsed = self.base_date_time.map(|base_date_time| self.get_datetime() - base_date_time);
+        self.rtc.set_datetime(&date)?;
+        let old_base_date_time = self.base_date_time;
+        self.base_date_time = Some(match elapsed {
+            Some(elapsed) => self.get_datetime() - elapsed,
+            None => date,
+        });
+        Ok(old_base_date_time)
     }
 
     pub fn get_relative_timestamp(&mut self) -> RelativeMillis {
@@ -84,8 +99,7 @@ impl<RTC: Rtc> DateTimeSource<RTC> {
                 let current_date_time = self.get_datetime();
                 let duration = current_date_time - base_date_time;
 
-                RelativeMillis( duration.whole_seconds() as u32 * Millisecond::per(Second) as u32
-                    + duration.subsec_nanoseconds() as u32 / Nanosecond::per(Millisecond) )
+                RelativeMillis(duration.whole_milliseconds() as u32)
             },
             None => {
                 self.base_date_time = Some(self.get_datetime());
@@ -95,12 +109,7 @@ impl<RTC: Rtc> DateTimeSource<RTC> {
     }
 
     pub fn relative_timestamp_to_date_time(&self, millis: RelativeMillis) -> Option<PrimitiveDateTime> {
-        self.base_date_time.map(|base_date_time| {
-            let duration = time::Duration::new(
-                (millis.0 / Millisecond::per(Second) as u32) as i64,
-                ((millis.0 % Millisecond::per(Second) as u32) * Nanosecond::per(Millisecond)) as i32);
-            base_date_time + duration
-        })
+        self.base_date_time.map(|base_date_time| base_date_time + Self::duration(millis))
     }
 
     pub fn get_relative_seconds(&mut self) -> RelativeSeconds {
@@ -124,6 +133,11 @@ impl<RTC: Rtc> DateTimeSource<RTC> {
         })
     }
 
+    fn duration(millis: RelativeMillis) -> time::Duration {
+        time::Duration::new(
+            (millis.0 / Millisecond::per(Second) as u32) as i64,
+            ((millis.0 % Millisecond::per(Second) as u32) * Nanosecond::per(Millisecond)) as i32)
+    }
 }
 
 
@@ -259,14 +273,16 @@ mod tests {
         let shift = RelativeMillis(shift1);
         mock.borrow_mut().set_time_passed(shift);
         assert_eq!(rtc_wrapper.get_relative_timestamp(), shift);
-        //if set_datetime is called, next calls should be based on new value
+        //if set_datetime is called, next calls should go on without a gap
         let date_time_2 = PrimitiveDateTime::new(
             time::Date::from_calendar_date(2021, Month::February, 2).unwrap(),
             time::Time::from_hms(1, 1, 10).unwrap());
         rtc_wrapper.set_datetime(date_time_2).unwrap();
-        let shift = RelativeMillis(shift2);
-        mock.borrow_mut().set_time_passed(shift);
-        assert_eq!(rtc_wrapper.get_relative_timestamp(), shift);
+        assert_eq!(rtc_wrapper.get_relative_timestamp(), RelativeMillis(shift1));
+        mock.borrow_mut().set_time_passed(RelativeMillis(shift2));
+        assert_eq!(rtc_wrapper.get_relative_timestamp(), RelativeMillis(shift1.wrapping_add(shift2)));
+        assert_eq!(rtc_wrapper.relative_timestamp_to_date_time(RelativeMillis(shift1)),
+                   Some(date_time_2));
     }
 
     #[quickcheck]
@@ -301,14 +317,13 @@ mod tests {
         let shift = RelativeSeconds(shift1);
         mock.borrow_mut().set_time_passed(RelativeMillis(shift.value() as u32 * 1000));
         assert_eq!(rtc_wrapper.get_relative_seconds(), shift);
-        //if set_datetime is called, next calls should be based on new value
+        //if set_datetime is called, next calls should go on without a gap
         let date_time_2 = PrimitiveDateTime::new(
             time::Date::from_calendar_date(2021, Month::February, 2).unwrap(),
             time::Time::from_hms(1, 1, 10).unwrap());
         rtc_wrapper.set_datetime(date_time_2).unwrap();
-        let shift = RelativeSeconds(shift2);
-        mock.borrow_mut().set_time_passed(RelativeMillis(shift.value() as u32 * 1000));
-        assert_eq!(rtc_wrapper.get_relative_seconds(), shift);
+        mock.borrow_mut().set_time_passed(RelativeMillis(shift2 * 1000));
+        assert_eq!(rtc_wrapper.get_relative_seconds(), RelativeSeconds(shift1 + shift2));
     }
 
     #[quickcheck]
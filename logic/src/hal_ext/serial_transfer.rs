@@ -1,9 +1,10 @@
 #![allow(unsafe_code)]
 
-use cortex_m_semihosting::hprintln;
 use embedded_dma::{ReadBuffer, WriteBuffer};
 use crate::errors::{DMAError, Errors};
+use crate::hal_ext::tx_queue::{TxPriority, TxQueue, TxQueueStats};
 use crate::utils::dma_read_buffer::BufferWriter;
+use crate::utils::logger::{self, Event, Stream};
 
 pub trait Decomposable<T>
 {
@@ -47,11 +48,50 @@ pub trait TransferProxy<BUF> {
     fn clear_transfer_error(&mut self);
 }
 
+/** Counters of the DMA stream events, `transfers` are the started ones for TX and the idle line events for RX. */
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct TransferStats {
+    transfers: u32,
+    fifo_errors: u32,
+    transfer_errors: u32,
+    direct_mode_errors: u32,
+    overruns: u32,
+}
+
+impl TransferStats {
+
+    #[inline(always)]
+    pub fn transfers(&self) -> u32 {
+        self.transfers
+    }
+
+    #[inline(always)]
+    pub fn fifo_errors(&self) -> u32 {
+        self.fifo_errors
+    }
+
+    #[inline(always)]
+    pub fn transfer_errors(&self) -> u32 {
+        self.transfer_errors
+    }
+
+    #[inline(always)]
+    pub fn direct_mode_errors(&self) -> u32 {
+        self.direct_mode_errors
+    }
+
+    /** RX buffer filled up before the line got idle. */
+    #[inline(always)]
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+}
+
 pub struct SerialTransfer<T, R, TxBuff, RxBuff>
 where
     T: TxTransferProxy<TxBuff>,
     R: RxTransferProxy<RxBuff>,
-    TxBuff: ReadBuffer + BufferWriter,
+    TxBuff: ReadBuffer<Word = u8> + BufferWriter,
     RxBuff: WriteBuffer + ReadableBuffer,
 {
     tx: TxTransfer<T, TxBuff>,
@@ -62,7 +102,7 @@ impl <T, R, TxBuff, RxBuff> SerialTransfer<T, R, TxBuff, RxBuff>
     where
         T: TxTransferProxy<TxBuff>,
         R: RxTransferProxy<RxBuff>,
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         RxBuff: WriteBuffer + ReadableBuffer,
 {
 
@@ -107,6 +147,7 @@ where
     back_buffer: Option<BUF>,
     transfer_error: bool,
     buffer_overflow: bool,
+    stats: TransferStats,
 }
 
 impl<R, BUF> RxTransfer<R, BUF>
@@ -120,32 +161,37 @@ impl<R, BUF> RxTransfer<R, BUF>
             back_buffer: Some(back_buffer),
             transfer_error: false,
             buffer_overflow: false,
+            stats: TransferStats::default(),
         }
     }
 
     pub fn on_dma_interrupts(&mut self) {
         if  self.rx_transfer.is_fifo_error() {
-            hprintln!("rx: is_fifo_error");
+            logger::log(Event::DmaFifoError(Stream::Rx));
             self.transfer_error = true;
+            self.stats.fifo_errors = self.stats.fifo_errors.saturating_add(1);
             self.rx_transfer.clear_fifo_error();
         }
         if  self.rx_transfer.is_transfer_complete() {
-            hprintln!("rx: is_transfer_complete");
+            logger::log(Event::DmaTransferComplete(Stream::Rx));
             self.buffer_overflow = true;
+            self.stats.overruns = self.stats.overruns.saturating_add(1);
             self.rx_transfer.clear_transfer_complete();
         }
         if self.rx_transfer.is_transfer_error() {
-            hprintln!("rx: is_transfer_error");
+            logger::log(Event::DmaTransferError(Stream::Rx));
             self.transfer_error = true;
+            self.stats.transfer_errors = self.stats.transfer_errors.saturating_add(1);
             self.rx_transfer.clear_transfer_error();
         }
         if self.rx_transfer.is_half_transfer() {
-            hprintln!("rx: is_half_transfer");
+            logger::log(Event::DmaHalfTransfer(Stream::Rx));
             self.rx_transfer.clear_half_transfer();
         }
         if self.rx_transfer.is_direct_mode_error() {
-            hprintln!("rx: is_direct_mode_error");
+            logger::log(Event::DmaDirectModeError(Stream::Rx));
             self.transfer_error = true;
+            self.stats.direct_mode_errors = self.stats.direct_mode_errors.saturating_add(1);
             self.rx_transfer.clear_direct_mode_error();
         }
 
@@ -157,6 +203,16 @@ impl<R, BUF> RxTransfer<R, BUF>
         self.buffer_overflow
     }
 
+    #[inline(always)]
+    pub fn stats(&self) -> TransferStats {
+        self.stats
+    }
+
+    #[inline(always)]
+    pub fn reset_stats(&mut self) {
+        self.stats = TransferStats::default();
+    }
+
     fn return_buffer(&mut self, buffer: BUF) {
         self.back_buffer = Some(buffer);
         self.transfer_error = false;
@@ -180,12 +236,16 @@ impl <R, BUF> Receiver for RxTransfer<R, BUF>
             let new_buffer = self.back_buffer.take().unwrap();
             match self.rx_transfer.next_transfer(new_buffer) {
                 Ok(buffer) => {
+                    self.stats.transfers = self.stats.transfers.saturating_add(1);
                     let result = receiver(buffer.slice_to(bytes_count));
                     self.return_buffer(buffer);
                     result
                 },
                 Err(err) => {
                     let (err, buffer) = err.decompose();
+                    if err == DMAError::Overrun(()) {
+                        self.stats.overruns = self.stats.overruns.saturating_add(1);
+                    }
                     self.return_buffer(buffer);
                     Err(Errors::DmaError(err))
                 }
@@ -198,7 +258,7 @@ impl <R, BUF> Receiver for RxTransfer<R, BUF>
 }
 
 pub trait Sender<BUF: ReadBuffer + BufferWriter> {
-    fn start_transfer<F: FnOnce(&mut BUF)->Result<(), Errors>>(&mut self, writter: F) -> Result<(), Errors>;
+    fn start_transfer<F: FnOnce(&mut BUF)->Result<(), Errors>>(&mut self, priority: TxPriority, writter: F) -> Result<(), Errors>;
 }
 
 pub struct TxTransfer<T, BUF>
@@ -210,13 +270,15 @@ where
     back_buffer: Option<BUF>,
     transfer_error: bool,
     last_transfer_ended: bool,
+    queue: TxQueue,
+    stats: TransferStats,
 }
 
 
 impl<T, BUF> TxTransfer<T, BUF>
     where
         T: TxTransferProxy<BUF>,
-        BUF: ReadBuffer + BufferWriter,
+        BUF: ReadBuffer<Word = u8> + BufferWriter,
 {
     pub fn new(tx_transfer: T, back_buffer: BUF) -> Self {
         Self {
@@ -224,34 +286,42 @@ impl<T, BUF> TxTransfer<T, BUF>
             back_buffer: Some(back_buffer),
             transfer_error: false,
             last_transfer_ended: true,
+            queue: TxQueue::new(),
+            stats: TransferStats::default(),
         }
     }
 
     pub fn on_dma_interrupts(&mut self) {
         if  self.tx_transfer.is_fifo_error() {
-            hprintln!("tx: is_fifo_error");
+            logger::log(Event::DmaFifoError(Stream::Tx));
             self.transfer_error = true;
+            self.stats.fifo_errors = self.stats.fifo_errors.saturating_add(1);
             self.tx_transfer.clear_fifo_error();
         }
         if  self.tx_transfer.is_transfer_complete() {
-            hprintln!("tx: is_transfer_complete");
+            logger::log(Event::DmaTransferComplete(Stream::Tx));
             self.last_transfer_ended = true;
             self.tx_transfer.clear_transfer_complete();
         }
         if self.tx_transfer.is_transfer_error() {
-            hprintln!("tx: is_transfer_error");
+            logger::log(Event::DmaTransferError(Stream::Tx));
             self.transfer_error = true;
+            self.stats.transfer_errors = self.stats.transfer_errors.saturating_add(1);
             self.tx_transfer.clear_transfer_error();
         }
         if self.tx_transfer.is_half_transfer() {
-            hprintln!("tx: is_half_transfer");
+            logger::log(Event::DmaHalfTransfer(Stream::Tx));
             self.tx_transfer.clear_half_transfer();
         }
         if self.tx_transfer.is_direct_mode_error() {
-            hprintln!("tx: is_direct_mode_error");
+            logger::log(Event::DmaDirectModeError(Stream::Tx));
             self.transfer_error = true;
+            self.stats.direct_mode_errors = self.stats.direct_mode_errors.saturating_add(1);
             self.tx_transfer.clear_direct_mode_error();
         }
+        if self.is_free() {
+            self.send_queued();
+        }
     }
 
     pub fn transfer_error(&self) -> bool {
@@ -260,28 +330,56 @@ impl<T, BUF> TxTransfer<T, BUF>
     pub fn last_transfer_ended(&self) -> bool {
         self.last_transfer_ended
     }
-}
 
-impl<T, BUF> Sender<BUF> for TxTransfer<T, BUF>
-    where
-        T: TxTransferProxy<BUF>,
-        BUF: ReadBuffer + BufferWriter,
-{
+    pub fn queue_stats(&self) -> TxQueueStats {
+        self.queue.stats()
+    }
 
-    /**
-    Takes writter function to generate send data and sens them to UART though DMA. Should always return Ok if
-    is called from one thread only at the same time.
-     */
-    fn start_transfer<F: FnOnce(&mut BUF)->Result<(), Errors>>(&mut self, writter: F) -> Result<(), Errors> {
-        if !self.last_transfer_ended && !self.transfer_error {
-            return Err(Errors::TransferInProgress);
-        }
-        let mut new_buffer = match self.back_buffer.take() {
-            Some(buffer) => Ok(buffer),
-            None => Err(Errors::NoBufferAvailable),
-        }?;
+    #[inline(always)]
+    pub fn stats(&self) -> TransferStats {
+        self.stats
+    }
+
+    #[inline(always)]
+    pub fn reset_stats(&mut self) {
+        self.stats = TransferStats::default();
+    }
+
+    #[inline(always)]
+    fn is_free(&self) -> bool {
+        self.last_transfer_ended || self.transfer_error
+    }
+
+    fn send_queued(&mut self) {
+        if let Some(frame) = self.queue.pop() {
+            if let Err(error) = self.transfer(|buffer| buffer.add(frame.bytes())) {
+                logger::log(Event::QueuedFrameNotSent(error));
+            }
+        }
+    }
+
+    /** The back buffer is not used by DMA, so its bytes are copied to the queue while the line is busy. */
+    fn enqueue<F: FnOnce(&mut BUF)->Result<(), Errors>>(&mut self, priority: TxPriority, writter: F) -> Result<(), Errors> {
+        let mut buffer = self.back_buffer.take().ok_or(Errors::NoBufferAvailable)?;
+        buffer.clear();
+        let result = writter(&mut buffer).and_then(|_| {
+            let (ptr, len) = unsafe { buffer.read_buffer() };
+            self.queue.push(priority, unsafe { core::slice::from_raw_parts(ptr, len) })
+        });
+        self.back_buffer = Some(buffer);
+        if result == Err(Errors::TxQueueOverflow) {
+            logger::log(Event::TxQueueOverflow(priority));
+        }
+        result
+    }
+
+    fn transfer<F: FnOnce(&mut BUF)->Result<(), Errors>>(&mut self, writter: F) -> Result<(), Errors> {
+        let mut new_buffer = self.back_buffer.take().ok_or(Errors::NoBufferAvailable)?;
         new_buffer.clear();
-        writter(&mut new_buffer)?;
+        if let Err(error) = writter(&mut new_buffer) {
+            self.back_buffer = Some(new_buffer);
+            return Err(error);
+        }
 
         self.transfer_error = false;
         self.last_transfer_ended = false;
@@ -290,6 +388,7 @@ impl<T, BUF> Sender<BUF> for TxTransfer<T, BUF>
         match self.tx_transfer.next_transfer( new_buffer) {
             Ok(buffer) => {
                 self.back_buffer = Some(buffer);
+                self.stats.transfers = self.stats.transfers.saturating_add(1);
                 Ok(())
             },
             Err(err) => {
@@ -299,6 +398,28 @@ impl<T, BUF> Sender<BUF> for TxTransfer<T, BUF>
             }
         }
     }
+}
+
+impl<T, BUF> Sender<BUF> for TxTransfer<T, BUF>
+    where
+        T: TxTransferProxy<BUF>,
+        BUF: ReadBuffer<Word = u8> + BufferWriter,
+{
+
+    /**
+    Takes writter function to generate send data and sens them to UART though DMA. While the previous transfer
+    is in progress the data are queued and sent on its completion, frames of higher priority first.
+     */
+    fn start_transfer<F: FnOnce(&mut BUF)->Result<(), Errors>>(&mut self, priority: TxPriority, writter: F) -> Result<(), Errors> {
+        if self.is_free() && self.queue.is_empty() {
+            return self.transfer(writter);
+        }
+        self.enqueue(priority, writter)?;
+        if self.is_free() {
+            self.send_queued();
+        }
+        Ok(())
+    }
 
 }
 
@@ -311,6 +432,7 @@ mod tests {
     use core::cmp::min;
     use super::*;
     use std::rc::Rc;
+    use crate::hal_ext::tx_queue::TX_QUEUE_CAPACITY;
     use quickcheck_macros::quickcheck;
     use rand::prelude::*;
 
@@ -622,40 +744,34 @@ mod tests {
         }
 
         fn is_direct_mode_error(&self) -> bool {
-            todo!()
+            false
         }
 
         fn is_half_transfer(&self) -> bool {
-            todo!()
+            false
         }
 
         fn is_transfer_error(&self) -> bool {
-            todo!()
+            false
         }
 
         fn clear_dma_interrupts(&mut self) {
             self.clear_dma_interrupts_calls += 1;
         }
 
-        fn clear_direct_mode_error(&mut self) {
-            todo!()
-        }
+        fn clear_direct_mode_error(&mut self) {}
 
         fn clear_fifo_error(&mut self) {
-            todo!()
+            self.fifo_error = false;
         }
 
-        fn clear_half_transfer(&mut self) {
-            todo!()
-        }
+        fn clear_half_transfer(&mut self) {}
 
         fn clear_transfer_complete(&mut self) {
-            todo!()
+            self.transfer_complete = false;
         }
 
-        fn clear_transfer_error(&mut self) {
-            todo!()
-        }
+        fn clear_transfer_error(&mut self) {}
     }
 
     impl TxTransferProxy<MockTxBuffer> for MockTxTransfer {
@@ -685,15 +801,15 @@ mod tests {
         }
 
         fn is_direct_mode_error(&self) -> bool {
-            todo!()
+            self.borrow().is_direct_mode_error()
         }
 
         fn is_half_transfer(&self) -> bool {
-            todo!()
+            self.borrow().is_half_transfer()
         }
 
         fn is_transfer_error(&self) -> bool {
-            todo!()
+            self.borrow().is_transfer_error()
         }
 
         fn clear_dma_interrupts(&mut self) {
@@ -701,23 +817,23 @@ mod tests {
         }
 
         fn clear_direct_mode_error(&mut self) {
-            todo!()
+            self.borrow_mut().clear_direct_mode_error()
         }
 
         fn clear_fifo_error(&mut self) {
-            todo!()
+            self.borrow_mut().clear_fifo_error()
         }
 
         fn clear_half_transfer(&mut self) {
-            todo!()
+            self.borrow_mut().clear_half_transfer()
         }
 
         fn clear_transfer_complete(&mut self) {
-            todo!()
+            self.borrow_mut().clear_transfer_complete()
         }
 
         fn clear_transfer_error(&mut self) {
-            todo!()
+            self.borrow_mut().clear_transfer_error()
         }
     }
 
@@ -880,20 +996,71 @@ mod tests {
         mock.borrow_mut().fifo_error = fifo_error;
         mock.borrow_mut().transfer_complete = transfer_complete;
         //move to transfer state
-        tx_transfer.start_transfer(|_| { Ok(()) }).unwrap();
+        tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }).unwrap();
         tx_transfer.on_dma_interrupts();
         assert_eq!(mock.borrow().clear_dma_interrupts_calls, 1);
-        assert_eq!(tx_transfer.fifo_error(), fifo_error);
+        assert_eq!(tx_transfer.transfer_error(), fifo_error);
         assert_eq!(tx_transfer.last_transfer_ended(), transfer_complete);
     }
 
     #[test]
-    fn test_start_transfer_should_return_error_if_transfer_not_ended()  {
+    fn test_start_transfer_should_queue_if_transfer_not_ended()  {
+        let (mut tx_transfer, mock) = create_testable_tx_transfer();
+        let curr_buff_num = mock.borrow().curr_buf.as_ref().unwrap().number;
+
+        assert_eq!(Ok(()), tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }));
+        let sent_buff_num = mock.borrow().curr_buf.as_ref().unwrap().number;
+
+        assert_eq!(Ok(()), tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }));
+
+        assert_eq!(sent_buff_num, mock.borrow().curr_buf.as_ref().unwrap().number);
+        assert_eq!(curr_buff_num, tx_transfer.back_buffer.as_ref().unwrap().number);
+        assert_eq!(1, tx_transfer.queue_stats().length());
+    }
+
+    #[test]
+    fn test_queued_frames_are_sent_on_transfer_complete() {
+        let (mut tx_transfer, mock) = create_testable_tx_transfer();
+        tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }).unwrap();
+        tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }).unwrap();
+        tx_transfer.start_transfer(TxPriority::Error, |_| { Ok(()) }).unwrap();
+
+        tx_transfer.on_dma_interrupts();
+        assert_eq!(2, tx_transfer.queue_stats().length());
+
+        for expected_length in [1, 0] {
+            mock.borrow_mut().transfer_complete = true;
+            tx_transfer.on_dma_interrupts();
+
+            assert!(!tx_transfer.last_transfer_ended());
+            assert_eq!(expected_length, tx_transfer.queue_stats().length());
+        }
+        assert_eq!(2, tx_transfer.queue_stats().queued());
+        assert_eq!(0, tx_transfer.queue_stats().overflows());
+    }
+
+    #[test]
+    fn test_start_transfer_should_return_error_on_queue_overflow() {
+        let (mut tx_transfer, _) = create_testable_tx_transfer();
+        tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }).unwrap();
+        for _ in 0..TX_QUEUE_CAPACITY {
+            tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }).unwrap();
+        }
+
+        assert_eq!(Err(Errors::TxQueueOverflow), tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }));
+        assert_eq!(Ok(()), tx_transfer.start_transfer(TxPriority::Error, |_| { Ok(()) }));
+        assert_eq!(2, tx_transfer.queue_stats().overflows());
+        assert!(tx_transfer.back_buffer.is_some());
+    }
+
+    #[test]
+    fn test_start_transfer_should_keep_buffer_on_writter_error() {
         let (mut tx_transfer, _) = create_testable_tx_transfer();
 
-        assert_eq!(Ok(()), tx_transfer.start_transfer(|_| { Ok(()) }));
+        assert_eq!(Err(Errors::DataOverflow), tx_transfer.start_transfer(TxPriority::Request, |_| { Err(Errors::DataOverflow) }));
 
-        assert_eq!(Err(Errors::TransferInProgress), tx_transfer.start_transfer(|_| { Ok(()) }));
+        assert!(tx_transfer.back_buffer.is_some());
+        assert_eq!(Ok(()), tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) }));
     }
 
     #[test]
@@ -904,7 +1071,7 @@ mod tests {
         let next_buff_num = tx_transfer.back_buffer.as_ref().unwrap().number;
         let curr_buff_num = mock.borrow().curr_buf.as_ref().unwrap().number;
 
-        let res = tx_transfer.start_transfer(|_| {
+        let res = tx_transfer.start_transfer(TxPriority::Request, |_| {
             callback = true;
             Ok(())
         });
@@ -934,7 +1101,7 @@ mod tests {
         let next_buff_num = tx_transfer.back_buffer.as_ref().unwrap().number;
         let mut buff_num_to_write = 0_u8;
 
-        let res = tx_transfer.start_transfer(|buff| {
+        let res = tx_transfer.start_transfer(TxPriority::Request, |buff| {
             callback = true;
             buff_num_to_write = buff.number;
             Ok(())
@@ -958,13 +1125,13 @@ mod tests {
         mock.borrow_mut().transfer_complete = true;
         tx_transfer.on_dma_interrupts();
         assert_eq!(true, tx_transfer.last_transfer_ended());
-        assert_eq!(true, tx_transfer.fifo_error());
+        assert_eq!(true, tx_transfer.transfer_error());
 
-        let res = tx_transfer.start_transfer(|_| { Ok(()) });
+        let res = tx_transfer.start_transfer(TxPriority::Request, |_| { Ok(()) });
 
         assert_eq!(Ok(()), res);
         assert_eq!(false, tx_transfer.last_transfer_ended());
-        assert_eq!(false, tx_transfer.fifo_error());
+        assert_eq!(false, tx_transfer.transfer_error());
 
     }
 
@@ -0,0 +1,234 @@
+#![deny(unsafe_code)]
+
+use crate::errors::Errors;
+
+pub const TX_QUEUE_CAPACITY: usize = 4;
+/** Longer frames are only sent when the line is free. */
+pub const TX_QUEUE_FRAME_SIZE: usize = 80;
+
+/** Frames of higher priority leave the queue first, frames of the same one - in the order they came. */
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum TxPriority {
+    Request,
+    Error,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct TxQueueStats {
+    length: usize,
+    max_length: usize,
+    queued: u32,
+    overflows: u32,
+}
+
+impl TxQueueStats {
+
+    /** Frames waiting for the line now. */
+    #[inline(always)]
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    #[inline(always)]
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    #[inline(always)]
+    pub fn queued(&self) -> u32 {
+        self.queued
+    }
+
+    /** Frames lost because the queue was full: rejected or pushed out by a frame of higher priority. */
+    #[inline(always)]
+    pub fn overflows(&self) -> u32 {
+        self.overflows
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct QueuedFrame {
+    priority: TxPriority,
+    data: [u8; TX_QUEUE_FRAME_SIZE],
+    size: usize,
+}
+
+impl QueuedFrame {
+
+    const EMPTY: QueuedFrame = QueuedFrame {
+        priority: TxPriority::Request,
+        data: [0; TX_QUEUE_FRAME_SIZE],
+        size: 0,
+    };
+
+    #[inline(always)]
+    pub fn priority(&self) -> TxPriority {
+        self.priority
+    }
+
+    #[inline(always)]
+    pub fn bytes(&self) -> &[u8] {
+        &self.data[..self.size]
+    }
+}
+
+/** Frames waiting while the DMA transfer of the previous one is in progress. */
+pub struct TxQueue {
+    frames: [QueuedFrame; TX_QUEUE_CAPACITY],
+    length: usize,
+    stats: TxQueueStats,
+}
+
+impl TxQueue {
+
+    pub const fn new() -> Self {
+        Self {
+            frames: [QueuedFrame::EMPTY; TX_QUEUE_CAPACITY],
+            length: 0,
+            stats: TxQueueStats {
+                length: 0,
+                max_length: 0,
+                queued: 0,
+                overflows: 0,
+            },
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn stats(&self) -> TxQueueStats {
+        TxQueueStats {
+            length: self.length,
+            ..self.stats
+        }
+    }
+
+    /** If the queue is full, the newest frame of the lowest priority below the new one is dropped. */
+    pub fn push(&mut self, priority: TxPriority, data: &[u8]) -> Result<(), Errors> {
+        if data.len() > TX_QUEUE_FRAME_SIZE {
+            return Err(Errors::DataOverflow);
+        }
+        if self.length == TX_QUEUE_CAPACITY {
+            self.stats.overflows = self.stats.overflows.saturating_add(1);
+            if self.frames[self.length - 1].priority >= priority {
+                return Err(Errors::TxQueueOverflow);
+            }
+            // frames are sorted by priority, so the last one is the newest of the lowest priority
+            self.length -= 1;
+        }
+        let position = self.frames[..self.length].iter()
+            .position(|frame| frame.priority < priority)
+            .unwrap_or(self.length);
+        self.frames.copy_within(position..self.length, position + 1);
+        let frame = &mut self.frames[position];
+        frame.priority = priority;
+        frame.data[..data.len()].copy_from_slice(data);
+        frame.size = data.len();
+        self.length += 1;
+        self.stats.queued = self.stats.queued.saturating_add(1);
+        self.stats.max_length = self.stats.max_length.max(self.length);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<QueuedFrame> {
+        if self.length == 0 {
+            return None;
+        }
+        let frame = self.frames[0];
+        self.frames.copy_within(1..self.length, 0);
+        self.length -= 1;
+        Some(frame)
+    }
+}
+
+impl Default for TxQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn test_errors_go_before_requests() {
+        let mut tested = TxQueue::new();
+
+        tested.push(TxPriority::Request, &[1]).unwrap();
+        tested.push(TxPriority::Error, &[2]).unwrap();
+        tested.push(TxPriority::Request, &[3]).unwrap();
+        tested.push(TxPriority::Error, &[4]).unwrap();
+
+        let order: Vec<u8> = core::iter::from_fn(|| tested.pop()).map(|frame| frame.bytes()[0]).collect();
+        assert_eq!(vec![2, 4, 1, 3], order);
+        assert!(tested.is_empty());
+    }
+
+    #[test]
+    fn test_full_queue_drops_newest_request_for_error() {
+        let mut tested = TxQueue::new();
+        for i in 0..TX_QUEUE_CAPACITY as u8 {
+            tested.push(TxPriority::Request, &[i]).unwrap();
+        }
+
+        assert_eq!(Err(Errors::TxQueueOverflow), tested.push(TxPriority::Request, &[0xff]));
+        assert_eq!(Ok(()), tested.push(TxPriority::Error, &[0xee]));
+
+        let order: Vec<u8> = core::iter::from_fn(|| tested.pop()).map(|frame| frame.bytes()[0]).collect();
+        let mut expected = vec![0xee];
+        expected.extend(0..TX_QUEUE_CAPACITY as u8 - 1);
+        assert_eq!(expected, order);
+        assert_eq!(2, tested.stats().overflows());
+        assert_eq!(TX_QUEUE_CAPACITY as u32 + 1, tested.stats().queued());
+        assert_eq!(TX_QUEUE_CAPACITY, tested.stats().max_length());
+    }
+
+    #[test]
+    fn test_full_queue_of_errors_rejects_error() {
+        let mut tested = TxQueue::new();
+        for i in 0..TX_QUEUE_CAPACITY as u8 {
+            tested.push(TxPriority::Error, &[i]).unwrap();
+        }
+
+        assert_eq!(Err(Errors::TxQueueOverflow), tested.push(TxPriority::Error, &[0xff]));
+        assert_eq!(TX_QUEUE_CAPACITY, tested.stats().length());
+        assert_eq!(1, tested.stats().overflows());
+
+        // the counters live as long as the link, they stop at the maximum
+        tested.stats.overflows = u32::MAX;
+        tested.stats.queued = u32::MAX;
+        tested.pop();
+        tested.push(TxPriority::Error, &[0xfe]).unwrap();
+        assert_eq!(Err(Errors::TxQueueOverflow), tested.push(TxPriority::Error, &[0xfd]));
+        assert_eq!(u32::MAX, tested.stats().overflows());
+        assert_eq!(u32::MAX, tested.stats().queued());
+    }
+
+    #[test]
+    fn test_keeps_frame_bytes() {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..TX_QUEUE_FRAME_SIZE).map(|_| rng.gen()).collect();
+        let mut tested = TxQueue::new();
+
+        assert_eq!(Err(Errors::DataOverflow), tested.push(TxPriority::Request, &[data.as_slice(), &[0]].concat()));
+        tested.push(TxPriority::Request, &data).unwrap();
+
+        let frame = tested.pop().unwrap();
+        assert_eq!(data.as_slice(), frame.bytes());
+        assert_eq!(TxPriority::Request, frame.priority());
+        assert_eq!(None, tested.pop());
+    }
+}
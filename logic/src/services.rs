@@ -1,5 +1,17 @@
+pub mod event_journal;
+pub mod host_protocol;
+pub mod hub_config;
 pub mod led;
+pub mod relay_controller;
+pub mod relay_scheduler;
+pub mod rules;
+pub mod slave_clock;
 pub mod slave_controller_link;
+pub mod slave_hub;
+pub mod slave_state_mirror;
+pub mod wall_clock;
+#[cfg(test)]
+pub mod test_support;
 
 
 #[cfg(test)]
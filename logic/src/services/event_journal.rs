@@ -0,0 +1,676 @@
+#![deny(unsafe_code)]
+
+pub mod journal_flash;
+
+use heapless::spsc::{Consumer, Producer, Queue};
+use heapless::Deque;
+use serde_derive::{Deserialize, Serialize};
+use crate::errors::Errors;
+use crate::services::host_protocol::HostClock;
+use crate::services::relay_controller::RelayController;
+use crate::services::relay_controller::reconciliation::DivergenceCause;
+use crate::services::slave_clock::HubTimestamp;
+use crate::services::slave_controller_link::connection_monitor::{ConnectionState, TransitionCause};
+use crate::services::slave_controller_link::domain::{DataInstructionCodes, DataInstructions, ErrorCode,
+                                                     RelaySignalDataGetter, SignalData};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::receiver_from_slave::ErrorHandler;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest};
+use crate::services::slave_controller_link::signals_controller::SignalsHandler;
+use crate::services::slave_hub::SlaveHub;
+use crate::services::wall_clock::UtcTimestamp;
+use crate::utils::logger::{self, Event};
+
+/** Count of the records kept in the RAM, the older ones go to the spill. */
+pub const JOURNAL_SIZE: usize = 64;
+/** Size of the queues between the links and the collector, they hold one item less. */
+pub const JOURNAL_QUEUE_SIZE: usize = 8;
+
+pub type JournalSignalsQueue = Queue<SignalData, JOURNAL_QUEUE_SIZE>;
+pub type JournalEventsQueue = Queue<JournalEvent, JOURNAL_QUEUE_SIZE>;
+
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum RequestFailure {
+    Timeout,
+    /** The answer came, but it was not parsed. */
+    Corrupted,
+}
+
+/** Error of the link receiving the slave frames. */
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum LinkFault {
+    Dma,
+    /** The frame did not fit the buffer. */
+    Overflow,
+    Corrupted,
+    Other,
+}
+
+impl LinkFault {
+    pub fn of(error: Errors) -> Self {
+        match error {
+            Errors::DmaError(_) => LinkFault::Dma,
+            Errors::DmaBufferOverflow | Errors::DataOverflow => LinkFault::Overflow,
+            Errors::FrameCrcMismatch | Errors::DataCorrupted | Errors::CommandDataCorrupted |
+            Errors::NotEnoughDataGot => LinkFault::Corrupted,
+            _ => LinkFault::Other,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum JournalEvent {
+    RelayChanged { relay_index: u8, on: bool },
+    /** The slave tries to bring the relay to the state it should have. */
+    FixTry { relay_index: u8, on: bool },
+    RequestFailed { instruction: DataInstructionCodes, failure: RequestFailure },
+    SlaveError { instruction: DataInstructionCodes, error: ErrorCode },
+    LinkFault(LinkFault),
+    Connection { state: ConnectionState, cause: TransitionCause },
+    /** The relay stays in the state other than the one requested by the hub. */
+    Diverged { relay_index: u8, desired: bool, cause: DivergenceCause },
+}
+
+impl JournalEvent {
+    pub fn relay_index(&self) -> Option<u8> {
+        match self {
+            JournalEvent::RelayChanged { relay_index, .. } | JournalEvent::FixTry { relay_index, .. } |
+            JournalEvent::Diverged { relay_index, .. } => Some(*relay_index),
+            _ => None,
+        }
+    }
+}
+
+/** An event of the slave on the port. The sequence numbers grow by one with each record. */
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct JournalRecord {
+    seq: u32,
+    time: UtcTimestamp,
+    port: u8,
+    slave_id: Option<u32>,
+    event: JournalEvent,
+}
+
+impl JournalRecord {
+
+    pub fn new(seq: u32, time: UtcTimestamp, port: u8, slave_id: Option<u32>, event: JournalEvent) -> Self {
+        Self { seq, time, port, slave_id, event }
+    }
+
+    #[inline(always)]
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    #[inline(always)]
+    pub fn time(&self) -> UtcTimestamp {
+        self.time
+    }
+
+    #[inline(always)]
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /** `None` if the slave id was not known yet. */
+    #[inline(always)]
+    pub fn slave_id(&self) -> Option<u32> {
+        self.slave_id
+    }
+
+    #[inline(always)]
+    pub fn event(&self) -> JournalEvent {
+        self.event
+    }
+}
+
+/** The records matching all the given values. A relay index matches only the relay events. */
+#[derive(Copy, Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct JournalFilter {
+    pub slave_id: Option<u32>,
+    pub relay_index: Option<u8>,
+}
+
+impl JournalFilter {
+    pub fn matches(&self, record: &JournalRecord) -> bool {
+        self.slave_id.is_none_or(|slave_id| record.slave_id == Some(slave_id))
+            && self.relay_index.is_none_or(|relay_index| record.event.relay_index() == Some(relay_index))
+    }
+}
+
+/** Storage of the records dropped from the RAM of the journal. */
+pub trait JournalSpill {
+    /** The records come in the order of their sequence numbers. */
+    fn store(&mut self, record: &JournalRecord) -> Result<(), Errors>;
+    /** The stored record with the lowest sequence number not less than the cursor, which matches the filter. */
+    fn find(&mut self, cursor: u32, filter: &JournalFilter) -> Result<Option<JournalRecord>, Errors>;
+    /** The sequence number after the last stored record, 0 if there are none. */
+    fn next_seq(&mut self) -> u32;
+    /** The sequence number of the oldest stored record, `None` if there are none. */
+    fn first_seq(&mut self) -> Result<Option<u32>, Errors>;
+}
+
+/** The dropped records are lost. */
+pub struct NoSpill;
+
+impl JournalSpill for NoSpill {
+    fn store(&mut self, _: &JournalRecord) -> Result<(), Errors> {
+        Ok(())
+    }
+
+    fn find(&mut self, _: u32, _: &JournalFilter) -> Result<Option<JournalRecord>, Errors> {
+        Ok(None)
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        0
+    }
+
+    fn first_seq(&mut self) -> Result<Option<u32>, Errors> {
+        Ok(None)
+    }
+}
+
+/**
+Ring buffer of the last `JOURNAL_SIZE` records, the oldest one is moved to the spill when a new one
+comes. The records are read by the cursor, the sequence number to start from, so the host reads them
+one by one, passing the sequence number after the last got record. The sequence numbers go on from
+the ones in the spill after the restart, the records in the RAM are lost then.
+ */
+pub struct EventJournal<S: JournalSpill> {
+    records: Deque<JournalRecord, JOURNAL_SIZE>,
+    next_seq: u32,
+    spill: S,
+}
+
+impl <S: JournalSpill> EventJournal<S> {
+
+    pub fn new(mut spill: S) -> Self {
+        let next_seq = spill.next_seq();
+        Self {
+            records: Deque::new(),
+            next_seq,
+            spill,
+        }
+    }
+
+    #[inline(always)]
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq
+    }
+
+    #[inline(always)]
+    pub fn spill(&mut self) -> &mut S {
+        &mut self.spill
+    }
+
+    /** Returns the sequence number of the record. A record failed to be spilled is logged and lost. */
+    pub fn record(&mut self, time: UtcTimestamp, port: u8, slave_id: Option<u32>, event: JournalEvent) -> u32 {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        if self.records.is_full() {
+            if let Some(oldest) = self.records.pop_front() {
+                if let Err(error) = self.spill.store(&oldest) {
+                    logger::log(Event::JournalSpillFailed(error));
+                }
+            }
+        }
+        let _ = self.records.push_back(JournalRecord::new(seq, time, port, slave_id, event));
+        seq
+    }
+
+    /**
+    The sequence number of the oldest record kept, the records before it are lost, `next_seq` while
+    there are none. The host reading from an earlier cursor knows so the records it missed.
+     */
+    pub fn first_seq(&mut self) -> Result<u32, Errors> {
+        let first_in_ram = self.records.front().map(|record| record.seq);
+        Ok(self.spill.first_seq()?.or(first_in_ram).unwrap_or(self.next_seq))
+    }
+
+    /** The record with the lowest sequence number not less than the cursor, which matches the filter. */
+    pub fn read(&mut self, cursor: u32, filter: &JournalFilter) -> Result<Option<JournalRecord>, Errors> {
+        let first_in_ram = self.records.front().map(|record| record.seq);
+        if first_in_ram.is_none_or(|first| cursor < first) {
+            if let Some(record) = self.spill.find(cursor, filter)? {
+                if first_in_ram.is_none_or(|first| record.seq < first) {
+                    return Ok(Some(record));
+                }
+            }
+        }
+        Ok(self.records.iter().find(|record| record.seq >= cursor && filter.matches(record)).copied())
+    }
+}
+
+/** Signals handler of a link passing the relay signals to the `JournalCollector`. */
+pub struct JournalSignalsForwarder<'a, SH: SignalsHandler> {
+    signals_handler: SH,
+    signals: Producer<'a, SignalData, JOURNAL_QUEUE_SIZE>,
+}
+
+impl <'a, SH: SignalsHandler> JournalSignalsForwarder<'a, SH> {
+
+    pub fn new(signals_handler: SH, signals: Producer<'a, SignalData, JOURNAL_QUEUE_SIZE>) -> Self {
+        Self { signals_handler, signals }
+    }
+
+    #[inline(always)]
+    pub fn signals_handler(&mut self) -> &mut SH {
+        &mut self.signals_handler
+    }
+}
+
+impl <SH: SignalsHandler> SignalsHandler for JournalSignalsForwarder<'_, SH> {
+
+    fn on_signal(&mut self, signal_data: SignalData, is_processed: bool) {
+        if matches!(signal_data, SignalData::RelayStateChanged(_) | SignalData::StateFixTry(_)) {
+            let _ = self.signals.enqueue(signal_data);
+        }
+        self.signals_handler.on_signal(signal_data, is_processed);
+    }
+
+    fn on_signal_parse_error(&mut self, error: Errors, sent_to_slave_success: bool, data: &[u8]) {
+        self.signals_handler.on_signal_parse_error(error, sent_to_slave_success, data);
+    }
+
+    fn on_signal_process_error(&mut self, error: Errors, sent_to_slave_success: bool, data: SignalData) {
+        self.signals_handler.on_signal_process_error(error, sent_to_slave_success, data);
+    }
+}
+
+/** Response handler of a link passing the failed requests to the `JournalCollector`. */
+pub struct JournalResponseForwarder<'a, RH: ResponseHandler> {
+    response_handler: RH,
+    events: Producer<'a, JournalEvent, JOURNAL_QUEUE_SIZE>,
+}
+
+impl <'a, RH: ResponseHandler> JournalResponseForwarder<'a, RH> {
+
+    pub fn new(response_handler: RH, events: Producer<'a, JournalEvent, JOURNAL_QUEUE_SIZE>) -> Self {
+        Self { response_handler, events }
+    }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+}
+
+impl <RH: ResponseHandler> ResponseHandler for JournalResponseForwarder<'_, RH> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        self.response_handler.on_request_success(request);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        self.response_handler.on_request_response(request, response);
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        let _ = self.events.enqueue(JournalEvent::SlaveError { instruction: request.instruction(), error: error_code });
+        self.response_handler.on_request_error(request, error_code);
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+        if let Some(request) = &request {
+            let _ = self.events.enqueue(JournalEvent::RequestFailed {
+                instruction: request.instruction(), failure: RequestFailure::Corrupted });
+        }
+        self.response_handler.on_request_parse_error(request, error, data);
+    }
+
+    fn on_request_search_error(&mut self, response: ResponseData, error: Errors) {
+        self.response_handler.on_request_search_error(response, error);
+    }
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        let _ = self.events.enqueue(JournalEvent::RequestFailed {
+            instruction: request.instruction(), failure: RequestFailure::Timeout });
+        self.response_handler.on_request_timeout(request);
+    }
+}
+
+/** Error handler of a link passing its receive errors, e.g. the DMA ones, to the `JournalCollector`. */
+pub struct JournalErrorForwarder<'a> {
+    events: Producer<'a, JournalEvent, JOURNAL_QUEUE_SIZE>,
+}
+
+impl <'a> JournalErrorForwarder<'a> {
+    pub fn new(events: Producer<'a, JournalEvent, JOURNAL_QUEUE_SIZE>) -> Self {
+        Self { events }
+    }
+}
+
+impl ErrorHandler for JournalErrorForwarder<'_> {
+    fn on_error(&mut self, error: Errors) {
+        let _ = self.events.enqueue(JournalEvent::LinkFault(LinkFault::of(error)));
+    }
+}
+
+/**
+Records to the journal the events passed by the forwarders of the links. The slave is known by the
+port, the relay signals get the time of their timestamp, if the slave clock is known, the connection
+state changes - the time they happened, the other events get the time of the collecting.
+ */
+pub struct JournalCollector<'a, const N: usize> {
+    signals: [Consumer<'a, SignalData, JOURNAL_QUEUE_SIZE>; N],
+    answers: [Consumer<'a, JournalEvent, JOURNAL_QUEUE_SIZE>; N],
+    faults: [Consumer<'a, JournalEvent, JOURNAL_QUEUE_SIZE>; N],
+}
+
+impl <'a, const N: usize> JournalCollector<'a, N> {
+
+    /**
+    The queues are indexed by the hub port, they are filled by the `JournalSignalsForwarder`,
+    `JournalResponseForwarder` and `JournalErrorForwarder` of the link on the port.
+     */
+    pub fn new(signals: [Consumer<'a, SignalData, JOURNAL_QUEUE_SIZE>; N],
+               answers: [Consumer<'a, JournalEvent, JOURNAL_QUEUE_SIZE>; N],
+               faults: [Consumer<'a, JournalEvent, JOURNAL_QUEUE_SIZE>; N]) -> Self {
+        Self { signals, answers, faults }
+    }
+
+    pub fn poll<C: HostClock, S: JournalSpill>(&mut self, hub: &mut SlaveHub<'_, N>, clock: &mut C,
+                                               journal: &mut EventJournal<S>) {
+        let now = clock.get();
+        let now_utc = UtcTimestamp::new(clock.unix_millis(), 0);
+        for port in 0..N {
+            let slave_id = hub.slave_id(port);
+            while let Some(signal) = self.signals[port].dequeue() {
+                let event = match signal {
+                    SignalData::RelayStateChanged(data) =>
+                        JournalEvent::RelayChanged { relay_index: data.get_relay_idx(), on: data.is_on() },
+                    SignalData::StateFixTry(data) =>
+                        JournalEvent::FixTry { relay_index: data.get_relay_idx(), on: data.is_on() },
+                    _ => continue,
+                };
+                let time = signal.relative_timestamp()
+                    .and_then(|timestamp| hub.link(port)?.slave_clock().to_hub_time(timestamp, now))
+                    .and_then(|timestamp| clock.to_utc(timestamp))
+                    .unwrap_or(now_utc);
+                journal.record(time, port as u8, slave_id, event);
+            }
+            while let Some(event) = self.answers[port].dequeue().or_else(|| self.faults[port].dequeue()) {
+                let time = clock.to_utc(HubTimestamp::new(now, 0)).unwrap_or(now_utc);
+                journal.record(time, port as u8, slave_id, event);
+            }
+        }
+        while let Some((port, transition)) = hub.take_connection_transition() {
+            let time = clock.to_utc(HubTimestamp::new(transition.at(), 0)).unwrap_or(now_utc);
+            let event = JournalEvent::Connection { state: transition.to(), cause: transition.cause() };
+            journal.record(time, port as u8, hub.slave_id(port), event);
+        }
+    }
+
+    /** Records the alarms of the relays, which the controller failed to bring to the desired states. */
+    pub fn record_alarms<C: HostClock, S: JournalSpill>(&mut self, hub: &mut SlaveHub<'_, N>,
+                                                        relays: &mut RelayController<'_, N>, clock: &mut C,
+                                                        journal: &mut EventJournal<S>) {
+        let now_utc = UtcTimestamp::new(clock.unix_millis(), 0);
+        while let Some(alarm) = relays.take_alarm() {
+            let port = match hub.port(alarm.slave_id()) {
+                Some(port) => port,
+                None => continue,
+            };
+            let time = clock.to_utc(HubTimestamp::new(alarm.at(), 0)).unwrap_or(now_utc);
+            let event = JournalEvent::Diverged { relay_index: alarm.relay_index(), desired: alarm.desired(),
+                cause: alarm.cause() };
+            journal.record(time, port as u8, Some(alarm.slave_id()), event);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::hal_ext::rtc_wrapper::{RelativeMillis, RelativeSeconds, RelativeTimestampSource};
+    use crate::services::event_journal::journal_flash::JournalFlash;
+    use crate::services::relay_controller::RelayAnswersQueue;
+    use crate::services::relay_controller::reconciliation::DEFAULT_SETTLE_TIME_MS;
+    use crate::services::slave_controller_link::connection_monitor::ConnectionTransition;
+    use crate::services::slave_controller_link::domain::{Conversation, Operation, RelaySignalData,
+                                                         RelaySignalDataExt, State};
+    use crate::services::test_support::MockLink;
+    use crate::utils::ram_flash::RamFlash;
+    use crate::utils::BitsU64;
+
+    const MOCK_UNIX_MILLIS: i64 = 1_686_644_790_000;
+
+    type TestFlash = RamFlash<1024, 4, 256>;
+
+    #[test]
+    fn test_records_are_read_by_cursor_over_spill() {
+        let flash = JournalFlash::open(TestFlash::new(), 0, 256, 4).unwrap();
+        let mut tested = EventJournal::new(flash);
+        let count = JOURNAL_SIZE as u32 + 10;
+
+        for seq in 0..count {
+            assert_eq!(seq, tested.record(time(seq), 1, Some(7), relay_changed(seq)));
+        }
+
+        let records = read(&mut tested, 0, &JournalFilter::default());
+        assert_eq!((0..count).collect::<Vec<_>>(), records.iter().map(JournalRecord::seq).collect::<Vec<_>>());
+        assert_eq!(JournalRecord::new(9, time(9), 1, Some(7), relay_changed(9)), records[9]);
+        assert_eq!(count - 5, read(&mut tested, count - 5, &JournalFilter::default())[0].seq());
+        assert_eq!(None, tested.read(count, &JournalFilter::default()).unwrap());
+
+        // the RAM records are lost on the restart, the sequence goes on
+        let mut tested = EventJournal::new(JournalFlash::open(tested.spill.release(), 0, 256, 4).unwrap());
+        assert_eq!(10, tested.next_seq());
+        tested.record(time(0), 0, None, JournalEvent::LinkFault(LinkFault::Dma));
+        assert_eq!((0..11).collect::<Vec<_>>(),
+                   read(&mut tested, 0, &JournalFilter::default()).iter().map(JournalRecord::seq).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_records_are_filtered_by_slave_and_relay() {
+        let mut tested = EventJournal::new(NoSpill);
+        tested.record(time(0), 0, Some(1), JournalEvent::RelayChanged { relay_index: 2, on: true });
+        tested.record(time(1), 1, Some(2), JournalEvent::RelayChanged { relay_index: 2, on: false });
+        tested.record(time(2), 0, Some(1), JournalEvent::FixTry { relay_index: 3, on: true });
+        tested.record(time(3), 0, Some(1), JournalEvent::RequestFailed {
+            instruction: DataInstructionCodes::State, failure: RequestFailure::Timeout });
+        tested.record(time(4), 0, None, JournalEvent::LinkFault(LinkFault::Dma));
+
+        let seqs = |tested: &mut EventJournal<NoSpill>, slave_id, relay_index| {
+            read(tested, 0, &JournalFilter { slave_id, relay_index }).iter().map(JournalRecord::seq).collect::<Vec<_>>()
+        };
+        assert_eq!(vec![0, 2, 3], seqs(&mut tested, Some(1), None));
+        assert_eq!(vec![0, 1], seqs(&mut tested, None, Some(2)));
+        assert_eq!(vec![2], seqs(&mut tested, Some(1), Some(3)));
+        assert_eq!(Vec::<u32>::new(), seqs(&mut tested, Some(2), Some(3)));
+        assert_eq!(5, seqs(&mut tested, None, None).len());
+    }
+
+    #[test]
+    fn test_oldest_records_are_lost_without_spill() {
+        let mut tested = EventJournal::new(NoSpill);
+        for seq in 0..JOURNAL_SIZE as u32 + 3 {
+            tested.record(time(seq), 0, None, relay_changed(seq));
+        }
+
+        assert_eq!(Some(3), tested.read(0, &JournalFilter::default()).unwrap().map(|record| record.seq()));
+        assert_eq!(Ok(3), tested.first_seq());
+        assert_eq!(Ok(0), EventJournal::new(NoSpill).first_seq());
+    }
+
+    #[test]
+    fn test_link_events_are_collected() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let mut signals = [JournalSignalsQueue::new(), JournalSignalsQueue::new()];
+        let mut answers = [JournalEventsQueue::new(), JournalEventsQueue::new()];
+        let mut faults = [JournalEventsQueue::new(), JournalEventsQueue::new()];
+        let [signals0, signals1] = &mut signals;
+        let [answers0, answers1] = &mut answers;
+        let [faults0, faults1] = &mut faults;
+        let (signals0_tx, signals0_rx) = signals0.split();
+        let (_, signals1_rx) = signals1.split();
+        let (_, answers0_rx) = answers0.split();
+        let (answers1_tx, answers1_rx) = answers1.split();
+        let (_, faults0_rx) = faults0.split();
+        let (faults1_tx, faults1_rx) = faults1.split();
+        let mut signals_forwarder = JournalSignalsForwarder::new(MockHandler, signals0_tx);
+        let mut response_forwarder = JournalResponseForwarder::new(MockHandler, answers1_tx);
+        let mut error_forwarder = JournalErrorForwarder::new(faults1_tx);
+        let now = RelativeMillis::new(rng.next_u32());
+        let connected_at = RelativeMillis::new(now.value().wrapping_sub(10));
+        let mut links = [MockLink::new(Some(id)), MockLink::new(None)];
+        links[0].transitions = vec![ConnectionTransition::new(ConnectionState::Handshaking, ConnectionState::Online,
+                                                              TransitionCause::Synced, connected_at)];
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut tested = JournalCollector::new([signals0_rx, signals1_rx], [answers0_rx, answers1_rx],
+                                               [faults0_rx, faults1_rx]);
+        let mut journal = EventJournal::new(NoSpill);
+        let relay_index = rng.gen_range(0..16);
+
+        signals_forwarder.on_signal(SignalData::RelayStateChanged(RelaySignalDataExt::new(
+            RelativeSeconds::new(1), relay_index, true, false)), true);
+        signals_forwarder.on_signal(SignalData::MonitoringStateChanged(RelaySignalData::new(
+            RelativeSeconds::new(1), relay_index, true)), true);
+        signals_forwarder.on_signal(SignalData::StateFixTry(RelaySignalData::new(
+            RelativeSeconds::new(2), relay_index, false)), true);
+        response_forwarder.on_request_timeout(SentRequest::new(None, Operation::Read, DataInstructionCodes::State, now));
+        response_forwarder.on_request_error(SentRequest::new(None, Operation::Set, DataInstructionCodes::RelaySwitchedOn, now),
+                                            ErrorCode::ERelayIndexOutOfRange);
+        response_forwarder.on_request_parse_error(None, Errors::DataCorrupted, &[]);
+        error_forwarder.on_error(Errors::DmaBufferOverflow);
+        tested.poll(&mut hub, &mut MockClock { now }, &mut journal);
+
+        // the slave clock is not known, so all the events get the time of the collecting
+        let now_utc = UtcTimestamp::new(MOCK_UNIX_MILLIS + now.value() as i64, 0);
+        assert_eq!(vec![
+            JournalRecord::new(0, now_utc, 0, Some(id), JournalEvent::RelayChanged { relay_index, on: true }),
+            JournalRecord::new(1, now_utc, 0, Some(id), JournalEvent::FixTry { relay_index, on: false }),
+            JournalRecord::new(2, now_utc, 1, None, JournalEvent::RequestFailed {
+                instruction: DataInstructionCodes::State, failure: RequestFailure::Timeout }),
+            JournalRecord::new(3, now_utc, 1, None, JournalEvent::SlaveError {
+                instruction: DataInstructionCodes::RelaySwitchedOn, error: ErrorCode::ERelayIndexOutOfRange }),
+            JournalRecord::new(4, now_utc, 1, None, JournalEvent::LinkFault(LinkFault::Overflow)),
+            JournalRecord::new(5, UtcTimestamp::new(MOCK_UNIX_MILLIS + connected_at.value() as i64, 0), 0, Some(id),
+                               JournalEvent::Connection { state: ConnectionState::Online, cause: TransitionCause::Synced }),
+        ], read(&mut journal, 0, &JournalFilter::default()));
+    }
+
+    #[test]
+    fn test_relay_alarms_are_recorded() {
+        let id = rand::thread_rng().next_u32();
+        let switched_at = RelativeMillis::new(1000);
+        let checked_at = RelativeMillis::new(switched_at.value() + DEFAULT_SETTLE_TIME_MS);
+        let mut signals = [JournalSignalsQueue::new()];
+        let mut answers = [JournalEventsQueue::new()];
+        let mut faults = [JournalEventsQueue::new()];
+        let mut relay_answers = RelayAnswersQueue::new();
+        let mut links = [MockLink::new(Some(id))];
+        // the slave reports the relay 1 disabled and off after the switch
+        links[0].mirror.apply_response(&DataInstructions::State(Conversation::Data(
+            State { data: BitsU64::new(0x20), count: 2 })), RelativeMillis::new(switched_at.value() + 1));
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut relays = RelayController::new([relay_answers.split().1]);
+        let [signals] = &mut signals;
+        let [answers] = &mut answers;
+        let [faults] = &mut faults;
+        let mut tested = JournalCollector::new([signals.split().1], [answers.split().1], [faults.split().1]);
+        let mut journal = EventJournal::new(NoSpill);
+
+        relays.switch_on(&mut hub, id, 1, switched_at).unwrap();
+        relays.reconcile(&mut hub, checked_at);
+        tested.record_alarms(&mut hub, &mut relays, &mut MockClock { now: checked_at }, &mut journal);
+
+        assert_eq!(vec![
+            JournalRecord::new(0, UtcTimestamp::new(MOCK_UNIX_MILLIS + checked_at.value() as i64, 0), 0, Some(id),
+                               JournalEvent::Diverged { relay_index: 1, desired: true, cause: DivergenceCause::Disabled }),
+        ], read(&mut journal, 0, &JournalFilter { slave_id: Some(id), relay_index: Some(1) }));
+    }
+
+    #[test]
+    fn test_largest_record_fits_slot() {
+        let record = JournalRecord::new(u32::MAX, UtcTimestamp::new(i64::MIN, u32::MAX), u8::MAX, Some(u32::MAX),
+                                        JournalEvent::SlaveError { instruction: DataInstructionCodes::Unknown,
+                                            error: ErrorCode::EUndefinedCode(u8::MAX) });
+        let mut buffer = [0; journal_flash::MAX_RECORD_SIZE];
+
+        let data = postcard::to_slice(&record, &mut buffer).unwrap();
+        assert_eq!(record, postcard::from_bytes(data).unwrap());
+    }
+
+    fn read<S: JournalSpill>(journal: &mut EventJournal<S>, mut cursor: u32, filter: &JournalFilter) -> Vec<JournalRecord> {
+        let mut records = Vec::new();
+        while let Some(record) = journal.read(cursor, filter).unwrap() {
+            cursor = record.seq() + 1;
+            records.push(record);
+        }
+        records
+    }
+
+    fn time(seq: u32) -> UtcTimestamp {
+        UtcTimestamp::new(MOCK_UNIX_MILLIS + seq as i64 * 1000, 0)
+    }
+
+    fn relay_changed(seq: u32) -> JournalEvent {
+        JournalEvent::RelayChanged { relay_index: (seq % 16) as u8, on: seq.is_multiple_of(2) }
+    }
+
+    struct MockClock {
+        now: RelativeMillis,
+    }
+
+    impl RelativeTimestampSource for MockClock {
+        fn get(&mut self) -> RelativeMillis {
+            self.now
+        }
+    }
+
+    impl HostClock for MockClock {
+        fn unix_millis(&mut self) -> i64 {
+            MOCK_UNIX_MILLIS + self.now.value() as i64
+        }
+
+        fn sync(&mut self, _: i64) -> Result<i64, Errors> {
+            Err(Errors::OutOfRange)
+        }
+
+        fn is_synced(&self) -> bool {
+            false
+        }
+
+        fn drift_ppm(&self) -> Option<i32> {
+            None
+        }
+
+        fn to_utc(&self, timestamp: HubTimestamp) -> Option<UtcTimestamp> {
+            Some(UtcTimestamp::new(MOCK_UNIX_MILLIS + timestamp.timestamp().value() as i64,
+                                   timestamp.uncertainty_ms()))
+        }
+    }
+
+    struct MockHandler;
+
+    impl SignalsHandler for MockHandler {
+        fn on_signal(&mut self, _: SignalData, _: bool) {}
+
+        fn on_signal_parse_error(&mut self, _: Errors, _: bool, _: &[u8]) {}
+
+        fn on_signal_process_error(&mut self, _: Errors, _: bool, _: SignalData) {}
+    }
+
+    impl ResponseHandler for MockHandler {
+        fn on_request_success(&mut self, _: SentRequest) {}
+
+        fn on_request_response(&mut self, _: SentRequest, _: DataInstructions) {}
+
+        fn on_request_error(&mut self, _: SentRequest, _: ErrorCode) {}
+
+        fn on_request_parse_error(&mut self, _: Option<SentRequest>, _: Errors, _: &[u8]) {}
+
+        fn on_request_search_error(&mut self, _: ResponseData, _: Errors) {}
+
+        fn on_request_timeout(&mut self, _: SentRequest) {}
+    }
+}
@@ -0,0 +1,320 @@
+#![deny(unsafe_code)]
+
+use embedded_storage::nor_flash::NorFlash;
+use crate::errors::Errors;
+use crate::services::event_journal::{JournalFilter, JournalRecord, JournalSpill};
+use crate::services::slave_controller_link::framing::crc16;
+use crate::utils::flash_pages::{aligned, flash_error, is_after, FlashPages, PAGE_HEADER_SIZE};
+
+/** Version of the stored records layout, the pages of another version are ignored. */
+pub const JOURNAL_FORMAT_VERSION: u8 = 1;
+pub const SLOT_SIZE: usize = 48;
+pub const MAX_RECORD_SIZE: usize = SLOT_SIZE - SLOT_HEADER_SIZE - SLOT_CRC_SIZE;
+pub use crate::utils::flash_pages::MAX_WRITE_SIZE;
+
+const PAGE_MAGIC: [u8; 2] = [0x4a, 0x4c];
+const SLOT_HEADER_SIZE: usize = 1;
+const SLOT_CRC_SIZE: usize = 2;
+const ERASED_LENGTH: u8 = 0xff;
+const MIN_PAGES_COUNT: u32 = 2;
+
+/**
+Journal spill over the pages of a NOR flash used in turn, the page next to the active one is erased
+when the active one is full, so its records are lost. The records are appended to the active page.
+
+The pages start with the `FlashPages` header, the active page has the greatest sequence. The records are in the slots of `SLOT_SIZE` bytes: the length of
+the record, the record in postcard and CRC-16 of all that (BE). The erased length ends the records, a
+slot failing the CRC, e.g. torn by the power loss, is skipped.
+ */
+pub struct JournalFlash<F: NorFlash> {
+    flash: F,
+    pages: FlashPages,
+    pages_count: u32,
+    active_page: u32,
+    sequence: u32,
+    write_offset: u32,
+    next_seq: u32,
+}
+
+impl <F: NorFlash> JournalFlash<F> {
+
+    /** Opens the journal in the pages starting at the offset. */
+    pub fn open(flash: F, offset: u32, page_size: u32, pages_count: u32) -> Result<Self, Errors> {
+        if F::WRITE_SIZE > MAX_WRITE_SIZE || !SLOT_SIZE.is_multiple_of(F::WRITE_SIZE)
+            || pages_count < MIN_PAGES_COUNT || !offset.is_multiple_of(F::ERASE_SIZE as u32)
+            || !page_size.is_multiple_of(F::ERASE_SIZE as u32)
+            || (offset + pages_count * page_size) as usize > flash.capacity()
+            || (page_size as usize) < aligned::<F>(PAGE_HEADER_SIZE) + SLOT_SIZE {
+            return Err(Errors::OutOfRange);
+        }
+        let mut journal = Self {
+            flash,
+            pages: FlashPages::new(offset, page_size, PAGE_MAGIC, JOURNAL_FORMAT_VERSION),
+            pages_count,
+            active_page: 0,
+            sequence: 0,
+            write_offset: 0,
+            next_seq: 0,
+        };
+        let mut active = None;
+        for page in 0..pages_count {
+            if let Some(sequence) = journal.page_sequence(page)? {
+                if active.is_none_or(|(_, active_sequence)| is_after(sequence, active_sequence)) {
+                    active = Some((page, sequence));
+                }
+            }
+        }
+        match active {
+            Some((page, sequence)) => journal.activate(page, sequence),
+            None => {
+                journal.erase_page(0)?;
+                journal.write_page_header(0, 0)?;
+                journal.activate(0, 0);
+            }
+        }
+        let mut next_seq = None;
+        for page in journal.pages_in_order() {
+            journal.scan(page, |record| next_seq = Some(record.seq().wrapping_add(1)))?;
+        }
+        journal.next_seq = next_seq.unwrap_or(0);
+        journal.write_offset = journal.scan(journal.active_page, |_| {})?;
+        Ok(journal)
+    }
+
+    #[inline(always)]
+    pub fn active_page(&self) -> u32 {
+        self.active_page
+    }
+
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    fn activate(&mut self, page: u32, sequence: u32) {
+        self.active_page = page;
+        self.sequence = sequence;
+    }
+
+    /** The pages from the oldest to the active one, the ones without the valid header are skipped by the scan. */
+    fn pages_in_order(&self) -> impl Iterator<Item = u32> {
+        let (active_page, pages_count) = (self.active_page, self.pages_count);
+        (1..=pages_count).map(move |step| (active_page + step) % pages_count)
+    }
+
+    /** Erases the page next to the active one and makes it active. */
+    fn next_page(&mut self) -> Result<(), Errors> {
+        let page = (self.active_page + 1) % self.pages_count;
+        let sequence = self.sequence.wrapping_add(1);
+        self.erase_page(page)?;
+        self.write_page_header(page, sequence)?;
+        self.activate(page, sequence);
+        self.write_offset = aligned::<F>(PAGE_HEADER_SIZE) as u32;
+        Ok(())
+    }
+
+    /** Calls `f` with the valid records of the page, if its header is valid. Returns the offset after them. */
+    fn scan<FN: FnMut(&JournalRecord)>(&mut self, page: u32, mut f: FN) -> Result<u32, Errors> {
+        if page != self.active_page && self.page_sequence(page)?.is_none() {
+            return Ok(self.page_size());
+        }
+        let mut offset = aligned::<F>(PAGE_HEADER_SIZE) as u32;
+        let mut slot = [0; SLOT_SIZE];
+        while offset + SLOT_SIZE as u32 <= self.page_size() {
+            self.flash.read(self.page_address(page) + offset, &mut slot).map_err(flash_error)?;
+            if slot[0] == ERASED_LENGTH {
+                return Ok(offset);
+            }
+            if let Some(record) = parse_slot(&slot) {
+                f(&record);
+            }
+            offset += SLOT_SIZE as u32;
+        }
+        Ok(self.page_size())
+    }
+
+    fn page_sequence(&mut self, page: u32) -> Result<Option<u32>, Errors> {
+        self.pages.sequence(&mut self.flash, page)
+    }
+
+    fn write_page_header(&mut self, page: u32, sequence: u32) -> Result<(), Errors> {
+        self.pages.write_header(&mut self.flash, page, sequence)
+    }
+
+    fn erase_page(&mut self, page: u32) -> Result<(), Errors> {
+        self.pages.erase(&mut self.flash, page)
+    }
+
+    #[inline(always)]
+    fn page_address(&self, page: u32) -> u32 {
+        self.pages.page_address(page)
+    }
+
+    #[inline(always)]
+    fn page_size(&self) -> u32 {
+        self.pages.page_size()
+    }
+}
+
+impl <F: NorFlash> JournalSpill for JournalFlash<F> {
+
+    fn store(&mut self, record: &JournalRecord) -> Result<(), Errors> {
+        let mut slot = [0xff; SLOT_SIZE];
+        let len = postcard::to_slice(record, &mut slot[SLOT_HEADER_SIZE..SLOT_HEADER_SIZE + MAX_RECORD_SIZE])
+            .map_err(|_| Errors::DataOverflow)?.len();
+        slot[0] = len as u8;
+        let crc_offset = SLOT_HEADER_SIZE + len;
+        let crc = crc16(&slot[..crc_offset]);
+        slot[crc_offset..crc_offset + SLOT_CRC_SIZE].copy_from_slice(&crc.to_be_bytes());
+
+        if self.write_offset + SLOT_SIZE as u32 > self.page_size() {
+            self.next_page()?;
+        }
+        let address = self.page_address(self.active_page) + self.write_offset;
+        match self.flash.write(address, &slot) {
+            Ok(()) => {
+                self.write_offset += SLOT_SIZE as u32;
+                self.next_seq = record.seq().wrapping_add(1);
+                Ok(())
+            }
+            Err(error) => {
+                // the rest of the page is unknown, the next record goes to the next page
+                self.write_offset = self.page_size();
+                Err(flash_error(error))
+            }
+        }
+    }
+
+    fn find(&mut self, cursor: u32, filter: &JournalFilter) -> Result<Option<JournalRecord>, Errors> {
+        let mut found = None;
+        for page in self.pages_in_order() {
+            self.scan(page, |record| {
+                if found.is_none() && record.seq() >= cursor && filter.matches(record) {
+                    found = Some(*record);
+                }
+            })?;
+            if found.is_some() {
+                break;
+            }
+        }
+        Ok(found)
+    }
+
+    #[inline(always)]
+    fn next_seq(&mut self) -> u32 {
+        self.next_seq
+    }
+
+    fn first_seq(&mut self) -> Result<Option<u32>, Errors> {
+        Ok(self.find(0, &JournalFilter::default())?.map(|record| record.seq()))
+    }
+}
+
+fn parse_slot(slot: &[u8; SLOT_SIZE]) -> Option<JournalRecord> {
+    let len = slot[0] as usize;
+    if len > MAX_RECORD_SIZE {
+        return None;
+    }
+    let crc_offset = SLOT_HEADER_SIZE + len;
+    let crc = u16::from_be_bytes([slot[crc_offset], slot[crc_offset + 1]]);
+    if crc16(&slot[..crc_offset]) != crc {
+        return None;
+    }
+    postcard::from_bytes(&slot[SLOT_HEADER_SIZE..crc_offset]).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use crate::services::event_journal::{JournalEvent, LinkFault};
+    use crate::services::wall_clock::UtcTimestamp;
+    use crate::utils::ram_flash::RamFlash;
+
+    const PAGE_SIZE: u32 = 256;
+    // 5 slots a page
+    const SLOTS_COUNT: u32 = (PAGE_SIZE - PAGE_HEADER_SIZE as u32) / SLOT_SIZE as u32;
+
+    type TestFlash = RamFlash<{ 3 * PAGE_SIZE as usize }, 4, 128>;
+
+    #[test]
+    fn test_records_are_found_after_reopening() {
+        let mut tested = JournalFlash::open(TestFlash::new(), 0, PAGE_SIZE, 3).unwrap();
+        assert_eq!(0, tested.next_seq());
+        assert_eq!(Ok(None), tested.first_seq());
+
+        for seq in 0..7 {
+            tested.store(&record(seq)).unwrap();
+        }
+        let mut tested = JournalFlash::open(tested.release(), 0, PAGE_SIZE, 3).unwrap();
+
+        assert_eq!(7, tested.next_seq());
+        assert_eq!(1, tested.active_page());
+        assert_eq!((0..7).collect::<Vec<_>>(), seqs(&mut tested, 0));
+        assert_eq!(Some(record(3)), tested.find(3, &JournalFilter::default()).unwrap());
+        assert_eq!(None, tested.find(7, &JournalFilter::default()).unwrap());
+        tested.store(&record(7)).unwrap();
+        assert_eq!((5..8).collect::<Vec<_>>(), seqs(&mut tested, 5));
+    }
+
+    #[test]
+    fn test_oldest_page_is_erased_when_all_are_full() {
+        let mut tested = JournalFlash::open(TestFlash::new(), 0, PAGE_SIZE, 3).unwrap();
+
+        for seq in 0..3 * SLOTS_COUNT + 1 {
+            tested.store(&record(seq)).unwrap();
+        }
+
+        assert_eq!(0, tested.active_page());
+        assert_eq!((SLOTS_COUNT..3 * SLOTS_COUNT + 1).collect::<Vec<_>>(), seqs(&mut tested, 0));
+        assert_eq!(Ok(Some(SLOTS_COUNT)), tested.first_seq());
+        let mut tested = JournalFlash::open(tested.release(), 0, PAGE_SIZE, 3).unwrap();
+        assert_eq!(0, tested.active_page());
+        assert_eq!(3 * SLOTS_COUNT + 1, tested.next_seq());
+    }
+
+    #[test]
+    fn test_torn_record_is_skipped() {
+        let mut tested = JournalFlash::open(TestFlash::new(), 0, PAGE_SIZE, 3).unwrap();
+        tested.store(&record(0)).unwrap();
+        let mut flash = tested.release();
+
+        flash.power_off_after(4);
+        let mut tested = JournalFlash::open(flash, 0, PAGE_SIZE, 3).unwrap();
+        assert!(tested.store(&record(1)).is_err());
+        let mut flash = tested.release();
+        flash.power_on();
+        let mut tested = JournalFlash::open(flash, 0, PAGE_SIZE, 3).unwrap();
+        tested.store(&record(2)).unwrap();
+
+        assert_eq!(vec![0, 2], seqs(&mut tested, 0));
+    }
+
+    #[test]
+    fn test_wrong_geometry_is_rejected() {
+        assert!(JournalFlash::open(TestFlash::new(), 0, PAGE_SIZE, 1).is_err());
+        assert!(JournalFlash::open(TestFlash::new(), 0, PAGE_SIZE, 4).is_err());
+        assert!(JournalFlash::open(TestFlash::new(), 64, PAGE_SIZE, 2).is_err());
+        assert!(JournalFlash::open(RamFlash::<256, 4, 128>::new(), 0, 128, 2).is_ok());
+        assert!(JournalFlash::open(RamFlash::<256, 32, 128>::new(), 0, 128, 2).is_err());
+    }
+
+    fn record(seq: u32) -> JournalRecord {
+        let event = match seq % 3 {
+            0 => JournalEvent::RelayChanged { relay_index: (seq % 16) as u8, on: true },
+            1 => JournalEvent::FixTry { relay_index: (seq % 16) as u8, on: false },
+            _ => JournalEvent::LinkFault(LinkFault::Dma),
+        };
+        JournalRecord::new(seq, UtcTimestamp::new(1_686_644_790_000 + seq as i64, seq), (seq % 3) as u8,
+                           Some(seq / 2), event)
+    }
+
+    fn seqs<F: NorFlash>(tested: &mut JournalFlash<F>, mut cursor: u32) -> Vec<u32> {
+        let mut seqs = Vec::new();
+        while let Some(record) = tested.find(cursor, &JournalFilter::default()).unwrap() {
+            seqs.push(record.seq());
+            cursor = record.seq() + 1;
+        }
+        seqs
+    }
+}
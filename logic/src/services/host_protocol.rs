@@ -0,0 +1,917 @@
+#![deny(unsafe_code)]
+
+pub mod messages;
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::spsc::{Consumer, Producer, Queue};
+use postcard::accumulator::{CobsAccumulator, FeedResult};
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::{RelativeMillis, RelativeSeconds, RelativeTimestampSource, Rtc};
+use crate::services::event_journal::{EventJournal, JournalSpill};
+use crate::services::hub_config::PersistentConfig;
+use crate::services::host_protocol::messages::{Host2Target, HostCommand, Rejection, RelayTimes, SlaveAnswer,
+                                               SlaveInfo, Target2Host, HOST_FRAME_SIZE, MAX_LISTED_SLAVES};
+use crate::services::slave_controller_link::connection_monitor::ConnectionState;
+use crate::services::slave_controller_link::domain::{Conversation, DataInstructionCodes, DataInstructions,
+                                                     EmptyRequest, ErrorCode, Operation, RelaySingleState,
+                                                     SignalData, MAX_RELAYS_COUNT};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest};
+use crate::services::slave_controller_link::signals_controller::SignalsHandler;
+use crate::services::slave_hub::SlaveHub;
+use crate::services::slave_clock::HubTimestamp;
+use crate::services::wall_clock::{UtcTimestamp, WallClock};
+
+/** Size of the queues between the links and the server, they hold one item less. */
+pub const HOST_QUEUE_SIZE: usize = 5;
+pub const MAX_PENDING_HOST_REQUESTS: usize = 8;
+/** The oldest pending request is dropped after this period if a slot is needed, its answer could be lost. */
+pub const PENDING_REQUEST_TTL_MS: u32 = 30_000;
+
+pub struct LinkAnswer {
+    id: Option<u32>,
+    operation: Operation,
+    instruction: DataInstructionCodes,
+    answer: SlaveAnswer<SlaveRelayTimes>,
+}
+
+/** Values of the relays with the slave times of their events, the server translates the times to UTC. */
+struct SlaveRelayTimes {
+    relays_count: u8,
+    values: [u8; MAX_RELAYS_COUNT as usize],
+    slave_times: [RelativeSeconds; MAX_RELAYS_COUNT as usize],
+}
+
+impl SlaveRelayTimes {
+
+    /** The relays over `MAX_RELAYS_COUNT` are dropped. */
+    fn collect<I: Iterator<Item = (u8, RelativeSeconds)>>(events: I) -> Self {
+        let mut times = Self {
+            relays_count: 0,
+            values: [0; MAX_RELAYS_COUNT as usize],
+            slave_times: [RelativeSeconds::new(0); MAX_RELAYS_COUNT as usize],
+        };
+        for (value, slave_time) in events.take(MAX_RELAYS_COUNT as usize) {
+            times.values[times.relays_count as usize] = value;
+            times.slave_times[times.relays_count as usize] = slave_time;
+            times.relays_count += 1;
+        }
+        times
+    }
+}
+
+pub type LinkAnswersQueue = Queue<LinkAnswer, HOST_QUEUE_SIZE>;
+pub type LinkSignalsQueue = Queue<SignalData, HOST_QUEUE_SIZE>;
+
+/** Response handler of a link passing the answers to the `HostServer`. They are lost while its queue is full. */
+pub struct HostResponseForwarder<'a> {
+    answers: Producer<'a, LinkAnswer, HOST_QUEUE_SIZE>,
+}
+
+impl <'a> HostResponseForwarder<'a> {
+
+    pub fn new(answers: Producer<'a, LinkAnswer, HOST_QUEUE_SIZE>) -> Self {
+        Self { answers }
+    }
+
+    fn forward(&mut self, request: &SentRequest, answer: SlaveAnswer<SlaveRelayTimes>) {
+        let _ = self.answers.enqueue(LinkAnswer {
+            id: request.id(),
+            operation: request.operation(),
+            instruction: request.instruction(),
+            answer,
+        });
+    }
+}
+
+impl ResponseHandler for HostResponseForwarder<'_> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        self.forward(&request, SlaveAnswer::Done);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        let answer = match response {
+            DataInstructions::All(conversation) => conversation.data().cloned().map(SlaveAnswer::AllData),
+            DataInstructions::StateFixSettings(conversation) =>
+                conversation.data().cloned().map(SlaveAnswer::StateFixSettings),
+            DataInstructions::SwitchCountingSettings(conversation) =>
+                conversation.data().cloned().map(SlaveAnswer::SwitchCountingSettings),
+            DataInstructions::CyclesStatistics(conversation) =>
+                conversation.data().cloned().map(SlaveAnswer::CyclesStatistics),
+            DataInstructions::FixData(conversation) => conversation.data().map(|fix_data| SlaveAnswer::FixData(
+                SlaveRelayTimes::collect((0..fix_data.get_fix_data_count())
+                    .filter_map(|index| fix_data.get_fix_data(index))
+                    .map(|fix| (fix.fix_try_count(), fix.fix_last_try_time()))))),
+            DataInstructions::SwitchData(conversation) => conversation.data().map(|switch_data| SlaveAnswer::SwitchData(
+                SlaveRelayTimes::collect(switch_data.data.iter().take(switch_data.count as usize)
+                    .map(|switch| (switch.state(), switch.time_stamp()))))),
+            _ => None,
+        };
+        if let Some(answer) = answer {
+            self.forward(&request, answer);
+        }
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        self.forward(&request, SlaveAnswer::Error(error_code));
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, _: Errors, _: &[u8]) {
+        if let Some(request) = request {
+            self.forward(&request, SlaveAnswer::Corrupted);
+        }
+    }
+
+    fn on_request_search_error(&mut self, _: ResponseData, _: Errors) {}
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        self.forward(&request, SlaveAnswer::Timeout);
+    }
+}
+
+/** Signals handler of a link passing the signals to the `HostServer`, except the link internal ones. */
+pub struct HostSignalsForwarder<'a> {
+    signals: Producer<'a, SignalData, HOST_QUEUE_SIZE>,
+}
+
+impl <'a> HostSignalsForwarder<'a> {
+    pub fn new(signals: Producer<'a, SignalData, HOST_QUEUE_SIZE>) -> Self {
+        Self { signals }
+    }
+}
+
+impl SignalsHandler for HostSignalsForwarder<'_> {
+
+    fn on_signal(&mut self, signal_data: SignalData, _: bool) {
+        if signal_data != SignalData::GetTimeStamp {
+            let _ = self.signals.enqueue(signal_data);
+        }
+    }
+
+    fn on_signal_parse_error(&mut self, _: Errors, _: bool, _: &[u8]) {}
+
+    fn on_signal_process_error(&mut self, _: Errors, _: bool, _: SignalData) {}
+}
+
+/** Connection to the host, e.g. USB serial port. Gets whole COBS frames. */
+pub trait HostSink {
+    fn send(&mut self, frame: &[u8]) -> Result<(), Errors>;
+}
+
+/** Clock of the hub, which the host can sync. The times are since the Unix epoch, UTC. */
+pub trait HostClock: RelativeTimestampSource {
+    fn unix_millis(&mut self) -> i64;
+    /** Returns the time just before the sync. */
+    fn sync(&mut self, unix_millis: i64) -> Result<i64, Errors>;
+    fn is_synced(&self) -> bool;
+    fn drift_ppm(&self) -> Option<i32>;
+    fn to_utc(&self, timestamp: HubTimestamp) -> Option<UtcTimestamp>;
+}
+
+impl <RTC: Rtc> HostClock for WallClock<RTC> {
+    fn unix_millis(&mut self) -> i64 {
+        self.now_unix_millis()
+    }
+
+    fn sync(&mut self, unix_millis: i64) -> Result<i64, Errors> {
+        WallClock::sync(self, unix_millis)
+    }
+
+    fn is_synced(&self) -> bool {
+        WallClock::is_synced(self)
+    }
+
+    fn drift_ppm(&self) -> Option<i32> {
+        WallClock::drift_ppm(self)
+    }
+
+    fn to_utc(&self, timestamp: HubTimestamp) -> Option<UtcTimestamp> {
+        WallClock::to_utc(self, timestamp)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct PendingRequest {
+    tag: u16,
+    port: usize,
+    id: Option<u32>,
+    operation: Operation,
+    instruction: DataInstructionCodes,
+    sent_at: RelativeMillis,
+}
+
+/**
+Executes the host commands on the slaves of the hub. The commands needing the slave answer are
+acknowledged at once, the answers are pushed later with the tag of the command, as well as the signals
+of the slaves while they are subscribed. The answers are matched to the commands by the port, the request
+id and the instruction, in the order of sending, so the answers to the hub own requests are not taken.
+ */
+pub struct HostServer<'a, const N: usize> {
+    accumulator: CobsAccumulator<HOST_FRAME_SIZE>,
+    answers: [Consumer<'a, LinkAnswer, HOST_QUEUE_SIZE>; N],
+    signals: [Consumer<'a, SignalData, HOST_QUEUE_SIZE>; N],
+    pending: [Option<PendingRequest>; MAX_PENDING_HOST_REQUESTS],
+    pending_count: usize,
+    subscribed: bool,
+}
+
+impl <'a, const N: usize> HostServer<'a, N> {
+
+    /** The queues of each port should be the ones, the forwarders of the link on the port put to. */
+    pub fn new(answers: [Consumer<'a, LinkAnswer, HOST_QUEUE_SIZE>; N],
+               signals: [Consumer<'a, SignalData, HOST_QUEUE_SIZE>; N]) -> Self {
+        Self {
+            accumulator: CobsAccumulator::new(),
+            answers,
+            signals,
+            pending: [None; MAX_PENDING_HOST_REQUESTS],
+            pending_count: 0,
+            subscribed: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn pending_count(&self) -> usize {
+        self.pending_count
+    }
+
+    #[inline(always)]
+    pub fn subscribed(&self) -> bool {
+        self.subscribed
+    }
+
+    /** Executes the commands completed by the bytes read from the host. A frame may come in several reads. */
+    pub fn on_received<C: HostClock, F: NorFlash, J: JournalSpill, S: HostSink>(
+            &mut self, data: &[u8], hub: &mut SlaveHub<'_, N>, clock: &mut C, config: &mut PersistentConfig<F, N>,
+            journal: &mut EventJournal<J>, sink: &mut S) -> Result<(), Errors> {
+        let mut result = Ok(());
+        let mut window = data;
+        while !window.is_empty() {
+            let (reply, remaining) = match self.accumulator.feed::<Host2Target>(window) {
+                FeedResult::Consumed => break,
+                FeedResult::OverFull(remaining) | FeedResult::DeserError(remaining) =>
+                    (Target2Host::Malformed, remaining),
+                FeedResult::Success { data, remaining } =>
+                    (self.execute(data, hub, clock, config, journal), remaining),
+            };
+            if let Err(error) = send(sink, &reply) {
+                result = Err(error);
+            }
+            window = remaining;
+        }
+        result
+    }
+
+    /**
+    Pushes the answers and signals passed by the links since the last call. The slave times of the signals
+    and of the fix and switch data are translated to UTC through the slave clock offset.
+     */
+    pub fn poll<C: HostClock, S: HostSink>(&mut self, hub: &mut SlaveHub<'_, N>, clock: &mut C,
+                                           sink: &mut S) -> Result<(), Errors> {
+        let now = clock.get();
+        let mut result = Ok(());
+        for port in 0..N {
+            while let Some(answer) = self.answers[port].dequeue() {
+                if let Some(tag) = self.take_pending(port, &answer) {
+                    let answer = answer.answer.map_times(|slave_times| {
+                        let mut times = RelayTimes::new();
+                        for index in 0..slave_times.relays_count as usize {
+                            let time = Self::utc_time(hub, port, clock, slave_times.slave_times[index], now);
+                            let _ = times.add(slave_times.values[index], time);
+                        }
+                        times
+                    });
+                    if let Err(error) = send(sink, &Target2Host::Answer { tag, answer }) {
+                        result = Err(error);
+                    }
+                }
+            }
+            while let Some(signal) = self.signals[port].dequeue() {
+                if self.subscribed {
+                    let time = signal.relative_timestamp()
+                        .and_then(|slave_time| Self::utc_time(hub, port, clock, slave_time, now));
+                    let message = Target2Host::Signal { slave_id: hub.slave_id(port), signal, time };
+                    if let Err(error) = send(sink, &message) {
+                        result = Err(error);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn execute<C: HostClock, F: NorFlash, J: JournalSpill>(&mut self, message: Host2Target, hub: &mut SlaveHub<'_, N>,
+                                                           clock: &mut C, config: &mut PersistentConfig<F, N>,
+                                                           journal: &mut EventJournal<J>) -> Target2Host {
+        let tag = message.tag;
+        let (slave_id, operation, instruction) = match message.command {
+            HostCommand::ListSlaves => {
+                return Target2Host::Slaves { tag, slaves: Self::slaves(hub) };
+            }
+            HostCommand::SubscribeSignals { on } => {
+                self.subscribed = on;
+                return Target2Host::Done { tag };
+            }
+            HostCommand::SetTime { unix_millis } => {
+                return match clock.sync(unix_millis) {
+                    Ok(previous_unix_millis) => Target2Host::TimeSet { tag, previous_unix_millis },
+                    Err(_) => Target2Host::Rejected { tag, reason: Rejection::TimeNotSet },
+                };
+            }
+            HostCommand::ReadTime => {
+                return Target2Host::Time { tag, unix_millis: clock.unix_millis(), synced: clock.is_synced(),
+                    drift_ppm: clock.drift_ppm() };
+            }
+            HostCommand::ReadConfig { index } => {
+                return Target2Host::Config { tag, index, entry: config.config().entries().nth(index as usize) };
+            }
+            HostCommand::WriteConfig { entry } => {
+                return match config.write(entry) {
+                    Ok(()) => Target2Host::Done { tag },
+                    Err(_) => Target2Host::Rejected { tag, reason: Rejection::ConfigNotWritten },
+                };
+            }
+            HostCommand::ReadJournal { cursor, filter } => {
+                let read = journal.first_seq()
+                    .and_then(|first_seq| journal.read(cursor, &filter).map(|record| (first_seq, record)));
+                return match read {
+                    Ok((first_seq, record)) => Target2Host::Journal { tag, first_seq, record },
+                    Err(_) => Target2Host::Rejected { tag, reason: Rejection::JournalNotRead },
+                };
+            }
+            HostCommand::ReadAllData { slave_id } => (slave_id, Operation::Read,
+                DataInstructions::All(Conversation::Request(EmptyRequest::new()))),
+            HostCommand::SetRelay { slave_id, relay_index, on } => {
+                if relay_index >= MAX_RELAYS_COUNT {
+                    return Target2Host::Rejected { tag, reason: Rejection::RelayIndexOutOfRange };
+                }
+                (slave_id, Operation::Set,
+                 DataInstructions::RelaySwitchedOn(Conversation::Data(RelaySingleState::new(relay_index, on))))
+            }
+            HostCommand::ReadStateFixSettings { slave_id } => (slave_id, Operation::Read,
+                DataInstructions::StateFixSettings(Conversation::Request(EmptyRequest::new()))),
+            HostCommand::WriteStateFixSettings { slave_id, settings } => (slave_id, Operation::Set,
+                DataInstructions::StateFixSettings(Conversation::Data(settings))),
+            HostCommand::ReadSwitchCountingSettings { slave_id } => (slave_id, Operation::Read,
+                DataInstructions::SwitchCountingSettings(Conversation::Request(EmptyRequest::new()))),
+            HostCommand::WriteSwitchCountingSettings { slave_id, settings } => (slave_id, Operation::Set,
+                DataInstructions::SwitchCountingSettings(Conversation::Data(settings))),
+            HostCommand::ReadCyclesStatistics { slave_id } => (slave_id, Operation::Read,
+                DataInstructions::CyclesStatistics(Conversation::Request(EmptyRequest::new()))),
+            HostCommand::ReadFixData { slave_id } => (slave_id, Operation::Read,
+                DataInstructions::FixData(Conversation::Request(EmptyRequest::new()))),
+            HostCommand::ReadSwitchData { slave_id } => (slave_id, Operation::Read,
+                DataInstructions::SwitchData(Conversation::Request(EmptyRequest::new()))),
+        };
+        let port = match hub.port(slave_id) {
+            Some(port) => port,
+            None => return Target2Host::Rejected { tag, reason: Rejection::SlaveNotFound },
+        };
+        let now = clock.get();
+        if !self.free_pending_slot(now) {
+            return Target2Host::Rejected { tag, reason: Rejection::TooManyPending };
+        }
+        let instruction_code = instruction.code();
+        match hub.send_request(slave_id, operation, instruction, now) {
+            Ok(id) => {
+                self.pending[self.pending_count] = Some(PendingRequest {
+                    tag,
+                    port,
+                    id,
+                    operation,
+                    instruction: instruction_code,
+                    sent_at: now,
+                });
+                self.pending_count += 1;
+                Target2Host::Accepted { tag }
+            }
+            Err(_) => Target2Host::Rejected { tag, reason: Rejection::SendFailed },
+        }
+    }
+
+    fn utc_time<C: HostClock>(hub: &mut SlaveHub<'_, N>, port: usize, clock: &C, slave_time: RelativeSeconds,
+                              now: RelativeMillis) -> Option<UtcTimestamp> {
+        hub.link(port)?.slave_clock().to_hub_time(slave_time, now).and_then(|timestamp| clock.to_utc(timestamp))
+    }
+
+    fn slaves(hub: &mut SlaveHub<'_, N>) -> [Option<SlaveInfo>; MAX_LISTED_SLAVES] {
+        let mut slaves = [None; MAX_LISTED_SLAVES];
+        for (port, slave) in slaves.iter_mut().enumerate().take(N) {
+            *slave = Some(SlaveInfo {
+                port: port as u8,
+                id: hub.slave_id(port),
+                version: hub.link(port).and_then(|link| link.version()),
+                connection: hub.connection_state(port).unwrap_or(ConnectionState::Unknown),
+            });
+        }
+        slaves
+    }
+
+    fn free_pending_slot(&mut self, now: RelativeMillis) -> bool {
+        if self.pending_count < MAX_PENDING_HOST_REQUESTS {
+            return true;
+        }
+        let oldest = self.pending[0].map(|pending| pending.sent_at.value()).unwrap_or(now.value());
+        if now.value().wrapping_sub(oldest) >= PENDING_REQUEST_TTL_MS {
+            self.remove_pending(0);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_pending(&mut self, port: usize, answer: &LinkAnswer) -> Option<u16> {
+        let position = self.pending[..self.pending_count].iter().flatten()
+            .position(|pending| pending.port == port && pending.id == answer.id
+                && pending.operation == answer.operation && pending.instruction == answer.instruction)?;
+        let tag = self.pending[position].map(|pending| pending.tag);
+        self.remove_pending(position);
+        tag
+    }
+
+    fn remove_pending(&mut self, position: usize) {
+        self.pending.copy_within(position + 1..self.pending_count, position);
+        self.pending_count -= 1;
+        self.pending[self.pending_count] = None;
+    }
+}
+
+fn send<S: HostSink>(sink: &mut S, message: &Target2Host) -> Result<(), Errors> {
+    let mut buffer = [0; HOST_FRAME_SIZE];
+    let frame = postcard::to_slice_cobs(message, &mut buffer).map_err(|_| Errors::DataOverflow)?;
+    sink.send(frame)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::hal_ext::rtc_wrapper::RelativeSeconds;
+    use crate::services::event_journal::{JournalEvent, JournalFilter, LinkFault, NoSpill, JOURNAL_SIZE};
+    use crate::services::hub_config::ConfigEntry;
+    use crate::services::slave_controller_link::domain::{AllData, FixDataContainer, RelaySignalData, StateFixSettings,
+                                                         StateSwitchDatas, Version};
+    use crate::services::test_support::MockLink;
+    use crate::utils::ram_flash::RamFlash;
+
+    #[test]
+    fn test_answer_is_pushed_with_command_tag() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let tag = rng.gen();
+        let mut answers = [LinkAnswersQueue::new(), LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new(), LinkSignalsQueue::new()];
+        let [answers0, answers1] = &mut answers;
+        let [signals0, signals1] = &mut signals;
+        let (_, answers0_rx) = answers0.split();
+        let (answers1_tx, answers1_rx) = answers1.split();
+        let (_, signals0_rx) = signals0.split();
+        let (_, signals1_rx) = signals1.split();
+        let mut forwarder = HostResponseForwarder::new(answers1_tx);
+        let mut links = [MockLink::new(None), MockLink::new(Some(id))];
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut tested = HostServer::new([answers0_rx, answers1_rx], [signals0_rx, signals1_rx]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let now = RelativeMillis::new(rng.next_u32());
+
+        tested.on_received(&frame(tag, HostCommand::ReadAllData { slave_id: id }),
+                           &mut hub, &mut MockClock::new(now), &mut config, &mut journal, &mut sink).unwrap();
+        let all_data = AllData::new(id, rng.gen());
+        forwarder.on_request_response(SentRequest::new(None, Operation::Read, DataInstructionCodes::All, now),
+                                      DataInstructions::All(Conversation::Data(all_data.clone())));
+        tested.poll(&mut hub, &mut MockClock::new(now), &mut sink).unwrap();
+
+        assert_eq!(vec![Target2Host::Accepted { tag },
+                        Target2Host::Answer { tag, answer: SlaveAnswer::AllData(all_data) }], sink.messages());
+        assert_eq!(0, tested.pending_count());
+        assert_eq!(vec![(Operation::Read, DataInstructionCodes::All, now)], links[1].sent_requests());
+    }
+
+    #[test]
+    fn test_answer_to_hub_own_request_is_not_taken() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let mut answers = [LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new()];
+        let (answers_tx, answers_rx) = answers[0].split();
+        let (_, signals_rx) = signals[0].split();
+        let mut forwarder = HostResponseForwarder::new(answers_tx);
+        let mut links = [MockLink::new(Some(id)).with_versions(&[Some(Version::V2)])];
+        let [link0] = &mut links;
+        let mut hub = SlaveHub::new([link0]);
+        let mut tested = HostServer::new([answers_rx], [signals_rx]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let now = RelativeMillis::new(rng.next_u32());
+
+        let hub_request_id = hub.send_request(id, Operation::Read,
+                                              DataInstructions::All(Conversation::Request(EmptyRequest::new())), now).unwrap();
+        tested.on_received(&frame(7, HostCommand::ReadAllData { slave_id: id }),
+                           &mut hub, &mut MockClock::new(now), &mut config, &mut journal, &mut sink).unwrap();
+        let hub_data = AllData::new(id, rng.gen());
+        let host_data = AllData::new(id, rng.gen());
+        // the hub request is answered first with the same instruction
+        forwarder.on_request_response(SentRequest::new(hub_request_id, Operation::Read, DataInstructionCodes::All, now),
+                                      DataInstructions::All(Conversation::Data(hub_data)));
+        tested.poll(&mut hub, &mut MockClock::new(now), &mut sink).unwrap();
+        assert_eq!(1, tested.pending_count());
+        forwarder.on_request_response(SentRequest::new(Some(2), Operation::Read, DataInstructionCodes::All, now),
+                                      DataInstructions::All(Conversation::Data(host_data.clone())));
+        tested.poll(&mut hub, &mut MockClock::new(now), &mut sink).unwrap();
+
+        assert_eq!(Some(1), hub_request_id);
+        assert_eq!(vec![Target2Host::Accepted { tag: 7 },
+                        Target2Host::Answer { tag: 7, answer: SlaveAnswer::AllData(host_data) }], sink.messages());
+        assert_eq!(0, tested.pending_count());
+    }
+
+    #[test]
+    fn test_answers_follow_order_of_commands() {
+        let mut rng = rand::thread_rng();
+        let mut answers = [LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new()];
+        let (answers_tx, answers_rx) = answers[0].split();
+        let (_, signals_rx) = signals[0].split();
+        let mut forwarder = HostResponseForwarder::new(answers_tx);
+        let mut links = [MockLink::new(Some(1))];
+        let [link0] = &mut links;
+        let mut hub = SlaveHub::new([link0]);
+        let mut tested = HostServer::new([answers_rx], [signals_rx]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let now = RelativeMillis::new(rng.next_u32());
+        let settings = StateFixSettings::new(rng.gen(), rng.gen(), rng.gen(), rng.gen());
+
+        let data = [frame(1, HostCommand::SetRelay { slave_id: 1, relay_index: 2, on: true }),
+            frame(2, HostCommand::ReadStateFixSettings { slave_id: 1 }),
+            frame(3, HostCommand::SetRelay { slave_id: 1, relay_index: 3, on: false })].concat();
+        // the frames are split by the USB packets
+        for chunk in data.chunks(rng.gen_range(1..8)) {
+            tested.on_received(chunk, &mut hub, &mut MockClock::new(now), &mut config, &mut journal, &mut sink).unwrap();
+        }
+        let set_request = SentRequest::new(None, Operation::Set, DataInstructionCodes::RelaySwitchedOn, now);
+        forwarder.on_request_timeout(set_request);
+        forwarder.on_request_response(SentRequest::new(None, Operation::Read, DataInstructionCodes::StateFixSettings, now),
+                                      DataInstructions::StateFixSettings(Conversation::Data(settings.clone())));
+        forwarder.on_request_error(set_request, ErrorCode::ERelayIndexOutOfRange);
+        // the hub own request is not the host business
+        forwarder.on_request_response(SentRequest::new(None, Operation::Read, DataInstructionCodes::Id, now),
+                                      DataInstructions::Id(Conversation::Data(1)));
+        tested.poll(&mut hub, &mut MockClock::new(now), &mut sink).unwrap();
+
+        assert_eq!(vec![Target2Host::Accepted { tag: 1 }, Target2Host::Accepted { tag: 2 }, Target2Host::Accepted { tag: 3 },
+                        Target2Host::Answer { tag: 1, answer: SlaveAnswer::Timeout },
+                        Target2Host::Answer { tag: 2, answer: SlaveAnswer::StateFixSettings(settings) },
+                        Target2Host::Answer { tag: 3, answer: SlaveAnswer::Error(ErrorCode::ERelayIndexOutOfRange) }],
+                   sink.messages());
+    }
+
+    #[test]
+    fn test_rejects_commands_which_can_not_be_sent() {
+        let mut rng = rand::thread_rng();
+        let mut answers = [LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new()];
+        let (_, answers_rx) = answers[0].split();
+        let (_, signals_rx) = signals[0].split();
+        let mut links = [MockLink::new(Some(1))];
+        let [link0] = &mut links;
+        let mut hub = SlaveHub::new([link0]);
+        let mut tested = HostServer::new([answers_rx], [signals_rx]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let start = rng.next_u32();
+
+        tested.on_received(&frame(1, HostCommand::ReadCyclesStatistics { slave_id: 2 }),
+                           &mut hub, &mut MockClock::new(RelativeMillis::new(start)), &mut config, &mut journal, &mut sink).unwrap();
+        tested.on_received(&frame(2, HostCommand::SetRelay { slave_id: 1, relay_index: MAX_RELAYS_COUNT, on: true }),
+                           &mut hub, &mut MockClock::new(RelativeMillis::new(start)), &mut config, &mut journal, &mut sink).unwrap();
+        tested.on_received(&[0x55, 0x13, 0x00], &mut hub, &mut MockClock::new(RelativeMillis::new(start)), &mut config, &mut journal, &mut sink).unwrap();
+        for tag in 0..MAX_PENDING_HOST_REQUESTS as u16 + 1 {
+            tested.on_received(&frame(tag, HostCommand::ReadCyclesStatistics { slave_id: 1 }),
+                               &mut hub, &mut MockClock::new(RelativeMillis::new(start)), &mut config, &mut journal, &mut sink).unwrap();
+        }
+        tested.on_received(&frame(20, HostCommand::ReadCyclesStatistics { slave_id: 1 }),
+                           &mut hub, &mut MockClock::new(RelativeMillis::new(start.wrapping_add(PENDING_REQUEST_TTL_MS))), &mut config, &mut journal, &mut sink).unwrap();
+
+        let messages = sink.messages();
+        assert_eq!(Target2Host::Rejected { tag: 1, reason: Rejection::SlaveNotFound }, messages[0]);
+        assert_eq!(Target2Host::Rejected { tag: 2, reason: Rejection::RelayIndexOutOfRange }, messages[1]);
+        assert_eq!(Target2Host::Malformed, messages[2]);
+        assert_eq!(Target2Host::Accepted { tag: MAX_PENDING_HOST_REQUESTS as u16 - 1 }, messages[2 + MAX_PENDING_HOST_REQUESTS]);
+        assert_eq!(Target2Host::Rejected { tag: MAX_PENDING_HOST_REQUESTS as u16, reason: Rejection::TooManyPending },
+                   messages[3 + MAX_PENDING_HOST_REQUESTS]);
+        assert_eq!(Target2Host::Accepted { tag: 20 }, messages[4 + MAX_PENDING_HOST_REQUESTS]);
+        assert_eq!(MAX_PENDING_HOST_REQUESTS, tested.pending_count());
+    }
+
+    #[test]
+    fn test_signals_are_pushed_while_subscribed() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let mut answers = [LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new()];
+        let (_, answers_rx) = answers[0].split();
+        let (signals_tx, signals_rx) = signals[0].split();
+        let mut forwarder = HostSignalsForwarder::new(signals_tx);
+        let mut links = [MockLink::new(Some(id))];
+        let now = RelativeMillis::new(rng.next_u32());
+        links[0].slave_clock.on_time_set(RelativeSeconds::new(rng.gen()), now, now);
+        let [link0] = &mut links;
+        let mut hub = SlaveHub::new([link0]);
+        let mut tested = HostServer::new([answers_rx], [signals_rx]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let first = SignalData::MonitoringStateChanged(RelaySignalData::new(RelativeSeconds::new(rng.gen()), 1, true));
+        let second = SignalData::StateFixTry(RelaySignalData::new(RelativeSeconds::new(rng.gen()), 2, false));
+
+        forwarder.on_signal(first, true);
+        tested.poll(&mut hub, &mut MockClock::new(now), &mut sink).unwrap();
+        tested.on_received(&frame(5, HostCommand::SubscribeSignals { on: true }),
+                           &mut hub, &mut MockClock::new(now), &mut config, &mut journal, &mut sink).unwrap();
+        forwarder.on_signal(SignalData::GetTimeStamp, true);
+        forwarder.on_signal(second, true);
+        tested.poll(&mut hub, &mut MockClock::new(now), &mut sink).unwrap();
+
+        let slave_time = second.relative_timestamp().unwrap();
+        let hub_time = links[0].slave_clock.to_hub_time(slave_time, now).unwrap();
+        assert_eq!(vec![Target2Host::Done { tag: 5 }, Target2Host::Signal { slave_id: Some(id), signal: second,
+            time: Some(UtcTimestamp::new(MOCK_UNIX_SECONDS * 1000 + hub_time.timestamp().value() as i64,
+                                         hub_time.uncertainty_ms())) }],
+                   sink.messages());
+        assert!(tested.subscribed());
+    }
+
+    #[test]
+    fn test_fix_and_switch_times_are_translated_to_utc() {
+        let mut rng = rand::thread_rng();
+        let mut answers = [LinkAnswersQueue::new(), LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new(), LinkSignalsQueue::new()];
+        let [answers0, answers1] = &mut answers;
+        let [signals0, signals1] = &mut signals;
+        let (answers0_tx, answers0_rx) = answers0.split();
+        let (answers1_tx, answers1_rx) = answers1.split();
+        let mut forwarder0 = HostResponseForwarder::new(answers0_tx);
+        let mut forwarder1 = HostResponseForwarder::new(answers1_tx);
+        let mut links = [MockLink::new(Some(1)), MockLink::new(Some(2))];
+        let now = RelativeMillis::new(rng.next_u32());
+        // the clock of the first slave is not known
+        links[1].slave_clock.on_time_set(RelativeSeconds::new(rng.gen()), now, now);
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut tested = HostServer::new([answers0_rx, answers1_rx], [signals0.split().1, signals1.split().1]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let mut fix_data = FixDataContainer::new();
+        fix_data.add_fix_data(3, rng.gen()).unwrap();
+        fix_data.add_fix_data(0, rng.gen()).unwrap();
+        let mut switch_data = StateSwitchDatas::new();
+        switch_data.add_switch_data(0x05, rng.gen()).unwrap();
+
+        for (tag, command) in [(1, HostCommand::ReadFixData { slave_id: 1 }), (2, HostCommand::ReadFixData { slave_id: 2 }),
+                               (3, HostCommand::ReadSwitchData { slave_id: 2 })] {
+            tested.on_received(&frame(tag, command), &mut hub, &mut MockClock::new(now), &mut config, &mut journal,
+                               &mut sink).unwrap();
+        }
+        let fix_request = SentRequest::new(None, Operation::Read, DataInstructionCodes::FixData, now);
+        forwarder0.on_request_response(fix_request, DataInstructions::FixData(Conversation::Data(fix_data.clone())));
+        forwarder1.on_request_response(fix_request, DataInstructions::FixData(Conversation::Data(fix_data.clone())));
+        forwarder1.on_request_response(SentRequest::new(None, Operation::Read, DataInstructionCodes::SwitchData, now),
+                                       DataInstructions::SwitchData(Conversation::Data(switch_data.clone())));
+        tested.poll(&mut hub, &mut MockClock::new(now), &mut sink).unwrap();
+
+        let utc = |slave_time: RelativeSeconds| links[1].slave_clock.to_hub_time(slave_time, now)
+            .map(|time| UtcTimestamp::new(MOCK_UNIX_SECONDS * 1000 + time.timestamp().value() as i64,
+                                          time.uncertainty_ms()));
+        let mut unknown_fix_times = RelayTimes::new();
+        let mut fix_times = RelayTimes::new();
+        for index in 0..2 {
+            let fix = fix_data.get_fix_data(index).unwrap();
+            unknown_fix_times.add(fix.fix_try_count(), None).unwrap();
+            fix_times.add(fix.fix_try_count(), utc(fix.fix_last_try_time())).unwrap();
+        }
+        let mut switch_times = RelayTimes::new();
+        switch_times.add(0x05, utc(switch_data.data[0].time_stamp())).unwrap();
+        assert!(fix_times.times[1].is_some());
+        assert_eq!(vec![Target2Host::Answer { tag: 1, answer: SlaveAnswer::FixData(unknown_fix_times) },
+                        Target2Host::Answer { tag: 2, answer: SlaveAnswer::FixData(fix_times) },
+                        Target2Host::Answer { tag: 3, answer: SlaveAnswer::SwitchData(switch_times) }],
+                   sink.messages()[3..]);
+    }
+
+    #[test]
+    fn test_lists_slaves() {
+        let mut answers = [LinkAnswersQueue::new(), LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new(), LinkSignalsQueue::new()];
+        let [answers0, answers1] = &mut answers;
+        let [signals0, signals1] = &mut signals;
+        let mut links = [MockLink::new(None), MockLink::new(Some(3)).with_versions(&[Some(Version::V2)])];
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut tested = HostServer::new([answers0.split().1, answers1.split().1],
+                                         [signals0.split().1, signals1.split().1]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+
+        tested.on_received(&frame(9, HostCommand::ListSlaves),
+                           &mut hub, &mut MockClock::new(RelativeMillis::new(0)), &mut config, &mut journal, &mut sink).unwrap();
+
+        let mut slaves = [None; MAX_LISTED_SLAVES];
+        slaves[0] = Some(SlaveInfo { port: 0, id: None, version: None, connection: ConnectionState::Online });
+        slaves[1] = Some(SlaveInfo { port: 1, id: Some(3), version: Some(Version::V2),
+            connection: ConnectionState::Online });
+        assert_eq!(vec![Target2Host::Slaves { tag: 9, slaves }], sink.messages());
+    }
+
+    #[test]
+    fn test_sets_time() {
+        let mut rng = rand::thread_rng();
+        let mut answers = [LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new()];
+        let mut links = [MockLink::new(Some(1))];
+        let [link0] = &mut links;
+        let mut hub = SlaveHub::new([link0]);
+        let mut tested = HostServer::new([answers[0].split().1], [signals[0].split().1]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let mut clock = MockClock::new(RelativeMillis::new(rng.next_u32()));
+        let previous = clock.unix_millis;
+        let unix_millis = rng.gen_range(0..i64::MAX / 2);
+
+        tested.on_received(&frame(2, HostCommand::ReadTime), &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+        tested.on_received(&frame(3, HostCommand::SetTime { unix_millis }), &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+        clock.fails = true;
+        tested.on_received(&frame(4, HostCommand::SetTime { unix_millis: 0 }), &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+        tested.on_received(&frame(5, HostCommand::ReadTime), &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+
+        assert_eq!(vec![Target2Host::Time { tag: 2, unix_millis: previous, synced: false, drift_ppm: None },
+                        Target2Host::TimeSet { tag: 3, previous_unix_millis: previous },
+                        Target2Host::Rejected { tag: 4, reason: Rejection::TimeNotSet },
+                        Target2Host::Time { tag: 5, unix_millis, synced: true, drift_ppm: None }], sink.messages());
+    }
+
+    #[test]
+    fn test_reads_and_writes_config() {
+        let mut rng = rand::thread_rng();
+        let mut answers = [LinkAnswersQueue::new(), LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new(), LinkSignalsQueue::new()];
+        let [answers0, answers1] = &mut answers;
+        let [signals0, signals1] = &mut signals;
+        let mut links = [MockLink::new(None), MockLink::new(None)];
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut tested = HostServer::new([answers0.split().1, answers1.split().1],
+                                         [signals0.split().1, signals1.split().1]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let mut clock = MockClock::new(RelativeMillis::new(rng.next_u32()));
+        let baud_rate = rng.gen_range(1..1_000_000);
+
+        tested.on_received(&frame(1, HostCommand::WriteConfig { entry: ConfigEntry::SlaveBaudRate { port: 1, baud_rate } }),
+                           &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+        tested.on_received(&frame(2, HostCommand::WriteConfig { entry: ConfigEntry::SlaveVersion { port: 2, version: Version::V2 } }),
+                           &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+        for index in 4..6 {
+            tested.on_received(&frame(index, HostCommand::ReadConfig { index }),
+                               &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+        }
+
+        assert_eq!(vec![Target2Host::Done { tag: 1 },
+                        Target2Host::Rejected { tag: 2, reason: Rejection::ConfigNotWritten },
+                        Target2Host::Config { tag: 4, index: 4, entry: Some(ConfigEntry::SlaveBaudRate { port: 1, baud_rate }) },
+                        Target2Host::Config { tag: 5, index: 5, entry: None }],
+                   sink.messages());
+        assert_eq!(baud_rate, config.config().slave(1).unwrap().baud_rate());
+    }
+
+    #[test]
+    fn test_reads_journal_by_cursor() {
+        let mut answers = [LinkAnswersQueue::new()];
+        let mut signals = [LinkSignalsQueue::new()];
+        let [answers0] = &mut answers;
+        let [signals0] = &mut signals;
+        let mut links = [MockLink::new(Some(3))];
+        let [link0] = &mut links;
+        let mut hub = SlaveHub::new([link0]);
+        let mut tested = HostServer::new([answers0.split().1], [signals0.split().1]);
+        let mut sink = MockSink::new();
+        let mut config = config();
+        let mut journal = EventJournal::new(NoSpill);
+        let mut clock = MockClock::new(RelativeMillis::new(0));
+        let time = UtcTimestamp::new(MOCK_UNIX_SECONDS * 1000, 0);
+        journal.record(time, 0, Some(3), JournalEvent::RelayChanged { relay_index: 1, on: true });
+        journal.record(time, 0, Some(3), JournalEvent::RelayChanged { relay_index: 2, on: true });
+        journal.record(time, 0, Some(3), JournalEvent::FixTry { relay_index: 1, on: true });
+        let filter = JournalFilter { slave_id: Some(3), relay_index: Some(1) };
+
+        for (tag, cursor) in [(1, 0), (2, 1), (3, 3)] {
+            tested.on_received(&frame(tag, HostCommand::ReadJournal { cursor, filter }),
+                               &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+        }
+        // the records of the slave are pushed out of the RAM
+        for _ in 0..JOURNAL_SIZE {
+            journal.record(time, 0, None, JournalEvent::LinkFault(LinkFault::Dma));
+        }
+        tested.on_received(&frame(4, HostCommand::ReadJournal { cursor: 0, filter }),
+                           &mut hub, &mut clock, &mut config, &mut journal, &mut sink).unwrap();
+
+        let records: Vec<_> = sink.messages().into_iter().map(|message| match message {
+            Target2Host::Journal { first_seq, record, .. } => (first_seq, record.map(|record| record.seq())),
+            message => panic!("unexpected {:?}", message),
+        }).collect();
+        assert_eq!(vec![(0, Some(0)), (0, Some(2)), (0, None), (3, None)], records);
+    }
+
+    fn frame(tag: u16, command: HostCommand) -> Vec<u8> {
+        let mut buffer = [0; HOST_FRAME_SIZE];
+        postcard::to_slice_cobs(&Host2Target { tag, command }, &mut buffer).unwrap().to_vec()
+    }
+
+    fn config<const N: usize>() -> PersistentConfig<RamFlash<2048, 1, 1024>, N> {
+        PersistentConfig::open(RamFlash::new(), 0, 1024).unwrap()
+    }
+
+    const MOCK_UNIX_SECONDS: i64 = 1_686_644_790;
+
+    struct MockClock {
+        now: RelativeMillis,
+        unix_millis: i64,
+        synced: bool,
+        fails: bool,
+    }
+
+    impl MockClock {
+        fn new(now: RelativeMillis) -> Self {
+            Self { now, unix_millis: MOCK_UNIX_SECONDS * 1000, synced: false, fails: false }
+        }
+    }
+
+    impl RelativeTimestampSource for MockClock {
+        fn get(&mut self) -> RelativeMillis {
+            self.now
+        }
+    }
+
+    impl HostClock for MockClock {
+        fn unix_millis(&mut self) -> i64 {
+            self.unix_millis
+        }
+
+        fn sync(&mut self, unix_millis: i64) -> Result<i64, Errors> {
+            if self.fails {
+                return Err(Errors::OutOfRange);
+            }
+            self.synced = true;
+            Ok(core::mem::replace(&mut self.unix_millis, unix_millis))
+        }
+
+        fn is_synced(&self) -> bool {
+            self.synced
+        }
+
+        fn drift_ppm(&self) -> Option<i32> {
+            None
+        }
+
+        fn to_utc(&self, timestamp: HubTimestamp) -> Option<UtcTimestamp> {
+            Some(UtcTimestamp::new(MOCK_UNIX_SECONDS * 1000 + timestamp.timestamp().value() as i64,
+                                   timestamp.uncertainty_ms()))
+        }
+    }
+
+    struct MockSink {
+        frames: Vec<Vec<u8>>,
+    }
+
+    impl MockSink {
+        fn new() -> Self {
+            Self { frames: Vec::new() }
+        }
+
+        fn messages(&mut self) -> Vec<Target2Host> {
+            self.frames.iter_mut()
+                .map(|frame| postcard::from_bytes_cobs(frame).unwrap())
+                .collect()
+        }
+    }
+
+    impl HostSink for MockSink {
+        fn send(&mut self, frame: &[u8]) -> Result<(), Errors> {
+            self.frames.push(frame.to_vec());
+            Ok(())
+        }
+    }
+}
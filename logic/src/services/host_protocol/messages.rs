@@ -0,0 +1,248 @@
+#![deny(unsafe_code)]
+
+use serde_derive::{Deserialize, Serialize};
+use crate::errors::Errors;
+use crate::services::event_journal::{JournalFilter, JournalRecord};
+use crate::services::hub_config::ConfigEntry;
+use crate::services::slave_controller_link::connection_monitor::ConnectionState;
+use crate::services::wall_clock::UtcTimestamp;
+use crate::services::slave_controller_link::domain::{AllData, CyclesStatistics, ErrorCode, SignalData,
+                                                     StateFixSettings, SwitchCountingSettings, Version,
+                                                     MAX_RELAYS_COUNT};
+
+/** Size of the buffer for a COBS encoded message, with the terminating zero. The relay times take the most. */
+pub const HOST_FRAME_SIZE: usize = 320;
+pub const MAX_LISTED_SLAVES: usize = 8;
+
+/** A message sent from the host to the target. The tag is returned in the messages answering it. */
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct Host2Target {
+    pub tag: u16,
+    pub command: HostCommand,
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum HostCommand {
+    ListSlaves,
+    ReadAllData { slave_id: u32 },
+    SetRelay { slave_id: u32, relay_index: u8, on: bool },
+    ReadStateFixSettings { slave_id: u32 },
+    WriteStateFixSettings { slave_id: u32, settings: StateFixSettings },
+    ReadSwitchCountingSettings { slave_id: u32 },
+    WriteSwitchCountingSettings { slave_id: u32, settings: SwitchCountingSettings },
+    ReadCyclesStatistics { slave_id: u32 },
+    /** Reads the fix try counts of the relays with the times of the last tries. */
+    ReadFixData { slave_id: u32 },
+    /** Reads the switch states of the relays with the times of the last switches. */
+    ReadSwitchData { slave_id: u32 },
+    SubscribeSignals { on: bool },
+    /** Syncs the hub clock, the time is UTC. */
+    SetTime { unix_millis: i64 },
+    ReadTime,
+    /** Reads an entry of the hub configuration, they are numbered from 0 with no gaps. */
+    ReadConfig { index: u16 },
+    /** Stores an entry of the hub configuration, most of them take effect after the restart. */
+    WriteConfig { entry: ConfigEntry },
+    /** Reads the first journal record since the cursor, matching the filter. */
+    ReadJournal { cursor: u32, filter: JournalFilter },
+}
+
+/** A message sent from the target to the host. They are built one at a time to be sent, so the size is not kept. */
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum Target2Host {
+    Slaves { tag: u16, slaves: [Option<SlaveInfo>; MAX_LISTED_SLAVES] },
+    /** The command is done on the target, nothing more will follow. */
+    Done { tag: u16 },
+    /** The request is sent to the slave, its answer will follow. */
+    Accepted { tag: u16 },
+    Rejected { tag: u16, reason: Rejection },
+    Answer { tag: u16, answer: SlaveAnswer },
+    TimeSet { tag: u16, previous_unix_millis: i64 },
+    /** `synced` is false until the first host sync since the start, the time is the one of the RTC then. */
+    Time { tag: u16, unix_millis: i64, synced: bool, drift_ppm: Option<i32> },
+    /** `None` after the last entry. */
+    Config { tag: u16, index: u16, entry: Option<ConfigEntry> },
+    /**
+    `None` if there are no more records, the next cursor is the sequence number after the record.
+    `first_seq` is the one of the oldest record kept, the records before it are lost.
+     */
+    Journal { tag: u16, first_seq: u32, record: Option<JournalRecord> },
+    /** Pushed while the signals are subscribed. `time` is the one of the signal timestamp, if it is known. */
+    Signal { slave_id: Option<u32>, signal: SignalData, time: Option<UtcTimestamp> },
+    /** The host message was not decoded, so its tag is unknown. */
+    Malformed,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct SlaveInfo {
+    pub port: u8,
+    /** `None` until the id is read after the handshake. */
+    pub id: Option<u32>,
+    /** `None` while the handshake is not done. */
+    pub version: Option<Version>,
+    pub connection: ConnectionState,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum Rejection {
+    SlaveNotFound,
+    RelayIndexOutOfRange,
+    TooManyPending,
+    SendFailed,
+    TimeNotSet,
+    ConfigNotWritten,
+    JournalNotRead,
+}
+
+/** `T` is the type of the relay times, the links pass the slave ones, which are smaller, to the server. */
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum SlaveAnswer<T = RelayTimes> {
+    Done,
+    AllData(AllData),
+    StateFixSettings(StateFixSettings),
+    SwitchCountingSettings(SwitchCountingSettings),
+    CyclesStatistics(CyclesStatistics),
+    /** The values are the fix try counts. */
+    FixData(T),
+    /** The values are the switch states. */
+    SwitchData(T),
+    Error(ErrorCode),
+    Timeout,
+    Corrupted,
+}
+
+impl <T> SlaveAnswer<T> {
+
+    pub fn map_times<U, F: FnOnce(T) -> U>(self, f: F) -> SlaveAnswer<U> {
+        match self {
+            SlaveAnswer::Done => SlaveAnswer::Done,
+            SlaveAnswer::AllData(data) => SlaveAnswer::AllData(data),
+            SlaveAnswer::StateFixSettings(settings) => SlaveAnswer::StateFixSettings(settings),
+            SlaveAnswer::SwitchCountingSettings(settings) => SlaveAnswer::SwitchCountingSettings(settings),
+            SlaveAnswer::CyclesStatistics(statistics) => SlaveAnswer::CyclesStatistics(statistics),
+            SlaveAnswer::FixData(times) => SlaveAnswer::FixData(f(times)),
+            SlaveAnswer::SwitchData(times) => SlaveAnswer::SwitchData(f(times)),
+            SlaveAnswer::Error(code) => SlaveAnswer::Error(code),
+            SlaveAnswer::Timeout => SlaveAnswer::Timeout,
+            SlaveAnswer::Corrupted => SlaveAnswer::Corrupted,
+        }
+    }
+}
+
+/**
+A value of each relay with the time of its last event, e.g. the last fix try. The slave times are translated
+to UTC through the slave clock offset, the time is `None` while the offset or the hub date is not known.
+ */
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct RelayTimes {
+    pub relays_count: u8,
+    pub values: [u8; MAX_RELAYS_COUNT as usize],
+    pub times: [Option<UtcTimestamp>; MAX_RELAYS_COUNT as usize],
+}
+
+impl RelayTimes {
+
+    pub const fn new() -> Self {
+        Self {
+            relays_count: 0,
+            values: [0; MAX_RELAYS_COUNT as usize],
+            times: [None; MAX_RELAYS_COUNT as usize],
+        }
+    }
+
+    pub fn add(&mut self, value: u8, time: Option<UtcTimestamp>) -> Result<(), Errors> {
+        if self.relays_count < MAX_RELAYS_COUNT {
+            self.values[self.relays_count as usize] = value;
+            self.times[self.relays_count as usize] = time;
+            self.relays_count += 1;
+            Ok(())
+        } else {
+            Err(Errors::RelayCountOverflow)
+        }
+    }
+}
+
+impl Default for RelayTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use quickcheck_macros::quickcheck;
+    use super::*;
+    use crate::hal_ext::rtc_wrapper::RelativeSeconds;
+    use crate::services::event_journal::JournalEvent;
+    use crate::services::hub_config::RELAY_NAME_SIZE;
+    use crate::services::slave_controller_link::domain::{DataInstructionCodes, RelaySignalDataExt};
+
+    #[test]
+    fn test_host_messages_round_trip() {
+        let messages = [
+            Host2Target { tag: 0, command: HostCommand::ListSlaves },
+            Host2Target { tag: u16::MAX, command: HostCommand::SetRelay { slave_id: u32::MAX, relay_index: 15, on: true } },
+            Host2Target { tag: 7, command: HostCommand::WriteStateFixSettings {
+                slave_id: u32::MAX, settings: StateFixSettings::new(u16::MAX, u8::MAX, u8::MAX, u16::MAX) } },
+            Host2Target { tag: u16::MAX, command: HostCommand::ReadJournal { cursor: u32::MAX,
+                filter: JournalFilter { slave_id: Some(u32::MAX), relay_index: Some(u8::MAX) } } },
+            Host2Target { tag: u16::MAX, command: HostCommand::WriteConfig { entry: ConfigEntry::RelayName {
+                port: u8::MAX, relay_index: u8::MAX, name: [0xff; RELAY_NAME_SIZE] } } },
+        ];
+        for message in messages {
+            let mut buffer = [0; HOST_FRAME_SIZE];
+            let frame = postcard::to_slice_cobs(&message, &mut buffer).unwrap();
+
+            assert_eq!(Some(&0), frame.last());
+            assert_eq!(message, postcard::from_bytes_cobs::<Host2Target>(frame).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_largest_target_messages_fit_frame() {
+        let mut all_data = AllData::new(u32::MAX, u8::MAX);
+        for _ in 0..MAX_RELAYS_COUNT {
+            all_data.add(u8::MAX, u8::MAX, u8::MAX, 0x0f).unwrap();
+        }
+        let mut relay_times = RelayTimes::new();
+        for _ in 0..MAX_RELAYS_COUNT {
+            relay_times.add(u8::MAX, Some(UtcTimestamp::new(i64::MIN, u32::MAX))).unwrap();
+        }
+        assert_eq!(Err(Errors::RelayCountOverflow), relay_times.add(0, None));
+        let messages = [
+            Target2Host::Answer { tag: u16::MAX, answer: SlaveAnswer::AllData(all_data) },
+            Target2Host::Answer { tag: u16::MAX, answer: SlaveAnswer::FixData(relay_times.clone()) },
+            Target2Host::Answer { tag: u16::MAX, answer: SlaveAnswer::SwitchData(relay_times) },
+            Target2Host::Slaves { tag: u16::MAX, slaves: [Some(SlaveInfo {
+                port: u8::MAX, id: Some(u32::MAX), version: Some(Version::V2), connection: ConnectionState::Offline });
+                MAX_LISTED_SLAVES] },
+            Target2Host::Answer { tag: u16::MAX, answer: SlaveAnswer::CyclesStatistics(
+                CyclesStatistics::new(u16::MAX, u16::MAX, u16::MAX, u64::MAX)) },
+            Target2Host::Time { tag: u16::MAX, unix_millis: i64::MIN, synced: true, drift_ppm: Some(i32::MIN) },
+            Target2Host::Config { tag: u16::MAX, index: u16::MAX, entry: Some(ConfigEntry::StateFixSettings {
+                port: u8::MAX, settings: StateFixSettings::new(u16::MAX, u8::MAX, u8::MAX, u16::MAX) }) },
+            Target2Host::Journal { tag: u16::MAX, first_seq: u32::MAX, record: Some(JournalRecord::new(u32::MAX,
+                UtcTimestamp::new(i64::MIN, u32::MAX), u8::MAX, Some(u32::MAX), JournalEvent::SlaveError {
+                    instruction: DataInstructionCodes::Unknown, error: ErrorCode::EUndefinedCode(u8::MAX) })) },
+        ];
+        for message in messages {
+            let mut buffer = [0; HOST_FRAME_SIZE];
+            let frame = postcard::to_slice_cobs(&message, &mut buffer).unwrap();
+
+            assert_eq!(message, postcard::from_bytes_cobs::<Target2Host>(frame).unwrap());
+        }
+    }
+
+    #[quickcheck]
+    fn test_signal_message_fits_frame(timestamp: u32, relay_idx: u8, is_on: bool, slave_id: Option<u32>,
+                                      time: Option<(i64, u32)>) -> bool {
+        let time = time.map(|(unix_millis, uncertainty_ms)| UtcTimestamp::new(unix_millis, uncertainty_ms));
+        let signal = SignalData::RelayStateChanged(RelaySignalDataExt::new(
+            RelativeSeconds::new(timestamp), relay_idx, is_on, false));
+        let mut buffer = [0; HOST_FRAME_SIZE];
+        postcard::to_slice_cobs(&Target2Host::Signal { slave_id, signal, time }, &mut buffer).is_ok()
+    }
+}
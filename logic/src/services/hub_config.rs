@@ -0,0 +1,395 @@
+#![deny(unsafe_code)]
+
+pub mod kv_store;
+
+use embedded_storage::nor_flash::NorFlash;
+use serde_derive::{Deserialize, Serialize};
+use crate::errors::Errors;
+use crate::services::hub_config::kv_store::{KvStore, MAX_VALUE_SIZE};
+use crate::services::relay_scheduler::{Schedule, ScheduleData, MAX_SCHEDULES, SCHEDULE_SIZE};
+use crate::services::rules::{Rule, RuleData, MAX_RULES, RULE_SIZE};
+use crate::services::slave_controller_link::domain::{StateFixSettings, Version, MAX_RELAYS_COUNT};
+
+/** Version of the stored records layout, the records of another version are ignored. */
+pub const CONFIG_FORMAT_VERSION: u8 = 1;
+pub const RELAY_NAME_SIZE: usize = 12;
+pub const DEFAULT_BAUD_RATE: u32 = 9600;
+/** 2023-06-13 08:26:30 UTC, the clock starts from it until the host sets it. */
+pub const DEFAULT_RTC_BASE_DATE: i64 = 1_686_644_790;
+
+/** Relay name in UTF-8, padded by zeros. */
+pub type RelayName = [u8; RELAY_NAME_SIZE];
+
+/** A single setting of the hub, the unit of the storing and of the transfer to the host. */
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum ConfigEntry {
+    SlaveVersion { port: u8, version: Version },
+    SlaveBaudRate { port: u8, baud_rate: u32 },
+    /** The settings the slave on the port should have. */
+    StateFixSettings { port: u8, settings: StateFixSettings },
+    RelayName { port: u8, relay_index: u8, name: RelayName },
+    /** The date the clock is set to, when it is found not initialized on the start. */
+    RtcBaseDate { unix_seconds: i64 },
+    /** A rule of the rules engine in its stored form, the zero bytes remove the rule. */
+    Rule { index: u8, rule: RuleData },
+    /** A relay schedule in its stored form, the zero bytes remove the schedule. */
+    Schedule { index: u8, schedule: ScheduleData },
+}
+
+impl ConfigEntry {
+
+    /** The store key, unique for the setting of the valid port and relay. */
+    pub fn key(&self) -> u16 {
+        match self {
+            ConfigEntry::SlaveVersion { port, .. } => 0x0100 | *port as u16,
+            ConfigEntry::SlaveBaudRate { port, .. } => 0x0200 | *port as u16,
+            ConfigEntry::StateFixSettings { port, .. } => 0x0300 | *port as u16,
+            ConfigEntry::RelayName { port, relay_index, .. } =>
+                0x0400 | (*port as u16) << 4 | (*relay_index as u16 & 0x0f),
+            ConfigEntry::RtcBaseDate { .. } => 0x0500,
+            ConfigEntry::Rule { index, .. } => 0x0600 | *index as u16,
+            ConfigEntry::Schedule { index, .. } => 0x0700 | *index as u16,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SlaveConfig {
+    version: Version,
+    baud_rate: u32,
+    state_fix_settings: Option<StateFixSettings>,
+    relay_names: [RelayName; MAX_RELAYS_COUNT as usize],
+}
+
+impl SlaveConfig {
+
+    pub fn new() -> Self {
+        Self {
+            version: Version::V1,
+            baud_rate: DEFAULT_BAUD_RATE,
+            state_fix_settings: None,
+            relay_names: [[0; RELAY_NAME_SIZE]; MAX_RELAYS_COUNT as usize],
+        }
+    }
+
+    #[inline(always)]
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    #[inline(always)]
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    #[inline(always)]
+    pub fn state_fix_settings(&self) -> Option<&StateFixSettings> {
+        self.state_fix_settings.as_ref()
+    }
+
+    /** The name of the relay, empty if it is not set. */
+    pub fn relay_name(&self, relay_index: u8) -> Option<&str> {
+        let name = self.relay_names.get(relay_index as usize)?;
+        let len = name.iter().position(|byte| *byte == 0).unwrap_or(RELAY_NAME_SIZE);
+        core::str::from_utf8(&name[..len]).ok()
+    }
+}
+
+impl Default for SlaveConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/** Configuration of the hub and its slaves on the ports. */
+#[derive(Clone, PartialEq, Debug)]
+pub struct HubConfig<const N: usize> {
+    slaves: [SlaveConfig; N],
+    rtc_base_date: i64,
+    rules: [RuleData; MAX_RULES],
+    schedules: [ScheduleData; MAX_SCHEDULES],
+}
+
+impl <const N: usize> HubConfig<N> {
+
+    pub fn new() -> Self {
+        Self {
+            slaves: core::array::from_fn(|_| SlaveConfig::new()),
+            rtc_base_date: DEFAULT_RTC_BASE_DATE,
+            rules: [[0; RULE_SIZE]; MAX_RULES],
+            schedules: [[0; SCHEDULE_SIZE]; MAX_SCHEDULES],
+        }
+    }
+
+    #[inline(always)]
+    pub fn slave(&self, port: usize) -> Option<&SlaveConfig> {
+        self.slaves.get(port)
+    }
+
+    #[inline(always)]
+    pub fn rtc_base_date(&self) -> i64 {
+        self.rtc_base_date
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = Rule> + '_ {
+        self.rules.iter().filter_map(|rule| Rule::parse(rule).ok().flatten())
+    }
+
+    pub fn schedules(&self) -> impl Iterator<Item = Schedule> + '_ {
+        self.schedules.iter().filter_map(|schedule| Schedule::parse(schedule).ok().flatten())
+    }
+
+    /** Checks the port and relay of the entry and the name encoding. */
+    pub fn check(&self, entry: &ConfigEntry) -> Result<(), Errors> {
+        match entry {
+            ConfigEntry::SlaveVersion { port, .. } | ConfigEntry::StateFixSettings { port, .. } =>
+                self.check_port(*port),
+            ConfigEntry::SlaveBaudRate { port, baud_rate } => {
+                self.check_port(*port)?;
+                if *baud_rate == 0 {
+                    return Err(Errors::OutOfRange);
+                }
+                Ok(())
+            }
+            ConfigEntry::RelayName { port, relay_index, name } => {
+                self.check_port(*port)?;
+                if *relay_index >= MAX_RELAYS_COUNT {
+                    return Err(Errors::RelayIndexOutOfRange);
+                }
+                let len = name.iter().position(|byte| *byte == 0).unwrap_or(RELAY_NAME_SIZE);
+                core::str::from_utf8(&name[..len]).map(|_| ()).map_err(|_| Errors::DataCorrupted)
+            }
+            ConfigEntry::RtcBaseDate { .. } => Ok(()),
+            ConfigEntry::Rule { index, rule } => {
+                if *index as usize >= MAX_RULES {
+                    return Err(Errors::OutOfRange);
+                }
+                Rule::parse(rule).map(|_| ())
+            }
+            ConfigEntry::Schedule { index, schedule } => {
+                if *index as usize >= MAX_SCHEDULES {
+                    return Err(Errors::OutOfRange);
+                }
+                Schedule::parse(schedule).map(|_| ())
+            }
+        }
+    }
+
+    fn check_port(&self, port: u8) -> Result<(), Errors> {
+        if port as usize >= N {
+            return Err(Errors::OutOfRange);
+        }
+        Ok(())
+    }
+
+    pub fn apply(&mut self, entry: ConfigEntry) -> Result<(), Errors> {
+        self.check(&entry)?;
+        match entry {
+            ConfigEntry::SlaveVersion { port, version } => self.slaves[port as usize].version = version,
+            ConfigEntry::SlaveBaudRate { port, baud_rate } => self.slaves[port as usize].baud_rate = baud_rate,
+            ConfigEntry::StateFixSettings { port, settings } =>
+                self.slaves[port as usize].state_fix_settings = Some(settings),
+            ConfigEntry::RelayName { port, relay_index, name } =>
+                self.slaves[port as usize].relay_names[relay_index as usize] = name,
+            ConfigEntry::RtcBaseDate { unix_seconds } => self.rtc_base_date = unix_seconds,
+            ConfigEntry::Rule { index, rule } => self.rules[index as usize] = rule,
+            ConfigEntry::Schedule { index, schedule } => self.schedules[index as usize] = schedule,
+        }
+        Ok(())
+    }
+
+    /** The entries making up the configuration: the clock base date, the slaves settings by ports, the rules, then the schedules. */
+    pub fn entries(&self) -> impl Iterator<Item = ConfigEntry> + '_ {
+        core::iter::once(ConfigEntry::RtcBaseDate { unix_seconds: self.rtc_base_date })
+            .chain(self.slaves.iter().enumerate().flat_map(|(port, slave)| {
+                let port = port as u8;
+                IntoIterator::into_iter([ConfigEntry::SlaveVersion { port, version: slave.version },
+                                         ConfigEntry::SlaveBaudRate { port, baud_rate: slave.baud_rate }])
+                    .chain(slave.state_fix_settings.clone()
+                        .map(|settings| ConfigEntry::StateFixSettings { port, settings }))
+                    .chain(slave.relay_names.iter().enumerate()
+                        .filter(|(_, name)| name[0] != 0)
+                        .map(move |(relay_index, name)|
+                            ConfigEntry::RelayName { port, relay_index: relay_index as u8, name: *name }))
+            }))
+            .chain(self.rules.iter().enumerate()
+                .filter(|(_, rule)| rule.iter().any(|byte| *byte != 0))
+                .map(|(index, rule)| ConfigEntry::Rule { index: index as u8, rule: *rule }))
+            .chain(self.schedules.iter().enumerate()
+                .filter(|(_, schedule)| schedule.iter().any(|byte| *byte != 0))
+                .map(|(index, schedule)| ConfigEntry::Schedule { index: index as u8, schedule: *schedule }))
+    }
+}
+
+impl <const N: usize> Default for HubConfig<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+The hub configuration kept in the flash. It is loaded on the start and the written entries are applied
+at once, though most of them take effect after the restart only.
+ */
+pub struct PersistentConfig<F: NorFlash, const N: usize> {
+    store: KvStore<F>,
+    config: HubConfig<N>,
+}
+
+impl <F: NorFlash, const N: usize> PersistentConfig<F, N> {
+
+    /** Loads the configuration from the store pages, the values not stored or not valid are the default. */
+    pub fn open(flash: F, offset: u32, page_size: u32) -> Result<Self, Errors> {
+        let mut store = KvStore::open(flash, offset, page_size, CONFIG_FORMAT_VERSION)?;
+        let mut config = HubConfig::new();
+        store.for_each(|_, value| {
+            if let Ok(entry) = postcard::from_bytes::<ConfigEntry>(value) {
+                let _ = config.apply(entry);
+            }
+        })?;
+        Ok(Self { store, config })
+    }
+
+    #[inline(always)]
+    pub fn config(&self) -> &HubConfig<N> {
+        &self.config
+    }
+
+    pub fn write(&mut self, entry: ConfigEntry) -> Result<(), Errors> {
+        self.config.check(&entry)?;
+        let mut buffer = [0; MAX_VALUE_SIZE];
+        let value = postcard::to_slice(&entry, &mut buffer).map_err(|_| Errors::DataOverflow)?;
+        self.store.write(entry.key(), value)?;
+        self.config.apply(entry)
+    }
+
+    pub fn release(self) -> F {
+        self.store.release()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::relay_scheduler::EVERY_DAY;
+    use crate::services::rules::{RuleAction, RuleTrigger};
+    use crate::services::slave_controller_link::domain::{DataInstructionCodes, Signals};
+    use crate::utils::ram_flash::RamFlash;
+
+    type TestFlash = RamFlash<2048, 1, 1024>;
+
+    #[test]
+    fn test_written_entries_are_loaded() {
+        let mut rng = rand::thread_rng();
+        let settings = StateFixSettings::new(rng.gen(), rng.gen(), rng.gen(), rng.gen());
+        let baud_rate = rng.gen_range(1..1_000_000);
+        let unix_seconds = rng.gen();
+        let mut tested = PersistentConfig::<_, 3>::open(TestFlash::new(), 0, 1024).unwrap();
+
+        tested.write(ConfigEntry::SlaveVersion { port: 2, version: Version::V3 }).unwrap();
+        tested.write(ConfigEntry::SlaveBaudRate { port: 1, baud_rate }).unwrap();
+        tested.write(ConfigEntry::StateFixSettings { port: 0, settings: settings.clone() }).unwrap();
+        tested.write(ConfigEntry::RelayName { port: 1, relay_index: 15, name: name("Кухня") }).unwrap();
+        tested.write(ConfigEntry::RtcBaseDate { unix_seconds }).unwrap();
+        tested.write(ConfigEntry::Rule { index: 3, rule: rule(rng.gen()).serialize() }).unwrap();
+        tested.write(ConfigEntry::Schedule { index: 0, schedule: schedule(rng.gen()).serialize() }).unwrap();
+        let written = tested.config().clone();
+        let tested = PersistentConfig::<_, 3>::open(tested.release(), 0, 1024).unwrap();
+
+        let config = tested.config();
+        assert_eq!(&written, config);
+        assert_eq!(Version::V3, config.slave(2).unwrap().version());
+        assert_eq!(Version::V1, config.slave(1).unwrap().version());
+        assert_eq!(baud_rate, config.slave(1).unwrap().baud_rate());
+        assert_eq!(DEFAULT_BAUD_RATE, config.slave(0).unwrap().baud_rate());
+        assert_eq!(Some(&settings), config.slave(0).unwrap().state_fix_settings());
+        assert_eq!(Some("Кухня"), config.slave(1).unwrap().relay_name(15));
+        assert_eq!(Some(""), config.slave(1).unwrap().relay_name(0));
+        assert_eq!(unix_seconds, config.rtc_base_date());
+        assert_eq!(1, config.rules().count());
+        assert_eq!(1, config.schedules().count());
+    }
+
+    #[test]
+    fn test_invalid_entries_are_rejected() {
+        let mut tested = PersistentConfig::<_, 2>::open(TestFlash::new(), 0, 1024).unwrap();
+        let mut not_utf8 = name("relay");
+        not_utf8[0] = 0xc3;
+        not_utf8[1] = 0x28;
+
+        assert_eq!(Err(Errors::OutOfRange), tested.write(ConfigEntry::SlaveVersion { port: 2, version: Version::V2 }));
+        assert_eq!(Err(Errors::OutOfRange), tested.write(ConfigEntry::SlaveBaudRate { port: 0, baud_rate: 0 }));
+        assert_eq!(Err(Errors::RelayIndexOutOfRange), tested.write(ConfigEntry::RelayName {
+            port: 0, relay_index: MAX_RELAYS_COUNT, name: name("relay") }));
+        assert_eq!(Err(Errors::DataCorrupted), tested.write(ConfigEntry::RelayName {
+            port: 0, relay_index: 0, name: not_utf8 }));
+        assert_eq!(Err(Errors::OutOfRange), tested.write(ConfigEntry::Rule {
+            index: MAX_RULES as u8, rule: rule(1).serialize() }));
+        assert_eq!(Err(Errors::InstructionNotRecognized(0x01)), tested.write(ConfigEntry::Rule {
+            index: 0, rule: [0x01; RULE_SIZE] }));
+        assert_eq!(Err(Errors::OutOfRange), tested.write(ConfigEntry::Schedule {
+            index: MAX_SCHEDULES as u8, schedule: schedule(1).serialize() }));
+        assert_eq!(Err(Errors::OutOfRange), tested.write(ConfigEntry::Schedule {
+            index: 0, schedule: [0x01; SCHEDULE_SIZE] }));
+        assert_eq!(&HubConfig::new(), tested.config());
+    }
+
+    #[test]
+    fn test_entries_cover_config() {
+        let mut config = HubConfig::<2>::new();
+        let entries = [
+            ConfigEntry::SlaveBaudRate { port: 1, baud_rate: 115_200 },
+            ConfigEntry::StateFixSettings { port: 1, settings: StateFixSettings::new(100, 3, 5, 20) },
+            ConfigEntry::RelayName { port: 0, relay_index: 4, name: name("pump") },
+            ConfigEntry::Rule { index: 5, rule: rule(7).serialize() },
+            ConfigEntry::Schedule { index: 2, schedule: schedule(7).serialize() },
+        ];
+        for entry in entries.iter().cloned() {
+            config.apply(entry).unwrap();
+        }
+
+        let listed: Vec<ConfigEntry> = config.entries().collect();
+
+        assert_eq!(9, listed.len());
+        assert_eq!(ConfigEntry::RtcBaseDate { unix_seconds: DEFAULT_RTC_BASE_DATE }, listed[0]);
+        let mut restored = HubConfig::<2>::new();
+        for entry in listed.iter().cloned() {
+            assert!(listed.iter().filter(|other| other.key() == entry.key()).count() == 1);
+            restored.apply(entry).unwrap();
+        }
+        assert_eq!(config, restored);
+    }
+
+    #[test]
+    fn test_largest_entry_fits_store_value() {
+        let entries = [
+            ConfigEntry::RelayName { port: u8::MAX, relay_index: u8::MAX, name: [0xff; RELAY_NAME_SIZE] },
+            ConfigEntry::StateFixSettings { port: u8::MAX, settings: StateFixSettings::new(u16::MAX, u8::MAX, u8::MAX, u16::MAX) },
+            ConfigEntry::RtcBaseDate { unix_seconds: i64::MIN },
+            ConfigEntry::Rule { index: u8::MAX, rule: [0xff; RULE_SIZE] },
+            ConfigEntry::Schedule { index: u8::MAX, schedule: [0xff; SCHEDULE_SIZE] },
+        ];
+        for entry in entries {
+            let mut buffer = [0; MAX_VALUE_SIZE];
+            assert!(postcard::to_slice(&entry, &mut buffer).is_ok());
+        }
+    }
+
+    fn rule(slave_id: u32) -> Rule {
+        Rule::new(RuleTrigger::new(slave_id, 3, Signals::RelayStateChanged, true),
+                  RuleAction::new(slave_id, 1, DataInstructionCodes::RelaySwitchedOn, false, 2000)).unwrap()
+    }
+
+    fn schedule(slave_id: u32) -> Schedule {
+        Schedule::new(slave_id, 2, EVERY_DAY, 18 * 60, 23 * 60).unwrap()
+    }
+
+    fn name(text: &str) -> RelayName {
+        let mut name = [0; RELAY_NAME_SIZE];
+        name[..text.len()].copy_from_slice(text.as_bytes());
+        name
+    }
+}
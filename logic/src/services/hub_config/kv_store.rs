@@ -0,0 +1,416 @@
+#![deny(unsafe_code)]
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::LinearMap;
+use crate::errors::Errors;
+use crate::services::slave_controller_link::framing::crc16;
+use crate::utils::flash_pages::{aligned, flash_error, is_after, FlashPages, PAGE_HEADER_SIZE};
+
+pub const MAX_VALUE_SIZE: usize = 64;
+/** Count of the different keys the store keeps, the values of the others are lost on the compaction. */
+pub const MAX_KEYS: usize = 96;
+pub use crate::utils::flash_pages::MAX_WRITE_SIZE;
+
+const PAGE_MAGIC: [u8; 2] = [0x48, 0x43];
+const RECORD_HEADER_SIZE: usize = 3;
+const RECORD_CRC_SIZE: usize = 2;
+const RECORD_BUFFER_SIZE: usize = 80;
+const ERASED_KEY: u16 = 0xffff;
+const PAGES_COUNT: u32 = 2;
+
+/**
+Key-value store over two pages of a NOR flash. The records are appended to the active page, the last
+record of a key holds its value. When the page is full, the last records of the keys are copied to the
+other page, which becomes active as soon as its header is written, so the erasing is spread over both
+pages and the power loss leaves at least one of them valid.
+
+The pages start with the `FlashPages` header, the active page has the greater sequence. The record is the key (LE u16), the value length, the value and
+CRC-16 of all that (BE), padded by the erased bytes up to the write size. The erased key ends the records.
+ */
+pub struct KvStore<F: NorFlash> {
+    flash: F,
+    pages: FlashPages,
+    active_page: u32,
+    sequence: u32,
+    write_offset: u32,
+}
+
+#[derive(Copy, Clone)]
+struct ScanEnd {
+    offset: u32,
+    corrupted: bool,
+}
+
+impl <F: NorFlash> KvStore<F> {
+
+    /**
+    Opens the store in `PAGES_COUNT` pages starting at the offset. The pages of another format version
+    are ignored, so the store is empty after the version change. A record torn by the power loss is
+    dropped by the compaction.
+     */
+    pub fn open(flash: F, offset: u32, page_size: u32, format_version: u8) -> Result<Self, Errors> {
+        if F::WRITE_SIZE > MAX_WRITE_SIZE || !offset.is_multiple_of(F::ERASE_SIZE as u32)
+            || !page_size.is_multiple_of(F::ERASE_SIZE as u32)
+            || (offset + PAGES_COUNT * page_size) as usize > flash.capacity()
+            || (page_size as usize) < aligned::<F>(PAGE_HEADER_SIZE) + RECORD_BUFFER_SIZE {
+            return Err(Errors::OutOfRange);
+        }
+        let mut store = Self {
+            flash,
+            pages: FlashPages::new(offset, page_size, PAGE_MAGIC, format_version),
+            active_page: 0,
+            sequence: 0,
+            write_offset: 0,
+        };
+        let sequences = [store.page_sequence(0)?, store.page_sequence(1)?];
+        match sequences {
+            [Some(first), Some(second)] if is_after(second, first) => store.activate(1, second),
+            [Some(first), _] => store.activate(0, first),
+            [None, Some(second)] => store.activate(1, second),
+            [None, None] => {
+                store.erase_page(0)?;
+                store.write_page_header(0, 0)?;
+                store.activate(0, 0);
+            }
+        }
+        let end = store.scan(|_, _, _| {})?;
+        store.write_offset = end.offset;
+        if end.corrupted {
+            store.compact()?;
+        }
+        Ok(store)
+    }
+
+    #[inline(always)]
+    pub fn active_page(&self) -> u32 {
+        self.active_page
+    }
+
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /** Reads the value of the key to the buffer, returns its length, if it is stored. */
+    pub fn read(&mut self, key: u16, value: &mut [u8]) -> Result<Option<usize>, Errors> {
+        let mut found = None;
+        self.scan(|record_key, record_offset, _| {
+            if record_key == key {
+                found = Some(record_offset);
+            }
+        })?;
+        match found {
+            Some(record_offset) => {
+                let mut buffer = [0; RECORD_BUFFER_SIZE];
+                let record_value = self.read_record(record_offset, &mut buffer)?;
+                let target = value.get_mut(..record_value.len()).ok_or(Errors::DataOverflow)?;
+                target.copy_from_slice(record_value);
+                Ok(Some(record_value.len()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /** Calls `f` with the keys and their values, each key once. */
+    pub fn for_each<FN: FnMut(u16, &[u8])>(&mut self, mut f: FN) -> Result<(), Errors> {
+        let last_records = self.last_records()?;
+        let mut buffer = [0; RECORD_BUFFER_SIZE];
+        for (key, record_offset) in last_records.iter() {
+            f(*key, self.read_record(*record_offset, &mut buffer)?);
+        }
+        Ok(())
+    }
+
+    /** Stores the value of the key, unless it is stored already. Compacts the records, if the page is full. */
+    pub fn write(&mut self, key: u16, value: &[u8]) -> Result<(), Errors> {
+        if key == ERASED_KEY {
+            return Err(Errors::OutOfRange);
+        }
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(Errors::DataOverflow);
+        }
+        let mut stored = [0; MAX_VALUE_SIZE];
+        if let Some(len) = self.read(key, &mut stored)? {
+            if &stored[..len] == value {
+                return Ok(());
+            }
+        }
+
+        let mut buffer = [0xff; RECORD_BUFFER_SIZE];
+        buffer[..2].copy_from_slice(&key.to_le_bytes());
+        buffer[2] = value.len() as u8;
+        let crc_offset = RECORD_HEADER_SIZE + value.len();
+        buffer[RECORD_HEADER_SIZE..crc_offset].copy_from_slice(value);
+        let crc = crc16(&buffer[..crc_offset]);
+        buffer[crc_offset..crc_offset + RECORD_CRC_SIZE].copy_from_slice(&crc.to_be_bytes());
+        let size = aligned::<F>(crc_offset + RECORD_CRC_SIZE) as u32;
+
+        if self.write_offset + size > self.page_size() {
+            self.compact()?;
+            if self.write_offset + size > self.page_size() {
+                return Err(Errors::ConfigStoreFull);
+            }
+        }
+        let address = self.page_address(self.active_page) + self.write_offset;
+        match self.flash.write(address, &buffer[..size as usize]) {
+            Ok(()) => {
+                self.write_offset += size;
+                Ok(())
+            }
+            Err(error) => {
+                // the rest of the page is unknown, the next write moves the records to the other page
+                self.write_offset = self.page_size();
+                Err(flash_error(error))
+            }
+        }
+    }
+
+    fn activate(&mut self, page: u32, sequence: u32) {
+        self.active_page = page;
+        self.sequence = sequence;
+    }
+
+    /** Copies the last records of the keys to the other page and makes it active. */
+    fn compact(&mut self) -> Result<(), Errors> {
+        let last_records = self.last_records()?;
+        let target_page = (self.active_page + 1) % PAGES_COUNT;
+        self.erase_page(target_page)?;
+        let mut target_offset = aligned::<F>(PAGE_HEADER_SIZE) as u32;
+        let mut buffer = [0; RECORD_BUFFER_SIZE];
+        for record_offset in last_records.values() {
+            let size = aligned::<F>(self.read_record(*record_offset, &mut buffer)?.len()
+                + RECORD_HEADER_SIZE + RECORD_CRC_SIZE);
+            self.flash.read(self.page_address(self.active_page) + record_offset, &mut buffer[..size])
+                .map_err(flash_error)?;
+            self.flash.write(self.page_address(target_page) + target_offset, &buffer[..size])
+                .map_err(flash_error)?;
+            target_offset += size as u32;
+        }
+        let sequence = self.sequence.wrapping_add(1);
+        self.write_page_header(target_page, sequence)?;
+        self.activate(target_page, sequence);
+        self.write_offset = target_offset;
+        Ok(())
+    }
+
+    fn last_records(&mut self) -> Result<LinearMap<u16, u32, MAX_KEYS>, Errors> {
+        let mut last_records = LinearMap::new();
+        let mut result = Ok(());
+        self.scan(|key, record_offset, _| {
+            if last_records.insert(key, record_offset).is_err() {
+                result = Err(Errors::ConfigStoreFull);
+            }
+        })?;
+        result.map(|()| last_records)
+    }
+
+    /**
+    Calls `f` with the key, offset and value length of the valid records of the active page. Returns
+    the offset after them and whether they are followed by a corrupted record.
+     */
+    fn scan<FN: FnMut(u16, u32, usize)>(&mut self, mut f: FN) -> Result<ScanEnd, Errors> {
+        let mut offset = aligned::<F>(PAGE_HEADER_SIZE) as u32;
+        let mut buffer = [0; RECORD_BUFFER_SIZE];
+        while offset + (RECORD_HEADER_SIZE as u32) <= self.page_size() {
+            let mut header = [0; RECORD_HEADER_SIZE];
+            self.flash.read(self.page_address(self.active_page) + offset, &mut header).map_err(flash_error)?;
+            if u16::from_le_bytes([header[0], header[1]]) == ERASED_KEY {
+                return Ok(ScanEnd { offset, corrupted: false });
+            }
+            let value_len = match self.read_record(offset, &mut buffer) {
+                Ok(value) => value.len(),
+                Err(Errors::DataCorrupted) => return Ok(ScanEnd { offset, corrupted: true }),
+                Err(error) => return Err(error),
+            };
+            f(u16::from_le_bytes([header[0], header[1]]), offset, value_len);
+            offset += aligned::<F>(RECORD_HEADER_SIZE + value_len + RECORD_CRC_SIZE) as u32;
+        }
+        Ok(ScanEnd { offset: self.page_size(), corrupted: false })
+    }
+
+    /** Reads the record of the active page at the offset to the buffer, returns its value. */
+    fn read_record<'b>(&mut self, offset: u32, buffer: &'b mut [u8; RECORD_BUFFER_SIZE]) -> Result<&'b [u8], Errors> {
+        let address = self.page_address(self.active_page) + offset;
+        self.flash.read(address, &mut buffer[..RECORD_HEADER_SIZE]).map_err(flash_error)?;
+        let value_len = buffer[2] as usize;
+        let crc_offset = RECORD_HEADER_SIZE + value_len;
+        if value_len > MAX_VALUE_SIZE || offset as usize + crc_offset + RECORD_CRC_SIZE > self.page_size() as usize {
+            return Err(Errors::DataCorrupted);
+        }
+        self.flash.read(address + RECORD_HEADER_SIZE as u32, &mut buffer[RECORD_HEADER_SIZE..crc_offset + RECORD_CRC_SIZE])
+            .map_err(flash_error)?;
+        let crc = u16::from_be_bytes([buffer[crc_offset], buffer[crc_offset + 1]]);
+        if crc16(&buffer[..crc_offset]) != crc {
+            return Err(Errors::DataCorrupted);
+        }
+        Ok(&buffer[RECORD_HEADER_SIZE..crc_offset])
+    }
+
+    fn page_sequence(&mut self, page: u32) -> Result<Option<u32>, Errors> {
+        self.pages.sequence(&mut self.flash, page)
+    }
+
+    fn write_page_header(&mut self, page: u32, sequence: u32) -> Result<(), Errors> {
+        self.pages.write_header(&mut self.flash, page, sequence)
+    }
+
+    fn erase_page(&mut self, page: u32) -> Result<(), Errors> {
+        self.pages.erase(&mut self.flash, page)
+    }
+
+    #[inline(always)]
+    fn page_address(&self, page: u32) -> u32 {
+        self.pages.page_address(page)
+    }
+
+    #[inline(always)]
+    fn page_size(&self) -> u32 {
+        self.pages.page_size()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use core::convert::TryInto;
+    use crate::utils::ram_flash::{RamFlash, ERASED_BYTE};
+
+    const PAGE_SIZE: u32 = 256;
+    const VERSION: u8 = 3;
+
+    type TestFlash = RamFlash<{ 2 * PAGE_SIZE as usize }, 4, 128>;
+
+    #[test]
+    fn test_last_value_is_read_after_reopening() {
+        let mut rng = rand::thread_rng();
+        let first: [u8; 5] = rng.gen();
+        let second: [u8; 7] = rng.gen();
+        let mut tested = KvStore::open(TestFlash::new(), 0, PAGE_SIZE, VERSION).unwrap();
+
+        tested.write(0x0102, &first).unwrap();
+        tested.write(0x0103, &[]).unwrap();
+        tested.write(0x0102, &second).unwrap();
+        let mut tested = KvStore::open(tested.release(), 0, PAGE_SIZE, VERSION).unwrap();
+
+        let mut value = [0; MAX_VALUE_SIZE];
+        assert_eq!(Some(second.len()), tested.read(0x0102, &mut value).unwrap());
+        assert_eq!(second, value[..second.len()]);
+        assert_eq!(Some(0), tested.read(0x0103, &mut value).unwrap());
+        assert_eq!(None, tested.read(0x0104, &mut value).unwrap());
+        assert_eq!(Err(Errors::DataOverflow), tested.read(0x0102, &mut value[..2]));
+        assert_eq!(Err(Errors::OutOfRange), tested.write(ERASED_KEY, &first));
+        assert_eq!(Err(Errors::DataOverflow), tested.write(1, &[0; MAX_VALUE_SIZE + 1]));
+    }
+
+    #[test]
+    fn test_pages_alternate_on_compaction() {
+        let mut rng = rand::thread_rng();
+        let mut tested = KvStore::open(TestFlash::new(), 0, PAGE_SIZE, VERSION).unwrap();
+        let mut pages = Vec::new();
+        let mut last_values = [None; 4];
+
+        for i in 0..200u32 {
+            let key = rng.gen_range(0..last_values.len());
+            last_values[key] = Some(i);
+            tested.write(key as u16, &i.to_le_bytes()).unwrap();
+            if pages.last() != Some(&tested.active_page()) {
+                pages.push(tested.active_page());
+            }
+        }
+        let mut tested = KvStore::open(tested.release(), 0, PAGE_SIZE, VERSION).unwrap();
+
+        let mut read = Vec::new();
+        tested.for_each(|key, value| read.push((key, u32::from_le_bytes(value.try_into().unwrap())))).unwrap();
+        read.sort();
+        let expected: Vec<(u16, u32)> = last_values.iter().enumerate()
+            .filter_map(|(key, value)| value.map(|value| (key as u16, value)))
+            .collect();
+        assert_eq!(expected, read);
+        assert!(pages.len() > 10);
+        assert!(pages.windows(2).all(|pair| pair[0] != pair[1]));
+        // the first page is formatted on the opening, a page is two erase blocks
+        assert_eq!(2 * pages.len() as u32, tested.release().erase_count());
+    }
+
+    #[test]
+    fn test_same_value_is_not_written_again() {
+        let mut tested = KvStore::open(TestFlash::new(), 0, PAGE_SIZE, VERSION).unwrap();
+
+        tested.write(5, &[1, 2, 3]).unwrap();
+        let written = tested.release().memory().to_vec();
+        let mut tested = KvStore::open(TestFlash::new(), 0, PAGE_SIZE, VERSION).unwrap();
+        for _ in 0..100 {
+            tested.write(5, &[1, 2, 3]).unwrap();
+        }
+
+        assert_eq!(written, tested.release().memory());
+    }
+
+    #[test]
+    fn test_torn_write_keeps_previous_value() {
+        let mut rng = rand::thread_rng();
+        let mut tested = KvStore::open(TestFlash::new(), 0, PAGE_SIZE, VERSION).unwrap();
+        tested.write(1, &[1; 8]).unwrap();
+        tested.write(2, &[2; 8]).unwrap();
+        let mut flash = tested.release();
+        flash.power_off_after(rng.gen_range(1..10));
+        let mut tested = KvStore::open(flash, 0, PAGE_SIZE, VERSION).unwrap();
+
+        assert!(tested.write(1, &[3; 8]).is_err());
+        let mut flash = tested.release();
+        flash.power_on();
+        let mut tested = KvStore::open(flash, 0, PAGE_SIZE, VERSION).unwrap();
+
+        let mut value = [0; MAX_VALUE_SIZE];
+        assert_eq!(Some(8), tested.read(1, &mut value).unwrap());
+        assert_eq!([1; 8], value[..8]);
+        tested.write(3, &[4; 8]).unwrap();
+        assert_eq!(Some(8), tested.read(2, &mut value).unwrap());
+        assert_eq!(Some(8), tested.read(3, &mut value).unwrap());
+        // the torn record is dropped by the compaction on the opening
+        assert_eq!(1, tested.active_page());
+    }
+
+    #[test]
+    fn test_compaction_interrupted_keeps_active_page() {
+        let mut tested = KvStore::open(TestFlash::new(), 0, PAGE_SIZE, VERSION).unwrap();
+        // 10 records of 24 bytes fill the page
+        for i in 0..10u8 {
+            tested.write((i % 3) as u16, &[i; 16]).unwrap();
+        }
+        let mut flash = tested.release();
+        // the copying of the records to the second page is broken before its header is written
+        flash.power_off_after(20);
+        let mut tested = KvStore::open(flash, 0, PAGE_SIZE, VERSION).unwrap();
+        assert!(tested.write(0, &[0xaa; 16]).is_err());
+        let mut flash = tested.release();
+        flash.power_on();
+
+        let mut tested = KvStore::open(flash, 0, PAGE_SIZE, VERSION).unwrap();
+        let mut value = [0; MAX_VALUE_SIZE];
+        assert_eq!(0, tested.active_page());
+        assert_eq!(Some(16), tested.read(0, &mut value).unwrap());
+        assert_eq!([9; 16], value[..16]);
+    }
+
+    #[test]
+    fn test_other_format_version_is_ignored() {
+        let mut tested = KvStore::open(TestFlash::new(), 0, PAGE_SIZE, VERSION).unwrap();
+        tested.write(1, &[1]).unwrap();
+
+        let mut tested = KvStore::open(tested.release(), 0, PAGE_SIZE, VERSION + 1).unwrap();
+
+        assert_eq!(None, tested.read(1, &mut [0; MAX_VALUE_SIZE]).unwrap());
+        let flash = tested.release();
+        assert_eq!([ERASED_BYTE; 4], flash.memory()[8..12]);
+    }
+
+    #[test]
+    fn test_wrong_geometry_is_rejected() {
+        assert_eq!(Some(Errors::OutOfRange), KvStore::open(TestFlash::new(), 128, PAGE_SIZE, VERSION).err());
+        assert_eq!(Some(Errors::OutOfRange), KvStore::open(TestFlash::new(), 0, 200, VERSION).err());
+        assert_eq!(Some(Errors::OutOfRange), KvStore::open(RamFlash::<512, 32, 128>::new(), 0, PAGE_SIZE, VERSION).err());
+    }
+}
@@ -0,0 +1,738 @@
+#![deny(unsafe_code)]
+
+pub mod reconciliation;
+
+use heapless::spsc::{Consumer, Producer, Queue};
+use heapless::Vec;
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::RelativeMillis;
+use crate::services::relay_controller::reconciliation::{DesiredStates, ReconcilePolicy, Reconciliation, RelayAlarm};
+use crate::services::slave_controller_link::domain::{AllData, Conversation, DataInstructionCodes, DataInstructions,
+                                                     EmptyRequest, ErrorCode, Operation, RelaySingleState, State,
+                                                     MAX_RELAYS_COUNT};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest,
+                                                                  DEFAULT_REQUEST_TIMEOUT_MS};
+use crate::services::slave_controller_link::retry_controller::{DEFAULT_MAX_ATTEMPTS, DEFAULT_RETRY_BACKOFF_MS};
+use crate::services::slave_hub::SlaveHub;
+use crate::utils::logger::{self, Event};
+
+/** Size of the queues between the links and the controller, they hold one item less. */
+pub const RELAY_QUEUE_SIZE: usize = 5;
+/** Sent requests and the resolved ones, which answers are not taken yet. */
+pub const MAX_RELAY_REQUESTS: usize = 8;
+/**
+Time the link takes to resolve a request by the default timeout and retry policy, all the attempts and
+the backoffs between them. The requests not answered longer are resolved as timed out.
+ */
+pub const RELAY_ANSWER_TIMEOUT_MS: u32 = DEFAULT_REQUEST_TIMEOUT_MS * DEFAULT_MAX_ATTEMPTS as u32
+    + DEFAULT_RETRY_BACKOFF_MS * ((1 << (DEFAULT_MAX_ATTEMPTS - 1)) - 1);
+
+const SENT_QUEUE_SIZE: usize = MAX_RELAY_REQUESTS + 1;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RelayAnswer {
+    Done,
+    State(State),
+    AllData(AllData),
+    Error(ErrorCode),
+    Corrupted,
+    Timeout,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct RelayRequestKey {
+    id: Option<u32>,
+    operation: Operation,
+    instruction: DataInstructionCodes,
+}
+
+impl RelayRequestKey {
+    fn of(request: &SentRequest) -> Self {
+        Self { id: request.id(), operation: request.operation(), instruction: request.instruction() }
+    }
+}
+
+pub struct RelayLinkAnswer {
+    key: RelayRequestKey,
+    answer: RelayAnswer,
+}
+
+/**
+Queues between a link and the controller: the requests sent by the controller go to the forwarder of
+the link, the answers to them go back.
+ */
+pub struct RelayAnswersQueue {
+    sent: Queue<RelayRequestKey, SENT_QUEUE_SIZE>,
+    answers: Queue<RelayLinkAnswer, RELAY_QUEUE_SIZE>,
+}
+
+impl RelayAnswersQueue {
+
+    pub const fn new() -> Self {
+        Self { sent: Queue::new(), answers: Queue::new() }
+    }
+
+    /** The first end is for the `RelayResponseForwarder`, the second one is for the `RelayController`. */
+    pub fn split(&mut self) -> (RelayForwarderEnd<'_>, RelayControllerEnd<'_>) {
+        let (sent_tx, sent_rx) = self.sent.split();
+        let (answers_tx, answers_rx) = self.answers.split();
+        (RelayForwarderEnd { sent: sent_rx, answers: answers_tx }, RelayControllerEnd { sent: sent_tx, answers: answers_rx })
+    }
+}
+
+impl Default for RelayAnswersQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct RelayForwarderEnd<'a> {
+    sent: Consumer<'a, RelayRequestKey, SENT_QUEUE_SIZE>,
+    answers: Producer<'a, RelayLinkAnswer, RELAY_QUEUE_SIZE>,
+}
+
+pub struct RelayControllerEnd<'a> {
+    sent: Producer<'a, RelayRequestKey, SENT_QUEUE_SIZE>,
+    answers: Consumer<'a, RelayLinkAnswer, RELAY_QUEUE_SIZE>,
+}
+
+/**
+Response handler of a link passing the answers to the requests of the `RelayController` back to it, the
+answers to the other requests of the link, e.g. the resync reads of the hub or the host requests, are
+not passed. The other handler of the link gets all of them. The answers are lost while the queue is full.
+ */
+pub struct RelayResponseForwarder<'a, RH: ResponseHandler> {
+    response_handler: RH,
+    channel: RelayForwarderEnd<'a>,
+    expected: Vec<RelayRequestKey, MAX_RELAY_REQUESTS>,
+}
+
+impl <'a, RH: ResponseHandler> RelayResponseForwarder<'a, RH> {
+
+    pub fn new(response_handler: RH, channel: RelayForwarderEnd<'a>) -> Self {
+        Self { response_handler, channel, expected: Vec::new() }
+    }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+
+    /** The oldest expected request is forgotten when there is no place for the sent one. */
+    fn forward(&mut self, request: &SentRequest, answer: RelayAnswer) {
+        while let Some(key) = self.channel.sent.dequeue() {
+            if self.expected.is_full() {
+                self.expected.remove(0);
+            }
+            let _ = self.expected.push(key);
+        }
+        let key = RelayRequestKey::of(request);
+        if let Some(position) = self.expected.iter().position(|expected| *expected == key) {
+            self.expected.remove(position);
+            let _ = self.channel.answers.enqueue(RelayLinkAnswer { key, answer });
+        }
+    }
+}
+
+impl <RH: ResponseHandler> ResponseHandler for RelayResponseForwarder<'_, RH> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        self.forward(&request, RelayAnswer::Done);
+        self.response_handler.on_request_success(request);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        let answer = match &response {
+            DataInstructions::State(conversation) => conversation.data().cloned().map(RelayAnswer::State),
+            DataInstructions::All(conversation) => conversation.data().cloned().map(RelayAnswer::AllData),
+            _ => None,
+        };
+        if let Some(answer) = answer {
+            self.forward(&request, answer);
+        }
+        self.response_handler.on_request_response(request, response);
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        self.forward(&request, RelayAnswer::Error(error_code));
+        self.response_handler.on_request_error(request, error_code);
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+        if let Some(request) = &request {
+            self.forward(request, RelayAnswer::Corrupted);
+        }
+        self.response_handler.on_request_parse_error(request, error, data);
+    }
+
+    fn on_request_search_error(&mut self, response: ResponseData, error: Errors) {
+        self.response_handler.on_request_search_error(response, error);
+    }
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        self.forward(&request, RelayAnswer::Timeout);
+        self.response_handler.on_request_timeout(request);
+    }
+}
+
+/** Request sent by the `RelayController`, its answer is got by `RelayController::take`. */
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RelayHandle(u16);
+
+struct TrackedRequest {
+    handle: RelayHandle,
+    port: usize,
+    key: RelayRequestKey,
+    sent_at: RelativeMillis,
+    answer: Option<RelayAnswer>,
+}
+
+/**
+Relay control of the hub slaves, addressed by the slave id. The requests are checked before sending:
+the relay index should be less than `MAX_RELAYS_COUNT` and the relays count reported by the slave,
+while the slave did not report it, only the first check is done. The answers come through the
+`RelayResponseForwarder` of each link, they are matched to the requests by the port, the request id
+and the instruction, in the order of sending. The requests left without the answer for
+`RELAY_ANSWER_TIMEOUT_MS`, e.g. when it was lost on the full queue, are resolved as timed out. The states sent by `switch_on` and `switch_off` are
+kept as the desired ones, `reconcile` switches the relays found in other states again.
+ */
+pub struct RelayController<'a, const N: usize> {
+    channels: [RelayControllerEnd<'a>; N],
+    requests: Vec<TrackedRequest, MAX_RELAY_REQUESTS>,
+    next_handle: u16,
+    desired: DesiredStates,
+}
+
+impl <'a, const N: usize> RelayController<'a, N> {
+
+    /** The queue of each port should be the one, the forwarder of the link on the port is built with. */
+    pub fn new(channels: [RelayControllerEnd<'a>; N]) -> Self {
+        Self {
+            channels,
+            requests: Vec::new(),
+            next_handle: 0,
+            desired: DesiredStates::new(ReconcilePolicy::default()),
+        }
+    }
+
+    #[inline(always)]
+    pub fn reconcile_policy(&mut self) -> &mut ReconcilePolicy {
+        self.desired.policy()
+    }
+
+    /** Requests waiting for the answer. */
+    pub fn pending_count(&self) -> usize {
+        self.requests.iter().filter(|request| request.answer.is_none()).count()
+    }
+
+    pub fn switch_on(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, relay_index: u8,
+                     now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        self.switch(hub, slave_id, relay_index, true, now)
+    }
+
+    pub fn switch_off(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, relay_index: u8,
+                      now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        self.switch(hub, slave_id, relay_index, false, now)
+    }
+
+    /** The slave keeps the relay disabled till its restart or `enable`, its desired state is forgotten. */
+    pub fn disable_temporarily(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, relay_index: u8,
+                               now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        let handle = self.set_relay(hub, slave_id, relay_index, now, DataInstructions::RelayDisabledTemp, true)?;
+        self.desired.forget(slave_id, relay_index);
+        Ok(handle)
+    }
+
+    pub fn enable(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, relay_index: u8,
+                  now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        self.set_relay(hub, slave_id, relay_index, now, DataInstructions::RelayDisabledTemp, false)
+    }
+
+    pub fn set_monitoring(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, relay_index: u8, on: bool,
+                          now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        self.set_relay(hub, slave_id, relay_index, now, DataInstructions::RelayMonitorOn, on)
+    }
+
+    pub fn set_control(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, relay_index: u8, on: bool,
+                       now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        self.set_relay(hub, slave_id, relay_index, now, DataInstructions::RelayControlOn, on)
+    }
+
+    pub fn read_all(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32,
+                    now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        self.send(hub, slave_id, Operation::Read, DataInstructions::All(Conversation::Request(EmptyRequest::new())), now)
+    }
+
+    pub fn read_state(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32,
+                      now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        self.send(hub, slave_id, Operation::Read, DataInstructions::State(Conversation::Request(EmptyRequest::new())), now)
+    }
+
+    /** Resolves the requests by the answers passed by the links since the last call and the timed out ones. */
+    pub fn poll(&mut self, now: RelativeMillis) {
+        for port in 0..N {
+            while let Some(answer) = self.channels[port].answers.dequeue() {
+                let request = self.requests.iter_mut()
+                    .find(|request| request.answer.is_none() && request.port == port && request.key == answer.key);
+                if let Some(request) = request {
+                    request.answer = Some(answer.answer);
+                }
+            }
+        }
+        self.resolve_timed_out(now);
+    }
+
+    /** The state sent last by `switch_on` or `switch_off`, `None` if it is not followed. */
+    pub fn desired_state(&self, slave_id: u32, relay_index: u8) -> Option<bool> {
+        self.desired.desired(slave_id, relay_index)
+    }
+
+    /**
+    Compares the desired relay states with the state mirrors of the slaves, reads the states not
+    updated since the last switch and switches the relays found in other states again. The answers
+    of these requests are not taken, they are dropped for the new requests.
+     */
+    pub fn reconcile(&mut self, hub: &mut SlaveHub<'_, N>, now: RelativeMillis) {
+        for position in 0..self.desired.len() {
+            let slave_id = match self.desired.slave_id(position) {
+                Some(slave_id) => slave_id,
+                None => break,
+            };
+            let reconciliation = match hub.state_mirror(slave_id) {
+                Some(mirror) => self.desired.check(position, mirror, now),
+                None => continue,
+            };
+            let result = match reconciliation {
+                Reconciliation::Wait => continue,
+                Reconciliation::ReadState => self.read_state(hub, slave_id, now)
+                    .map(|_| self.desired.on_read(slave_id, now)),
+                Reconciliation::Switch { relay_index, on } =>
+                    self.set_relay(hub, slave_id, relay_index, now, DataInstructions::RelaySwitchedOn, on)
+                        .map(|_| self.desired.on_switched(position, now)),
+            };
+            if let Err(error) = result {
+                logger::log(Event::ReconcileActionFailed(error));
+            }
+        }
+    }
+
+    /** Relays left in the states other than the desired ones, e.g. to be recorded to the journal. */
+    pub fn take_alarm(&mut self) -> Option<RelayAlarm> {
+        self.desired.take_alarm()
+    }
+
+    /**
+    Takes the answer of the resolved request, `None` while it is waiting. The handle is not valid after
+    the answer is taken, as well as after its answer is dropped for a new request.
+     */
+    pub fn take(&mut self, handle: RelayHandle) -> Result<Option<RelayAnswer>, Errors> {
+        let position = self.requests.iter().position(|request| request.handle == handle)
+            .ok_or(Errors::NoRequestsFound)?;
+        if self.requests[position].answer.is_none() {
+            return Ok(None);
+        }
+        Ok(self.requests.remove(position).answer)
+    }
+
+    fn switch(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, relay_index: u8, on: bool,
+              now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        let handle = self.set_relay(hub, slave_id, relay_index, now, DataInstructions::RelaySwitchedOn, on)?;
+        self.desired.record(slave_id, relay_index, on, now);
+        Ok(handle)
+    }
+
+    fn set_relay<F>(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, relay_index: u8, now: RelativeMillis,
+                    instruction: F, on: bool) -> Result<RelayHandle, Errors>
+        where F: FnOnce(Conversation<'static, EmptyRequest, RelaySingleState>) -> DataInstructions<'static>
+    {
+        if relay_index >= MAX_RELAYS_COUNT {
+            return Err(Errors::RelayIndexOutOfRange);
+        }
+        let relays_count = hub.state_mirror(slave_id).ok_or(Errors::SlaveNotFound(slave_id))?.relays_count();
+        if relays_count > 0 && relay_index >= relays_count {
+            return Err(Errors::RelayIndexOutOfRange);
+        }
+        let instruction = instruction(Conversation::Data(RelaySingleState::new(relay_index, on)));
+        self.send(hub, slave_id, Operation::Set, instruction, now)
+    }
+
+    fn send(&mut self, hub: &mut SlaveHub<'_, N>, slave_id: u32, operation: Operation, instruction: DataInstructions,
+            now: RelativeMillis) -> Result<RelayHandle, Errors> {
+        let port = hub.port(slave_id).ok_or(Errors::SlaveNotFound(slave_id))?;
+        self.free_slot(now)?;
+        let instruction_code = instruction.code();
+        let id = hub.send_request(slave_id, operation, instruction, now)?;
+        let key = RelayRequestKey { id, operation, instruction: instruction_code };
+        let _ = self.channels[port].sent.enqueue(key);
+        let handle = RelayHandle(self.next_handle);
+        self.next_handle = self.next_handle.wrapping_add(1);
+        let _ = self.requests.push(TrackedRequest {
+            handle,
+            port,
+            key,
+            sent_at: now,
+            answer: None,
+        });
+        Ok(handle)
+    }
+
+    fn resolve_timed_out(&mut self, now: RelativeMillis) {
+        for request in self.requests.iter_mut().filter(|request| request.answer.is_none()) {
+            if now.value().wrapping_sub(request.sent_at.value()) >= RELAY_ANSWER_TIMEOUT_MS {
+                request.answer = Some(RelayAnswer::Timeout);
+            }
+        }
+    }
+
+    /**
+    The oldest answer, which was not taken, is dropped if there is no place for the request, the timed out
+    requests are resolved before.
+     */
+    fn free_slot(&mut self, now: RelativeMillis) -> Result<(), Errors> {
+        if !self.requests.is_full() {
+            return Ok(());
+        }
+        self.resolve_timed_out(now);
+        let position = self.requests.iter().position(|request| request.answer.is_some())
+            .ok_or(Errors::RequestsLimitReached)?;
+        self.requests.remove(position);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::relay_controller::reconciliation::{DivergenceCause, DEFAULT_SETTLE_TIME_MS};
+    use crate::services::slave_controller_link::domain::{SwitchCountingSettings, Version};
+    use crate::services::test_support::MockLink;
+    use crate::utils::BitsU64;
+
+    const SETTLE: u32 = DEFAULT_SETTLE_TIME_MS;
+
+    #[test]
+    fn test_switch_is_resolved_by_matching_answer() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let now = RelativeMillis::new(rng.next_u32());
+        let mut queues = [RelayAnswersQueue::new(), RelayAnswersQueue::new()];
+        let [queue0, queue1] = &mut queues;
+        let (_, answers0) = queue0.split();
+        let (answers1_tx, answers1) = queue1.split();
+        let mut forwarder = RelayResponseForwarder::new(MockResponseHandler::default(), answers1_tx);
+        let mut links = [link(None, 0), link(Some(id), 3)];
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut tested = RelayController::new([answers0, answers1]);
+
+        let on = tested.switch_on(&mut hub, id, 2, now).unwrap();
+        let off = tested.switch_off(&mut hub, id, 2, now).unwrap();
+        assert_eq!(Ok(None), tested.take(on));
+
+        // answers of the other requests are not taken for the relay ones
+        forwarder.on_request_success(SentRequest::new(Some(1), Operation::Set, DataInstructionCodes::Settings, now));
+        forwarder.on_request_timeout(SentRequest::new(Some(2), Operation::Set, DataInstructionCodes::RelaySwitchedOn, now));
+        forwarder.on_request_success(SentRequest::new(Some(1), Operation::Set, DataInstructionCodes::RelaySwitchedOn, now));
+        tested.poll(now);
+
+        assert_eq!(Ok(Some(RelayAnswer::Done)), tested.take(on));
+        assert_eq!(Err(Errors::NoRequestsFound), tested.take(on));
+        assert_eq!(Ok(Some(RelayAnswer::Timeout)), tested.take(off));
+        assert_eq!(3, forwarder.response_handler().calls);
+        assert_eq!(0, tested.pending_count());
+        assert_eq!(vec![
+            (Operation::Set, DataInstructionCodes::RelaySwitchedOn, Some((2, true))),
+            (Operation::Set, DataInstructionCodes::RelaySwitchedOn, Some((2, false))),
+        ], links[1].sent_relay_requests());
+    }
+
+    #[test]
+    fn test_requests_send_relay_instructions() {
+        let id = rand::thread_rng().next_u32();
+        let now = RelativeMillis::new(0);
+        let mut queue = RelayAnswersQueue::new();
+        let (_, answers) = queue.split();
+        let mut links = [link(Some(id), 0)];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut tested = RelayController::new([answers]);
+
+        tested.disable_temporarily(&mut hub, id, 1, now).unwrap();
+        tested.enable(&mut hub, id, 1, now).unwrap();
+        tested.set_monitoring(&mut hub, id, 2, true, now).unwrap();
+        tested.set_control(&mut hub, id, 3, false, now).unwrap();
+        tested.read_all(&mut hub, id, now).unwrap();
+        tested.read_state(&mut hub, id, now).unwrap();
+
+        assert_eq!(6, tested.pending_count());
+        assert_eq!(vec![
+            (Operation::Set, DataInstructionCodes::RelayDisabledTemp, Some((1, true))),
+            (Operation::Set, DataInstructionCodes::RelayDisabledTemp, Some((1, false))),
+            (Operation::Set, DataInstructionCodes::RelayMonitorOn, Some((2, true))),
+            (Operation::Set, DataInstructionCodes::RelayControlOn, Some((3, false))),
+            (Operation::Read, DataInstructionCodes::All, None),
+            (Operation::Read, DataInstructionCodes::State, None),
+        ], links[0].sent_relay_requests());
+    }
+
+    #[test]
+    fn test_read_is_resolved_with_data() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let now = RelativeMillis::new(rng.next_u32());
+        let mut queue = RelayAnswersQueue::new();
+        let (answers_tx, answers) = queue.split();
+        let mut forwarder = RelayResponseForwarder::new(MockResponseHandler::default(), answers_tx);
+        let mut links = [link(Some(id), 0)];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut tested = RelayController::new([answers]);
+
+        let all = tested.read_all(&mut hub, id, now).unwrap();
+        let state = tested.read_state(&mut hub, id, now).unwrap();
+        let failed_state = tested.read_state(&mut hub, id, now).unwrap();
+        let all_data = AllData::new(id, rng.gen());
+        let state_data = State::create(4, rng.gen()).unwrap();
+        forwarder.on_request_error(SentRequest::new(Some(3), Operation::Read, DataInstructionCodes::State, now),
+                                   ErrorCode::EInstructionUnrecognized);
+        forwarder.on_request_response(SentRequest::new(Some(2), Operation::Read, DataInstructionCodes::State, now),
+                                      DataInstructions::State(Conversation::Data(state_data.clone())));
+        forwarder.on_request_response(SentRequest::new(Some(1), Operation::Read, DataInstructionCodes::All, now),
+                                      DataInstructions::All(Conversation::Data(all_data.clone())));
+        tested.poll(now);
+
+        assert_eq!(Ok(Some(RelayAnswer::State(state_data))), tested.take(state));
+        assert_eq!(Ok(Some(RelayAnswer::AllData(all_data))), tested.take(all));
+        assert_eq!(Ok(Some(RelayAnswer::Error(ErrorCode::EInstructionUnrecognized))), tested.take(failed_state));
+        assert_eq!(3, forwarder.response_handler().calls);
+    }
+
+    #[test]
+    fn test_relay_index_is_checked() {
+        let id = rand::thread_rng().next_u32();
+        let now = RelativeMillis::new(0);
+        let mut queues = [RelayAnswersQueue::new(), RelayAnswersQueue::new()];
+        let [queue0, queue1] = &mut queues;
+        let mut links = [link(Some(id), 0), link(Some(id + 1), 3)];
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut tested = RelayController::new([queue0.split().1, queue1.split().1]);
+
+        assert_eq!(Err(Errors::RelayIndexOutOfRange), tested.switch_on(&mut hub, id, MAX_RELAYS_COUNT, now));
+        // the relays count is not known yet
+        assert!(tested.switch_on(&mut hub, id, MAX_RELAYS_COUNT - 1, now).is_ok());
+        assert_eq!(Err(Errors::RelayIndexOutOfRange), tested.set_control(&mut hub, id + 1, 3, true, now));
+        assert!(tested.set_control(&mut hub, id + 1, 2, true, now).is_ok());
+        assert_eq!(Err(Errors::SlaveNotFound(id + 2)), tested.switch_off(&mut hub, id + 2, 0, now));
+        assert_eq!(Err(Errors::SlaveNotFound(id + 2)), tested.read_state(&mut hub, id + 2, now));
+        assert_eq!(1, links[0].sent_relay_requests().len());
+        assert_eq!(1, links[1].sent_relay_requests().len());
+    }
+
+    #[test]
+    fn test_oldest_answer_is_dropped_when_full() {
+        let id = rand::thread_rng().next_u32();
+        let now = RelativeMillis::new(0);
+        let mut queue = RelayAnswersQueue::new();
+        let (answers_tx, answers) = queue.split();
+        let mut forwarder = RelayResponseForwarder::new(MockResponseHandler::default(), answers_tx);
+        let mut links = [link(Some(id), 0)];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut tested = RelayController::new([answers]);
+
+        let handles = (0..MAX_RELAY_REQUESTS)
+            .map(|_| tested.read_state(&mut hub, id, now).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(Err(Errors::RequestsLimitReached), tested.read_state(&mut hub, id, now));
+
+        for request_id in [3, 2] {
+            forwarder.on_request_timeout(SentRequest::new(Some(request_id), Operation::Read, DataInstructionCodes::State, now));
+        }
+        tested.poll(now);
+        let handle = tested.read_state(&mut hub, id, now).unwrap();
+
+        assert_eq!(Err(Errors::NoRequestsFound), tested.take(handles[1]));
+        assert_eq!(Ok(Some(RelayAnswer::Timeout)), tested.take(handles[2]));
+        assert_eq!(Ok(None), tested.take(handle));
+        assert_eq!(MAX_RELAY_REQUESTS - 1, tested.pending_count());
+    }
+
+    #[test]
+    fn test_requests_with_lost_answers_time_out() {
+        let id = rand::thread_rng().next_u32();
+        let mut queue = RelayAnswersQueue::new();
+        let (answers_tx, answers) = queue.split();
+        let mut forwarder = RelayResponseForwarder::new(MockResponseHandler::default(), answers_tx);
+        let mut links = [link(Some(id), 3)];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut tested = RelayController::new([answers]);
+        let state = State::create(3, 0x01).unwrap();
+
+        let handles = (0..MAX_RELAY_REQUESTS)
+            .map(|_| tested.read_state(&mut hub, id, at(0)).unwrap())
+            .collect::<Vec<_>>();
+        // the answers are not polled in time, the queue holds 4 of them, the others are lost
+        for request_id in 1..=MAX_RELAY_REQUESTS as u32 {
+            forwarder.on_request_response(SentRequest::new(Some(request_id), Operation::Read, DataInstructionCodes::State, at(0)),
+                                          DataInstructions::State(Conversation::Data(state.clone())));
+        }
+        tested.poll(at(0));
+        for handle in &handles[..RELAY_QUEUE_SIZE - 1] {
+            assert_eq!(Ok(Some(RelayAnswer::State(state.clone()))), tested.take(*handle));
+        }
+        for _ in 0..RELAY_QUEUE_SIZE - 1 {
+            tested.read_state(&mut hub, id, at(1)).unwrap();
+        }
+        assert_eq!(Err(Errors::RequestsLimitReached), tested.switch_on(&mut hub, id, 1, at(RELAY_ANSWER_TIMEOUT_MS - 1)));
+
+        let on = tested.switch_on(&mut hub, id, 1, at(RELAY_ANSWER_TIMEOUT_MS)).unwrap();
+        forwarder.on_request_success(SentRequest::new(Some(13), Operation::Set, DataInstructionCodes::RelaySwitchedOn,
+                                                      at(RELAY_ANSWER_TIMEOUT_MS)));
+        tested.poll(at(RELAY_ANSWER_TIMEOUT_MS));
+
+        assert_eq!(Err(Errors::NoRequestsFound), tested.take(handles[4]));
+        assert_eq!(Ok(Some(RelayAnswer::Timeout)), tested.take(handles[5]));
+        assert_eq!(Ok(Some(RelayAnswer::Done)), tested.take(on));
+        assert_eq!(RELAY_QUEUE_SIZE - 1, tested.pending_count());
+    }
+
+    #[test]
+    fn test_answers_to_other_requests_are_not_forwarded() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let now = RelativeMillis::new(rng.next_u32());
+        let mut queue = RelayAnswersQueue::new();
+        let (answers_tx, answers) = queue.split();
+        let mut forwarder = RelayResponseForwarder::new(MockResponseHandler::default(), answers_tx);
+        let mut links = [link(Some(id), 3)];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut tested = RelayController::new([answers]);
+
+        let on = tested.switch_on(&mut hub, id, 0, now).unwrap();
+        // e.g. the state reads of the hub and the host requests to the slave
+        for request_id in 2..=RELAY_QUEUE_SIZE as u32 + 1 {
+            forwarder.on_request_response(SentRequest::new(Some(request_id), Operation::Read, DataInstructionCodes::State, now),
+                                          DataInstructions::State(Conversation::Data(State::create(3, rng.gen()).unwrap())));
+        }
+        forwarder.on_request_success(SentRequest::new(Some(1), Operation::Set, DataInstructionCodes::RelaySwitchedOn, now));
+        tested.poll(now);
+
+        assert_eq!(Ok(Some(RelayAnswer::Done)), tested.take(on));
+        assert_eq!(RELAY_QUEUE_SIZE + 1, forwarder.response_handler().calls);
+    }
+
+    #[test]
+    fn test_diverged_relay_is_switched_again_within_slave_limit() {
+        let id = rand::thread_rng().next_u32();
+        let mut queue = RelayAnswersQueue::new();
+        let (_, answers) = queue.split();
+        let mut links = [link(Some(id), 3).with_slave(0, false, SwitchCountingSettings::new(60, 2))];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut tested = RelayController::new([answers]);
+
+        tested.switch_on(&mut hub, id, 1, at(0)).unwrap();
+        // the state is read after the settle time, it is compared on the next call
+        for millis in [SETTLE - 1, SETTLE, SETTLE + 1, 2 * SETTLE + 1, 2 * SETTLE + 2, 3 * SETTLE] {
+            tested.reconcile(&mut hub, at(millis));
+        }
+        assert_eq!(Some(true), tested.desired_state(id, 1));
+        assert_eq!(Some(RelayAlarm::new(id, 1, true, DivergenceCause::SwitchLimit, at(2 * SETTLE + 2))),
+                   tested.take_alarm());
+
+        // the alarm is raised once, the switches are allowed again in the next slave interval
+        tested.reconcile(&mut hub, at(59_999));
+        assert_eq!(None, tested.take_alarm());
+        tested.reconcile(&mut hub, at(60_000));
+
+        let read = (Operation::Read, DataInstructionCodes::State, None);
+        let switch_on = (Operation::Set, DataInstructionCodes::RelaySwitchedOn, Some((1, true)));
+        assert_eq!(vec![switch_on, read, switch_on, read, switch_on], links[0].sent_relay_requests());
+    }
+
+    #[test]
+    fn test_relay_in_desired_state_is_left_and_disabled_one_raises_alarm() {
+        let id = rand::thread_rng().next_u32();
+        let mut queue = RelayAnswersQueue::new();
+        let (_, answers) = queue.split();
+        let mut links = [link(Some(id), 3).with_slave(0, true, SwitchCountingSettings::new(60, 0))];
+        let mut tested = RelayController::new([answers]);
+        {
+            let [link] = &mut links;
+            let mut hub = SlaveHub::new([link]);
+            tested.switch_on(&mut hub, id, 0, at(0)).unwrap();
+            tested.switch_off(&mut hub, id, 2, at(0)).unwrap();
+            for millis in [SETTLE, SETTLE + 1, 5 * SETTLE] {
+                tested.reconcile(&mut hub, at(millis));
+            }
+            assert_eq!(None, tested.take_alarm());
+        }
+        assert_eq!(3, links[0].sent_relay_requests().len());
+
+        // someone switched the relay 2 on and the slave disabled the relay 0
+        links[0].actual = Some(0x0102);
+        links[0].mirror.apply_response(&DataInstructions::State(Conversation::Data(
+            State { data: BitsU64::new(0x0102), count: 3 })), at(6 * SETTLE));
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        tested.reconcile(&mut hub, at(6 * SETTLE));
+        tested.reconcile(&mut hub, at(7 * SETTLE - 1));
+
+        assert_eq!(Some(RelayAlarm::new(id, 0, true, DivergenceCause::Disabled, at(6 * SETTLE))), tested.take_alarm());
+        assert_eq!(None, tested.take_alarm());
+        tested.disable_temporarily(&mut hub, id, 2, at(7 * SETTLE)).unwrap();
+        assert_eq!(None, tested.desired_state(id, 2));
+        assert_eq!(vec![
+            (Operation::Set, DataInstructionCodes::RelaySwitchedOn, Some((2, false))),
+            (Operation::Set, DataInstructionCodes::RelayDisabledTemp, Some((2, true))),
+        ], links[0].sent_relay_requests()[3..]);
+    }
+
+    /** The link of a V2 slave, so the requests get ids. */
+    fn link(id: Option<u32>, relays_count: u8) -> MockLink {
+        let link = MockLink::new(id).with_versions(&[Some(Version::V2)]);
+        if relays_count > 0 { link.with_relays(relays_count) } else { link }
+    }
+
+    fn at(millis: u32) -> RelativeMillis {
+        RelativeMillis::new(millis)
+    }
+
+    #[derive(Default)]
+    struct MockResponseHandler {
+        calls: usize,
+    }
+
+    impl ResponseHandler for MockResponseHandler {
+        fn on_request_success(&mut self, _: SentRequest) {
+            self.calls += 1;
+        }
+
+        fn on_request_response(&mut self, _: SentRequest, _: DataInstructions) {
+            self.calls += 1;
+        }
+
+        fn on_request_error(&mut self, _: SentRequest, _: ErrorCode) {
+            self.calls += 1;
+        }
+
+        fn on_request_parse_error(&mut self, _: Option<SentRequest>, _: Errors, _: &[u8]) {
+            self.calls += 1;
+        }
+
+        fn on_request_search_error(&mut self, _: ResponseData, _: Errors) {
+            self.calls += 1;
+        }
+
+        fn on_request_timeout(&mut self, _: SentRequest) {
+            self.calls += 1;
+        }
+    }
+}
@@ -0,0 +1,312 @@
+#![deny(unsafe_code)]
+
+use heapless::{Deque, Vec};
+use serde_derive::{Deserialize, Serialize};
+use crate::hal_ext::rtc_wrapper::RelativeMillis;
+use crate::services::slave_state_mirror::SlaveStateMirror;
+use crate::utils::logger::{self, Event};
+
+/** Relays, which requested states are followed, the oldest request is forgotten for a new relay. */
+pub const MAX_DESIRED_RELAYS: usize = 16;
+pub const DEFAULT_SETTLE_TIME_MS: u32 = 2000;
+pub const DEFAULT_MAX_SWITCHES: u8 = 3;
+pub const DEFAULT_SWITCH_INTERVAL_MS: u32 = 60_000;
+const MAX_PENDING_ALARMS: usize = 4;
+
+/** Why the relay is left in the state other than the requested one. */
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub enum DivergenceCause {
+    /** The relay was switched as many times, as the switch limit allows in its interval. */
+    SwitchLimit,
+    /** The slave disabled the relay, so it does not switch it, e.g. after its own limit was exceeded. */
+    Disabled,
+}
+
+/** The relay stays in the state other than the requested one, it is raised once till the states match. */
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RelayAlarm {
+    slave_id: u32,
+    relay_index: u8,
+    desired: bool,
+    cause: DivergenceCause,
+    at: RelativeMillis,
+}
+
+impl RelayAlarm {
+
+    pub fn new(slave_id: u32, relay_index: u8, desired: bool, cause: DivergenceCause, at: RelativeMillis) -> Self {
+        Self { slave_id, relay_index, desired, cause, at }
+    }
+
+    #[inline(always)]
+    pub fn slave_id(&self) -> u32 {
+        self.slave_id
+    }
+
+    #[inline(always)]
+    pub fn relay_index(&self) -> u8 {
+        self.relay_index
+    }
+
+    /** The requested state, `true` for on. */
+    #[inline(always)]
+    pub fn desired(&self) -> bool {
+        self.desired
+    }
+
+    #[inline(always)]
+    pub fn cause(&self) -> DivergenceCause {
+        self.cause
+    }
+
+    /** Hub time of the check, which found the divergence. */
+    #[inline(always)]
+    pub fn at(&self) -> RelativeMillis {
+        self.at
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ReconcilePolicy {
+    settle_time: u32,
+    max_switches: u8,
+    switch_interval: u32,
+}
+
+impl ReconcilePolicy {
+
+    /**
+    The relay state is compared with the mirror `settle_time` milliseconds after the switch, the
+    state is read if the mirror was not updated since then. The relay is switched at most
+    `max_switches` times in `switch_interval` milliseconds, while the slave switch limit is not
+    known or it is turned off, otherwise the limit of the slave is used.
+     */
+    pub const fn new(settle_time: u32, max_switches: u8, switch_interval: u32) -> Self {
+        Self {
+            settle_time,
+            max_switches,
+            switch_interval,
+        }
+    }
+
+    #[inline(always)]
+    pub fn settle_time(&self) -> u32 {
+        self.settle_time
+    }
+
+    #[inline(always)]
+    pub fn max_switches(&self) -> u8 {
+        self.max_switches
+    }
+
+    #[inline(always)]
+    pub fn switch_interval(&self) -> u32 {
+        self.switch_interval
+    }
+}
+
+impl Default for ReconcilePolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_SETTLE_TIME_MS, DEFAULT_MAX_SWITCHES, DEFAULT_SWITCH_INTERVAL_MS)
+    }
+}
+
+/** What should be sent to the slave to bring the relay to the requested state. */
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Reconciliation {
+    Wait,
+    ReadState,
+    Switch { relay_index: u8, on: bool },
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct DesiredRelay {
+    slave_id: u32,
+    relay_index: u8,
+    on: bool,
+    switched_at: RelativeMillis,
+    read_at: Option<RelativeMillis>,
+    switches: u8,
+    window_start: RelativeMillis,
+    alarmed: bool,
+}
+
+/**
+Requested relay states and the switches sent to reach them. `check` compares a state with the
+mirror of the slave and tells what to send, the caller reports the sent requests back with
+`on_switched` and `on_read`. The switches are counted against the slave switch limit, so the
+relay is not disabled by the repeated ones, when the limit is reached or the slave disabled the
+relay, the divergence is raised as an alarm.
+ */
+pub struct DesiredStates {
+    policy: ReconcilePolicy,
+    relays: Vec<DesiredRelay, MAX_DESIRED_RELAYS>,
+    alarms: Deque<RelayAlarm, MAX_PENDING_ALARMS>,
+}
+
+impl DesiredStates {
+
+    pub fn new(policy: ReconcilePolicy) -> Self {
+        Self {
+            policy,
+            relays: Vec::new(),
+            alarms: Deque::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn policy(&mut self) -> &mut ReconcilePolicy {
+        &mut self.policy
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.relays.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.relays.is_empty()
+    }
+
+    pub fn desired(&self, slave_id: u32, relay_index: u8) -> Option<bool> {
+        self.position(slave_id, relay_index).map(|position| self.relays[position].on)
+    }
+
+    /** The slave id of the state at the position, the positions change only by `record` and `forget`. */
+    pub fn slave_id(&self, position: usize) -> Option<u32> {
+        self.relays.get(position).map(|relay| relay.slave_id)
+    }
+
+    /** The state sent by the application, the switch is counted against the limit. */
+    pub fn record(&mut self, slave_id: u32, relay_index: u8, on: bool, now: RelativeMillis) {
+        let position = match self.position(slave_id, relay_index) {
+            Some(position) => position,
+            None => {
+                if self.relays.is_full() {
+                    self.relays.remove(0);
+                }
+                let _ = self.relays.push(DesiredRelay {
+                    slave_id,
+                    relay_index,
+                    on,
+                    switched_at: now,
+                    read_at: None,
+                    switches: 0,
+                    window_start: now,
+                    alarmed: false,
+                });
+                self.relays.len() - 1
+            }
+        };
+        let relay = &mut self.relays[position];
+        relay.on = on;
+        relay.alarmed = false;
+        self.on_switched(position, now);
+    }
+
+    /** The relay state is not followed any more, e.g. after it was disabled by the application. */
+    pub fn forget(&mut self, slave_id: u32, relay_index: u8) {
+        if let Some(position) = self.position(slave_id, relay_index) {
+            self.relays.remove(position);
+        }
+    }
+
+    pub fn check(&mut self, position: usize, mirror: &SlaveStateMirror, now: RelativeMillis) -> Reconciliation {
+        let policy = self.policy;
+        let relay = match self.relays.get_mut(position) {
+            Some(relay) => relay,
+            None => return Reconciliation::Wait,
+        };
+        if elapsed(relay.switched_at, now) < policy.settle_time {
+            return Reconciliation::Wait;
+        }
+        // the mirror update time is the send time of the request, so the one at the switch time could be older
+        let switched_at = relay.switched_at;
+        let mirrored = mirror.relay(relay.relay_index)
+            .filter(|mirrored| mirrored.updated_at().is_some_and(|at| is_before(switched_at, at)));
+        let mirrored = match mirrored {
+            Some(mirrored) => mirrored,
+            // the slave has no such relay, the switch was rejected
+            None if mirror.relays_count() > 0 && mirror.relay(relay.relay_index).is_none() =>
+                return Reconciliation::Wait,
+            None => {
+                let read_due = relay.read_at.is_none_or(|read_at| elapsed(read_at, now) >= policy.settle_time);
+                return if read_due { Reconciliation::ReadState } else { Reconciliation::Wait };
+            }
+        };
+        if mirrored.is_on() == relay.on {
+            relay.alarmed = false;
+            return Reconciliation::Wait;
+        }
+        if mirrored.is_disabled() {
+            let alarm = Self::raise(relay, DivergenceCause::Disabled, now);
+            self.push_alarm(alarm);
+            return Reconciliation::Wait;
+        }
+        let (max_switches, interval) = match mirror.switch_counting_settings() {
+            Some(settings) if settings.max_switch_count > 0 =>
+                (settings.max_switch_count, settings.switch_limit_interval_seconds() as u32 * 1000),
+            _ => (policy.max_switches, policy.switch_interval),
+        };
+        if elapsed(relay.window_start, now) >= interval {
+            relay.switches = 0;
+            relay.window_start = now;
+        }
+        if relay.switches < max_switches {
+            return Reconciliation::Switch { relay_index: relay.relay_index, on: relay.on };
+        }
+        let alarm = Self::raise(relay, DivergenceCause::SwitchLimit, now);
+        self.push_alarm(alarm);
+        Reconciliation::Wait
+    }
+
+    pub fn on_switched(&mut self, position: usize, now: RelativeMillis) {
+        if let Some(relay) = self.relays.get_mut(position) {
+            relay.switched_at = now;
+            relay.read_at = None;
+            relay.switches = relay.switches.saturating_add(1);
+        }
+    }
+
+    /** The state read answers for all the relays of the slave. */
+    pub fn on_read(&mut self, slave_id: u32, now: RelativeMillis) {
+        for relay in self.relays.iter_mut().filter(|relay| relay.slave_id == slave_id) {
+            relay.read_at = Some(now);
+        }
+    }
+
+    pub fn take_alarm(&mut self) -> Option<RelayAlarm> {
+        self.alarms.pop_front()
+    }
+
+    fn position(&self, slave_id: u32, relay_index: u8) -> Option<usize> {
+        self.relays.iter().position(|relay| relay.slave_id == slave_id && relay.relay_index == relay_index)
+    }
+
+    fn raise(relay: &mut DesiredRelay, cause: DivergenceCause, now: RelativeMillis) -> Option<RelayAlarm> {
+        if core::mem::replace(&mut relay.alarmed, true) {
+            return None;
+        }
+        logger::log(Event::RelayDiverged { slave_id: relay.slave_id, relay_index: relay.relay_index });
+        Some(RelayAlarm::new(relay.slave_id, relay.relay_index, relay.on, cause, now))
+    }
+
+    /** The oldest alarm is dropped when they are not taken. */
+    fn push_alarm(&mut self, alarm: Option<RelayAlarm>) {
+        if let Some(alarm) = alarm {
+            if self.alarms.is_full() {
+                self.alarms.pop_front();
+            }
+            let _ = self.alarms.push_back(alarm);
+        }
+    }
+}
+
+fn elapsed(since: RelativeMillis, now: RelativeMillis) -> u32 {
+    now.value().wrapping_sub(since.value())
+}
+
+fn is_before(at: RelativeMillis, other: RelativeMillis) -> bool {
+    (at.value().wrapping_sub(other.value()) as i32) < 0
+}
@@ -0,0 +1,336 @@
+#![deny(unsafe_code)]
+
+use heapless::Vec;
+use time::{Duration, PrimitiveDateTime, Time, Weekday};
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::RelativeMillis;
+use crate::services::relay_controller::RelayController;
+use crate::services::slave_controller_link::domain::MAX_RELAYS_COUNT;
+use crate::services::slave_hub::SlaveHub;
+use crate::utils::logger::{self, Event};
+
+pub const SCHEDULE_SIZE: usize = 10;
+pub const MAX_SCHEDULES: usize = 16;
+pub const MINUTES_PER_DAY: u16 = 1440;
+/** The days mask of all the week days, the bit 0 is Monday. */
+pub const EVERY_DAY: u8 = 0x7f;
+const DAYS_PER_WEEK: i64 = 7;
+
+/** Stored schedule, see `Schedule::serialize` for the layout. All zero bytes stand for no schedule. */
+pub type ScheduleData = [u8; SCHEDULE_SIZE];
+
+/**
+Weekly switching of a slave relay: it is switched on at the on minute of the days in the mask and
+off at the off minute of the same day, or of the next one when it is not after the on minute, e.g.
+22:00 - 06:00. The minutes are counted from the midnight of the hub clock.
+ */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Schedule {
+    slave_id: u32,
+    relay_index: u8,
+    weekdays: u8,
+    on_minute: u16,
+    off_minute: u16,
+}
+
+impl Schedule {
+
+    /** The bit 0 of the days mask is Monday, the bit 6 is Sunday. */
+    pub fn new(slave_id: u32, relay_index: u8, weekdays: u8, on_minute: u16,
+               off_minute: u16) -> Result<Self, Errors> {
+        if relay_index >= MAX_RELAYS_COUNT {
+            return Err(Errors::RelayIndexOutOfRange);
+        }
+        if weekdays == 0 || weekdays & !EVERY_DAY != 0 || on_minute >= MINUTES_PER_DAY
+                || off_minute >= MINUTES_PER_DAY || on_minute == off_minute {
+            return Err(Errors::OutOfRange);
+        }
+        Ok(Self { slave_id, relay_index, weekdays, on_minute, off_minute })
+    }
+
+    #[inline(always)]
+    pub fn slave_id(&self) -> u32 {
+        self.slave_id
+    }
+
+    #[inline(always)]
+    pub fn relay_index(&self) -> u8 {
+        self.relay_index
+    }
+
+    #[inline(always)]
+    pub fn weekdays(&self) -> u8 {
+        self.weekdays
+    }
+
+    #[inline(always)]
+    pub fn on_minute(&self) -> u16 {
+        self.on_minute
+    }
+
+    #[inline(always)]
+    pub fn off_minute(&self) -> u16 {
+        self.off_minute
+    }
+
+    #[inline(always)]
+    pub fn is_on_day(&self, weekday: Weekday) -> bool {
+        self.weekdays & (1 << weekday.number_days_from_monday()) != 0
+    }
+
+    /** The last switching not after the moment and the state it sets, `None` if there was no one for a week. */
+    pub fn last_transition(&self, now: PrimitiveDateTime) -> Option<(PrimitiveDateTime, bool)> {
+        let mut last: Option<(PrimitiveDateTime, bool)> = None;
+        for days_back in 0..=DAYS_PER_WEEK {
+            let date = match now.date().checked_sub(Duration::days(days_back)) {
+                Some(date) => date,
+                None => break,
+            };
+            if !self.is_on_day(date.weekday()) {
+                continue;
+            }
+            let day_start = PrimitiveDateTime::new(date, Time::MIDNIGHT);
+            let on_at = day_start + Duration::minutes(self.on_minute as i64);
+            let mut off_at = day_start + Duration::minutes(self.off_minute as i64);
+            if self.off_minute < self.on_minute {
+                off_at += Duration::days(1);
+            }
+            for (at, on) in [(on_at, true), (off_at, false)] {
+                if at <= now && last.is_none_or(|(last_at, _)| at > last_at) {
+                    last = Some((at, on));
+                }
+            }
+        }
+        last
+    }
+
+    /** `None` for the empty data. */
+    pub fn parse(data: &ScheduleData) -> Result<Option<Self>, Errors> {
+        if data.iter().all(|byte| *byte == 0) {
+            return Ok(None);
+        }
+        Self::new(u32::from_le_bytes([data[0], data[1], data[2], data[3]]), data[4], data[5],
+                  u16::from_le_bytes([data[6], data[7]]), u16::from_le_bytes([data[8], data[9]])).map(Some)
+    }
+
+    /**
+    Layout: slave id (4 bytes LE), relay index, days mask, on minute (2 bytes LE), off minute
+    (2 bytes LE).
+     */
+    pub fn serialize(&self) -> ScheduleData {
+        let mut data = [0; SCHEDULE_SIZE];
+        data[0..4].copy_from_slice(&self.slave_id.to_le_bytes());
+        data[4] = self.relay_index;
+        data[5] = self.weekdays;
+        data[6..8].copy_from_slice(&self.on_minute.to_le_bytes());
+        data[8..10].copy_from_slice(&self.off_minute.to_le_bytes());
+        data
+    }
+}
+
+struct ScheduledRelay {
+    schedule: Schedule,
+    /** The switching, which state was sent to the slave. */
+    applied: Option<PrimitiveDateTime>,
+}
+
+/**
+Switches the slave relays by the schedules. The state of the last switching of a schedule is sent,
+when the switching differs from the one sent before. So the switchings missed while the hub was off
+are caught up by the latest one after the restart, as well as the ones skipped or repeated when the
+clock is moved. The states are sent by the `RelayController`, the failed ones are logged and sent
+again on the next poll.
+ */
+pub struct RelayScheduler {
+    schedules: Vec<ScheduledRelay, MAX_SCHEDULES>,
+}
+
+impl RelayScheduler {
+
+    pub fn new<I: IntoIterator<Item = Schedule>>(schedules: I) -> Self {
+        Self {
+            schedules: schedules.into_iter().take(MAX_SCHEDULES)
+                .map(|schedule| ScheduledRelay { schedule, applied: None })
+                .collect(),
+        }
+    }
+
+    pub fn schedules(&self) -> impl Iterator<Item = &Schedule> {
+        self.schedules.iter().map(|scheduled| &scheduled.schedule)
+    }
+
+    /** Replaces the schedules, e.g. after the host changed the configuration. The kept ones are not sent again. */
+    pub fn set_schedules<I: IntoIterator<Item = Schedule>>(&mut self, schedules: I) {
+        let previous = core::mem::take(&mut self.schedules);
+        self.schedules = schedules.into_iter().take(MAX_SCHEDULES)
+            .map(|schedule| {
+                let applied = previous.iter()
+                    .find(|scheduled| scheduled.schedule == schedule)
+                    .and_then(|scheduled| scheduled.applied);
+                ScheduledRelay { schedule, applied }
+            })
+            .collect();
+    }
+
+    /** Sends the states of the schedules, which switched since the last call. `now` is the date of the hub clock. */
+    pub fn poll<const N: usize>(&mut self, hub: &mut SlaveHub<'_, N>, relays: &mut RelayController<'_, N>,
+                                now: PrimitiveDateTime, timestamp: RelativeMillis) {
+        for scheduled in self.schedules.iter_mut() {
+            let (at, on) = match scheduled.schedule.last_transition(now) {
+                Some(transition) => transition,
+                None => continue,
+            };
+            if scheduled.applied == Some(at) {
+                continue;
+            }
+            let (slave_id, relay_index) = (scheduled.schedule.slave_id, scheduled.schedule.relay_index);
+            let result = if on {
+                relays.switch_on(hub, slave_id, relay_index, timestamp)
+            } else {
+                relays.switch_off(hub, slave_id, relay_index, timestamp)
+            };
+            match result {
+                Ok(_) => scheduled.applied = Some(at),
+                Err(error) => logger::log(Event::ScheduleActionFailed(error)),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use time::{Date, Month};
+    use crate::hal_ext::rtc_wrapper::{DateTimeSource, RelativeTimestampSource};
+    use crate::services::relay_controller::RelayAnswersQueue;
+    use crate::services::test_support::{MockLink, MockRtc};
+
+    const WEEKDAYS: u8 = 0x1f;
+
+    #[quickcheck_macros::quickcheck]
+    fn test_schedule_round_trip(slave_id: u32, relay_index: u8, weekdays: u8, minutes: (u16, u16)) -> bool {
+        let on_minute = minutes.0 % MINUTES_PER_DAY;
+        let off_minute = (on_minute + 1 + minutes.1 % (MINUTES_PER_DAY - 1)) % MINUTES_PER_DAY;
+        let schedule = Schedule::new(slave_id, relay_index % MAX_RELAYS_COUNT, weekdays % EVERY_DAY + 1,
+                                     on_minute, off_minute).unwrap();
+
+        let data = schedule.serialize();
+
+        Schedule::parse(&data) == Ok(Some(schedule)) && data.iter().any(|byte| *byte != 0)
+    }
+
+    #[test]
+    fn test_schedule_layout() {
+        let schedule = Schedule::new(0x01020304, 3, WEEKDAYS, 18 * 60, 23 * 60).unwrap();
+
+        assert_eq!([0x04, 0x03, 0x02, 0x01, 0x03, 0x1f, 0x38, 0x04, 0x64, 0x05], schedule.serialize());
+        assert_eq!(Ok(None), Schedule::parse(&[0; SCHEDULE_SIZE]));
+    }
+
+    #[test]
+    fn test_invalid_schedules_are_rejected() {
+        assert_eq!(Err(Errors::RelayIndexOutOfRange), Schedule::new(1, MAX_RELAYS_COUNT, WEEKDAYS, 0, 60));
+        assert_eq!(Err(Errors::OutOfRange), Schedule::new(1, 0, 0, 0, 60));
+        assert_eq!(Err(Errors::OutOfRange), Schedule::new(1, 0, 0x80, 0, 60));
+        assert_eq!(Err(Errors::OutOfRange), Schedule::new(1, 0, WEEKDAYS, MINUTES_PER_DAY, 60));
+        assert_eq!(Err(Errors::OutOfRange), Schedule::new(1, 0, WEEKDAYS, 0, MINUTES_PER_DAY));
+        assert_eq!(Err(Errors::OutOfRange), Schedule::new(1, 0, WEEKDAYS, 60, 60));
+        let mut data = Schedule::new(1, 0, WEEKDAYS, 0, 60).unwrap().serialize();
+        data[4] = MAX_RELAYS_COUNT;
+        assert_eq!(Err(Errors::RelayIndexOutOfRange), Schedule::parse(&data));
+    }
+
+    #[test]
+    fn test_last_transition_over_midnight() {
+        // Saturday only, 22:00 - 06:00
+        let schedule = Schedule::new(1, 0, 1 << 5, 22 * 60, 6 * 60).unwrap();
+
+        assert_eq!(Some((date_time(17, 22, 0), true)), schedule.last_transition(date_time(18, 5, 59)));
+        assert_eq!(Some((date_time(18, 6, 0), false)), schedule.last_transition(date_time(18, 6, 0)));
+        // a week after, before the next on
+        assert_eq!(Some((date_time(18, 6, 0), false)), schedule.last_transition(date_time(24, 21, 59)));
+        assert_eq!(Some((date_time(24, 22, 0), true)), schedule.last_transition(date_time(24, 22, 0)));
+    }
+
+    #[test]
+    fn test_relay_is_switched_by_schedule() {
+        let id = rand::thread_rng().next_u32();
+        let rtc = Rc::new(RefCell::new(MockRtc::new(date_time(16, 17, 0))));
+        let mut source = DateTimeSource::new(rtc.clone());
+        let mut queue = RelayAnswersQueue::new();
+        let mut links = [MockLink::new(Some(id))];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut relays = RelayController::new([queue.split().1]);
+        let mut tested = RelayScheduler::new([Schedule::new(id, 2, WEEKDAYS, 18 * 60, 23 * 60).unwrap()]);
+
+        // the Thursday off is caught up on the start on Friday
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+        rtc.borrow_mut().date_time = date_time(16, 18, 0);
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+        rtc.borrow_mut().date_time = date_time(16, 22, 59);
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+        rtc.borrow_mut().date_time = date_time(16, 23, 0);
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+        // no switching on the weekend
+        rtc.borrow_mut().date_time = date_time(17, 19, 0);
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+
+        assert_eq!(vec![(2, false), (2, true), (2, false)], links[0].switches());
+    }
+
+    #[test]
+    fn test_missed_switching_is_caught_up_after_clock_change() {
+        let id = rand::thread_rng().next_u32();
+        let rtc = Rc::new(RefCell::new(MockRtc::new(date_time(16, 19, 0))));
+        let mut source = DateTimeSource::new(rtc.clone());
+        let mut queue = RelayAnswersQueue::new();
+        let mut links = [MockLink::new(Some(id))];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut relays = RelayController::new([queue.split().1]);
+        let schedule = Schedule::new(id, 1, WEEKDAYS, 18 * 60, 23 * 60).unwrap();
+        let mut tested = RelayScheduler::new([schedule]);
+
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+        // the clock is moved past the off and then back into the on
+        rtc.borrow_mut().date_time = date_time(19, 8, 0);
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+        rtc.borrow_mut().date_time = date_time(16, 20, 0);
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+        // the kept schedule is not sent again
+        tested.set_schedules([schedule, Schedule::new(id, 3, EVERY_DAY, 0, 12 * 60).unwrap()]);
+        tested.poll(&mut hub, &mut relays, source.get_datetime(), source.get());
+
+        assert_eq!(vec![(1, true), (1, false), (1, true), (3, false)], links[0].switches());
+        assert_eq!(2, tested.schedules().count());
+    }
+
+    #[test]
+    fn test_failed_switching_is_not_marked_sent() {
+        let id = rand::thread_rng().next_u32();
+        let now = date_time(16, 19, 0);
+        let mut queue = RelayAnswersQueue::new();
+        let mut links = [MockLink::new(Some(id))];
+        let [link] = &mut links;
+        let mut hub = SlaveHub::new([link]);
+        let mut relays = RelayController::new([queue.split().1]);
+        let mut tested = RelayScheduler::new([Schedule::new(id.wrapping_add(1), 0, EVERY_DAY, 0, 60).unwrap()]);
+
+        tested.poll(&mut hub, &mut relays, now, RelativeMillis::new(0));
+
+        assert!(tested.schedules.iter().all(|scheduled| scheduled.applied.is_none()));
+        assert!(links[0].switches().is_empty());
+    }
+
+    /** Day of June 2023, the 16th is Friday. */
+    fn date_time(day: u8, hour: u8, minute: u8) -> PrimitiveDateTime {
+        PrimitiveDateTime::new(Date::from_calendar_date(2023, Month::June, day).unwrap(),
+                               Time::from_hms(hour, minute, 0).unwrap())
+    }
+}
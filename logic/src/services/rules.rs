@@ -0,0 +1,496 @@
+#![deny(unsafe_code)]
+
+use heapless::spsc::{Consumer, Producer, Queue};
+use heapless::Vec;
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::RelativeMillis;
+use crate::services::relay_controller::RelayController;
+use crate::services::slave_controller_link::domain::{DataInstructionCodes, RelaySignalDataGetter, SignalData, Signals,
+                                                     MAX_RELAYS_COUNT};
+use crate::services::slave_controller_link::signals_controller::SignalsHandler;
+use crate::services::slave_hub::SlaveHub;
+use crate::utils::logger::{self, Event};
+
+pub const RULE_SIZE: usize = 14;
+pub const MAX_RULES: usize = 16;
+/** Actions waiting for their delay, the ones which do not fit are dropped. */
+pub const MAX_DELAYED_ACTIONS: usize = 8;
+/** Size of the queues between the links and the engine, they hold one item less. */
+pub const RULES_QUEUE_SIZE: usize = 5;
+/** The rule delays are stored in these units. */
+pub const RULE_DELAY_STEP_MS: u32 = 100;
+const STATE_BIT: u8 = 0x80;
+
+/** Stored rule, see `Rule::serialize` for the layout. All zero bytes stand for no rule. */
+pub type RuleData = [u8; RULE_SIZE];
+
+pub type RulesSignalsQueue = Queue<SignalData, RULES_QUEUE_SIZE>;
+
+/** Signal of a slave relay, which state got the value. */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RuleTrigger {
+    slave_id: u32,
+    relay_index: u8,
+    signal: Signals,
+    on: bool,
+}
+
+impl RuleTrigger {
+
+    /** The signal should be one of `RelayStateChanged`, `MonitoringStateChanged` and `ControlStateChanged`. */
+    pub fn new(slave_id: u32, relay_index: u8, signal: Signals, on: bool) -> Self {
+        Self { slave_id, relay_index, signal, on }
+    }
+
+    #[inline(always)]
+    pub fn slave_id(&self) -> u32 {
+        self.slave_id
+    }
+
+    #[inline(always)]
+    pub fn relay_index(&self) -> u8 {
+        self.relay_index
+    }
+
+    #[inline(always)]
+    pub fn signal(&self) -> Signals {
+        self.signal
+    }
+
+    #[inline(always)]
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    fn matches(&self, slave_id: u32, signal: &SignalData) -> bool {
+        let relay_state = match signal {
+            SignalData::RelayStateChanged(data) => Some((data.get_relay_idx(), data.is_on())),
+            SignalData::MonitoringStateChanged(data) | SignalData::ControlStateChanged(data) =>
+                Some((data.get_relay_idx(), data.is_on())),
+            _ => None,
+        };
+        self.slave_id == slave_id && self.signal == signal.code() && relay_state == Some((self.relay_index, self.on))
+    }
+}
+
+/** Setting of a slave relay state made after the delay. */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RuleAction {
+    slave_id: u32,
+    relay_index: u8,
+    instruction: DataInstructionCodes,
+    on: bool,
+    delay_ms: u32,
+}
+
+impl RuleAction {
+
+    /**
+    The instruction should be one of `RelaySwitchedOn`, `RelayDisabledTemp`, `RelayMonitorOn` and
+    `RelayControlOn`, the delay should be a multiple of `RULE_DELAY_STEP_MS`.
+     */
+    pub fn new(slave_id: u32, relay_index: u8, instruction: DataInstructionCodes, on: bool, delay_ms: u32) -> Self {
+        Self { slave_id, relay_index, instruction, on, delay_ms }
+    }
+
+    #[inline(always)]
+    pub fn slave_id(&self) -> u32 {
+        self.slave_id
+    }
+
+    #[inline(always)]
+    pub fn relay_index(&self) -> u8 {
+        self.relay_index
+    }
+
+    #[inline(always)]
+    pub fn instruction(&self) -> DataInstructionCodes {
+        self.instruction
+    }
+
+    #[inline(always)]
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    #[inline(always)]
+    pub fn delay_ms(&self) -> u32 {
+        self.delay_ms
+    }
+
+    fn execute<const N: usize>(&self, hub: &mut SlaveHub<'_, N>, relays: &mut RelayController<'_, N>,
+                               now: RelativeMillis) -> Result<(), Errors> {
+        let (slave_id, relay_index) = (self.slave_id, self.relay_index);
+        match (self.instruction, self.on) {
+            (DataInstructionCodes::RelaySwitchedOn, true) => relays.switch_on(hub, slave_id, relay_index, now),
+            (DataInstructionCodes::RelaySwitchedOn, false) => relays.switch_off(hub, slave_id, relay_index, now),
+            (DataInstructionCodes::RelayDisabledTemp, true) =>
+                relays.disable_temporarily(hub, slave_id, relay_index, now),
+            (DataInstructionCodes::RelayDisabledTemp, false) => relays.enable(hub, slave_id, relay_index, now),
+            (DataInstructionCodes::RelayMonitorOn, on) => relays.set_monitoring(hub, slave_id, relay_index, on, now),
+            (DataInstructionCodes::RelayControlOn, on) => relays.set_control(hub, slave_id, relay_index, on, now),
+            (instruction, _) => Err(Errors::InstructionNotRecognized(instruction as u8)),
+        }.map(|_| ())
+    }
+}
+
+/** When the trigger signal comes, the action is made, e.g. slave A relay 3 turns on - switch slave B relay 1 off after 2 s. */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rule {
+    trigger: RuleTrigger,
+    action: RuleAction,
+}
+
+impl Rule {
+
+    pub fn new(trigger: RuleTrigger, action: RuleAction) -> Result<Self, Errors> {
+        if !matches!(trigger.signal, Signals::RelayStateChanged | Signals::MonitoringStateChanged |
+                Signals::ControlStateChanged) {
+            return Err(Errors::InstructionNotRecognized(trigger.signal as u8));
+        }
+        if !matches!(action.instruction, DataInstructionCodes::RelaySwitchedOn |
+                DataInstructionCodes::RelayDisabledTemp | DataInstructionCodes::RelayMonitorOn |
+                DataInstructionCodes::RelayControlOn) {
+            return Err(Errors::InstructionNotRecognized(action.instruction as u8));
+        }
+        if trigger.relay_index >= MAX_RELAYS_COUNT || action.relay_index >= MAX_RELAYS_COUNT {
+            return Err(Errors::RelayIndexOutOfRange);
+        }
+        if !action.delay_ms.is_multiple_of(RULE_DELAY_STEP_MS) || action.delay_ms / RULE_DELAY_STEP_MS > u16::MAX as u32 {
+            return Err(Errors::OutOfRange);
+        }
+        Ok(Self { trigger, action })
+    }
+
+    #[inline(always)]
+    pub fn trigger(&self) -> &RuleTrigger {
+        &self.trigger
+    }
+
+    #[inline(always)]
+    pub fn action(&self) -> &RuleAction {
+        &self.action
+    }
+
+    /** `None` for the empty data. */
+    pub fn parse(data: &RuleData) -> Result<Option<Self>, Errors> {
+        if data.iter().all(|byte| *byte == 0) {
+            return Ok(None);
+        }
+        let trigger = RuleTrigger::new(u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+                                       data[4] & !STATE_BIT, Signals::get(data[5])?, data[4] & STATE_BIT != 0);
+        let action = RuleAction::new(u32::from_le_bytes([data[6], data[7], data[8], data[9]]),
+                                     data[10] & !STATE_BIT, DataInstructionCodes::get(data[11])?,
+                                     data[10] & STATE_BIT != 0,
+                                     u16::from_le_bytes([data[12], data[13]]) as u32 * RULE_DELAY_STEP_MS);
+        Self::new(trigger, action).map(Some)
+    }
+
+    /**
+    Layout: trigger slave id (4 bytes LE), trigger relay index with the state in the high bit, signal code,
+    action slave id (4 bytes LE), action relay index with the state in the high bit, instruction code,
+    delay in `RULE_DELAY_STEP_MS` units (2 bytes LE).
+     */
+    pub fn serialize(&self) -> RuleData {
+        let mut data = [0; RULE_SIZE];
+        data[0..4].copy_from_slice(&self.trigger.slave_id.to_le_bytes());
+        data[4] = self.trigger.relay_index | if self.trigger.on { STATE_BIT } else { 0 };
+        data[5] = self.trigger.signal as u8;
+        data[6..10].copy_from_slice(&self.action.slave_id.to_le_bytes());
+        data[10] = self.action.relay_index | if self.action.on { STATE_BIT } else { 0 };
+        data[11] = self.action.instruction as u8;
+        data[12..14].copy_from_slice(&((self.action.delay_ms / RULE_DELAY_STEP_MS) as u16).to_le_bytes());
+        data
+    }
+}
+
+/** Signals handler of a link passing the relay signals to the `RulesEngine`, the other handler gets all of them. */
+pub struct RulesSignalsForwarder<'a, SH: SignalsHandler> {
+    signals_handler: SH,
+    signals: Producer<'a, SignalData, RULES_QUEUE_SIZE>,
+}
+
+impl <'a, SH: SignalsHandler> RulesSignalsForwarder<'a, SH> {
+
+    pub fn new(signals_handler: SH, signals: Producer<'a, SignalData, RULES_QUEUE_SIZE>) -> Self {
+        Self { signals_handler, signals }
+    }
+
+    #[inline(always)]
+    pub fn signals_handler(&mut self) -> &mut SH {
+        &mut self.signals_handler
+    }
+}
+
+impl <SH: SignalsHandler> SignalsHandler for RulesSignalsForwarder<'_, SH> {
+
+    fn on_signal(&mut self, signal_data: SignalData, is_processed: bool) {
+        if matches!(signal_data, SignalData::RelayStateChanged(_) | SignalData::MonitoringStateChanged(_) |
+                SignalData::ControlStateChanged(_)) {
+            let _ = self.signals.enqueue(signal_data);
+        }
+        self.signals_handler.on_signal(signal_data, is_processed);
+    }
+
+    fn on_signal_parse_error(&mut self, error: Errors, sent_to_slave_success: bool, data: &[u8]) {
+        self.signals_handler.on_signal_parse_error(error, sent_to_slave_success, data);
+    }
+
+    fn on_signal_process_error(&mut self, error: Errors, sent_to_slave_success: bool, data: SignalData) {
+        self.signals_handler.on_signal_process_error(error, sent_to_slave_success, data);
+    }
+}
+
+struct DelayedAction {
+    action: RuleAction,
+    triggered_at: RelativeMillis,
+}
+
+/**
+Makes the actions of the rules, which triggers match the slaves signals. The signals come through the
+`RulesSignalsForwarder` of each link and the slave is known by the port, the signals of the slaves
+with unknown id are skipped. The actions are sent by the `RelayController`, the failed ones are logged.
+ */
+pub struct RulesEngine<'a, const N: usize> {
+    signals: [Consumer<'a, SignalData, RULES_QUEUE_SIZE>; N],
+    rules: Vec<Rule, MAX_RULES>,
+    delayed: Vec<DelayedAction, MAX_DELAYED_ACTIONS>,
+}
+
+impl <'a, const N: usize> RulesEngine<'a, N> {
+
+    /** `signals[port]` is filled by the `RulesSignalsForwarder` of the hub link on `port`, rules over `MAX_RULES` are dropped. */
+    pub fn new<I: IntoIterator<Item = Rule>>(signals: [Consumer<'a, SignalData, RULES_QUEUE_SIZE>; N],
+                                             rules: I) -> Self {
+        Self {
+            signals,
+            rules: rules.into_iter().take(MAX_RULES).collect(),
+            delayed: Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    #[inline(always)]
+    pub fn delayed_count(&self) -> usize {
+        self.delayed.len()
+    }
+
+    /** Replaces the rules, e.g. after the host changed the configuration. The delayed actions are kept. */
+    pub fn set_rules<I: IntoIterator<Item = Rule>>(&mut self, rules: I) {
+        self.rules = rules.into_iter().take(MAX_RULES).collect();
+    }
+
+    /** Evaluates the rules on the signals passed since the last call and makes the due actions. */
+    pub fn poll(&mut self, hub: &mut SlaveHub<'_, N>, relays: &mut RelayController<'_, N>, now: RelativeMillis) {
+        for port in 0..N {
+            while let Some(signal) = self.signals[port].dequeue() {
+                if let Some(slave_id) = hub.slave_id(port) {
+                    self.on_signal(slave_id, &signal, now);
+                }
+            }
+        }
+        let mut i = 0;
+        while i < self.delayed.len() {
+            let delayed = &self.delayed[i];
+            if now.value().wrapping_sub(delayed.triggered_at.value()) >= delayed.action.delay_ms {
+                let action = self.delayed.remove(i).action;
+                if let Err(error) = action.execute(hub, relays, now) {
+                    logger::log(Event::RuleActionFailed(error));
+                }
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /** The actions of the matching rules are kept till `poll`, even the ones without the delay. */
+    pub fn on_signal(&mut self, slave_id: u32, signal: &SignalData, now: RelativeMillis) {
+        for rule in self.rules.iter().filter(|rule| rule.trigger.matches(slave_id, signal)) {
+            let delayed = DelayedAction { action: rule.action, triggered_at: now };
+            if self.delayed.push(delayed).is_err() {
+                logger::log(Event::RuleActionFailed(Errors::RequestsLimitReached));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use crate::hal_ext::rtc_wrapper::RelativeSeconds;
+    use crate::services::relay_controller::RelayAnswersQueue;
+    use crate::services::slave_controller_link::domain::{Operation, RelaySignalData, RelaySignalDataExt};
+    use crate::services::test_support::MockLink;
+
+    const SIGNALS: [Signals; 3] = [Signals::RelayStateChanged, Signals::MonitoringStateChanged,
+        Signals::ControlStateChanged];
+    const INSTRUCTIONS: [DataInstructionCodes; 4] = [DataInstructionCodes::RelaySwitchedOn,
+        DataInstructionCodes::RelayDisabledTemp, DataInstructionCodes::RelayMonitorOn,
+        DataInstructionCodes::RelayControlOn];
+
+    #[quickcheck_macros::quickcheck]
+    fn test_rule_round_trip(slaves_ids: (u32, u32), relays: (u8, u8), states: (bool, bool), codes: (u8, u8),
+                            delay_steps: u16) -> bool {
+        let trigger = RuleTrigger::new(slaves_ids.0, relays.0 % MAX_RELAYS_COUNT,
+                                       SIGNALS[codes.0 as usize % SIGNALS.len()], states.0);
+        let action = RuleAction::new(slaves_ids.1, relays.1 % MAX_RELAYS_COUNT,
+                                     INSTRUCTIONS[codes.1 as usize % INSTRUCTIONS.len()], states.1,
+                                     delay_steps as u32 * RULE_DELAY_STEP_MS);
+        let rule = Rule::new(trigger, action).unwrap();
+
+        let data = rule.serialize();
+
+        Rule::parse(&data) == Ok(Some(rule)) && data.iter().any(|byte| *byte != 0)
+    }
+
+    #[test]
+    fn test_rule_layout() {
+        let rule = Rule::new(RuleTrigger::new(0x01020304, 3, Signals::RelayStateChanged, true),
+                             RuleAction::new(0x0a0b0c0d, 1, DataInstructionCodes::RelaySwitchedOn, false, 2000)).unwrap();
+
+        assert_eq!([0x04, 0x03, 0x02, 0x01, 0x83, Signals::RelayStateChanged as u8, 0x0d, 0x0c, 0x0b, 0x0a, 0x01,
+                    DataInstructionCodes::RelaySwitchedOn as u8, 20, 0], rule.serialize());
+        assert_eq!(Ok(None), Rule::parse(&[0; RULE_SIZE]));
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let trigger = RuleTrigger::new(1, 3, Signals::RelayStateChanged, true);
+        let action = RuleAction::new(2, 1, DataInstructionCodes::RelaySwitchedOn, false, 2000);
+        let data = Rule::new(trigger, action).unwrap().serialize();
+
+        assert_eq!(Err(Errors::InstructionNotRecognized(Signals::GetTimeStamp as u8)),
+                   Rule::new(RuleTrigger::new(1, 3, Signals::GetTimeStamp, true), action));
+        assert_eq!(Err(Errors::InstructionNotRecognized(DataInstructionCodes::Settings as u8)),
+                   Rule::new(trigger, RuleAction::new(2, 1, DataInstructionCodes::Settings, false, 0)));
+        assert_eq!(Err(Errors::RelayIndexOutOfRange),
+                   Rule::new(trigger, RuleAction::new(2, MAX_RELAYS_COUNT, DataInstructionCodes::RelaySwitchedOn, false, 0)));
+        assert_eq!(Err(Errors::OutOfRange),
+                   Rule::new(trigger, RuleAction::new(2, 1, DataInstructionCodes::RelaySwitchedOn, false, 150)));
+        assert_eq!(Err(Errors::OutOfRange), Rule::new(trigger, RuleAction::new(2, 1,
+            DataInstructionCodes::RelaySwitchedOn, false, (u16::MAX as u32 + 1) * RULE_DELAY_STEP_MS)));
+        let mut wrong_signal = data;
+        wrong_signal[5] = 0x01;
+        assert_eq!(Err(Errors::InstructionNotRecognized(0x01)), Rule::parse(&wrong_signal));
+        let mut wrong_relay = data;
+        wrong_relay[4] = 0x10;
+        assert_eq!(Err(Errors::RelayIndexOutOfRange), Rule::parse(&wrong_relay));
+    }
+
+    #[test]
+    fn test_action_is_made_after_delay() {
+        let mut rng = rand::thread_rng();
+        let (id_a, id_b) = (rng.gen_range(0..u32::MAX / 2), rng.gen_range(u32::MAX / 2..u32::MAX));
+        let now = RelativeMillis::new(rng.next_u32());
+        let mut relay_queues = [RelayAnswersQueue::new(), RelayAnswersQueue::new()];
+        let [relays_queue0, relays_queue1] = &mut relay_queues;
+        let mut signal_queues = [RulesSignalsQueue::new(), RulesSignalsQueue::new()];
+        let [signals0, signals1] = &mut signal_queues;
+        let (signals0_tx, signals0_rx) = signals0.split();
+        let mut forwarder = RulesSignalsForwarder::new(MockSignalsHandler::default(), signals0_tx);
+        let mut links = [MockLink::new(Some(id_a)), MockLink::new(Some(id_b))];
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut relays = RelayController::new([relays_queue0.split().1, relays_queue1.split().1]);
+        let rules = [
+            Rule::new(RuleTrigger::new(id_a, 3, Signals::RelayStateChanged, true),
+                      RuleAction::new(id_b, 1, DataInstructionCodes::RelaySwitchedOn, false, 2000)).unwrap(),
+            Rule::new(RuleTrigger::new(id_a, 3, Signals::RelayStateChanged, true),
+                      RuleAction::new(id_a, 2, DataInstructionCodes::RelayControlOn, true, 0)).unwrap(),
+        ];
+        let mut tested = RulesEngine::new([signals0_rx, signals1.split().1], rules);
+
+        forwarder.on_signal(SignalData::RelayStateChanged(
+            RelaySignalDataExt::new(RelativeSeconds::new(10), 3, true, false)), false);
+        tested.poll(&mut hub, &mut relays, now);
+        assert_eq!(1, tested.delayed_count());
+        tested.poll(&mut hub, &mut relays, RelativeMillis::new(now.value().wrapping_add(1999)));
+        assert_eq!(1, tested.delayed_count());
+        tested.poll(&mut hub, &mut relays, RelativeMillis::new(now.value().wrapping_add(2000)));
+
+        assert_eq!(0, tested.delayed_count());
+        assert_eq!(1, forwarder.signals_handler().signals);
+        assert_eq!(vec![(Operation::Set, DataInstructionCodes::RelayControlOn, Some((2, true)))], links[0].sent_relay_requests());
+        assert_eq!(vec![(Operation::Set, DataInstructionCodes::RelaySwitchedOn, Some((1, false)))], links[1].sent_relay_requests());
+    }
+
+    #[test]
+    fn test_not_matching_signals_are_skipped() {
+        let id = rand::thread_rng().next_u32();
+        let now = RelativeMillis::new(0);
+        let mut relay_queues = [RelayAnswersQueue::new(), RelayAnswersQueue::new()];
+        let [relays_queue0, relays_queue1] = &mut relay_queues;
+        let mut signal_queues = [RulesSignalsQueue::new(), RulesSignalsQueue::new()];
+        let [signals0, signals1] = &mut signal_queues;
+        let (signals0_tx, signals0_rx) = signals0.split();
+        let (signals1_tx, signals1_rx) = signals1.split();
+        let mut forwarder0 = RulesSignalsForwarder::new(MockSignalsHandler::default(), signals0_tx);
+        let mut forwarder1 = RulesSignalsForwarder::new(MockSignalsHandler::default(), signals1_tx);
+        let mut links = [MockLink::new(Some(id)), MockLink::new(None)];
+        let [link0, link1] = &mut links;
+        let mut hub = SlaveHub::new([link0, link1]);
+        let mut relays = RelayController::new([relays_queue0.split().1, relays_queue1.split().1]);
+        let rule = Rule::new(RuleTrigger::new(id, 3, Signals::MonitoringStateChanged, false),
+                             RuleAction::new(id, 1, DataInstructionCodes::RelayDisabledTemp, true, 0)).unwrap();
+        let mut tested = RulesEngine::new([signals0_rx, signals1_rx], [rule]);
+
+        for signal in [
+            SignalData::MonitoringStateChanged(RelaySignalData::new(RelativeSeconds::new(1), 2, false)),
+            SignalData::MonitoringStateChanged(RelaySignalData::new(RelativeSeconds::new(1), 3, true)),
+            SignalData::ControlStateChanged(RelaySignalData::new(RelativeSeconds::new(1), 3, false)),
+            SignalData::StateFixTry(RelaySignalData::new(RelativeSeconds::new(1), 3, false)),
+            SignalData::GetTimeStamp,
+        ] {
+            forwarder0.on_signal(signal, false);
+        }
+        // the slave on the port is not known yet
+        forwarder1.on_signal(SignalData::MonitoringStateChanged(
+            RelaySignalData::new(RelativeSeconds::new(1), 3, false)), false);
+        tested.poll(&mut hub, &mut relays, now);
+        assert_eq!(0, relays.pending_count());
+
+        tested.on_signal(id, &SignalData::MonitoringStateChanged(
+            RelaySignalData::new(RelativeSeconds::new(1), 3, false)), now);
+        tested.poll(&mut hub, &mut relays, now);
+        assert_eq!(vec![(Operation::Set, DataInstructionCodes::RelayDisabledTemp, Some((1, true)))],
+                   links[0].sent_relay_requests());
+        assert_eq!(5, forwarder0.signals_handler().signals);
+    }
+
+    #[test]
+    fn test_actions_over_limit_are_dropped() {
+        let id = rand::thread_rng().next_u32();
+        let mut signals = RulesSignalsQueue::new();
+        let rule = Rule::new(RuleTrigger::new(id, 0, Signals::ControlStateChanged, true),
+                             RuleAction::new(id, 0, DataInstructionCodes::RelayMonitorOn, true, 100)).unwrap();
+        let mut tested: RulesEngine<1> = RulesEngine::new([signals.split().1], [rule]);
+        let signal = SignalData::ControlStateChanged(RelaySignalData::new(RelativeSeconds::new(1), 0, true));
+
+        for _ in 0..MAX_DELAYED_ACTIONS + 1 {
+            tested.on_signal(id, &signal, RelativeMillis::new(0));
+        }
+
+        assert_eq!(MAX_DELAYED_ACTIONS, tested.delayed_count());
+    }
+
+    #[derive(Default)]
+    struct MockSignalsHandler {
+        signals: usize,
+    }
+
+    impl SignalsHandler for MockSignalsHandler {
+        fn on_signal(&mut self, _: SignalData, _: bool) {
+            self.signals += 1;
+        }
+
+        fn on_signal_parse_error(&mut self, _: Errors, _: bool, _: &[u8]) {}
+
+        fn on_signal_process_error(&mut self, _: Errors, _: bool, _: SignalData) {}
+    }
+}
@@ -0,0 +1,358 @@
+#![deny(unsafe_code)]
+
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::{RelativeMillis, RelativeSeconds};
+use crate::services::slave_controller_link::domain::{DataInstructionCodes, DataInstructions, ErrorCode, Operation};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest};
+
+/** Bound of the drift between the hub and slave clocks, the offset uncertainty grows by it with the time. */
+pub const MAX_CLOCK_DRIFT_PPM: u64 = 200;
+/** The slave counts the whole seconds, so its timestamp stands for any moment of the second. */
+const SLAVE_TICK_MS: i64 = 1000;
+const PPM: u64 = 1_000_000;
+
+/** A moment of the hub time, which is known to be within `uncertainty_ms` from `timestamp`. */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HubTimestamp {
+    timestamp: RelativeMillis,
+    uncertainty_ms: u32,
+}
+
+impl HubTimestamp {
+
+    pub fn new(timestamp: RelativeMillis, uncertainty_ms: u32) -> Self {
+        Self { timestamp, uncertainty_ms }
+    }
+
+    #[inline(always)]
+    pub fn timestamp(&self) -> RelativeMillis {
+        self.timestamp
+    }
+
+    #[inline(always)]
+    pub fn uncertainty_ms(&self) -> u32 {
+        self.uncertainty_ms
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct ClockOffset {
+    /** The hub time less the slave one. */
+    offset_ms: i64,
+    uncertainty_ms: u32,
+    measured_at: RelativeMillis,
+}
+
+/**
+Offset of the slave clock against the hub one. The slave clock is set by the hub on the slave request,
+then it is read by `CurrentTime` requests. Each answer bounds the moment the slave used its clock by
+the request send and answer times, so the offset is got with the uncertainty of half the round trip.
+ */
+pub struct SlaveClock {
+    offset: Option<ClockOffset>,
+}
+
+impl SlaveClock {
+
+    pub const fn new() -> Self {
+        Self { offset: None }
+    }
+
+    /** `None` until the slave clock is set or read. */
+    #[inline(always)]
+    pub fn offset_ms(&self) -> Option<i64> {
+        self.offset.map(|offset| offset.offset_ms)
+    }
+
+    /** Uncertainty of the offset grown by the possible drift since its measurement. */
+    pub fn uncertainty_ms(&self, now: RelativeMillis) -> Option<u32> {
+        self.offset.map(|offset| Self::aged_uncertainty(&offset, now))
+    }
+
+    /** The slave restarted, so its clock is not set any more. */
+    #[inline(always)]
+    pub fn invalidate(&mut self) {
+        self.offset = None;
+    }
+
+    /** The slave clock was set to `set_to` at some moment between `sent_at` and `answered_at`. */
+    pub fn on_time_set(&mut self, set_to: RelativeSeconds, sent_at: RelativeMillis, answered_at: RelativeMillis) {
+        // the previous offset is not valid after the clock step
+        self.offset = Some(Self::measure(Self::slave_millis(set_to), 0, sent_at, answered_at));
+    }
+
+    /** The slave clock showed `slave_time` at some moment between `sent_at` and `answered_at`. */
+    pub fn on_time_read(&mut self, slave_time: RelativeSeconds, sent_at: RelativeMillis, answered_at: RelativeMillis) {
+        let measured = Self::measure(Self::slave_millis(slave_time), SLAVE_TICK_MS - 1, sent_at, answered_at);
+        let keep_current = self.offset
+            .is_some_and(|current| Self::aged_uncertainty(&current, answered_at) < measured.uncertainty_ms);
+        if !keep_current {
+            self.offset = Some(measured);
+        }
+    }
+
+    /**
+    Hub time of a slave timestamp, `None` while the offset is not known. The offset drifted as well between
+    the measurement and an older timestamp, e.g. the one of the last fix try read after the clock.
+     */
+    pub fn to_hub_time(&self, slave_time: RelativeSeconds, now: RelativeMillis) -> Option<HubTimestamp> {
+        self.offset.map(|offset| {
+            let second_start = RelativeMillis::new((Self::slave_millis(slave_time) + offset.offset_ms) as u32);
+            let since_measurement = now.value().wrapping_sub(offset.measured_at.value());
+            let before_measurement = (offset.measured_at.value().wrapping_sub(second_start.value()) as i32).max(0);
+            let uncertainty_ms = Self::grown_uncertainty(&offset, since_measurement.max(before_measurement as u32));
+            HubTimestamp {
+                timestamp: RelativeMillis::new(second_start.value().wrapping_add((SLAVE_TICK_MS / 2) as u32)),
+                uncertainty_ms: uncertainty_ms.saturating_add((SLAVE_TICK_MS / 2) as u32),
+            }
+        })
+    }
+
+    /**
+    The slave time was between `slave_millis` and `slave_millis + slave_spread` at a moment between
+    `sent_at` and `answered_at`, the offset is the middle of the possible range.
+     */
+    fn measure(slave_millis: i64, slave_spread: i64, sent_at: RelativeMillis, answered_at: RelativeMillis) -> ClockOffset {
+        let sent = sent_at.value() as i64;
+        let answered = sent + answered_at.value().wrapping_sub(sent_at.value()) as i64;
+        let min_offset = sent - slave_millis - slave_spread;
+        let max_offset = answered - slave_millis;
+        ClockOffset {
+            offset_ms: min_offset + (max_offset - min_offset) / 2,
+            uncertainty_ms: ((max_offset - min_offset + 1) / 2) as u32,
+            measured_at: answered_at,
+        }
+    }
+
+    fn aged_uncertainty(offset: &ClockOffset, now: RelativeMillis) -> u32 {
+        Self::grown_uncertainty(offset, now.value().wrapping_sub(offset.measured_at.value()))
+    }
+
+    fn grown_uncertainty(offset: &ClockOffset, elapsed: u32) -> u32 {
+        offset.uncertainty_ms.saturating_add((elapsed as u64 * MAX_CLOCK_DRIFT_PPM).div_ceil(PPM) as u32)
+    }
+
+    fn slave_millis(slave_time: RelativeSeconds) -> i64 {
+        slave_time.value() as i64 * SLAVE_TICK_MS
+    }
+}
+
+impl Default for SlaveClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+Keeps the slave clock offset up to date with the answers of the `RemoteTimestamp` and `CurrentTime`
+requests. The answer time is the one set by `set_now` at the start of processing of the received data.
+ */
+pub struct ClockResponseHandler<RH: ResponseHandler> {
+    response_handler: RH,
+    clock: SlaveClock,
+    now: RelativeMillis,
+    time_set: Option<RelativeSeconds>,
+}
+
+impl <RH: ResponseHandler> ClockResponseHandler<RH> {
+
+    pub fn new(response_handler: RH) -> Self {
+        Self {
+            response_handler,
+            clock: SlaveClock::new(),
+            now: RelativeMillis::new(0),
+            time_set: None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+
+    #[inline(always)]
+    pub fn clock(&self) -> &SlaveClock {
+        &self.clock
+    }
+
+    #[inline(always)]
+    pub fn set_now(&mut self, now: RelativeMillis) {
+        self.now = now;
+    }
+
+    /** The slave restarted without asking for the time, so its clock is not known. */
+    #[inline(always)]
+    pub fn invalidate(&mut self) {
+        self.clock.invalidate();
+        self.time_set = None;
+    }
+
+    /**
+    The slave asked for the time after its restart and `RemoteTimestamp` with the value is sent. A retry
+    of the request is sent later than the value, so the value is kept till the acknowledgement.
+     */
+    pub fn on_time_requested(&mut self, set_to: RelativeSeconds) {
+        self.clock.invalidate();
+        self.time_set = Some(set_to);
+    }
+}
+
+impl <RH: ResponseHandler> ResponseHandler for ClockResponseHandler<RH> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        if request.operation() == Operation::Set && request.instruction() == DataInstructionCodes::RemoteTimestamp {
+            let set_to = self.time_set.take().unwrap_or(request.rel_timestamp().seconds());
+            self.clock.on_time_set(set_to, request.rel_timestamp(), self.now);
+        }
+        self.response_handler.on_request_success(request);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        if let DataInstructions::CurrentTime(conversation) = &response {
+            if let Some(slave_time) = conversation.data() {
+                self.clock.on_time_read(*slave_time, request.rel_timestamp(), self.now);
+            }
+        }
+        self.response_handler.on_request_response(request, response);
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        self.response_handler.on_request_error(request, error_code);
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+        self.response_handler.on_request_parse_error(request, error, data);
+    }
+
+    fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
+        self.response_handler.on_request_search_error(payload, error);
+    }
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        self.response_handler.on_request_timeout(request);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::slave_controller_link::domain::Conversation;
+
+    #[test]
+    fn test_time_set_bounds_offset_by_round_trip() {
+        let mut tested = SlaveClock::new();
+        assert_eq!(None, tested.to_hub_time(RelativeSeconds::new(1), RelativeMillis::new(0)));
+
+        tested.on_time_set(RelativeSeconds::new(100), RelativeMillis::new(100_200), RelativeMillis::new(100_260));
+
+        assert_eq!(Some(230), tested.offset_ms());
+        assert_eq!(Some(30), tested.uncertainty_ms(RelativeMillis::new(100_260)));
+        // the drift bound adds 1 ms per 5 s
+        assert_eq!(Some(42), tested.uncertainty_ms(RelativeMillis::new(160_260)));
+        assert_eq!(Some(HubTimestamp::new(RelativeMillis::new(105_730), 530)),
+                   tested.to_hub_time(RelativeSeconds::new(105), RelativeMillis::new(100_260)));
+        tested.invalidate();
+        assert_eq!(None, tested.offset_ms());
+    }
+
+    #[test]
+    fn test_read_replaces_offset_when_more_certain() {
+        let mut tested = SlaveClock::new();
+        tested.on_time_set(RelativeSeconds::new(10), RelativeMillis::new(10_000), RelativeMillis::new(10_100));
+
+        // the read uncertainty is at least half of the slave second
+        tested.on_time_read(RelativeSeconds::new(20), RelativeMillis::new(20_000), RelativeMillis::new(20_020));
+        assert_eq!(Some(50), tested.offset_ms());
+
+        let later = 10_100 + 5_000_000;
+        tested.on_time_read(RelativeSeconds::new(later / 1000), RelativeMillis::new(later), RelativeMillis::new(later + 20));
+        assert_eq!(Some(-390), tested.offset_ms());
+        assert_eq!(Some(510), tested.uncertainty_ms(RelativeMillis::new(later + 20)));
+
+        // the set steps the slave clock, so it always replaces the offset
+        tested.on_time_set(RelativeSeconds::new(0), RelativeMillis::new(later + 100), RelativeMillis::new(later + 300));
+        assert_eq!(Some(later as i64 + 200), tested.offset_ms());
+    }
+
+    #[test]
+    fn test_offset_is_measured_over_wrap_of_hub_time() {
+        let mut tested = SlaveClock::new();
+        let sent_at = RelativeMillis::new(u32::MAX - 10);
+
+        tested.on_time_set(sent_at.seconds(), sent_at, RelativeMillis::new(30));
+
+        let hub_time = tested.to_hub_time(RelativeSeconds::new(sent_at.seconds().value() + 2), RelativeMillis::new(30));
+        // 4294969805 ms wrapped
+        assert_eq!(Some(HubTimestamp::new(RelativeMillis::new(2_509), 21 + 500)), hub_time);
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn test_slave_timestamps_are_within_uncertainty(set_at: u32, round_trips: (u16, u16), drift_ppm: i16,
+                                                    read_after_s: u32, event_after_ms: u32) -> bool {
+        let mut rng = thread_rng();
+        let drift = (drift_ppm % (MAX_CLOCK_DRIFT_PPM as i16 + 1)) as f64 / PPM as f64;
+        let (set_round_trip, read_round_trip) = (round_trips.0 as u64, round_trips.1 as u64);
+        let read_sent_at = set_round_trip + (read_after_s % 1_000_000) as u64 * 1000;
+        let now = read_sent_at + read_round_trip + rng.gen_range(0..100_000);
+        // the hub times are counted from the set request, the slave clock starts at the set moment
+        let slave_set_at = rng.gen_range(0..=set_round_trip);
+        let set_to = RelativeMillis::new(set_at).seconds();
+        let slave_seconds = |hub_time: u64| {
+            let slave_millis = (hub_time as f64 - slave_set_at as f64) * (1.0 + drift);
+            RelativeSeconds::new(set_to.value().wrapping_add((slave_millis / 1000.0).floor() as u32))
+        };
+        let hub = |time: u64| RelativeMillis::new(set_at.wrapping_add(time as u32));
+        let mut tested = SlaveClock::new();
+
+        tested.on_time_set(set_to, hub(0), hub(set_round_trip));
+        let slave_read_at = rng.gen_range(read_sent_at..=read_sent_at + read_round_trip);
+        tested.on_time_read(slave_seconds(slave_read_at), hub(read_sent_at), hub(read_sent_at + read_round_trip));
+
+        // the events before the read are converted by the offset measured after them
+        let event_at = slave_set_at + event_after_ms as u64 % (now - slave_set_at);
+        let hub_time = tested.to_hub_time(slave_seconds(event_at), hub(now)).unwrap();
+        let error = hub_time.timestamp().value().wrapping_sub(hub(event_at).value()) as i32;
+        error.unsigned_abs() <= hub_time.uncertainty_ms()
+    }
+
+    #[test]
+    fn test_handler_uses_sent_time_value_for_retried_set() {
+        let mut tested = ClockResponseHandler::new(NoHandler);
+        tested.on_time_requested(RelativeSeconds::new(50));
+        tested.set_now(RelativeMillis::new(63_000));
+
+        // the retry is sent later than the value
+        tested.on_request_success(SentRequest::new(Some(1), Operation::Set, DataInstructionCodes::RemoteTimestamp,
+                                                   RelativeMillis::new(60_000)));
+        assert_eq!(Some(11_500), tested.clock().offset_ms());
+
+        tested.set_now(RelativeMillis::new(70_010));
+        tested.on_request_response(SentRequest::new(None, Operation::Read, DataInstructionCodes::CurrentTime,
+                                                    RelativeMillis::new(70_000)),
+                                   DataInstructions::CurrentTime(Conversation::Data(RelativeSeconds::new(59))));
+        assert_eq!(Some(10_505), tested.clock().offset_ms());
+        assert_eq!(Some(505), tested.clock().uncertainty_ms(RelativeMillis::new(70_010)));
+
+        tested.on_time_requested(RelativeSeconds::new(0));
+        assert_eq!(None, tested.clock().offset_ms());
+    }
+
+    struct NoHandler;
+
+    impl ResponseHandler for NoHandler {
+        fn on_request_success(&mut self, _: SentRequest) {}
+
+        fn on_request_response(&mut self, _: SentRequest, _: DataInstructions) {}
+
+        fn on_request_error(&mut self, _: SentRequest, _: ErrorCode) {}
+
+        fn on_request_parse_error(&mut self, _: Option<SentRequest>, _: Errors, _: &[u8]) {}
+
+        fn on_request_search_error(&mut self, _: ResponseData, _: Errors) {}
+
+        fn on_request_timeout(&mut self, _: SentRequest) {}
+    }
+}
@@ -1,35 +1,46 @@
 #![deny(unsafe_code)]
 
+pub mod connection_monitor;
 pub mod domain;
+pub mod framing;
+pub mod link_stats;
 pub mod parsers;
+pub mod reassembler;
 pub mod requests_controller;
+pub mod retry_controller;
 pub mod signals_controller;
 mod transmitter_to_slave;
 pub mod receiver_from_slave;
+pub mod version_negotiator;
+#[cfg(test)]
+pub mod simulator;
 
 use embedded_dma::{ReadBuffer, WriteBuffer};
 use domain::{*};
 use crate::errors::Errors;
 use crate::hal_ext::rtc_wrapper::{RelativeMillis, RelativeTimestampSource};
 use crate::hal_ext::serial_transfer::{ ReadableBuffer, RxTransfer, RxTransferProxy, SerialTransfer, TxTransfer, TxTransferProxy};
-use crate::services::slave_controller_link::parsers::{init_cache_getters, PayloadParserImpl, ResponseBodyParserImpl, ResponseParser, ResponseParserImpl, SignalParserImpl};
+use crate::hal_ext::tx_queue::TxQueueStats;
+use crate::services::slave_controller_link::connection_monitor::{read_request, ConnectionMonitor, ConnectionPolicy, ConnectionState,
+                                                                 ConnectionTransition};
+use crate::services::slave_controller_link::link_stats::{LinkStats, StatsErrorHandler, StatsResponseHandler};
+use crate::services::slave_controller_link::parsers::{PayloadParserImpl, ResponseBodyParserImpl, ResponseParser, ResponseParserImpl, SignalParserImpl};
+use crate::services::slave_controller_link::reassembler::FrameReassembler;
 use crate::services::slave_controller_link::receiver_from_slave::{ErrorHandler, ReceiverFromSlaveController, RequestsControllerSource};
 use crate::utils::dma_read_buffer::BufferWriter;
-use crate::services::slave_controller_link::requests_controller::{RequestsController, ResponseHandler};
+use crate::services::slave_controller_link::requests_controller::{RequestsController, RequestTimeouts, ResponseHandler, SentRequest};
+use crate::services::slave_controller_link::retry_controller::{RetryController, RetryPayload, RetryPolicy};
+use crate::services::slave_controller_link::version_negotiator::VersionNegotiator;
 use crate::services::slave_controller_link::signals_controller::{ControlledRequestSender, SignalControllerImpl, SignalsHandler};
+use crate::services::slave_clock::{ClockResponseHandler, SlaveClock};
+use crate::services::slave_state_mirror::{MirrorResponseHandler, MirrorSignalsHandler, SlaveStateMirror};
 use crate::services::slave_controller_link::transmitter_to_slave::{ErrorsSender, RequestsSender, TransmitterToSlaveController};
 use crate::services::slave_controller_link::receiver_from_slave::ReceiverFromSlaveControllerAbstract;
 
 
-pub fn init_slave_controllers() {
-    init_cache_getters();
-}
-
-
-
 pub struct SlaveControllerLink<T, R, TxBuff, RxBuff, SH, RH, EH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         RxBuff: WriteBuffer + ReadableBuffer,
         T: TxTransferProxy<TxBuff>,
         R: RxTransferProxy<RxBuff>,
@@ -38,15 +49,19 @@ pub struct SlaveControllerLink<T, R, TxBuff, RxBuff, SH, RH, EH>
         EH: ErrorHandler,
 {
     tx: TransmitterToSlaveController<TxBuff, TxTransfer<T, TxBuff>>,
-    rx: ReceiverFromSlaveController<RxTransfer<R, RxBuff>, EH, PayloadParserImpl, SignalParserImpl, ResponseParserImpl>,
-    signal_controller: SignalControllerImpl<SH>,
-    requests_controller: RequestsController<RH, ResponseBodyParserImpl>,
+    rx: ReceiverFromSlaveController<RxTransfer<R, RxBuff>, StatsErrorHandler<EH>, PayloadParserImpl, SignalParserImpl, ResponseParserImpl>,
+    signal_controller: SignalControllerImpl<MirrorSignalsHandler<SH>>,
+    requests_controller: LinkRequestsController<MirrorResponseHandler<RH>>,
 }
 
+type LinkRequestsController<RH> = RequestsController<StatsResponseHandler<ClockResponseHandler<LinkRetryController<RH>>>,
+    ResponseBodyParserImpl>;
+type LinkRetryController<RH> = RetryController<ConnectionMonitor<VersionNegotiator<RH>>>;
+
 
 impl <T, R, TxBuff, RxBuff, SH, RH, EH> SlaveControllerLink<T, R,TxBuff, RxBuff, SH, RH, EH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         RxBuff: WriteBuffer + ReadableBuffer,
         T: TxTransferProxy<TxBuff>,
         R: RxTransferProxy<RxBuff>,
@@ -54,22 +69,31 @@ impl <T, R, TxBuff, RxBuff, SH, RH, EH> SlaveControllerLink<T, R,TxBuff, RxBuff,
         RH: ResponseHandler,
         EH: ErrorHandler,
 {
+    /**
+    V1 and V2 `api_version` is only used until the slave reports its version, the handshake runs on
+    the first `poll_timeouts` and again after each slave restart. V3 selects framed link and skips it.
+     */
     pub fn create(serial_transfer: SerialTransfer<T, R, TxBuff, RxBuff>, signals_handler: SH,
                   responses_handler: RH, receive_error_handler: EH, api_version: Version) -> Result<Self, Errors>
     {
         let (tx, rx) = serial_transfer.into();
-        let tx = TransmitterToSlaveController::new(tx);
-        let response_body_parser = ResponseBodyParserImpl::create()?;
-        let requests_controller = RequestsController::new(responses_handler,
-                                                          response_body_parser, api_version);
+        let tx = TransmitterToSlaveController::new(tx, api_version);
+        let response_body_parser = ResponseBodyParserImpl::new();
+        let version_negotiator = VersionNegotiator::new(MirrorResponseHandler::new(responses_handler), api_version);
+        let connection_monitor = ConnectionMonitor::new(version_negotiator, ConnectionPolicy::default());
+        let retry_controller = RetryController::new(connection_monitor, RetryPolicy::default());
+        let requests_controller = RequestsController::new(
+            StatsResponseHandler::new(ClockResponseHandler::new(retry_controller)), response_body_parser, api_version);
          // let signals_handler = SignalsHandlerProxy::new(signals_handler,
          //                                                || {rtc.get_relative_timestamp()},
          //                                                &mut tx);
 
-        let signal_controller = SignalControllerImpl::new(signals_handler);
-        let payload_parser = PayloadParserImpl::new();
+        let signal_controller = SignalControllerImpl::new(MirrorSignalsHandler::new(signals_handler));
+        let payload_parser = PayloadParserImpl::new(api_version);
 
-        let rx = ReceiverFromSlaveController::new(rx, receive_error_handler, payload_parser);
+        let reassembler = FrameReassembler::new(api_version.framed());
+        let rx = ReceiverFromSlaveController::new(rx, StatsErrorHandler::new(receive_error_handler),
+                                                  payload_parser, reassembler);
         Ok(Self {
             tx,
             rx,
@@ -80,10 +104,25 @@ impl <T, R, TxBuff, RxBuff, SH, RH, EH> SlaveControllerLink<T, R,TxBuff, RxBuff,
 
     #[inline(always)]
     pub fn on_get_command<TS: RelativeTimestampSource>( &mut self, time_source: &mut TS) {
+        let now = time_source.get();
+        self.requests_controller.response_handler().set_now(now);
+        self.clock_handler().set_now(now);
+        self.connection_monitor().set_now(now);
         let Self{ rx, tx,
             signal_controller, requests_controller} = { &mut *self };
         let mut sender = SenderImp::new(tx, requests_controller);
         rx.on_get_command(signal_controller,  &mut sender, time_source);
+        if let Some(time_requested_at) = self.signal_controller.take_time_requested() {
+            self.version_negotiator().restart();
+            self.connection_monitor().on_restart();
+            self.clock_handler().on_time_requested(time_requested_at.seconds());
+        }
+        let now = time_source.get();
+        let Self { signal_controller, requests_controller, .. } = self;
+        signal_controller.signal_handler().apply_pending(state_mirror_of(requests_controller), now);
+        self.negotiate_version(now);
+        self.supervise_connection(now);
+        self.resend_failed_requests(now);
     }
 
     #[inline(always)]
@@ -98,13 +137,230 @@ impl <T, R, TxBuff, RxBuff, SH, RH, EH> SlaveControllerLink<T, R,TxBuff, RxBuff,
 
     #[inline(always)]
     pub fn send_request<I: DataInstruction>(&mut self, operation: Operation, instruction: I, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
-         self.tx.send_request(operation, instruction, timestamp, &mut self.requests_controller)
+        send_tracked(&mut self.tx, &mut self.requests_controller, operation, instruction, timestamp)
+    }
+
+    /** Tracked as a request, the acknowledgement comes to `ResponseHandler::on_request_success`. */
+    #[inline(always)]
+    pub fn send_command(&mut self, command: Commands, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
+        send_tracked(&mut self.tx, &mut self.requests_controller, Operation::Command, command, timestamp)
+    }
+
+    /** Expires unanswered requests and resends the failed ones which backoff has passed. */
+    pub fn poll_timeouts<TS: RelativeTimestampSource>(&mut self, time_source: &mut TS) {
+        let now = time_source.get();
+        self.requests_controller.poll_timeouts(now);
+        self.negotiate_version(now);
+        self.supervise_connection(now);
+        self.resend_failed_requests(now);
+    }
+
+    /** Version of the slave, `None` until the handshake completes. */
+    #[inline(always)]
+    pub fn version(&mut self) -> Option<Version> {
+        self.version_negotiator().version()
+    }
+
+    /** Last known state of the slave relays. */
+    #[inline(always)]
+    pub fn state_mirror(&mut self) -> &SlaveStateMirror {
+        state_mirror_of(&mut self.requests_controller)
+    }
+
+    /** Offset of the slave clock, for the translation of the slave timestamps to the hub time. */
+    #[inline(always)]
+    pub fn slave_clock(&mut self) -> &SlaveClock {
+        self.clock_handler().clock()
+    }
+
+    /** Connection state of the slave, followed by its answers and the heartbeat reads. */
+    #[inline(always)]
+    pub fn connection_state(&mut self) -> ConnectionState {
+        self.connection_monitor().state()
+    }
+
+    /** The connection state changes in the order they happened, the oldest are lost if not taken. */
+    #[inline(always)]
+    pub fn take_connection_transition(&mut self) -> Option<ConnectionTransition> {
+        self.connection_monitor().take_transition()
+    }
+
+    #[inline(always)]
+    pub fn connection_policy(&mut self) -> &mut ConnectionPolicy {
+        self.connection_monitor().policy()
+    }
+
+    #[inline(always)]
+    fn clock_handler(&mut self) -> &mut ClockResponseHandler<LinkRetryController<MirrorResponseHandler<RH>>> {
+        clock_handler_of(&mut self.requests_controller)
+    }
+
+    #[inline(always)]
+    fn version_negotiator(&mut self) -> &mut VersionNegotiator<MirrorResponseHandler<RH>> {
+        self.connection_monitor().response_handler()
+    }
+
+    #[inline(always)]
+    fn connection_monitor(&mut self) -> &mut ConnectionMonitor<VersionNegotiator<MirrorResponseHandler<RH>>> {
+        self.retry_controller().response_handler()
+    }
+
+    #[inline(always)]
+    fn retry_controller(&mut self) -> &mut LinkRetryController<MirrorResponseHandler<RH>> {
+        retry_controller_of(&mut self.requests_controller)
+    }
+
+    /** Sends the heartbeat and resync reads, forgets the slave version and clock after its restart. */
+    fn supervise_connection(&mut self, now: RelativeMillis) {
+        if self.connection_monitor().take_restart() {
+            self.version_negotiator().restart();
+            state_mirror_of(&mut self.requests_controller).invalidate();
+            self.clock_handler().invalidate();
+        }
+        let version = self.version_negotiator().version();
+        self.connection_monitor().poll(now, version);
+        let Some(version) = version else {
+            return;
+        };
+        if let Some(code) = self.connection_monitor().next_request(version, now) {
+            if let Some(instruction) = read_request(code) {
+                let result = send_tracked(&mut self.tx, &mut self.requests_controller, Operation::Read, instruction, now);
+                if result.is_ok() {
+                    self.connection_monitor().on_requested(code, now);
+                }
+            }
+        }
+    }
+
+    fn negotiate_version(&mut self, now: RelativeMillis) {
+        if let Some(version) = self.version_negotiator().version() {
+            self.requests_controller.set_version(version);
+        } else if self.version_negotiator().needs_request() && !self.requests_controller.has_sent_requests() {
+            // answers to the requests sent in the previous version format would not be matched in V1 one
+            // slave of any version should understand V1 request
+            self.requests_controller.set_version(Version::V1);
+            let result = send_tracked(&mut self.tx, &mut self.requests_controller, Operation::Read,
+                                      DataInstructions::Version(Conversation::Request(EmptyRequest::new())), now);
+            if result.is_ok() {
+                self.version_negotiator().on_requested();
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn retry_policy(&mut self) -> &mut RetryPolicy {
+        self.retry_controller().policy()
+    }
+
+    fn resend_failed_requests(&mut self, now: RelativeMillis) {
+        self.retry_controller().schedule(now);
+        while let Some(pending) = self.retry_controller().take_due(now) {
+            let result = self.tx.resend_request(pending.request(), pending.payload(), now, &mut self.requests_controller);
+            self.retry_controller().on_resent(pending, now, result);
+        }
+    }
+
+    #[inline(always)]
+    pub fn request_timeouts(&mut self) -> &mut RequestTimeouts {
+        self.requests_controller.timeouts_mut()
+    }
+
+    pub fn stats(&mut self) -> LinkStats {
+        let frames_received = self.rx.reassembler().frames();
+        let rx_stats = self.rx.inner_rx().stats();
+        let tx_stats = self.tx.inner_tx().stats();
+        LinkStats::new(frames_received, rx_stats, tx_stats, self.rx.error_handler(),
+                       self.requests_controller.response_handler())
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.rx.reassembler().reset_frames();
+        self.rx.inner_rx().reset_stats();
+        self.rx.error_handler().reset();
+        self.tx.inner_tx().reset_stats();
+        self.requests_controller.response_handler().reset();
+    }
+
+    /** Frames waiting for the end of the current transfer and the ones lost on the queue overflow. */
+    #[inline(always)]
+    pub fn tx_queue_stats(&mut self) -> TxQueueStats {
+        self.tx.inner_tx().queue_stats()
+    }
+}
+
+/** Object safe view of a link, so links on different ports could be kept together. */
+pub trait SlaveLink {
+    fn on_get_command(&mut self, time_source: &mut dyn RelativeTimestampSource);
+    fn on_rx_dma_interrupts(&mut self);
+    fn on_tx_dma_interrupts(&mut self);
+    fn poll_timeouts(&mut self, time_source: &mut dyn RelativeTimestampSource);
+    fn send_request(&mut self, operation: Operation, instruction: DataInstructions, timestamp: RelativeMillis) -> Result<Option<u32>, Errors>;
+    fn send_command(&mut self, command: Commands, timestamp: RelativeMillis) -> Result<Option<u32>, Errors>;
+    fn version(&mut self) -> Option<Version>;
+    fn state_mirror(&mut self) -> &SlaveStateMirror;
+    fn slave_clock(&mut self) -> &SlaveClock;
+    fn connection_state(&mut self) -> ConnectionState;
+    fn take_connection_transition(&mut self) -> Option<ConnectionTransition>;
+}
+
+impl <T, R, TxBuff, RxBuff, SH, RH, EH> SlaveLink for SlaveControllerLink<T, R,TxBuff, RxBuff, SH, RH, EH>
+    where
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
+        RxBuff: WriteBuffer + ReadableBuffer,
+        T: TxTransferProxy<TxBuff>,
+        R: RxTransferProxy<RxBuff>,
+        SH: SignalsHandler,
+        RH: ResponseHandler,
+        EH: ErrorHandler,
+{
+    fn on_get_command(&mut self, mut time_source: &mut dyn RelativeTimestampSource) {
+        SlaveControllerLink::on_get_command(self, &mut time_source);
+    }
+
+    fn on_rx_dma_interrupts(&mut self) {
+        SlaveControllerLink::on_rx_dma_interrupts(self);
+    }
+
+    fn on_tx_dma_interrupts(&mut self) {
+        SlaveControllerLink::on_tx_dma_interrupts(self);
+    }
+
+    fn poll_timeouts(&mut self, mut time_source: &mut dyn RelativeTimestampSource) {
+        SlaveControllerLink::poll_timeouts(self, &mut time_source);
+    }
+
+    fn send_request(&mut self, operation: Operation, instruction: DataInstructions, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
+        SlaveControllerLink::send_request(self, operation, instruction, timestamp)
+    }
+
+    fn send_command(&mut self, command: Commands, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
+        SlaveControllerLink::send_command(self, command, timestamp)
+    }
+
+    fn version(&mut self) -> Option<Version> {
+        SlaveControllerLink::version(self)
+    }
+
+    fn state_mirror(&mut self) -> &SlaveStateMirror {
+        SlaveControllerLink::state_mirror(self)
+    }
+
+    fn slave_clock(&mut self) -> &SlaveClock {
+        SlaveControllerLink::slave_clock(self)
+    }
+
+    fn connection_state(&mut self) -> ConnectionState {
+        SlaveControllerLink::connection_state(self)
+    }
+
+    fn take_connection_transition(&mut self) -> Option<ConnectionTransition> {
+        SlaveControllerLink::take_connection_transition(self)
     }
 }
 
 impl <T, R, TxBuff, RxBuff, SH, RH, EH> ControlledRequestSender for SlaveControllerLink<T, R,TxBuff, RxBuff, SH, RH, EH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         RxBuff: WriteBuffer + ReadableBuffer,
         T: TxTransferProxy<TxBuff>,
         R: RxTransferProxy<RxBuff>,
@@ -119,7 +375,7 @@ impl <T, R, TxBuff, RxBuff, SH, RH, EH> ControlledRequestSender for SlaveControl
 
 impl <T, R, TxBuff, RxBuff, SH, RH, EH> ErrorsSender for SlaveControllerLink<T, R,TxBuff, RxBuff, SH, RH, EH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         RxBuff: WriteBuffer + ReadableBuffer,
         T: TxTransferProxy<TxBuff>,
         R: RxTransferProxy<RxBuff>,
@@ -133,24 +389,66 @@ impl <T, R, TxBuff, RxBuff, SH, RH, EH> ErrorsSender for SlaveControllerLink<T,
     }
 }
 
+fn send_tracked<I, T, TxBuff, RH>(tx: &mut TransmitterToSlaveController<TxBuff, TxTransfer<T, TxBuff>>,
+                                  requests_controller: &mut LinkRequestsController<RH>,
+                                  operation: Operation, instruction: I, timestamp: RelativeMillis) -> Result<Option<u32>, Errors>
+    where
+        I: DataInstruction,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
+        T: TxTransferProxy<TxBuff>,
+        RH: ResponseHandler,
+{
+    retry_controller_of(requests_controller).check_track()?;
+    let code = instruction.code();
+    // too long payloads are sent without retries
+    let payload = RetryPayload::create(&instruction);
+    let id = tx.send_request(operation, instruction, timestamp, requests_controller)?;
+    if let Ok(payload) = payload {
+        retry_controller_of(requests_controller).track(SentRequest::new(id, operation, code, timestamp), payload);
+    }
+    Ok(id)
+}
+
+/**
+The layers of the response handlers are reached only through these functions, so they are the only place
+which knows the order of the layers. They take the requests controller, not the link, for the split borrows.
+ */
+#[inline(always)]
+fn clock_handler_of<RH: ResponseHandler>(requests_controller: &mut LinkRequestsController<RH>)
+        -> &mut ClockResponseHandler<LinkRetryController<RH>> {
+    requests_controller.response_handler().response_handler()
+}
+
+#[inline(always)]
+fn retry_controller_of<RH: ResponseHandler>(requests_controller: &mut LinkRequestsController<RH>)
+        -> &mut LinkRetryController<RH> {
+    clock_handler_of(requests_controller).response_handler()
+}
+
+#[inline(always)]
+fn state_mirror_of<RH: ResponseHandler>(requests_controller: &mut LinkRequestsController<MirrorResponseHandler<RH>>)
+        -> &mut SlaveStateMirror {
+    retry_controller_of(requests_controller).response_handler().response_handler().response_handler().mirror_mut()
+}
+
 struct SenderImp<'a, T, TxBuff, RH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         T: TxTransferProxy<TxBuff>,
         RH: ResponseHandler,
 {
     tx: &'a mut TransmitterToSlaveController<TxBuff, TxTransfer<T, TxBuff>>,
-    requests_controller: &'a mut RequestsController<RH, ResponseBodyParserImpl>,
+    requests_controller: &'a mut LinkRequestsController<RH>,
 }
 
 impl <'a, T, TxBuff, RH>SenderImp<'a, T, TxBuff, RH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         T: TxTransferProxy<TxBuff>,
         RH: ResponseHandler,
 {
     fn new(tx: &'a mut TransmitterToSlaveController<TxBuff, TxTransfer<T, TxBuff>>,
-           requests_controller: &'a mut RequestsController<RH, ResponseBodyParserImpl>) -> Self {
+           requests_controller: &'a mut LinkRequestsController<RH>) -> Self {
         Self {
             tx,
             requests_controller
@@ -160,18 +458,18 @@ impl <'a, T, TxBuff, RH>SenderImp<'a, T, TxBuff, RH>
 
 impl <'a, T, TxBuff, RH> ControlledRequestSender for SenderImp<'a, T, TxBuff, RH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         T: TxTransferProxy<TxBuff>,
         RH: ResponseHandler,
 {
     fn send(&mut self, operation: Operation, instruction: DataInstructions, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
-        self.tx.send_request(operation, instruction, timestamp, self.requests_controller)
+        send_tracked(self.tx, self.requests_controller, operation, instruction, timestamp)
     }
 }
 
 impl <'a, T, TxBuff, RH> ErrorsSender for SenderImp<'a, T, TxBuff, RH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         T: TxTransferProxy<TxBuff>,
         RH: ResponseHandler,
 {
@@ -181,16 +479,16 @@ impl <'a, T, TxBuff, RH> ErrorsSender for SenderImp<'a, T, TxBuff, RH>
     }
 }
 
-impl <'a, T, TxBuff, RH, RP> RequestsControllerSource<RequestsController<RH, ResponseBodyParserImpl>, RP> for SenderImp<'a, T, TxBuff, RH>
+impl <'a, T, TxBuff, RH, RP> RequestsControllerSource<LinkRequestsController<RH>, RP> for SenderImp<'a, T, TxBuff, RH>
     where
-        TxBuff: ReadBuffer + BufferWriter,
+        TxBuff: ReadBuffer<Word = u8> + BufferWriter,
         T: TxTransferProxy<TxBuff>,
         RH: ResponseHandler,
         RP: ResponseParser,
 {
     #[inline(always)]
 
-    fn requests_controller(&mut self) -> &mut RequestsController<RH, ResponseBodyParserImpl> {
+    fn requests_controller(&mut self) -> &mut LinkRequestsController<RH> {
         &mut self.requests_controller
     }
 }
@@ -198,5 +496,613 @@ impl <'a, T, TxBuff, RH, RP> RequestsControllerSource<RequestsController<RH, Res
 
 #[cfg(test)]
 mod tests {
+    use alloc::rc::Rc;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::slave_controller_link::connection_monitor::{TransitionCause, DEFAULT_HEARTBEAT_INTERVAL_MS,
+                                                                     DEFAULT_OFFLINE_AFTER_MS};
+    use crate::services::slave_controller_link::parsers::ResponseData;
+    use crate::services::slave_controller_link::simulator::{serial_transfer, Fault, SimulatedRxTransfer, SimulatedSlave, SimulatedTxTransfer};
+    use crate::utils::dma_read_buffer::Buffer;
+
+    const BUFFER_SIZE: usize = 128;
+
+    #[test]
+    fn test_v1_round_trip() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V1, id, 7), 2, Version::V1);
+
+        harness.negotiate();
+        assert_eq!(Some(Version::V1), harness.link.version());
+
+        assert_eq!(Ok(None), harness.send(Operation::Read, DataInstructions::Id(Conversation::Request(EmptyRequest::new()))));
+        assert_eq!(Ok(None), harness.send(Operation::Read, DataInstructions::State(Conversation::Request(EmptyRequest::new()))));
+
+        assert_eq!(vec![DataInstructionCodes::Id, DataInstructionCodes::State], harness.handlers.borrow().response_codes());
+        let mirror = harness.link.state_mirror();
+        assert_eq!(Some(id), mirror.id());
+        assert_eq!(2, mirror.relays_count());
+        assert!(harness.handlers.borrow().errors.is_empty());
+    }
+
+    #[test]
+    fn test_v2_slave_is_negotiated_and_answers_by_id() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 3, Version::V1);
+
+        harness.negotiate();
+        assert_eq!(Some(Version::V2), harness.link.version());
+
+        let id = harness.send(Operation::Read, DataInstructions::Settings(Conversation::Request(EmptyRequest::new())));
+
+        assert!(matches!(id, Ok(Some(_))));
+        assert_eq!(Some(&(Operation::Read, DataInstructionCodes::Settings, id.unwrap())),
+                   harness.slave.borrow().requests().last());
+        let handlers = harness.handlers.borrow();
+        let (request, response) = handlers.responses.last().unwrap();
+        assert_eq!(id.unwrap(), request.id());
+        match response {
+            DataInstructions::Settings(Conversation::Data(settings)) => assert_eq!(3, settings.get_relays().len()),
+            _ => panic!("Settings data expected, got {:?}", response),
+        }
+        drop(handlers);
+        let mirror = harness.link.state_mirror();
+        assert_eq!(3, mirror.relays_count());
+        assert_eq!(2, mirror.relay(1).unwrap().settings().set_pin().data());
+    }
+
+    #[test]
+    fn test_v3_framed_round_trip() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V3, 1, 7), 2, Version::V3);
+        assert_eq!(Some(Version::V3), harness.link.version());
+
+        assert!(harness.send(Operation::Set, DataInstructions::RelaySwitchedOn(
+            Conversation::Data(RelaySingleState::new(1, true)))).is_ok());
+        assert!(harness.send(Operation::Read, DataInstructions::All(Conversation::Request(EmptyRequest::new()))).is_ok());
+
+        assert!(harness.slave.borrow().relays()[1].on);
+        assert_eq!(1, harness.handlers.borrow().successes.len());
+        let mirror = harness.link.state_mirror();
+        assert_eq!(Some(1), mirror.id());
+        assert!(!mirror.relay(0).unwrap().is_on());
+        assert!(mirror.relay(1).unwrap().is_on());
+        assert_eq!(0, harness.slave.borrow().dropped_frames());
+    }
+
+    #[test]
+    fn test_injected_error_is_reported() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V2);
+        harness.negotiate();
+        harness.slave.borrow_mut().inject(DataInstructionCodes::RelayState, Fault::Error(ErrorCode::ERelayIndexOutOfRange));
+
+        assert!(harness.send(Operation::Read, DataInstructions::RelayState(
+            Conversation::Request(RelayIndexRequest::new(1)))).is_ok());
+
+        let handlers = harness.handlers.borrow();
+        assert_eq!(1, handlers.request_errors.len());
+        assert_eq!(DataInstructionCodes::RelayState, handlers.request_errors[0].0.instruction());
+        assert_eq!(ErrorCode::ERelayIndexOutOfRange, handlers.request_errors[0].1);
+        assert!(handlers.responses.is_empty());
+    }
 
+    #[test]
+    fn test_retryable_error_is_resent() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V2);
+        harness.negotiate();
+        let backoff = harness.link.retry_policy().backoff();
+        harness.slave.borrow_mut().inject(DataInstructionCodes::Id, Fault::Error(ErrorCode::EInstructionUnrecognized));
+
+        assert!(harness.send(Operation::Read, DataInstructions::Id(Conversation::Request(EmptyRequest::new()))).is_ok());
+        assert!(harness.handlers.borrow().responses.is_empty());
+
+        harness.advance(backoff);
+        harness.poll();
+
+        assert_eq!(vec![DataInstructionCodes::Id], harness.handlers.borrow().response_codes());
+        assert!(harness.handlers.borrow().request_errors.is_empty());
+        assert_eq!(Some(1), harness.link.state_mirror().id());
+    }
+
+    #[test]
+    fn test_lost_response_times_out() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V2);
+        harness.negotiate();
+        *harness.link.retry_policy() = RetryPolicy::new(1, 0);
+        harness.slave.borrow_mut().inject(DataInstructionCodes::CurrentTime, Fault::NoResponse);
+
+        assert!(harness.send(Operation::Read, DataInstructions::CurrentTime(Conversation::Request(EmptyRequest::new()))).is_ok());
+        let timeout = harness.link.request_timeouts().get(DataInstructionCodes::CurrentTime);
+        harness.advance(timeout);
+        harness.poll();
+
+        let handlers = harness.handlers.borrow();
+        assert_eq!(1, handlers.timeouts.len());
+        assert_eq!(DataInstructionCodes::CurrentTime, handlers.timeouts[0].instruction());
+        // the heartbeat sent after the silence is answered
+        assert!(handlers.responses.iter().all(|(request, _)| request.id() != handlers.timeouts[0].id()));
+    }
+
+    #[test]
+    fn test_corrupted_frame_is_not_matched() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V1, 1, 7), 2, Version::V1);
+        harness.negotiate();
+        harness.slave.borrow_mut().inject(DataInstructionCodes::Id, Fault::Corrupt);
+
+        assert!(harness.send(Operation::Read, DataInstructions::Id(Conversation::Request(EmptyRequest::new()))).is_ok());
+
+        let handlers = harness.handlers.borrow();
+        assert!(handlers.responses.is_empty());
+        assert_eq!(1, handlers.errors.len());
+    }
+
+    #[test]
+    fn test_relay_signals_update_mirror() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V2);
+        harness.negotiate();
+        assert!(harness.send(Operation::Read, DataInstructions::State(Conversation::Request(EmptyRequest::new()))).is_ok());
+
+        harness.slave.borrow_mut().switch_relay(1, true).unwrap();
+        harness.slave.borrow_mut().set_monitoring(0, true).unwrap();
+        harness.pump();
+
+        let signals = harness.handlers.borrow().signals.iter().map(|signal| signal.code()).collect::<Vec<_>>();
+        assert_eq!(vec![Signals::RelayStateChanged, Signals::MonitoringStateChanged], signals);
+        let mirror = harness.link.state_mirror();
+        assert!(mirror.relay(1).unwrap().is_on());
+        assert!(mirror.relay(0).unwrap().is_monitoring_on());
+    }
+
+    #[test]
+    fn test_restart_sets_timestamp_and_repeats_handshake() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V1);
+        harness.negotiate();
+        harness.advance(rand::thread_rng().gen_range(10_000..1_000_000));
+
+        harness.slave.borrow_mut().restart();
+        harness.pump();
+        harness.negotiate();
+
+        assert_eq!(harness.time_source.get().seconds(), harness.slave.borrow().remote_timestamp());
+        assert_eq!(Some(Version::V2), harness.link.version());
+        let handshakes = harness.slave.borrow().requests().iter()
+            .filter(|(operation, instruction, id)| *operation == Operation::Read &&
+                *instruction == DataInstructionCodes::Version && id.is_none())
+            .count();
+        assert_eq!(2, handshakes);
+    }
+
+    #[test]
+    fn test_signal_timestamps_are_translated_to_hub_time() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V2);
+        harness.negotiate();
+        harness.advance(rand::thread_rng().gen_range(10_000..1_000_000));
+        assert_eq!(None, harness.link.slave_clock().offset_ms());
+
+        harness.slave.borrow_mut().restart();
+        harness.pump();
+        harness.negotiate();
+        let set_at = harness.time_source.get();
+        assert!(harness.link.slave_clock().offset_ms().is_some());
+
+        harness.advance(30_000);
+        harness.slave.borrow_mut().advance(30);
+        assert!(harness.send(Operation::Read, DataInstructions::CurrentTime(Conversation::Request(EmptyRequest::new()))).is_ok());
+        harness.slave.borrow_mut().switch_relay(1, true).unwrap();
+        harness.pump();
+
+        let now = harness.time_source.get();
+        let signal_time = harness.handlers.borrow().signals.last().and_then(|signal| signal.relative_timestamp());
+        let hub_time = signal_time.and_then(|time| harness.link.slave_clock().to_hub_time(time, now)).unwrap();
+        assert_eq!(set_at.value().wrapping_add(30_000), now.value());
+        let error = hub_time.timestamp().value().wrapping_sub(now.value()) as i32;
+        assert!(error.unsigned_abs() <= hub_time.uncertainty_ms());
+    }
+
+    #[test]
+    fn test_switch_limit_disables_relay() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 1, Version::V2);
+        harness.negotiate();
+        harness.slave.borrow_mut().set_switch_counting(60, 1);
+        assert!(harness.send(Operation::Read, DataInstructions::State(Conversation::Request(EmptyRequest::new()))).is_ok());
+
+        for on in [true, false] {
+            assert!(harness.send(Operation::Set, DataInstructions::RelaySwitchedOn(
+                Conversation::Data(RelaySingleState::new(0, on)))).is_ok());
+        }
+        assert!(harness.send(Operation::Read, DataInstructions::RelayState(
+            Conversation::Request(RelayIndexRequest::new(0)))).is_ok());
+
+        let relay = harness.link.state_mirror().relay(0).copied();
+        assert!(relay.is_some_and(|relay| relay.is_on() && relay.is_disabled()));
+    }
+
+    #[test]
+    fn test_command_is_acknowledged() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 1, Version::V2);
+        harness.negotiate();
+        harness.slave.borrow_mut().set_switch_counting(60, 1);
+        assert!(harness.send(Operation::Set, DataInstructions::RelaySwitchedOn(
+            Conversation::Data(RelaySingleState::new(0, true)))).is_ok());
+
+        let now = harness.time_source.get();
+        let id = harness.link.send_command(Commands::ClearSwitchCount, now);
+        harness.pump();
+        assert!(harness.send(Operation::Set, DataInstructions::RelaySwitchedOn(
+            Conversation::Data(RelaySingleState::new(0, false)))).is_ok());
+
+        assert!(matches!(id, Ok(Some(_))));
+        assert_eq!(Some(&(Operation::Command, DataInstructionCodes::ClearSwitchCount, id.unwrap())),
+                   harness.slave.borrow().requests().iter().rev().nth(1));
+        let handlers = harness.handlers.borrow();
+        let acknowledged = handlers.successes.iter().map(|request| request.operation()).collect::<Vec<_>>();
+        assert_eq!(vec![Operation::Set, Operation::Command, Operation::Set], acknowledged);
+        // the counter was cleared, so the second switch is still allowed
+        assert!(!harness.slave.borrow().relays()[0].on);
+        assert!(!harness.slave.borrow().relays()[0].disabled);
+    }
+
+    #[test]
+    fn test_rejected_command_is_reported() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V1, 1, 7), 1, Version::V1);
+        harness.negotiate();
+        harness.slave.borrow_mut().inject(DataInstructionCodes::ClearSwitchCount, Fault::Error(ErrorCode::EUndefinedOperation));
+        *harness.link.retry_policy() = RetryPolicy::new(1, 0);
+
+        let now = harness.time_source.get();
+        assert_eq!(Ok(None), harness.link.send_command(Commands::ClearSwitchCount, now));
+        harness.pump();
+
+        let handlers = harness.handlers.borrow();
+        assert_eq!(1, handlers.request_errors.len());
+        assert_eq!(Operation::Command, handlers.request_errors[0].0.operation());
+        assert_eq!(ErrorCode::EUndefinedOperation, handlers.request_errors[0].1);
+        assert!(handlers.successes.is_empty());
+    }
+
+    #[test]
+    fn test_back_to_back_signals_in_one_chunk() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V1, 1, 7), 2, Version::V1);
+        harness.negotiate();
+
+        harness.slave.borrow_mut().switch_relay(0, true).unwrap();
+        harness.slave.borrow_mut().set_control(1, true).unwrap();
+        harness.slave.borrow_mut().rechunk_output(usize::MAX);
+        harness.pump();
+
+        let signals = harness.handlers.borrow().signals.iter().map(|signal| signal.code()).collect::<Vec<_>>();
+        assert_eq!(vec![Signals::RelayStateChanged, Signals::ControlStateChanged], signals);
+        assert!(harness.handlers.borrow().errors.is_empty());
+    }
+
+    #[test]
+    fn test_framed_response_split_across_idle_events() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V3, 1, 7), 16, Version::V3);
+        harness.negotiate();
+        let now = harness.time_source.get();
+
+        assert!(harness.link.send_request(Operation::Read, DataInstructions::All(Conversation::Request(EmptyRequest::new())), now).is_ok());
+        harness.slave.borrow_mut().switch_relay(3, true).unwrap();
+        harness.slave.borrow_mut().rechunk_output(5);
+        harness.pump();
+
+        assert_eq!(vec![DataInstructionCodes::All], harness.handlers.borrow().response_codes());
+        assert_eq!(1, harness.handlers.borrow().signals.len());
+        assert!(harness.handlers.borrow().errors.is_empty());
+        assert_eq!(16, harness.link.state_mirror().relays_count());
+    }
+
+    #[test]
+    fn test_time_stamp_reply_is_queued_behind_request() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V1, 1, 7), 2, Version::V1);
+        harness.negotiate();
+        harness.advance(rand::thread_rng().gen_range(10_000..1_000_000));
+        let now = harness.time_source.get();
+
+        harness.slave.borrow_mut().restart();
+        assert!(harness.link.send_request(Operation::Read, DataInstructions::Id(Conversation::Request(EmptyRequest::new())), now).is_ok());
+        // the request transfer is not completed yet, so the reply has to wait for it
+        while harness.slave.borrow().has_output() {
+            harness.link.on_get_command(&mut harness.time_source);
+        }
+        assert!(harness.link.tx_queue_stats().length() > 0);
+        harness.pump();
+
+        assert_eq!(now.seconds(), harness.slave.borrow().remote_timestamp());
+        // the state of the restarted slave is read again
+        assert_eq!(vec![DataInstructionCodes::Id, DataInstructionCodes::Settings, DataInstructionCodes::State,
+                        DataInstructionCodes::StateFixSettings], harness.handlers.borrow().response_codes());
+        assert_eq!(Some(Version::V1), harness.link.version());
+        let stats = harness.link.tx_queue_stats();
+        assert_eq!(0, stats.length());
+        assert_eq!(0, stats.overflows());
+        assert!(harness.handlers.borrow().errors.is_empty());
+    }
+
+    #[test]
+    fn test_stats_count_traffic_and_failures() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V2);
+        harness.negotiate();
+        harness.link.reset_stats();
+        *harness.link.retry_policy() = RetryPolicy::new(1, 0);
+        let delay = rand::thread_rng().gen_range(1..100);
+
+        let now = harness.time_source.get();
+        assert!(harness.link.send_request(Operation::Read, DataInstructions::Id(Conversation::Request(EmptyRequest::new())), now).is_ok());
+        harness.advance(delay);
+        harness.pump();
+        harness.slave.borrow_mut().inject(DataInstructionCodes::RelayState, Fault::Error(ErrorCode::ERelayIndexOutOfRange));
+        assert!(harness.send(Operation::Read, DataInstructions::RelayState(Conversation::Request(RelayIndexRequest::new(1)))).is_ok());
+        harness.slave.borrow_mut().inject(DataInstructionCodes::Id, Fault::Corrupt);
+        assert!(harness.send(Operation::Read, DataInstructions::Id(Conversation::Request(EmptyRequest::new()))).is_ok());
+        harness.slave.borrow_mut().inject(DataInstructionCodes::CurrentTime, Fault::NoResponse);
+        assert!(harness.send(Operation::Read, DataInstructions::CurrentTime(Conversation::Request(EmptyRequest::new()))).is_ok());
+        let timeout = harness.link.request_timeouts().get(DataInstructionCodes::CurrentTime);
+        harness.advance(timeout);
+        harness.poll();
+
+        let stats = harness.link.stats();
+        // with the heartbeat sent after the silence
+        assert_eq!(5, stats.frames_sent());
+        assert_eq!(4, stats.frames_received());
+        assert_eq!(1, stats.slave_errors().get(ErrorCode::ERelayIndexOutOfRange));
+        assert_eq!(1, stats.parse_errors().total());
+        // the request with the corrupted answer is not matched and times out too
+        assert_eq!(2, stats.timeouts());
+        assert_eq!(0, stats.overruns());
+        assert_eq!(Some(delay), stats.round_trip().max());
+        assert_eq!(Some(0), stats.round_trip().min());
+
+        harness.link.reset_stats();
+
+        let stats = harness.link.stats();
+        assert_eq!(0, stats.frames_sent());
+        assert_eq!(0, stats.frames_received());
+        assert_eq!(0, stats.slave_errors().total());
+        assert_eq!(0, stats.timeouts());
+        assert_eq!(None, stats.round_trip().average());
+    }
+
+    #[test]
+    fn test_connection_is_followed_and_resynced_after_reconnect() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V1);
+        assert_eq!(ConnectionState::Unknown, harness.link.connection_state());
+
+        harness.negotiate();
+        assert_eq!(vec![(ConnectionState::Handshaking, TransitionCause::Connected),
+                        (ConnectionState::Online, TransitionCause::Synced)], harness.transitions());
+        let resync = [DataInstructionCodes::All, DataInstructionCodes::StateFixSettings,
+            DataInstructionCodes::SwitchCountingSettings];
+        assert_eq!(resync.to_vec(), harness.slave_reads()[1..]);
+
+        harness.slave.borrow_mut().set_connected(false);
+        harness.wait(DEFAULT_OFFLINE_AFTER_MS);
+        assert_eq!(ConnectionState::Offline, harness.link.connection_state());
+        assert_eq!(Some(&(ConnectionState::Offline, TransitionCause::Silent)), harness.transitions().last());
+
+        harness.slave.borrow_mut().set_connected(true);
+        let reads_count = harness.slave_reads().len();
+        harness.wait(DEFAULT_HEARTBEAT_INTERVAL_MS);
+
+        assert_eq!(ConnectionState::Online, harness.link.connection_state());
+        assert_eq!(vec![(ConnectionState::Handshaking, TransitionCause::Connected),
+                        (ConnectionState::Online, TransitionCause::Synced)], harness.transitions());
+        let reads = harness.slave_reads();
+        assert_eq!(DataInstructionCodes::CurrentTime, reads[reads_count]);
+        assert_eq!(resync.to_vec(), reads[reads_count + 1..]);
+    }
+
+    #[test]
+    fn test_failed_requests_degrade_connection() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V2);
+        harness.negotiate();
+        harness.transitions();
+        *harness.link.retry_policy() = RetryPolicy::new(1, 0);
+
+        for _ in 0..2 {
+            harness.slave.borrow_mut().inject(DataInstructionCodes::Id, Fault::NoResponse);
+            assert!(harness.send(Operation::Read, DataInstructions::Id(Conversation::Request(EmptyRequest::new()))).is_ok());
+        }
+        let timeout = harness.link.request_timeouts().get(DataInstructionCodes::Id);
+        harness.advance(timeout);
+        harness.poll();
+
+        // the heartbeat sent after the silence is answered
+        assert_eq!(vec![(ConnectionState::Degraded, TransitionCause::RequestsFailed),
+                        (ConnectionState::Online, TransitionCause::Recovered)], harness.transitions());
+    }
+
+    #[test]
+    fn test_slave_clock_going_back_is_restart() {
+        let mut harness = Harness::new(SimulatedSlave::new(Version::V2, 1, 7), 2, Version::V1);
+        harness.negotiate();
+        harness.slave.borrow_mut().advance(30);
+        harness.wait(DEFAULT_HEARTBEAT_INTERVAL_MS);
+        assert!(harness.link.slave_clock().offset_ms().is_some());
+        harness.transitions();
+
+        // the time request signal is lost
+        harness.slave.borrow_mut().restart();
+        harness.slave.borrow_mut().lose_output();
+        harness.wait(2 * DEFAULT_HEARTBEAT_INTERVAL_MS);
+
+        assert_eq!(vec![(ConnectionState::Handshaking, TransitionCause::Rebooted),
+                        (ConnectionState::Online, TransitionCause::Synced)], harness.transitions());
+        let handshakes = harness.slave_reads().iter().filter(|code| **code == DataInstructionCodes::Version).count();
+        assert_eq!(2, handshakes);
+        assert!(harness.link.state_mirror().synced_at().is_some());
+    }
+
+    type SimulatedLink = SlaveControllerLink<SimulatedTxTransfer<BUFFER_SIZE>, SimulatedRxTransfer<BUFFER_SIZE>,
+        Buffer<BUFFER_SIZE>, Buffer<BUFFER_SIZE>, Rc<RefCell<MockHandlers>>, Rc<RefCell<MockHandlers>>, Rc<RefCell<MockHandlers>>>;
+
+    struct Harness {
+        slave: Rc<RefCell<SimulatedSlave>>,
+        link: SimulatedLink,
+        handlers: Rc<RefCell<MockHandlers>>,
+        time_source: MockTimeSource,
+    }
+
+    impl Harness {
+        fn new(mut slave: SimulatedSlave, relays_count: u8, api_version: Version) -> Self {
+            for i in 0..relays_count {
+                slave.add_relay(i + 1, i + 10, i + 20).unwrap();
+            }
+            let slave = Rc::new(RefCell::new(slave));
+            let handlers = Rc::new(RefCell::new(MockHandlers::new()));
+            let link = SlaveControllerLink::create(serial_transfer(&slave), handlers.clone(),
+                                                   handlers.clone(), handlers.clone(), api_version).unwrap();
+            Self {
+                slave,
+                link,
+                handlers,
+                time_source: MockTimeSource(RelativeMillis::new(0)),
+            }
+        }
+
+        fn advance(&mut self, millis: u32) {
+            self.time_source.0 = RelativeMillis::new(self.time_source.0.value().wrapping_add(millis));
+        }
+
+        /** Delivers the frames of both sides until the line is quiet. */
+        fn pump(&mut self) {
+            self.link.on_tx_dma_interrupts();
+            while self.slave.borrow().has_output() || self.link.tx_queue_stats().length() > 0 {
+                if self.slave.borrow().has_output() {
+                    self.link.on_get_command(&mut self.time_source);
+                }
+                self.link.on_tx_dma_interrupts();
+            }
+        }
+
+        fn poll(&mut self) {
+            self.link.poll_timeouts(&mut self.time_source);
+            self.pump();
+        }
+
+        /** Does the handshake and the resync reads, their answers are forgotten by the handlers. */
+        fn negotiate(&mut self) {
+            self.poll();
+            assert!(self.link.version().is_some());
+            while self.link.connection_state() != ConnectionState::Online {
+                self.poll();
+            }
+            self.handlers.borrow_mut().clear();
+        }
+
+        /** Polls the link every 100 ms for the period. */
+        fn wait(&mut self, millis: u32) {
+            for _ in 0..millis / 100 {
+                self.advance(100);
+                self.poll();
+            }
+        }
+
+        fn transitions(&mut self) -> Vec<(ConnectionState, TransitionCause)> {
+            let mut transitions = Vec::new();
+            while let Some(transition) = self.link.take_connection_transition() {
+                transitions.push((transition.to(), transition.cause()));
+            }
+            transitions
+        }
+
+        fn slave_reads(&self) -> Vec<DataInstructionCodes> {
+            self.slave.borrow().requests().iter()
+                .filter(|(operation, _, _)| *operation == Operation::Read)
+                .map(|(_, instruction, _)| *instruction)
+                .collect()
+        }
+
+        fn send(&mut self, operation: Operation, instruction: DataInstructions) -> Result<Option<u32>, Errors> {
+            let now = self.time_source.get();
+            let result = self.link.send_request(operation, instruction, now);
+            self.pump();
+            result
+        }
+    }
+
+    struct MockTimeSource(RelativeMillis);
+
+    impl RelativeTimestampSource for MockTimeSource {
+        fn get(&mut self) -> RelativeMillis {
+            self.0
+        }
+    }
+
+    struct MockHandlers {
+        signals: Vec<SignalData>,
+        successes: Vec<SentRequest>,
+        responses: Vec<(SentRequest, DataInstructions<'static>)>,
+        request_errors: Vec<(SentRequest, ErrorCode)>,
+        timeouts: Vec<SentRequest>,
+        errors: Vec<Errors>,
+    }
+
+    impl MockHandlers {
+        fn new() -> Self {
+            Self {
+                signals: Vec::new(),
+                successes: Vec::new(),
+                responses: Vec::new(),
+                request_errors: Vec::new(),
+                timeouts: Vec::new(),
+                errors: Vec::new(),
+            }
+        }
+
+        fn clear(&mut self) {
+            *self = Self::new();
+        }
+
+        fn response_codes(&self) -> Vec<DataInstructionCodes> {
+            self.responses.iter().map(|(request, _)| request.instruction()).collect()
+        }
+    }
+
+    impl SignalsHandler for Rc<RefCell<MockHandlers>> {
+        fn on_signal(&mut self, signal_data: SignalData, _: bool) {
+            self.borrow_mut().signals.push(signal_data);
+        }
+
+        fn on_signal_parse_error(&mut self, error: Errors, _: bool, _: &[u8]) {
+            self.borrow_mut().errors.push(error);
+        }
+
+        fn on_signal_process_error(&mut self, error: Errors, _: bool, _: SignalData) {
+            self.borrow_mut().errors.push(error);
+        }
+    }
+
+    impl ResponseHandler for Rc<RefCell<MockHandlers>> {
+        fn on_request_success(&mut self, request: SentRequest) {
+            self.borrow_mut().successes.push(request);
+        }
+
+        fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+            self.borrow_mut().responses.push((request, response.into_owned()));
+        }
+
+        fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+            self.borrow_mut().request_errors.push((request, error_code));
+        }
+
+        fn on_request_parse_error(&mut self, _: Option<SentRequest>, error: Errors, _: &[u8]) {
+            self.borrow_mut().errors.push(error);
+        }
+
+        fn on_request_search_error(&mut self, _: ResponseData, error: Errors) {
+            self.borrow_mut().errors.push(error);
+        }
+
+        fn on_request_timeout(&mut self, request: SentRequest) {
+            self.borrow_mut().timeouts.push(request);
+        }
+    }
+
+    impl ErrorHandler for Rc<RefCell<MockHandlers>> {
+        fn on_error(&mut self, error: Errors) {
+            self.borrow_mut().errors.push(error);
+        }
+    }
 }
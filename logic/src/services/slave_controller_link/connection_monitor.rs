@@ -0,0 +1,546 @@
+#![deny(unsafe_code)]
+
+use heapless::Deque;
+use serde_derive::{Deserialize, Serialize};
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::{RelativeMillis, RelativeSeconds};
+use crate::services::slave_controller_link::domain::{Conversation, DataInstructionCodes, DataInstructions, EmptyRequest, ErrorCode,
+                                                     Operation, Version};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest};
+
+pub const DEFAULT_HEARTBEAT_INTERVAL_MS: u32 = 1000;
+pub const DEFAULT_DEGRADED_AFTER_FAILURES: u8 = 2;
+pub const DEFAULT_OFFLINE_AFTER_MS: u32 = 5000;
+/** The oldest not taken transitions are dropped, the application still gets the latest state. */
+pub const MAX_PENDING_TRANSITIONS: usize = 4;
+
+const V1_RESYNC: [DataInstructionCodes; 3] = [DataInstructionCodes::Settings, DataInstructionCodes::State,
+    DataInstructionCodes::StateFixSettings];
+const RESYNC: [DataInstructionCodes; 3] = [DataInstructionCodes::All, DataInstructionCodes::StateFixSettings,
+    DataInstructionCodes::SwitchCountingSettings];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub enum ConnectionState {
+    /** Nothing was answered by the slave since the start yet. */
+    Unknown,
+    /** The protocol version handshake or the reads of the slave state are not done. */
+    Handshaking,
+    Online,
+    /** The slave answers, but its requests fail in a row. */
+    Degraded,
+    /** Nothing was answered for the offline timeout. */
+    Offline,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub enum TransitionCause {
+    Connected,
+    Synced,
+    RequestsFailed,
+    Recovered,
+    Silent,
+    /** The slave asked for the time or its clock went back. */
+    Rebooted,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ConnectionTransition {
+    from: ConnectionState,
+    to: ConnectionState,
+    cause: TransitionCause,
+    at: RelativeMillis,
+}
+
+impl ConnectionTransition {
+
+    pub fn new(from: ConnectionState, to: ConnectionState, cause: TransitionCause, at: RelativeMillis) -> Self {
+        Self { from, to, cause, at }
+    }
+
+    #[inline(always)]
+    pub fn from(&self) -> ConnectionState {
+        self.from
+    }
+
+    #[inline(always)]
+    pub fn to(&self) -> ConnectionState {
+        self.to
+    }
+
+    #[inline(always)]
+    pub fn cause(&self) -> TransitionCause {
+        self.cause
+    }
+
+    #[inline(always)]
+    pub fn at(&self) -> RelativeMillis {
+        self.at
+    }
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct ConnectionPolicy {
+    heartbeat_interval: u32,
+    degraded_after_failures: u8,
+    offline_after: u32,
+}
+
+impl ConnectionPolicy {
+
+    /**
+    `CurrentTime` is read when the slave answered nothing for `heartbeat_interval` milliseconds, a
+    failed read is repeated after the same period. Failures are counted after the retries.
+     */
+    pub const fn new(heartbeat_interval: u32, degraded_after_failures: u8, offline_after: u32) -> Self {
+        Self {
+            heartbeat_interval,
+            degraded_after_failures,
+            offline_after,
+        }
+    }
+
+    #[inline(always)]
+    pub fn heartbeat_interval(&self) -> u32 {
+        self.heartbeat_interval
+    }
+
+    #[inline(always)]
+    pub fn degraded_after_failures(&self) -> u8 {
+        self.degraded_after_failures
+    }
+
+    #[inline(always)]
+    pub fn offline_after(&self) -> u32 {
+        self.offline_after
+    }
+}
+
+impl Default for ConnectionPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_HEARTBEAT_INTERVAL_MS, DEFAULT_DEGRADED_AFTER_FAILURES, DEFAULT_OFFLINE_AFTER_MS)
+    }
+}
+
+/**
+Follows the connection state of the slave by its answers. The link asks `next_request` for the
+heartbeat and resync reads after the version handshake and sends them as usual requests, so their
+answers reach the clock, the mirror and the application handlers too. The slave state is read
+again after the start, a restart and a reconnect. A restart is seen by the time request signal,
+which the link reports with `on_restart`, or by the slave clock going back, then `take_restart`
+tells the link to forget the slave version and clock.
+ */
+pub struct ConnectionMonitor<RH: ResponseHandler> {
+    response_handler: RH,
+    policy: ConnectionPolicy,
+    state: ConnectionState,
+    now: RelativeMillis,
+    started_at: Option<RelativeMillis>,
+    answered_at: Option<RelativeMillis>,
+    failures: u8,
+    in_flight: Option<(DataInstructionCodes, RelativeMillis)>,
+    failed_at: Option<RelativeMillis>,
+    resync_needed: bool,
+    resync: u32,
+    slave_time: Option<RelativeSeconds>,
+    rebooted: bool,
+    restart_detected: bool,
+    transitions: Deque<ConnectionTransition, MAX_PENDING_TRANSITIONS>,
+}
+
+impl <RH: ResponseHandler> ConnectionMonitor<RH> {
+
+    pub fn new(response_handler: RH, policy: ConnectionPolicy) -> Self {
+        Self {
+            response_handler,
+            policy,
+            state: ConnectionState::Unknown,
+            now: RelativeMillis::new(0),
+            started_at: None,
+            answered_at: None,
+            failures: 0,
+            in_flight: None,
+            failed_at: None,
+            resync_needed: true,
+            resync: 0,
+            slave_time: None,
+            rebooted: false,
+            restart_detected: false,
+            transitions: Deque::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+
+    #[inline(always)]
+    pub fn policy(&mut self) -> &mut ConnectionPolicy {
+        &mut self.policy
+    }
+
+    #[inline(always)]
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    #[inline(always)]
+    pub fn set_now(&mut self, now: RelativeMillis) {
+        self.now = now;
+    }
+
+    #[inline(always)]
+    pub fn take_transition(&mut self) -> Option<ConnectionTransition> {
+        self.transitions.pop_front()
+    }
+
+    /** The slave restarted, so its state is read again. */
+    pub fn on_restart(&mut self) {
+        self.rebooted = self.state != ConnectionState::Unknown;
+        self.resync_needed = true;
+        self.resync = 0;
+        self.slave_time = None;
+    }
+
+    /** `true` once after the restart is found by the slave clock. */
+    #[inline(always)]
+    pub fn take_restart(&mut self) -> bool {
+        core::mem::replace(&mut self.restart_detected, false)
+    }
+
+    /** Moves to the state got by the answers and failures, `version` is `None` while the handshake is not done. */
+    pub fn poll(&mut self, now: RelativeMillis, version: Option<Version>) {
+        self.now = now;
+        let started_at = *self.started_at.get_or_insert(now);
+        if let Some(version) = version {
+            if self.resync_needed {
+                self.resync_needed = false;
+                self.resync = Self::resync_steps(version).iter().fold(0, |mask, code| mask | Self::bit(*code));
+            }
+        }
+        let silent_since = self.answered_at.unwrap_or(started_at);
+        let (state, cause) = if Self::elapsed(silent_since, now) >= self.policy.offline_after {
+            (ConnectionState::Offline, TransitionCause::Silent)
+        } else if self.answered_at.is_none() {
+            (ConnectionState::Unknown, TransitionCause::Connected)
+        } else if version.is_none() || self.resync_needed || self.resync != 0 {
+            let cause = if self.rebooted { TransitionCause::Rebooted } else { TransitionCause::Connected };
+            (ConnectionState::Handshaking, cause)
+        } else if self.failures >= self.policy.degraded_after_failures {
+            (ConnectionState::Degraded, TransitionCause::RequestsFailed)
+        } else if self.state == ConnectionState::Degraded {
+            (ConnectionState::Online, TransitionCause::Recovered)
+        } else {
+            (ConnectionState::Online, TransitionCause::Synced)
+        };
+        if state == ConnectionState::Handshaking || state == ConnectionState::Online {
+            self.rebooted = false;
+        }
+        if state != self.state {
+            if self.transitions.is_full() {
+                self.transitions.pop_front();
+            }
+            self.transitions.push_back(ConnectionTransition { from: self.state, to: state, cause, at: now }).ok();
+            self.state = state;
+        }
+    }
+
+    /**
+    Read to send for the connection supervision, the resync ones go one by one. `on_requested` is
+    expected after the request is sent.
+     */
+    pub fn next_request(&self, version: Version, now: RelativeMillis) -> Option<DataInstructionCodes> {
+        if let Some((_, sent_at)) = self.in_flight {
+            // the answer could be lost without a callback, when the requests overflow
+            if Self::elapsed(sent_at, now) < self.policy.offline_after {
+                return None;
+            }
+        }
+        if self.failed_at.is_some_and(|failed_at| Self::elapsed(failed_at, now) < self.policy.heartbeat_interval) {
+            return None;
+        }
+        if let Some(code) = Self::resync_steps(version).iter().find(|code| self.resync & Self::bit(**code) != 0) {
+            return Some(*code);
+        }
+        let silent_since = self.answered_at.or(self.started_at)?;
+        if Self::elapsed(silent_since, now) >= self.policy.heartbeat_interval {
+            Some(DataInstructionCodes::CurrentTime)
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn on_requested(&mut self, code: DataInstructionCodes, now: RelativeMillis) {
+        self.in_flight = Some((code, now));
+    }
+
+    /** The reads which make the state of the slave known, V1 slaves have no `All` and switch counting. */
+    pub fn resync_steps(version: Version) -> &'static [DataInstructionCodes] {
+        match version {
+            Version::V1 => &V1_RESYNC,
+            _ => &RESYNC,
+        }
+    }
+
+    fn on_answer(&mut self) {
+        if self.state == ConnectionState::Offline {
+            self.resync_needed = true;
+            self.resync = 0;
+        }
+        self.answered_at = Some(self.now);
+        self.failures = 0;
+    }
+
+    fn on_failure(&mut self, request: &SentRequest) {
+        self.failures = self.failures.saturating_add(1);
+        if self.complete(request) {
+            self.failed_at = Some(self.now);
+        }
+    }
+
+    /** `true` if it is the read sent for the supervision. */
+    fn complete(&mut self, request: &SentRequest) -> bool {
+        match self.in_flight {
+            Some((code, _)) if request.operation() == Operation::Read && request.instruction() == code => {
+                self.in_flight = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn on_slave_time(&mut self, slave_time: RelativeSeconds) {
+        if self.slave_time.is_some_and(|previous| slave_time < previous) {
+            self.restart_detected = true;
+            self.on_restart();
+        }
+        self.slave_time = Some(slave_time);
+    }
+
+    #[inline(always)]
+    fn bit(code: DataInstructionCodes) -> u32 {
+        1 << (code as u8 & 0x1f)
+    }
+
+    #[inline(always)]
+    fn elapsed(since: RelativeMillis, now: RelativeMillis) -> u32 {
+        now.value().wrapping_sub(since.value())
+    }
+}
+
+impl <RH: ResponseHandler> ResponseHandler for ConnectionMonitor<RH> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        self.on_answer();
+        if request.operation() == Operation::Set && request.instruction() == DataInstructionCodes::RemoteTimestamp {
+            // the slave clock is stepped to the hub time
+            self.slave_time = None;
+        }
+        self.response_handler.on_request_success(request);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        self.on_answer();
+        self.complete(&request);
+        if request.operation() == Operation::Read {
+            self.resync &= !Self::bit(response.code());
+        }
+        if let DataInstructions::CurrentTime(conversation) = &response {
+            if let Some(slave_time) = conversation.data() {
+                self.on_slave_time(*slave_time);
+            }
+        }
+        self.response_handler.on_request_response(request, response);
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        // the slave is alive and it could not have the data, so the resync step is not repeated
+        self.on_answer();
+        self.complete(&request);
+        if request.operation() == Operation::Read {
+            self.resync &= !Self::bit(request.instruction());
+        }
+        self.response_handler.on_request_error(request, error_code);
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+        match &request {
+            Some(request) => self.on_failure(request),
+            None => self.failures = self.failures.saturating_add(1),
+        }
+        self.response_handler.on_request_parse_error(request, error, data);
+    }
+
+    fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
+        // a late answer still tells the slave is there
+        self.answered_at = Some(self.now);
+        self.response_handler.on_request_search_error(payload, error);
+    }
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        self.on_failure(&request);
+        self.response_handler.on_request_timeout(request);
+    }
+}
+
+/** Empty read request of a supervision instruction. */
+pub fn read_request(code: DataInstructionCodes) -> Option<DataInstructions<'static>> {
+    match code {
+        DataInstructionCodes::Settings => Some(DataInstructions::Settings(Conversation::Request(EmptyRequest::new()))),
+        DataInstructionCodes::State => Some(DataInstructions::State(Conversation::Request(EmptyRequest::new()))),
+        DataInstructionCodes::StateFixSettings =>
+            Some(DataInstructions::StateFixSettings(Conversation::Request(EmptyRequest::new()))),
+        DataInstructionCodes::SwitchCountingSettings =>
+            Some(DataInstructions::SwitchCountingSettings(Conversation::Request(EmptyRequest::new()))),
+        DataInstructionCodes::All => Some(DataInstructions::All(Conversation::Request(EmptyRequest::new()))),
+        DataInstructionCodes::CurrentTime => Some(DataInstructions::CurrentTime(Conversation::Request(EmptyRequest::new()))),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::slave_controller_link::domain::{AllData, RelaysSettings, State, StateFixSettings,
+                                                         SwitchCountingSettings};
+
+    #[test]
+    fn test_resync_goes_step_by_step_then_heartbeat() {
+        let start = rand::thread_rng().next_u32();
+        let mut tested = ConnectionMonitor::new(MockResponsesHandler, ConnectionPolicy::default());
+
+        tested.poll(at(start, 0), Some(Version::V1));
+        assert_eq!(ConnectionState::Unknown, tested.state());
+        for code in ConnectionMonitor::<MockResponsesHandler>::resync_steps(Version::V1) {
+            assert_eq!(Some(*code), tested.next_request(Version::V1, at(start, 0)));
+            tested.on_requested(*code, at(start, 0));
+            assert_eq!(None, tested.next_request(Version::V1, at(start, 0)));
+            tested.on_request_response(request(*code, at(start, 0)), response(*code));
+            tested.poll(at(start, 0), Some(Version::V1));
+            assert_eq!(ConnectionState::Handshaking == tested.state(), *code != DataInstructionCodes::StateFixSettings);
+        }
+
+        assert_eq!(ConnectionState::Online, tested.state());
+        assert_eq!(None, tested.next_request(Version::V1, at(start, DEFAULT_HEARTBEAT_INTERVAL_MS - 1)));
+        assert_eq!(Some(DataInstructionCodes::CurrentTime),
+                   tested.next_request(Version::V1, at(start, DEFAULT_HEARTBEAT_INTERVAL_MS)));
+        assert_eq!(Some(ConnectionTransition::new(ConnectionState::Unknown, ConnectionState::Handshaking,
+                                                  TransitionCause::Connected, at(start, 0))), tested.take_transition());
+        assert_eq!(Some(ConnectionTransition::new(ConnectionState::Handshaking, ConnectionState::Online,
+                                                  TransitionCause::Synced, at(start, 0))), tested.take_transition());
+        assert_eq!(None, tested.take_transition());
+    }
+
+    #[test]
+    fn test_rejected_resync_step_is_not_repeated() {
+        let mut tested = online(Version::V2);
+        tested.on_restart();
+        tested.poll(at(0, 0), Some(Version::V2));
+        tested.on_request_response(request(DataInstructionCodes::All, at(0, 0)), response(DataInstructionCodes::All));
+        tested.on_request_response(request(DataInstructionCodes::StateFixSettings, at(0, 0)),
+                                   response(DataInstructionCodes::StateFixSettings));
+
+        tested.on_requested(DataInstructionCodes::SwitchCountingSettings, at(0, 0));
+        tested.on_request_error(request(DataInstructionCodes::SwitchCountingSettings, at(0, 0)),
+                                ErrorCode::EInstructionUnrecognized);
+        tested.poll(at(0, 0), Some(Version::V2));
+
+        assert_eq!(ConnectionState::Online, tested.state());
+        assert_eq!(None, tested.next_request(Version::V2, at(0, 0)));
+    }
+
+    #[test]
+    fn test_silent_slave_goes_offline_and_is_resynced_on_answer() {
+        let mut tested = online(Version::V2);
+        let offline_after = DEFAULT_OFFLINE_AFTER_MS;
+
+        tested.on_requested(DataInstructionCodes::CurrentTime, at(0, DEFAULT_HEARTBEAT_INTERVAL_MS));
+        tested.set_now(at(0, 2 * DEFAULT_HEARTBEAT_INTERVAL_MS));
+        tested.on_request_timeout(request(DataInstructionCodes::CurrentTime, at(0, DEFAULT_HEARTBEAT_INTERVAL_MS)));
+        // the failed heartbeat is repeated after the interval
+        assert_eq!(None, tested.next_request(Version::V2, at(0, 3 * DEFAULT_HEARTBEAT_INTERVAL_MS - 1)));
+        assert_eq!(Some(DataInstructionCodes::CurrentTime),
+                   tested.next_request(Version::V2, at(0, 3 * DEFAULT_HEARTBEAT_INTERVAL_MS)));
+        tested.poll(at(0, offline_after - 1), Some(Version::V2));
+        assert_eq!(ConnectionState::Online, tested.state());
+        tested.poll(at(0, offline_after), Some(Version::V2));
+        assert_eq!(ConnectionState::Offline, tested.state());
+
+        tested.set_now(at(0, offline_after + 1));
+        tested.on_request_response(request(DataInstructionCodes::CurrentTime, at(0, offline_after)),
+                                   DataInstructions::CurrentTime(Conversation::Data(RelativeSeconds::new(10))));
+        tested.poll(at(0, offline_after + 1), Some(Version::V2));
+
+        assert_eq!(ConnectionState::Handshaking, tested.state());
+        assert_eq!(Some(DataInstructionCodes::All), tested.next_request(Version::V2, at(0, offline_after + 1)));
+    }
+
+    #[test]
+    fn test_slave_clock_going_back_is_restart() {
+        let mut tested = online(Version::V2);
+
+        for (seconds, restart) in [(10, false), (10, false), (11, false), (3, true), (4, false)] {
+            tested.on_request_response(request(DataInstructionCodes::CurrentTime, at(0, 0)),
+                                       DataInstructions::CurrentTime(Conversation::Data(RelativeSeconds::new(seconds))));
+            assert_eq!(restart, tested.take_restart());
+        }
+        tested.poll(at(0, 0), Some(Version::V2));
+
+        assert_eq!(ConnectionState::Handshaking, tested.state());
+        assert_eq!(Some(TransitionCause::Rebooted), tested.take_transition().map(|transition| transition.cause()));
+    }
+
+    /** Monitor of the slave, which answered the resync reads at the start, transitions are taken. */
+    fn online(version: Version) -> ConnectionMonitor<MockResponsesHandler> {
+        let mut tested = ConnectionMonitor::new(MockResponsesHandler, ConnectionPolicy::default());
+        tested.poll(at(0, 0), Some(version));
+        for code in ConnectionMonitor::<MockResponsesHandler>::resync_steps(version) {
+            tested.on_request_response(request(*code, at(0, 0)), response(*code));
+        }
+        tested.poll(at(0, 0), Some(version));
+        assert_eq!(ConnectionState::Online, tested.state());
+        while tested.take_transition().is_some() {}
+        tested
+    }
+
+    fn at(start: u32, millis: u32) -> RelativeMillis {
+        RelativeMillis::new(start.wrapping_add(millis))
+    }
+
+    fn request(code: DataInstructionCodes, sent_at: RelativeMillis) -> SentRequest {
+        SentRequest::new(None, Operation::Read, code, sent_at)
+    }
+
+    fn response(code: DataInstructionCodes) -> DataInstructions<'static> {
+        match code {
+            DataInstructionCodes::All => DataInstructions::All(Conversation::Data(AllData::new(1, 7))),
+            DataInstructionCodes::SwitchCountingSettings =>
+                DataInstructions::SwitchCountingSettings(Conversation::Data(SwitchCountingSettings::new(60, 5))),
+            DataInstructionCodes::StateFixSettings =>
+                DataInstructions::StateFixSettings(Conversation::Data(StateFixSettings::default())),
+            DataInstructionCodes::Settings => DataInstructions::Settings(Conversation::Data(RelaysSettings::new())),
+            DataInstructionCodes::State => DataInstructions::State(Conversation::Data(State::new())),
+            _ => DataInstructions::CurrentTime(Conversation::Data(RelativeSeconds::new(0))),
+        }
+    }
+
+    struct MockResponsesHandler;
+
+    impl ResponseHandler for MockResponsesHandler {
+        fn on_request_success(&mut self, _: SentRequest) {}
+
+        fn on_request_response(&mut self, _: SentRequest, _: DataInstructions) {}
+
+        fn on_request_error(&mut self, _: SentRequest, _: ErrorCode) {}
+
+        fn on_request_parse_error(&mut self, _: Option<SentRequest>, _: Errors, _: &[u8]) {}
+
+        fn on_request_search_error(&mut self, _: ResponseData, _: Errors) {}
+
+        fn on_request_timeout(&mut self, _: SentRequest) {}
+    }
+}
@@ -4,6 +4,7 @@ use crate::errors::Errors;
 use crate::hal_ext::rtc_wrapper::{RelativeSeconds};
 use crate::utils::{BitsU64, BitsU8};
 use crate::utils::dma_read_buffer::{BufferWriter};
+use serde_derive::{Deserialize, Serialize};
 
 
 pub const MAX_RELAYS_COUNT: u8 = 16;
@@ -27,6 +28,7 @@ pub enum OperationCodes {
     Unknown = 0x0f
 }
 
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Operation {
     None,
@@ -48,18 +50,52 @@ impl Operation {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Version {
     V1,
     V2,
+    // V2 with length prefix and CRC-16 trailer around every frame
+    V3,
 }
 
-pub enum Commands {
-    ClearSwitchCount = 0x08,
+impl Version {
+    #[inline(always)]
+    pub fn framed(&self) -> bool {
+        *self == Version::V3
+    }
+
+    pub fn for_code(code: u8) -> Result<Version, Errors> {
+        match code {
+            1 => Ok(Version::V1),
+            2 => Ok(Version::V2),
+            3 => Ok(Version::V3),
+            _ => Err(Errors::OutOfRange),
+        }
+    }
 }
 
+/** Actions without data, sent with the `Command` operation and acknowledged as `Set` requests. */
 #[repr(u8)]
 #[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Commands {
+    ClearSwitchCount = DataInstructionCodes::ClearSwitchCount as u8,
+}
+
+impl DataInstruction for Commands {
+    fn code(&self) -> DataInstructionCodes {
+        match self {
+            Commands::ClearSwitchCount => DataInstructionCodes::ClearSwitchCount,
+        }
+    }
+
+    fn serialize<B: BufferWriter>(&self, _: &mut B) -> Result<(), Errors> {
+        Ok(())
+    }
+}
+
+#[repr(u8)]
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum DataInstructionCodes {
     None = 0x00,
     Settings = 0x01,
@@ -82,6 +118,8 @@ pub enum DataInstructionCodes {
     RelayMonitorOn = 0x0c,
     RelayControlOn = 0x0d,
     All = 0x0e,
+    //commands share the codes with instructions, so acknowledgements are tracked the same way
+    ClearSwitchCount = 0x08,
     Last = 0x19,
     Unknown = 0xff,
 }
@@ -127,6 +165,8 @@ impl DataInstructionCodes {
             Ok(Self::RelayControlOn)
         } else if code_value == Self::All as u8 {
             Ok(Self::All)
+        } else if code_value == Self::ClearSwitchCount as u8 {
+            Ok(Self::ClearSwitchCount)
         } else {
             Err(Errors::InstructionNotRecognized(code_value))
         }
@@ -140,30 +180,30 @@ pub trait DataInstruction {
 
 #[repr(u8)]
 #[derive(PartialEq, Debug)]
-pub enum DataInstructions {
-    Settings(Conversation<EmptyRequest, RelaysSettings>) = DataInstructionCodes::Settings as u8,
-    State(Conversation<EmptyRequest, State>) = DataInstructionCodes::State as u8,
-    Id(Conversation<EmptyRequest, u32>) = DataInstructionCodes::Id as u8,
-    InterruptPin(Conversation<EmptyRequest, u8>) = DataInstructionCodes::InterruptPin as u8,
-    RemoteTimestamp(Conversation<EmptyRequest, RelativeSeconds>) = DataInstructionCodes::RemoteTimestamp as u8,
-    StateFixSettings(Conversation<EmptyRequest, StateFixSettings>) = DataInstructionCodes::StateFixSettings as u8,
-    RelayState(Conversation<RelayIndexRequest, RelayState>) = DataInstructionCodes::RelayState as u8,
-    Version(Conversation<EmptyRequest, u8>) = DataInstructionCodes::Version as u8,
-    CurrentTime(Conversation<EmptyRequest, RelativeSeconds>) = DataInstructionCodes::CurrentTime as u8,
-    ContactWaitData(Conversation<EmptyRequest, ContactsWaitData>) = DataInstructionCodes::ContactWaitData as u8,
-    FixData(Conversation<EmptyRequest, FixDataContainer>) = DataInstructionCodes::FixData as u8,
-    SwitchData(Conversation<EmptyRequest, StateSwitchDatas>) = DataInstructionCodes::SwitchData as u8,
-    CyclesStatistics(Conversation<EmptyRequest, CyclesStatistics>) = DataInstructionCodes::CyclesStatistics as u8,
+pub enum DataInstructions<'a> {
+    Settings(Conversation<'a, EmptyRequest, RelaysSettings>) = DataInstructionCodes::Settings as u8,
+    State(Conversation<'a, EmptyRequest, State>) = DataInstructionCodes::State as u8,
+    Id(Conversation<'a, EmptyRequest, u32>) = DataInstructionCodes::Id as u8,
+    InterruptPin(Conversation<'a, EmptyRequest, u8>) = DataInstructionCodes::InterruptPin as u8,
+    RemoteTimestamp(Conversation<'a, EmptyRequest, RelativeSeconds>) = DataInstructionCodes::RemoteTimestamp as u8,
+    StateFixSettings(Conversation<'a, EmptyRequest, StateFixSettings>) = DataInstructionCodes::StateFixSettings as u8,
+    RelayState(Conversation<'a, RelayIndexRequest, RelayState>) = DataInstructionCodes::RelayState as u8,
+    Version(Conversation<'a, EmptyRequest, u8>) = DataInstructionCodes::Version as u8,
+    CurrentTime(Conversation<'a, EmptyRequest, RelativeSeconds>) = DataInstructionCodes::CurrentTime as u8,
+    ContactWaitData(Conversation<'a, EmptyRequest, ContactsWaitData>) = DataInstructionCodes::ContactWaitData as u8,
+    FixData(Conversation<'a, EmptyRequest, FixDataContainer>) = DataInstructionCodes::FixData as u8,
+    SwitchData(Conversation<'a, EmptyRequest, StateSwitchDatas>) = DataInstructionCodes::SwitchData as u8,
+    CyclesStatistics(Conversation<'a, EmptyRequest, CyclesStatistics>) = DataInstructionCodes::CyclesStatistics as u8,
     //v2 instructions
-    SwitchCountingSettings(Conversation<EmptyRequest, SwitchCountingSettings>) = DataInstructionCodes::SwitchCountingSettings as u8,
-    RelayDisabledTemp(Conversation<EmptyRequest, RelaySingleState>) = DataInstructionCodes::RelayDisabledTemp as u8,
-    RelaySwitchedOn(Conversation<EmptyRequest, RelaySingleState>) = DataInstructionCodes::RelaySwitchedOn as u8,
-    RelayMonitorOn(Conversation<EmptyRequest, RelaySingleState>) = DataInstructionCodes::RelayMonitorOn as u8,
-    RelayControlOn(Conversation<EmptyRequest, RelaySingleState>) = DataInstructionCodes::RelayControlOn as u8,
-    All(Conversation<EmptyRequest, AllData>) = DataInstructionCodes::All as u8,
+    SwitchCountingSettings(Conversation<'a, EmptyRequest, SwitchCountingSettings>) = DataInstructionCodes::SwitchCountingSettings as u8,
+    RelayDisabledTemp(Conversation<'a, EmptyRequest, RelaySingleState>) = DataInstructionCodes::RelayDisabledTemp as u8,
+    RelaySwitchedOn(Conversation<'a, EmptyRequest, RelaySingleState>) = DataInstructionCodes::RelaySwitchedOn as u8,
+    RelayMonitorOn(Conversation<'a, EmptyRequest, RelaySingleState>) = DataInstructionCodes::RelayMonitorOn as u8,
+    RelayControlOn(Conversation<'a, EmptyRequest, RelaySingleState>) = DataInstructionCodes::RelayControlOn as u8,
+    All(Conversation<'a, EmptyRequest, AllData>) = DataInstructionCodes::All as u8,
 }
 
-impl DataInstructions {
+impl DataInstructions<'_> {
 
     pub fn code(&self) -> DataInstructionCodes {
         match self {
@@ -360,73 +400,95 @@ impl DataInstructions {
 
     pub fn serialize<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors> {
         match self {
-            DataInstructions::RemoteTimestamp(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::RemoteTimestamp(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::CurrentTime(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::CurrentTime(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::Id(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::Id(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::Version(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::Version(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::StateFixSettings(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::StateFixSettings(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::RelayState(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::RelayState(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::State(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::State(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::CyclesStatistics(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::CyclesStatistics(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::FixData(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::FixData(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::Settings(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::Settings(conversation) => {
+                conversation.serialize(buffer)
             }
             //v2 instructions
-            DataInstructions::ContactWaitData(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::ContactWaitData(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::SwitchData(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::SwitchData(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::InterruptPin(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::InterruptPin(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::SwitchCountingSettings(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::SwitchCountingSettings(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::RelayDisabledTemp(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::RelayDisabledTemp(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::RelaySwitchedOn(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::RelaySwitchedOn(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::RelayMonitorOn(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::RelayMonitorOn(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::RelayControlOn(Conversation::Data(value)) => {
-                value.serialize(buffer)
+            DataInstructions::RelayControlOn(conversation) => {
+                conversation.serialize(buffer)
             }
-            DataInstructions::All(Conversation::Data(value)) => {
-                value.serialize(buffer)
-            }
-            _ => {
-                Err(Errors::InstructionNotSerializable)
+            DataInstructions::All(conversation) => {
+                conversation.serialize(buffer)
             }
         }
     }
 
+    /** Copy of the instruction which does not borrow the response parser buffers. */
+    pub fn into_owned(self) -> DataInstructions<'static> {
+        match self {
+            DataInstructions::Settings(conversation) => DataInstructions::Settings(conversation.into_owned()),
+            DataInstructions::State(conversation) => DataInstructions::State(conversation.into_owned()),
+            DataInstructions::Id(conversation) => DataInstructions::Id(conversation.into_owned()),
+            DataInstructions::InterruptPin(conversation) => DataInstructions::InterruptPin(conversation.into_owned()),
+            DataInstructions::RemoteTimestamp(conversation) => DataInstructions::RemoteTimestamp(conversation.into_owned()),
+            DataInstructions::StateFixSettings(conversation) => DataInstructions::StateFixSettings(conversation.into_owned()),
+            DataInstructions::RelayState(conversation) => DataInstructions::RelayState(conversation.into_owned()),
+            DataInstructions::Version(conversation) => DataInstructions::Version(conversation.into_owned()),
+            DataInstructions::CurrentTime(conversation) => DataInstructions::CurrentTime(conversation.into_owned()),
+            DataInstructions::ContactWaitData(conversation) => DataInstructions::ContactWaitData(conversation.into_owned()),
+            DataInstructions::FixData(conversation) => DataInstructions::FixData(conversation.into_owned()),
+            DataInstructions::SwitchData(conversation) => DataInstructions::SwitchData(conversation.into_owned()),
+            DataInstructions::CyclesStatistics(conversation) => DataInstructions::CyclesStatistics(conversation.into_owned()),
+            DataInstructions::SwitchCountingSettings(conversation) => DataInstructions::SwitchCountingSettings(conversation.into_owned()),
+            DataInstructions::RelayDisabledTemp(conversation) => DataInstructions::RelayDisabledTemp(conversation.into_owned()),
+            DataInstructions::RelaySwitchedOn(conversation) => DataInstructions::RelaySwitchedOn(conversation.into_owned()),
+            DataInstructions::RelayMonitorOn(conversation) => DataInstructions::RelayMonitorOn(conversation.into_owned()),
+            DataInstructions::RelayControlOn(conversation) => DataInstructions::RelayControlOn(conversation.into_owned()),
+            DataInstructions::All(conversation) => DataInstructions::All(conversation.into_owned()),
+        }
+    }
+
 }
 
-impl DataInstruction for DataInstructions {
+impl DataInstruction for DataInstructions<'_> {
 
     #[inline(always)]
     fn code(&self) -> DataInstructionCodes {
@@ -441,7 +503,8 @@ impl DataInstruction for DataInstructions {
 }
 
 #[repr(u8)]
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum ErrorCode {
     OK = 0x00,
     ERequestDataNoValue = 0x01,
@@ -510,15 +573,58 @@ impl ErrorCode {
     }
 }
 
+/** `DataCashed` data is parsed into the buffers of the link response parser and borrows them. */
 #[derive(PartialEq, Debug)]
-pub enum Conversation<RQ: Request, D: Data + 'static> {
+pub enum Conversation<'a, RQ: Request, D: Data> {
     Request(RQ),
     Data(D),
-    DataCashed(&'static mut D),
+    DataCashed(&'a mut D),
     Response(Response),
 }
 
-pub trait Request {  }
+impl <RQ: Request, D: Data> Conversation<'_, RQ, D> {
+
+    pub fn data(&self) -> Option<&D> {
+        match self {
+            Conversation::Data(data) => Some(data),
+            Conversation::DataCashed(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /** Copies the cached data, so the conversation could be kept after the parser buffers are reused. */
+    pub fn into_owned(self) -> Conversation<'static, RQ, D> where D: Clone {
+        match self {
+            Conversation::Request(request) => Conversation::Request(request),
+            Conversation::Data(data) => Conversation::Data(data),
+            Conversation::DataCashed(data) => Conversation::Data(data.clone()),
+            Conversation::Response(response) => Conversation::Response(response),
+        }
+    }
+
+    pub fn serialize<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors> {
+        match self {
+            Conversation::Request(request) => {
+                request.serialize(buffer)
+            }
+            Conversation::Data(data) => {
+                data.serialize(buffer)
+            }
+            Conversation::DataCashed(data) => {
+                data.serialize(buffer)
+            }
+            Conversation::Response(_) => {
+                Err(Errors::InstructionNotSerializable)
+            }
+        }
+    }
+
+}
+
+/**
+Parameters of a Read request. Serialized right after the instruction code (and request id for V2).
+ */
+pub trait Request: Serializable {}
 
 pub trait AutoCreator {
     fn default() -> Self where Self: Sized;
@@ -555,6 +661,15 @@ impl EmptyRequest {
     }
 }
 
+impl Serializable for EmptyRequest {
+
+    #[inline(always)]
+    fn serialize<B: BufferWriter>(&self, _: &mut B) -> Result<(), Errors> {
+        Ok(())
+    }
+
+}
+
 impl Request for EmptyRequest {}
 
 #[derive(PartialEq, Debug)]
@@ -562,6 +677,21 @@ pub struct RelayIndexRequest {
     pub index: u8,
 }
 
+impl RelayIndexRequest {
+    pub fn new(index: u8) -> Self {
+        Self { index }
+    }
+}
+
+impl Serializable for RelayIndexRequest {
+
+    #[inline(always)]
+    fn serialize<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors> {
+        buffer.add_u8(self.index)
+    }
+
+}
+
 impl Request for RelayIndexRequest {}
 
 impl Parser for RelativeSeconds {
@@ -625,13 +755,13 @@ impl Extractor for u64 {
 }
 
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct RelativeMillis16(u16);
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct RelativeSeconds8(u8);
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct RelativeSeconds16(u16);
 
 impl Extractor for RelativeMillis16 {
@@ -758,7 +888,7 @@ impl AutoCreator for u32 {
 
 impl Data for u32 {}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct AllData {
     pub id: u32,
     pub interrupt_pin: u8,
@@ -852,7 +982,7 @@ impl AutoCreator for AllData {
 
 impl Data for AllData {}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct SwitchCountingSettings {
     pub switch_limit_interval: RelativeSeconds16,
     pub max_switch_count: u8,
@@ -873,6 +1003,11 @@ impl SwitchCountingSettings {
         }
     }
 
+    #[inline(always)]
+    pub fn switch_limit_interval_seconds(&self) -> u16 {
+        self.switch_limit_interval.0
+    }
+
 }
 
 impl Parser for SwitchCountingSettings {
@@ -908,7 +1043,7 @@ impl AutoCreator for SwitchCountingSettings {
 
 impl Data for SwitchCountingSettings {}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct StateSwitchDatas {
     pub data: [StateSwitchData; SWITCHES_DATA_BUFFER_SIZE as usize],
     pub count: u8,
@@ -1019,9 +1154,19 @@ impl StateSwitchData {
         }
     }
 
+    #[inline(always)]
+    pub fn state(&self) -> u8 {
+        self.state.bits
+    }
+
+    /** Slave time of the switch. */
+    #[inline(always)]
+    pub fn time_stamp(&self) -> RelativeSeconds {
+        self.time_stamp
+    }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct ContactsWaitData {
     relays_count: u8,
     contacts_wait_start_timestamps: [RelativeSeconds; MAX_RELAYS_COUNT as usize],
@@ -1108,7 +1253,7 @@ impl AutoCreator for ContactsWaitData {
 
 impl Data for ContactsWaitData {}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct FixDataContainer {
     fix_data: [FixData; MAX_RELAYS_COUNT as usize],
     fix_data_count: u8,
@@ -1234,9 +1379,19 @@ impl FixData {
         }
     }
 
+    #[inline(always)]
+    pub fn fix_try_count(&self) -> u8 {
+        self.fix_try_count
+    }
+
+    /** Slave time of the last fix try. */
+    #[inline(always)]
+    pub fn fix_last_try_time(&self) -> RelativeSeconds {
+        self.fix_last_try_time
+    }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct CyclesStatistics {
     min_cycle_duration: RelativeMillis16,
     max_cycle_duration: RelativeMillis16,
@@ -1273,6 +1428,26 @@ impl CyclesStatistics {
         }
     }
 
+    #[inline(always)]
+    pub fn min_cycle_duration(&self) -> u16 {
+        self.min_cycle_duration.0
+    }
+
+    #[inline(always)]
+    pub fn max_cycle_duration(&self) -> u16 {
+        self.max_cycle_duration.0
+    }
+
+    #[inline(always)]
+    pub fn avg_cycle_duration(&self) -> u16 {
+        self.avg_cycle_duration.0
+    }
+
+    #[inline(always)]
+    pub fn cycles_count(&self) -> u64 {
+        self.cycles_count
+    }
+
 }
 
 impl Parser for CyclesStatistics {
@@ -1310,7 +1485,7 @@ impl AutoCreator for CyclesStatistics {
 
 impl Data for CyclesStatistics {}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct StateFixSettings {
     switch_try_duration: RelativeMillis16,
     switch_try_count: u8,
@@ -1338,6 +1513,26 @@ impl StateFixSettings {
         }
     }
 
+    #[inline(always)]
+    pub fn switch_try_duration(&self) -> u16 {
+        self.switch_try_duration.0
+    }
+
+    #[inline(always)]
+    pub fn switch_try_count(&self) -> u8 {
+        self.switch_try_count
+    }
+
+    #[inline(always)]
+    pub fn wait_delay(&self) -> u8 {
+        self.wait_delay.0
+    }
+
+    #[inline(always)]
+    pub fn contact_ready_wait_delay(&self) -> u16 {
+        self.contact_ready_wait_delay.0
+    }
+
 }
 
 impl Parser for StateFixSettings {
@@ -1379,7 +1574,7 @@ impl AutoCreator for StateFixSettings {
 
 impl Data for StateFixSettings {}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct State {
     pub data: BitsU64,
     pub count: u8,
@@ -1449,7 +1644,7 @@ impl AutoCreator for State {
 
 impl Data for State {}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct RelaySingleState {
     data: BitsU8,
 }
@@ -1502,7 +1697,7 @@ impl AutoCreator for RelaySingleState {
 
 impl Data for RelaySingleState {}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct RelayState {
     data: BitsU8,
 }
@@ -1582,7 +1777,7 @@ impl AutoCreator for RelayState {
 
 impl Data for RelayState {}
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct PinData {
     data: u8,
 }
@@ -1600,7 +1795,7 @@ impl PinData {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct RelaySettings {
     set_pin: PinData,
     monitor_pin: PinData,
@@ -1645,7 +1840,7 @@ impl RelaySettings {
 
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct RelaysSettings {
     pub relays: [RelaySettings; MAX_RELAYS_COUNT as usize],
     pub relays_count: u8,
@@ -1755,7 +1950,7 @@ pub enum Signals {
 }
 
 #[repr(u8)]
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum SignalData {
     GetTimeStamp = Signals::GetTimeStamp as u8,
     RelayStateChanged(RelaySignalDataExt) = Signals::RelayStateChanged as u8,
@@ -1786,6 +1981,16 @@ impl SignalData {
         }
     }
 
+    /** The time of the relay event on the slave, `None` for the signals not bound to a relay. */
+    pub fn relative_timestamp(&self) -> Option<RelativeSeconds> {
+        match self {
+            SignalData::GetTimeStamp => None,
+            SignalData::RelayStateChanged(data) => Some(data.get_relative_timestamp()),
+            SignalData::MonitoringStateChanged(data) | SignalData::ControlStateChanged(data)
+            | SignalData::StateFixTry(data) => Some(data.get_relative_timestamp()),
+        }
+    }
+
     pub fn parse(signal: Signals, data: &[u8]) -> Result<Self, Errors> {
         match signal {
             Signals::GetTimeStamp => Ok(SignalData::GetTimeStamp),
@@ -1841,7 +2046,7 @@ trait RelaySignalDataSetter : AutoCreator {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
 pub struct RelaySignalData {
     relative_timestamp: RelativeSeconds,
     relay_idx: u8,
@@ -1911,7 +2116,7 @@ impl AutoCreator for RelaySignalData {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
 pub struct RelaySignalDataExt {
     relative_timestamp: RelativeSeconds,
     relay_idx: u8,
@@ -0,0 +1,437 @@
+#![deny(unsafe_code)]
+
+use crc_any::CRCu16;
+use crate::errors::Errors;
+use crate::services::slave_controller_link::domain::{DataInstructionCodes, OperationCodes, Signals};
+use crate::utils::dma_read_buffer::BufferWriter;
+
+/**
+V3 frame: `[length][body][crc]`, where `length` is the body size in one byte and `crc` is the big-endian
+CRC-16/CCITT-FALSE of the length byte and the body. The body is the same as the whole V2 frame.
+ */
+pub const FRAME_LENGTH_SIZE: usize = 1;
+pub const FRAME_CRC_SIZE: usize = 2;
+pub const MAX_FRAME_BODY_SIZE: usize = u8::MAX as usize;
+pub const MAX_FRAME_SIZE: usize = FRAME_LENGTH_SIZE + MAX_FRAME_BODY_SIZE + FRAME_CRC_SIZE;
+
+// raw frames start with `[0, operation, instruction or signal code]`
+const RAW_HEADER_SIZE: usize = 3;
+const REQUEST_ID_SIZE: usize = 4;
+const RELAY_SIGNAL_DATA_SIZE: usize = 5;
+const ALL_DATA_HEADER_SIZE: usize = 6;
+
+/** Size of the frame at the start of received bytes. */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FrameSize {
+    /** It could be more than the bytes received yet. */
+    Known(usize),
+    /** More bytes are needed to find out the size. */
+    Unknown,
+    /** Raw frames with data of variable size are delimited by the idle line only. */
+    ToChunkEnd,
+}
+
+/**
+V3 frames carry the length. Raw acknowledgements, errors and signals have fixed sizes, which are found
+out from the operation and signal codes, so several of them could be taken from one received chunk.
+Raw responses are sized by the instruction and the relays count at the start of its data, so a long
+response split by an idle line is joined before it is parsed.
+ */
+pub fn frame_size(data: &[u8], framed: bool) -> FrameSize {
+    if framed {
+        return match data.first() {
+            Some(length) => FrameSize::Known(FRAME_LENGTH_SIZE + *length as usize + FRAME_CRC_SIZE),
+            None => FrameSize::Unknown,
+        };
+    }
+    if data.len() < 2 {
+        return if data.first().is_none_or(|start| *start == OperationCodes::None as u8) {
+            FrameSize::Unknown
+        } else {
+            FrameSize::ToChunkEnd
+        };
+    }
+    if data[0] != OperationCodes::None as u8 {
+        return FrameSize::ToChunkEnd;
+    }
+    let operation = data[1];
+    if operation == OperationCodes::Success as u8 {
+        FrameSize::Known(RAW_HEADER_SIZE)
+    } else if operation == OperationCodes::SuccessV2 as u8 {
+        FrameSize::Known(RAW_HEADER_SIZE + REQUEST_ID_SIZE)
+    } else if operation == OperationCodes::Error as u8 {
+        FrameSize::Known(RAW_HEADER_SIZE + 1)
+    } else if operation == OperationCodes::ErrorV2 as u8 {
+        FrameSize::Known(RAW_HEADER_SIZE + 1 + REQUEST_ID_SIZE)
+    } else if operation == OperationCodes::Signal as u8 {
+        match data.get(2).map(|code| Signals::get(*code)) {
+            None => FrameSize::Unknown,
+            Some(Ok(Signals::GetTimeStamp)) => FrameSize::Known(RAW_HEADER_SIZE),
+            Some(Ok(Signals::RelayStateChanged)) | Some(Ok(Signals::MonitoringStateChanged)) |
+            Some(Ok(Signals::ControlStateChanged)) | Some(Ok(Signals::StateFixTry)) =>
+                FrameSize::Known(RAW_HEADER_SIZE + RELAY_SIGNAL_DATA_SIZE),
+            Some(_) => FrameSize::ToChunkEnd,
+        }
+    } else if operation == OperationCodes::Response as u8 {
+        response_size(data, RAW_HEADER_SIZE)
+    } else if operation == OperationCodes::ResponseV2 as u8 {
+        response_size(data, RAW_HEADER_SIZE + REQUEST_ID_SIZE)
+    } else {
+        FrameSize::ToChunkEnd
+    }
+}
+
+/** The response data starts at `data_start`, its size is the same for all the slave versions. */
+fn response_size(data: &[u8], data_start: usize) -> FrameSize {
+    let instruction = match data.get(2).map(|code| DataInstructionCodes::get(*code)) {
+        None => return FrameSize::Unknown,
+        Some(Ok(instruction)) => instruction,
+        Some(Err(_)) => return FrameSize::ToChunkEnd,
+    };
+    let fixed = |size: usize| FrameSize::Known(data_start + size);
+    let counted = |position: usize, size: fn(usize) -> usize| match data.get(data_start + position) {
+        Some(count) => FrameSize::Known(data_start + position + 1 + size(*count as usize)),
+        None => FrameSize::Unknown,
+    };
+    match instruction {
+        DataInstructionCodes::Id | DataInstructionCodes::RemoteTimestamp | DataInstructionCodes::CurrentTime => fixed(4),
+        DataInstructionCodes::InterruptPin | DataInstructionCodes::Version | DataInstructionCodes::RelayState |
+        DataInstructionCodes::RelayDisabledTemp | DataInstructionCodes::RelaySwitchedOn |
+        DataInstructionCodes::RelayMonitorOn | DataInstructionCodes::RelayControlOn => fixed(1),
+        DataInstructionCodes::StateFixSettings => fixed(6),
+        DataInstructionCodes::SwitchCountingSettings => fixed(3),
+        DataInstructionCodes::CyclesStatistics => fixed(14),
+        DataInstructionCodes::State => counted(0, |count| count.div_ceil(2)),
+        DataInstructionCodes::Settings => counted(0, |count| count * 3),
+        DataInstructionCodes::ContactWaitData => counted(0, |count| count * 4),
+        DataInstructionCodes::SwitchData | DataInstructionCodes::FixData => counted(0, |count| count * 5),
+        DataInstructionCodes::All => counted(ALL_DATA_HEADER_SIZE - 1, |count| count * 3 + count.div_ceil(2)),
+        _ => FrameSize::ToChunkEnd,
+    }
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = CRCu16::crc16ccitt_false();
+    crc.digest(data);
+    crc.get_crc()
+}
+
+/** Checks length and CRC of a V3 frame and returns its body. */
+pub fn unframe(data: &[u8]) -> Result<&[u8], Errors> {
+    if data.len() < FRAME_LENGTH_SIZE + FRAME_CRC_SIZE {
+        return Err(Errors::NotEnoughDataGot);
+    }
+    let body_end = FRAME_LENGTH_SIZE + data[0] as usize;
+    if data.len() < body_end + FRAME_CRC_SIZE {
+        return Err(Errors::NotEnoughDataGot);
+    }
+    let crc = u16::from_be_bytes([data[body_end], data[body_end + 1]]);
+    if crc != crc16(&data[..body_end]) {
+        return Err(Errors::FrameCrcMismatch);
+    }
+    Ok(&data[FRAME_LENGTH_SIZE..body_end])
+}
+
+/** Serializes frame bodies, twice for V3: once to get the length and then to the buffer. */
+pub trait FrameBody {
+    fn write<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors>;
+}
+
+pub fn write_frame<B: BufferWriter, F: FrameBody>(buffer: &mut B, body: &F, framed: bool) -> Result<(), Errors> {
+    buffer.clear();
+    if !framed {
+        return body.write(buffer);
+    }
+    let mut counter = SizeCounter(0);
+    body.write(&mut counter)?;
+    if counter.0 > MAX_FRAME_BODY_SIZE {
+        return Err(Errors::DataOverflow);
+    }
+    let crc = {
+        let mut writer = CrcWriter::new(buffer);
+        writer.add_u8(counter.0 as u8)?;
+        body.write(&mut writer)?;
+        writer.crc.get_crc()
+    };
+    buffer.add_u16(crc)
+}
+
+struct SizeCounter(usize);
+
+impl BufferWriter for SizeCounter {
+
+    #[inline(always)]
+    fn add_str(&mut self, string: &str) -> Result<(), Errors> {
+        self.add(string.as_bytes())
+    }
+
+    #[inline(always)]
+    fn add(&mut self, data: &[u8]) -> Result<(), Errors> {
+        self.0 += data.len();
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn add_u8(&mut self, _: u8) -> Result<(), Errors> {
+        self.0 += 1;
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn add_u16(&mut self, _: u16) -> Result<(), Errors> {
+        self.0 += 2;
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn add_u32(&mut self, _: u32) -> Result<(), Errors> {
+        self.0 += 4;
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn add_u64(&mut self, _: u64) -> Result<(), Errors> {
+        self.0 += 8;
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.0 = 0;
+    }
+}
+
+struct CrcWriter<'a, B: BufferWriter> {
+    buffer: &'a mut B,
+    crc: CRCu16,
+}
+
+impl <'a, B: BufferWriter> CrcWriter<'a, B> {
+    fn new(buffer: &'a mut B) -> Self {
+        Self {
+            buffer,
+            crc: CRCu16::crc16ccitt_false(),
+        }
+    }
+}
+
+impl <'a, B: BufferWriter> BufferWriter for CrcWriter<'a, B> {
+
+    #[inline(always)]
+    fn add_str(&mut self, string: &str) -> Result<(), Errors> {
+        self.add(string.as_bytes())
+    }
+
+    fn add(&mut self, data: &[u8]) -> Result<(), Errors> {
+        self.buffer.add(data)?;
+        self.crc.digest(data);
+        Ok(())
+    }
+
+    fn add_u8(&mut self, byte: u8) -> Result<(), Errors> {
+        self.buffer.add_u8(byte)?;
+        self.crc.digest(&[byte]);
+        Ok(())
+    }
+
+    fn add_u16(&mut self, value: u16) -> Result<(), Errors> {
+        self.buffer.add_u16(value)?;
+        self.crc.digest(&value.to_be_bytes());
+        Ok(())
+    }
+
+    fn add_u32(&mut self, value: u32) -> Result<(), Errors> {
+        self.buffer.add_u32(value)?;
+        self.crc.digest(&value.to_be_bytes());
+        Ok(())
+    }
+
+    fn add_u64(&mut self, value: u64) -> Result<(), Errors> {
+        self.buffer.add_u64(value)?;
+        self.crc.digest(&value.to_be_bytes());
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.crc.reset();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::utils::dma_read_buffer::Buffer;
+
+    #[test]
+    fn test_crc16_check_value() {
+        assert_eq!(0x29b1, crc16(b"123456789"));
+    }
+
+    #[test]
+    fn test_write_frame_unframed() {
+        let body = random_body();
+        let mut buffer = new_buffer();
+
+        write_frame(&mut buffer, &body, false).unwrap();
+
+        assert_eq!(body.0.as_slice(), buffer.bytes());
+    }
+
+    #[test]
+    fn test_write_frame_framed() {
+        let body = random_body();
+        let mut buffer = new_buffer();
+
+        write_frame(&mut buffer, &body, true).unwrap();
+
+        let mut expected = [body.0.len() as u8].to_vec();
+        expected.extend_from_slice(body.0.as_slice());
+        let crc = crc16(expected.as_slice());
+        expected.extend_from_slice(&crc.to_be_bytes());
+        assert_eq!(expected.as_slice(), buffer.bytes());
+        assert_eq!(Ok(body.0.as_slice()), unframe(buffer.bytes()));
+    }
+
+    #[test]
+    fn test_write_frame_should_return_error_on_too_long_body() {
+        let body = TestBody([0; MAX_FRAME_BODY_SIZE + 1].to_vec());
+        let mut buffer = new_buffer();
+
+        assert_eq!(Err(Errors::DataOverflow), write_frame(&mut buffer, &body, true));
+    }
+
+    #[test]
+    fn test_unframe_should_ignore_bytes_after_frame() {
+        let body = random_body();
+        let mut buffer = new_buffer();
+        write_frame(&mut buffer, &body, true).unwrap();
+        let mut data = buffer.bytes().to_vec();
+        data.push(rand::thread_rng().gen());
+
+        assert_eq!(Ok(body.0.as_slice()), unframe(data.as_slice()));
+    }
+
+    #[test]
+    fn test_unframe_should_return_error_on_not_enough_data() {
+        let body = random_body();
+        let mut buffer = new_buffer();
+        write_frame(&mut buffer, &body, true).unwrap();
+        let data = buffer.bytes();
+
+        for size in 0..data.len() {
+            assert_eq!(Err(Errors::NotEnoughDataGot), unframe(&data[..size]));
+        }
+    }
+
+    #[test]
+    fn test_unframe_should_reject_corrupted_frames() {
+        let mut rng = rand::thread_rng();
+        let body = random_body();
+        let mut buffer = new_buffer();
+        write_frame(&mut buffer, &body, true).unwrap();
+
+        for position in 1..buffer.bytes().len() {
+            let mut data = buffer.bytes().to_vec();
+            data[position] ^= rng.gen_range(1..u8::MAX);
+
+            assert_eq!(Err(Errors::FrameCrcMismatch), unframe(data.as_slice()));
+        }
+    }
+
+    #[test]
+    fn test_frame_size_framed() {
+        let mut rng = rand::thread_rng();
+        let length: u8 = rng.gen();
+
+        assert_eq!(FrameSize::Unknown, frame_size(&[], true));
+        assert_eq!(FrameSize::Known(length as usize + 3), frame_size(&[length], true));
+        assert_eq!(FrameSize::Known(length as usize + 3), frame_size(&[length, 0, OperationCodes::Signal as u8], true));
+    }
+
+    #[test]
+    fn test_frame_size_raw() {
+        let mut rng = rand::thread_rng();
+        let expected = [
+            (OperationCodes::Success, 3),
+            (OperationCodes::SuccessV2, 7),
+            (OperationCodes::Error, 4),
+            (OperationCodes::ErrorV2, 8),
+        ];
+        for (operation, size) in expected {
+            let data = [0, operation as u8, rng.gen()];
+            assert_eq!(FrameSize::Known(size), frame_size(&data, false));
+        }
+        assert_eq!(FrameSize::Known(3), frame_size(&[0, OperationCodes::Signal as u8, Signals::GetTimeStamp as u8], false));
+        for signal in [Signals::RelayStateChanged, Signals::MonitoringStateChanged, Signals::ControlStateChanged, Signals::StateFixTry] {
+            assert_eq!(FrameSize::Known(8), frame_size(&[0, OperationCodes::Signal as u8, signal as u8], false));
+        }
+        assert_eq!(FrameSize::ToChunkEnd, frame_size(&[0, OperationCodes::Signal as u8, Signals::Unknown as u8], false));
+        assert_eq!(FrameSize::ToChunkEnd, frame_size(&[0, OperationCodes::Response as u8, DataInstructionCodes::Unknown as u8], false));
+        assert_eq!(FrameSize::ToChunkEnd, frame_size(&[0, OperationCodes::ResponseV2 as u8, DataInstructionCodes::Unknown as u8], false));
+        assert_eq!(FrameSize::Unknown, frame_size(&[0, OperationCodes::Response as u8], false));
+        assert_eq!(FrameSize::ToChunkEnd, frame_size(&[rng.gen_range(1..u8::MAX)], false));
+        assert_eq!(FrameSize::Unknown, frame_size(&[], false));
+        assert_eq!(FrameSize::Unknown, frame_size(&[0], false));
+        assert_eq!(FrameSize::Unknown, frame_size(&[0, OperationCodes::Signal as u8], false));
+    }
+
+    #[test]
+    fn test_frame_size_raw_response() {
+        let mut rng = rand::thread_rng();
+        let count: u8 = rng.gen_range(0..=16);
+        let pairs = (count as usize).div_ceil(2);
+        let expected = [
+            (DataInstructionCodes::Id, 4),
+            (DataInstructionCodes::Version, 1),
+            (DataInstructionCodes::RelaySwitchedOn, 1),
+            (DataInstructionCodes::StateFixSettings, 6),
+            (DataInstructionCodes::SwitchCountingSettings, 3),
+            (DataInstructionCodes::CyclesStatistics, 14),
+            (DataInstructionCodes::State, 1 + pairs),
+            (DataInstructionCodes::Settings, 1 + count as usize * 3),
+            (DataInstructionCodes::ContactWaitData, 1 + count as usize * 4),
+            (DataInstructionCodes::SwitchData, 1 + count as usize * 5),
+            (DataInstructionCodes::FixData, 1 + count as usize * 5),
+        ];
+        for (instruction, size) in expected {
+            let v1 = [0, OperationCodes::Response as u8, instruction as u8, count];
+            assert_eq!(FrameSize::Known(3 + size), frame_size(&v1, false));
+            let v2 = [0, OperationCodes::ResponseV2 as u8, instruction as u8, rng.gen(), rng.gen(), rng.gen(), rng.gen(), count];
+            assert_eq!(FrameSize::Known(7 + size), frame_size(&v2, false));
+        }
+
+        let all = [0, OperationCodes::ResponseV2 as u8, DataInstructionCodes::All as u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, count];
+        assert_eq!(FrameSize::Known(13 + count as usize * 3 + pairs), frame_size(&all, false));
+        assert_eq!(FrameSize::Unknown, frame_size(&all[..all.len() - 1], false));
+        assert_eq!(FrameSize::Unknown, frame_size(&[0, OperationCodes::Response as u8, DataInstructionCodes::State as u8], false));
+    }
+
+    fn random_body() -> TestBody {
+        let mut rng = rand::thread_rng();
+        let size = rng.gen_range(1..32);
+        TestBody((0..size).map(|_| rng.gen()).collect())
+    }
+
+    fn new_buffer() -> Buffer<512> {
+        Buffer::new(Box::leak(Box::new([0_u8; 512])))
+    }
+
+    struct TestBody(Vec<u8>);
+
+    impl FrameBody for TestBody {
+        fn write<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors> {
+            for (idx, byte) in self.0.iter().enumerate() {
+                if idx % 2 == 0 {
+                    buffer.add_u8(*byte)?;
+                } else {
+                    buffer.add(&[*byte])?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,461 @@
+#![deny(unsafe_code)]
+
+use core::mem::discriminant;
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::RelativeMillis;
+use crate::hal_ext::serial_transfer::TransferStats;
+use crate::services::slave_controller_link::domain::{DataInstructions, ErrorCode};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::receiver_from_slave::ErrorHandler;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest};
+use crate::utils::logger::{self, Event};
+
+pub const COUNTED_KINDS_COUNT: usize = 8;
+
+/**
+Counts values by enum variant, the variants which do not fit are counted together as `other`. The
+counts stop at `u32::MAX`.
+ */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct KindCounts<K: Copy> {
+    kinds: [Option<(K, u32)>; COUNTED_KINDS_COUNT],
+    other: u32,
+}
+
+impl <K: Copy> KindCounts<K> {
+
+    pub const fn new() -> Self {
+        Self {
+            kinds: [None; COUNTED_KINDS_COUNT],
+            other: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, kind: K) {
+        self.add_count(kind, 1);
+    }
+
+    /** Count of the `kind` variant, whatever data it holds. */
+    pub fn get(&self, kind: K) -> u32 {
+        self.iter()
+            .find(|(known, _)| discriminant(known) == discriminant(&kind))
+            .map_or(0, |(_, count)| count)
+    }
+
+    /** Counted variants, each one with the first value seen. */
+    pub fn iter(&self) -> impl Iterator<Item = (K, u32)> + '_ {
+        self.kinds.iter().flatten().copied()
+    }
+
+    #[inline(always)]
+    pub fn other(&self) -> u32 {
+        self.other
+    }
+
+    pub fn total(&self) -> u32 {
+        self.iter().fold(self.other, |total, (_, count)| total.saturating_add(count))
+    }
+
+    fn merge(&mut self, counts: &KindCounts<K>) {
+        for (kind, count) in counts.iter() {
+            self.add_count(kind, count);
+        }
+        self.other = self.other.saturating_add(counts.other);
+    }
+
+    fn add_count(&mut self, kind: K, count: u32) {
+        for slot in self.kinds.iter_mut() {
+            match slot {
+                Some((known, total)) if discriminant(known) == discriminant(&kind) => {
+                    *total = total.saturating_add(count);
+                    return;
+                }
+                None => {
+                    *slot = Some((kind, count));
+                    return;
+                }
+                _ => {}
+            }
+        }
+        self.other = self.other.saturating_add(count);
+    }
+}
+
+impl <K: Copy> Default for KindCounts<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/** Time in milliseconds from sending a request to getting its answer. */
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct RoundTripStats {
+    count: u32,
+    min: u32,
+    max: u32,
+    total: u64,
+}
+
+impl RoundTripStats {
+
+    pub fn record(&mut self, millis: u32) {
+        if self.count == 0 || millis < self.min {
+            self.min = millis;
+        }
+        self.max = self.max.max(millis);
+        self.total = self.total.saturating_add(millis as u64);
+        self.count = self.count.saturating_add(1);
+    }
+
+    #[inline(always)]
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<u32> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<u32> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    pub fn average(&self) -> Option<u32> {
+        (self.count > 0).then(|| (self.total / self.count as u64) as u32)
+    }
+}
+
+/** Health counters of one link, a snapshot taken by `SlaveControllerLink::stats`. */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LinkStats {
+    frames_received: u32,
+    rx: TransferStats,
+    tx: TransferStats,
+    parse_errors: KindCounts<Errors>,
+    slave_errors: KindCounts<ErrorCode>,
+    timeouts: u32,
+    round_trip: RoundTripStats,
+}
+
+impl LinkStats {
+
+    pub fn new<EH: ErrorHandler, RH: ResponseHandler>(frames_received: u32, rx: TransferStats, tx: TransferStats,
+                                                      error_handler: &StatsErrorHandler<EH>,
+                                                      response_handler: &StatsResponseHandler<RH>) -> Self {
+        let mut parse_errors = error_handler.errors;
+        parse_errors.merge(&response_handler.parse_errors);
+        Self {
+            frames_received,
+            rx,
+            tx,
+            parse_errors,
+            slave_errors: response_handler.slave_errors,
+            timeouts: response_handler.timeouts,
+            round_trip: response_handler.round_trip,
+        }
+    }
+
+    #[inline(always)]
+    pub fn frames_received(&self) -> u32 {
+        self.frames_received
+    }
+
+    #[inline(always)]
+    pub fn frames_sent(&self) -> u32 {
+        self.tx.transfers()
+    }
+
+    /** DMA FIFO, transfer and direct mode errors of the receiving stream. */
+    #[inline(always)]
+    pub fn rx(&self) -> &TransferStats {
+        &self.rx
+    }
+
+    /** DMA FIFO, transfer and direct mode errors of the transmitting stream. */
+    #[inline(always)]
+    pub fn tx(&self) -> &TransferStats {
+        &self.tx
+    }
+
+    #[inline(always)]
+    pub fn overruns(&self) -> u32 {
+        self.rx.overruns()
+    }
+
+    /** Errors of the received frames and of the responses which could not be parsed or matched. */
+    #[inline(always)]
+    pub fn parse_errors(&self) -> &KindCounts<Errors> {
+        &self.parse_errors
+    }
+
+    /** Error codes the slave answered with, retried requests included. */
+    #[inline(always)]
+    pub fn slave_errors(&self) -> &KindCounts<ErrorCode> {
+        &self.slave_errors
+    }
+
+    #[inline(always)]
+    pub fn timeouts(&self) -> u32 {
+        self.timeouts
+    }
+
+    #[inline(always)]
+    pub fn round_trip(&self) -> &RoundTripStats {
+        &self.round_trip
+    }
+}
+
+/** Counts errors of the received data before passing them on. */
+pub struct StatsErrorHandler<EH: ErrorHandler> {
+    error_handler: EH,
+    errors: KindCounts<Errors>,
+}
+
+impl <EH: ErrorHandler> StatsErrorHandler<EH> {
+
+    pub fn new(error_handler: EH) -> Self {
+        Self {
+            error_handler,
+            errors: KindCounts::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn error_handler(&mut self) -> &mut EH {
+        &mut self.error_handler
+    }
+
+    #[inline(always)]
+    pub fn reset(&mut self) {
+        self.errors = KindCounts::new();
+    }
+}
+
+impl <EH: ErrorHandler> ErrorHandler for StatsErrorHandler<EH> {
+    fn on_error(&mut self, error: Errors) {
+        self.errors.add(error);
+        logger::log(Event::ReceiveError(error));
+        self.error_handler.on_error(error);
+    }
+}
+
+/**
+Counts the answers and failures of requests before the retries and the handshake hide them. The round
+trip is measured to the time set by `set_now` at the start of processing of the received data.
+ */
+pub struct StatsResponseHandler<RH: ResponseHandler> {
+    response_handler: RH,
+    now: RelativeMillis,
+    parse_errors: KindCounts<Errors>,
+    slave_errors: KindCounts<ErrorCode>,
+    timeouts: u32,
+    round_trip: RoundTripStats,
+}
+
+impl <RH: ResponseHandler> StatsResponseHandler<RH> {
+
+    pub fn new(response_handler: RH) -> Self {
+        Self {
+            response_handler,
+            now: RelativeMillis::new(0),
+            parse_errors: KindCounts::new(),
+            slave_errors: KindCounts::new(),
+            timeouts: 0,
+            round_trip: RoundTripStats::default(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+
+    #[inline(always)]
+    pub fn set_now(&mut self, now: RelativeMillis) {
+        self.now = now;
+    }
+
+    pub fn reset(&mut self) {
+        self.parse_errors = KindCounts::new();
+        self.slave_errors = KindCounts::new();
+        self.timeouts = 0;
+        self.round_trip = RoundTripStats::default();
+    }
+
+    fn on_answer(&mut self, request: &SentRequest) {
+        self.round_trip.record(self.now.value().wrapping_sub(request.rel_timestamp().value()));
+    }
+}
+
+impl <RH: ResponseHandler> ResponseHandler for StatsResponseHandler<RH> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        self.on_answer(&request);
+        self.response_handler.on_request_success(request);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        self.on_answer(&request);
+        self.response_handler.on_request_response(request, response);
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        self.on_answer(&request);
+        self.slave_errors.add(error_code);
+        logger::log(Event::SlaveError(request.instruction(), error_code));
+        self.response_handler.on_request_error(request, error_code);
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+        if let Some(request) = request.as_ref() {
+            self.on_answer(request);
+        }
+        self.parse_errors.add(error);
+        logger::log(Event::ResponseError(error));
+        self.response_handler.on_request_parse_error(request, error, data);
+    }
+
+    fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
+        self.parse_errors.add(error);
+        logger::log(Event::ResponseError(error));
+        self.response_handler.on_request_search_error(payload, error);
+    }
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        self.timeouts = self.timeouts.saturating_add(1);
+        logger::log(Event::RequestTimeout(request.instruction()));
+        self.response_handler.on_request_timeout(request);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::slave_controller_link::domain::{DataInstructionCodes, Operation};
+
+    #[test]
+    fn test_kind_counts_group_by_variant() {
+        let mut tested = KindCounts::new();
+
+        tested.add(Errors::OperationNotRecognized(1));
+        tested.add(Errors::OperationNotRecognized(2));
+        tested.add(Errors::FrameCrcMismatch);
+
+        assert_eq!(2, tested.get(Errors::OperationNotRecognized(0)));
+        assert_eq!(1, tested.get(Errors::FrameCrcMismatch));
+        assert_eq!(0, tested.get(Errors::DataOverflow));
+        assert_eq!(3, tested.total());
+        assert_eq!(0, tested.other());
+    }
+
+    #[test]
+    fn test_kind_counts_overflow_to_other() {
+        let mut tested = KindCounts::new();
+        let errors = [Errors::NoBufferAvailable, Errors::TransferInProgress, Errors::DmaBufferOverflow,
+            Errors::CommandDataCorrupted, Errors::NotEnoughDataGot, Errors::DataCorrupted, Errors::FromAfterTo,
+            Errors::OutOfRange, Errors::InvalidDataSize, Errors::DataOverflow];
+
+        for error in errors {
+            tested.add(error);
+        }
+
+        assert_eq!(COUNTED_KINDS_COUNT, tested.iter().count());
+        assert_eq!((errors.len() - COUNTED_KINDS_COUNT) as u32, tested.other());
+        assert_eq!(errors.len() as u32, tested.total());
+        assert_eq!(0, tested.get(Errors::DataOverflow));
+    }
+
+    #[test]
+    fn test_counts_saturate() {
+        let mut tested = KindCounts::new();
+        tested.add_count(Errors::DataOverflow, u32::MAX);
+        tested.add(Errors::DataOverflow);
+        tested.add(Errors::OutOfRange);
+        let mut round_trip = RoundTripStats { count: u32::MAX, min: 1, max: 1, total: u64::MAX };
+
+        round_trip.record(1);
+
+        assert_eq!(u32::MAX, tested.get(Errors::DataOverflow));
+        assert_eq!(u32::MAX, tested.total());
+        assert_eq!(u32::MAX, round_trip.count());
+    }
+
+    #[test]
+    fn test_round_trip_stats() {
+        let mut tested = RoundTripStats::default();
+        assert_eq!(None, tested.min());
+        assert_eq!(None, tested.average());
+
+        for millis in [30, 10, 20] {
+            tested.record(millis);
+        }
+
+        assert_eq!(3, tested.count());
+        assert_eq!(Some(10), tested.min());
+        assert_eq!(Some(30), tested.max());
+        assert_eq!(Some(20), tested.average());
+    }
+
+    #[test]
+    fn test_response_handler_counts_answers_and_failures() {
+        let mut rng = rand::thread_rng();
+        let sent = rng.gen_range(0..u32::MAX / 2);
+        let request = SentRequest::new(None, Operation::Read, DataInstructionCodes::Id, RelativeMillis::new(sent));
+        let mut tested = StatsResponseHandler::new(CountingHandler::default());
+        tested.set_now(RelativeMillis::new(sent + 15));
+
+        tested.on_request_success(request);
+        tested.on_request_error(request, ErrorCode::ERelayIndexOutOfRange);
+        tested.on_request_error(request, ErrorCode::EUndefinedCode(0x7f));
+        tested.on_request_parse_error(None, Errors::NotEnoughDataGot, &[]);
+        tested.on_request_timeout(request);
+
+        assert_eq!(5, tested.response_handler().calls);
+        assert_eq!(3, tested.round_trip.count());
+        assert_eq!(Some(15), tested.round_trip.max());
+        assert_eq!(1, tested.slave_errors.get(ErrorCode::ERelayIndexOutOfRange));
+        assert_eq!(1, tested.slave_errors.get(ErrorCode::EUndefinedCode(0)));
+        assert_eq!(1, tested.parse_errors.get(Errors::NotEnoughDataGot));
+        assert_eq!(1, tested.timeouts);
+
+        tested.reset();
+
+        assert_eq!(0, tested.round_trip.count());
+        assert_eq!(0, tested.slave_errors.total());
+        assert_eq!(0, tested.timeouts);
+    }
+
+    #[derive(Default)]
+    struct CountingHandler {
+        calls: u32,
+    }
+
+    impl ResponseHandler for CountingHandler {
+        fn on_request_success(&mut self, _: SentRequest) {
+            self.calls += 1;
+        }
+
+        fn on_request_response(&mut self, _: SentRequest, _: DataInstructions) {
+            self.calls += 1;
+        }
+
+        fn on_request_error(&mut self, _: SentRequest, _: ErrorCode) {
+            self.calls += 1;
+        }
+
+        fn on_request_parse_error(&mut self, _: Option<SentRequest>, _: Errors, _: &[u8]) {
+            self.calls += 1;
+        }
+
+        fn on_request_search_error(&mut self, _: ResponseData, _: Errors) {
+            self.calls += 1;
+        }
+
+        fn on_request_timeout(&mut self, _: SentRequest) {
+            self.calls += 1;
+        }
+    }
+}
@@ -1,149 +1,97 @@
-#![allow(unsafe_code)]
+#![deny(unsafe_code)]
 
-use alloc::boxed::Box;
-use core::mem::size_of;
 use crate::errors::Errors;
-use crate::services::slave_controller_link::domain::{AllData, ContactsWaitData, Conversation, Data, DataInstructionCodes, DataInstructions, ErrorCode, Extractor, FixDataContainer, Operation, OperationCodes, RelaysSettings, Request, SignalData, Signals, StateSwitchDatas, Version};
+use crate::services::slave_controller_link::framing::unframe;
+use crate::services::slave_controller_link::domain::{AllData, AutoCreator, ContactsWaitData, Conversation, Data, DataInstructionCodes, DataInstructions, EmptyRequest, ErrorCode, Extractor, FixDataContainer, Operation, OperationCodes, RelaysSettings, Request, SignalData, Signals, StateSwitchDatas, Version};
 
 
+/**
+Buffer for the large responses. Only one of them could be requested at a time (see
+`ResponseBodyParser::request_needs_cache`), so they share the space.
+ */
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+enum ResponseBuffer {
+    Empty,
+    Settings(RelaysSettings),
+    ContactWaitData(ContactsWaitData),
+    SwitchData(StateSwitchDatas),
+    FixData(FixDataContainer),
+    All(AllData),
+}
 
-const fn max_of(size1: usize, size2: usize, size3: usize, size4: usize, size5: usize) -> usize {
-    let mut max = size1;
-    if size2 > max { max = size2; }
-    if size3 > max { max = size3; }
-    if size4 > max { max = size4; }
-    if size5 > max { max = size5; }
-    max
+/** Place of the data in the buffer, the previous content is replaced if it is of another type. */
+trait CachedData: Data + Sized {
+    fn slot(buffer: &mut ResponseBuffer) -> &mut Self;
 }
 
-fn get_next_static_buffer_index() -> Result<usize, Errors> {
-    let static_buffers_idx = unsafe {
-        if INSTANCES_COUNT >= MAX_INSTANCES_COUNT {
-            return Err(Errors::SlaveControllersInstancesMaxCountReached);
+macro_rules! cached_data {
+    ($data:ty, $variant:ident) => {
+        impl CachedData for $data {
+            fn slot(buffer: &mut ResponseBuffer) -> &mut Self {
+                if !matches!(buffer, ResponseBuffer::$variant(_)) {
+                    *buffer = ResponseBuffer::$variant(<$data as AutoCreator>::default());
+                }
+                match buffer {
+                    ResponseBuffer::$variant(data) => data,
+                    _ => unreachable!(),
+                }
+            }
         }
-        let instances_count = INSTANCES_COUNT;
-        INSTANCES_COUNT += 1;
-        instances_count as usize
     };
-    Ok(static_buffers_idx)
-}
-
-const RESPONSE_BUFFER_SIZE: usize = max_of(size_of::<FixDataContainer>(), size_of::<RelaysSettings>(),
-                                           size_of::<ContactsWaitData>(), size_of::<StateSwitchDatas>(), size_of::<AllData>());
-
-type ResponseBuffer = [u8; RESPONSE_BUFFER_SIZE];
-
-const MAX_INSTANCES_COUNT: usize = 3;
-static mut STATIC_BUFFERS: [ResponseBuffer; MAX_INSTANCES_COUNT] = [ [0; RESPONSE_BUFFER_SIZE], [0; RESPONSE_BUFFER_SIZE], [0; RESPONSE_BUFFER_SIZE] ];
-static mut INSTANCES_COUNT: usize = 0;
-
-unsafe fn get_data_container_cashed<RQ: Request, D: Data + 'static>(static_buffers_idx: usize) -> Conversation<RQ, D> {
-    let buf = &mut STATIC_BUFFERS[static_buffers_idx];
-    let raw_ptr  = buf.as_mut_ptr() as *mut D;
-    Conversation::DataCashed(&mut *raw_ptr)
-}
-
-trait CashedInstructionGetter {
-    fn get(&self, static_buffers_idx: usize) -> DataInstructions;
 }
 
-#[derive(Copy, Clone)]
-struct RelasySettingsCashedInstructionGetter;
-impl CashedInstructionGetter for RelasySettingsCashedInstructionGetter {
-    fn get(&self, static_buffers_idx: usize) -> DataInstructions {
-        DataInstructions::Settings(unsafe {get_data_container_cashed(static_buffers_idx)})
-    }
-}
+cached_data!(RelaysSettings, Settings);
+cached_data!(ContactsWaitData, ContactWaitData);
+cached_data!(StateSwitchDatas, SwitchData);
+cached_data!(FixDataContainer, FixData);
+cached_data!(AllData, All);
 
-#[derive(Copy, Clone)]
-struct ContactsWaitDataCashedInstructionGetter;
-impl CashedInstructionGetter for ContactsWaitDataCashedInstructionGetter {
-    fn get(&self, static_buffers_idx: usize) -> DataInstructions {
-        DataInstructions::ContactWaitData(unsafe {get_data_container_cashed(static_buffers_idx)})
-    }
-}
-
-#[derive(Copy, Clone)]
-struct StateSwitchDataCashedInstructionGetter;
-impl CashedInstructionGetter for StateSwitchDataCashedInstructionGetter {
-    fn get(&self, static_buffers_idx: usize) -> DataInstructions {
-        DataInstructions::SwitchData(unsafe {get_data_container_cashed(static_buffers_idx)})
-    }
-}
-
-#[derive(Copy, Clone)]
-struct FixDataContainerCashedInstructionGetter;
-impl CashedInstructionGetter for FixDataContainerCashedInstructionGetter {
-    fn get(&self, static_buffers_idx: usize) -> DataInstructions {
-        DataInstructions::FixData(unsafe {get_data_container_cashed(static_buffers_idx)})
-    }
-}
-
-#[derive(Copy, Clone)]
-struct AllDataCashedInstructionGetter;
-impl CashedInstructionGetter for AllDataCashedInstructionGetter {
-    fn get(&self, static_buffers_idx: usize) -> DataInstructions {
-        DataInstructions::All(unsafe {get_data_container_cashed(static_buffers_idx)})
-    }
-}
-
-
-const INSTRUCTIONS_COUNT: usize = DataInstructionCodes::Last as usize;
-
-static mut DF: [Option<Box<dyn CashedInstructionGetter>>; INSTRUCTIONS_COUNT] = [None, None, None, None, None, None, None,
-    None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None];
-
-pub fn init_cache_getters() {
-    unsafe {
-        DF[DataInstructionCodes::Settings as usize] = Some(Box::new(RelasySettingsCashedInstructionGetter));
-        DF[DataInstructionCodes::ContactWaitData as usize] = Some(Box::new(ContactsWaitDataCashedInstructionGetter));
-        DF[DataInstructionCodes::SwitchData as usize] = Some(Box::new(StateSwitchDataCashedInstructionGetter));
-        DF[DataInstructionCodes::FixData as usize] = Some(Box::new(FixDataContainerCashedInstructionGetter));
-        DF[DataInstructionCodes::All as usize] = Some(Box::new(AllDataCashedInstructionGetter));
-    }
-}
-
-fn cache_getter(code: DataInstructionCodes) -> Option< &'static Box<dyn CashedInstructionGetter>> {
-    unsafe {
-        DF[code as usize].as_ref()
-    }
+fn parse_cached<'a, RQ: Request, D: CachedData>(buffer: &'a mut ResponseBuffer, data: &[u8]) -> Result<Conversation<'a, RQ, D>, Errors> {
+    let cached = D::slot(buffer);
+    cached.parse_from(data)?;
+    Ok(Conversation::DataCashed(cached))
 }
 
 pub trait ResponseBodyParser {
     fn request_needs_cache(&self, instruction: DataInstructionCodes) -> bool;
-    fn parse(&self, instruction: DataInstructionCodes, data: &[u8]) -> Result<DataInstructions, Errors>;
+    fn parse(&mut self, instruction: DataInstructionCodes, data: &[u8]) -> Result<DataInstructions<'_>, Errors>;
 }
 
 pub struct ResponseBodyParserImpl {
-    static_buffers_idx: usize,
+    buffer: ResponseBuffer,
 }
 
 impl ResponseBodyParserImpl {
-    pub fn create() -> Result<Self, Errors> {
-        let static_buffers_idx = get_next_static_buffer_index()?;
-        Ok(Self {
-            static_buffers_idx,
-        })
+    pub fn new() -> Self {
+        Self {
+            buffer: ResponseBuffer::Empty,
+        }
+    }
+}
+
+impl Default for ResponseBodyParserImpl {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl ResponseBodyParser for ResponseBodyParserImpl {
-    fn parse(&self, instruction: DataInstructionCodes, data: &[u8]) -> Result<DataInstructions, Errors> {
-        match cache_getter(instruction) {
-            Some(getter) => {
-                let mut cached_instruction = getter.get(self.static_buffers_idx);
-                cached_instruction.parse_from(data)?;
-                Ok(cached_instruction)
-            },
-            None => Ok(DataInstructions::parse(instruction, data)?)
+    fn parse(&mut self, instruction: DataInstructionCodes, data: &[u8]) -> Result<DataInstructions<'_>, Errors> {
+        let buffer = &mut self.buffer;
+        match instruction {
+            DataInstructionCodes::Settings => Ok(DataInstructions::Settings(parse_cached::<EmptyRequest, _>(buffer, data)?)),
+            DataInstructionCodes::ContactWaitData => Ok(DataInstructions::ContactWaitData(parse_cached::<EmptyRequest, _>(buffer, data)?)),
+            DataInstructionCodes::SwitchData => Ok(DataInstructions::SwitchData(parse_cached::<EmptyRequest, _>(buffer, data)?)),
+            DataInstructionCodes::FixData => Ok(DataInstructions::FixData(parse_cached::<EmptyRequest, _>(buffer, data)?)),
+            DataInstructionCodes::All => Ok(DataInstructions::All(parse_cached::<EmptyRequest, _>(buffer, data)?)),
+            _ => DataInstructions::parse(instruction, data),
         }
     }
 
     fn request_needs_cache(&self, instruction: DataInstructionCodes) -> bool {
-        match cache_getter(instruction) {
-            Some(_) => { true },
-            None => { false }
-        }
+        matches!(instruction, DataInstructionCodes::Settings | DataInstructionCodes::ContactWaitData |
+            DataInstructionCodes::SwitchData | DataInstructionCodes::FixData | DataInstructionCodes::All)
     }
 
 }
@@ -222,11 +170,15 @@ pub trait PayloadParser<SP, RP>
     fn parse<'a>(&self, data: &'a[u8]) -> Result<(PayloadParserResult<SP, RP>, &'a[u8]), Errors>;
 }
 
-pub struct PayloadParserImpl ();
+pub struct PayloadParserImpl {
+    version: Version,
+}
 
 impl PayloadParserImpl {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(version: Version) -> Self {
+        Self {
+            version,
+        }
     }
     
     fn parse_operation(data: &[u8]) -> Result<(Operation, &[u8]), Errors> {
@@ -258,6 +210,7 @@ impl PayloadParserImpl {
 
 impl PayloadParser<SignalParserImpl, ResponseParserImpl> for PayloadParserImpl {
     fn parse<'a>(&self, data: &'a[u8]) -> Result<(PayloadParserResult<SignalParserImpl, ResponseParserImpl>, &'a[u8]), Errors> {
+        let data = if self.version.framed() { unframe(data)? } else { data };
         if data.len() < 2 {
             Err(Errors::NotEnoughDataGot)
         } else if data[0] != OperationCodes::None as u8 {
@@ -300,7 +253,7 @@ impl ResponseParser for ResponseParserImpl {
                 Version::V1 => {
                     Ok( (None, data) )
                 },
-                Version::V2 => {
+                Version::V2 | Version::V3 => {
                     if data.len() >= 4 {
                         let data_res = if data.len() > 4 { &data[4..] } else { &data[0..0] };
                         Ok( (Some(u32::extract(&(data)[0..4])), data_res) )
@@ -344,6 +297,7 @@ impl SignalParser for SignalParserImpl {
 mod tests {
     #![allow(unsafe_code)]
 
+    use alloc::boxed::Box;
     use alloc::vec::Vec;
     use core::cell::RefCell;
     use embedded_dma::ReadBuffer;
@@ -351,6 +305,7 @@ mod tests {
     use rand::prelude::*;
     use crate::hal_ext::rtc_wrapper::RelativeSeconds;
     use crate::services::slave_controller_link::domain::{Serializable, DataInstructions, RelaySignalData, RelaySignalDataExt, Signals, MAX_RELAYS_COUNT, State, StateFixSettings, RelayState, CyclesStatistics, SwitchCountingSettings, RelaySingleState};
+    use crate::services::slave_controller_link::framing::crc16;
     use crate::utils::dma_read_buffer::{Buffer, BufferWriter};
 
     #[test]
@@ -610,7 +565,7 @@ mod tests {
     #[test]
     fn test_payload_parser_parse_should_return_error_on_not_enough_data() {
         let mut rng = rand::thread_rng();
-        let parser = PayloadParserImpl::new();
+        let parser = PayloadParserImpl::new(Version::V1);
         let data = [rng.gen_range(1..u8::MAX)];
 
         let result = parser.parse(&data);
@@ -623,7 +578,7 @@ mod tests {
     fn test_payload_parser_parse_should_return_error_on_not_none_second_code() {
         for operation_code in ALL_POSSIBLE_OPERATION_CODES {
             let mut rng = rand::thread_rng();
-            let parser = PayloadParserImpl::new();
+            let parser = PayloadParserImpl::new(Version::V1);
             let data = [operation_code as u8, rng.gen_range(1..u8::MAX)];
 
             let result = parser.parse(&data);
@@ -640,7 +595,7 @@ mod tests {
         let operations = [Operation::Set, Operation::Read, Operation::Error, Operation::Set, Operation::Read, Operation::Error];
         for idx in 0..operation_codes.len() {
             let mut rng = rand::thread_rng();
-            let parser = PayloadParserImpl::new();
+            let parser = PayloadParserImpl::new(Version::V1);
             let data = [OperationCodes::None as u8, operation_codes[idx] as u8, rng.gen_range(1..u8::MAX)];
 
             let result = parser.parse(&data);
@@ -656,7 +611,7 @@ mod tests {
     #[test]
     fn test_payload_parser_parse_signal() {
         let mut rng = rand::thread_rng();
-        let parser = PayloadParserImpl::new();
+        let parser = PayloadParserImpl::new(Version::V1);
         let data = [OperationCodes::None as u8, OperationCodes::Signal as u8, rng.gen_range(1..u8::MAX)];
 
         let result = parser.parse(&data);
@@ -673,7 +628,7 @@ mod tests {
         let operation_codes = [OperationCodes::None, OperationCodes::Read, OperationCodes::Set, OperationCodes::Command];
         for idx in 0..operation_codes.len() {
             let mut rng = rand::thread_rng();
-            let parser = PayloadParserImpl::new();
+            let parser = PayloadParserImpl::new(Version::V1);
             let data = [OperationCodes::None as u8, operation_codes[idx] as u8, rng.gen_range(1..u8::MAX)];
 
             let result = parser.parse(&data);
@@ -682,6 +637,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_payload_parser_parse_v3_checks_frame() {
+        let mut rng = rand::thread_rng();
+        let parser = PayloadParserImpl::new(Version::V3);
+        let body = [OperationCodes::None as u8, OperationCodes::ResponseV2 as u8, rng.gen_range(1..u8::MAX)];
+        let mut data = [body.len() as u8].to_vec();
+        data.extend_from_slice(&body);
+        data.extend_from_slice(&crc16(data.as_slice()).to_be_bytes());
+
+        let result = parser.parse(data.as_slice());
+
+        assert_eq!(
+            Ok((PayloadParserResult::ResponsePayload(ResponseParserImpl::new(Operation::Read)), &body[2..])),
+            result
+        );
+
+        let last = data.len() - 1;
+        data[last] ^= rng.gen_range(1..u8::MAX);
+        assert_eq!(Err(Errors::FrameCrcMismatch), parser.parse(data.as_slice()));
+        // unframed data should not pass
+        assert!(parser.parse(&body).is_err());
+    }
+
     static mut BUFFER_ARR: [u8; 256] = [0_u8; 256];
 
     #[test]
@@ -697,12 +675,11 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::Settings, buffer.bytes());
 
-        assert_eq!(Ok(DataInstructions::Settings(Conversation::Data(data_object))), result);
+        assert_eq!(Ok(DataInstructions::Settings(Conversation::DataCashed(&mut data_object))), result);
     }
 
     #[test]
@@ -711,8 +688,7 @@ mod tests {
         let data_object = State::create(rng.gen_range(0..15), rng.next_u64()).unwrap();
         let mut buffer = unsafe { Buffer::new(&mut BUFFER_ARR) };
         data_object.serialize(&mut buffer).unwrap();
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::State, buffer.bytes());
 
@@ -725,8 +701,7 @@ mod tests {
         let data_object: u32 = rng.next_u32();
         let mut buffer = unsafe { Buffer::new(&mut BUFFER_ARR) };
         data_object.serialize(&mut buffer).unwrap();
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::Id, buffer.bytes());
 
@@ -742,8 +717,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::InterruptPin, buffer.bytes());
 
@@ -759,8 +733,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::RemoteTimestamp, buffer.bytes());
 
@@ -781,8 +754,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::StateFixSettings, buffer.bytes());
 
@@ -802,8 +774,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::RelayState, buffer.bytes());
 
@@ -819,8 +790,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::Version, buffer.bytes());
 
@@ -836,8 +806,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::CurrentTime, buffer.bytes());
 
@@ -857,12 +826,11 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::ContactWaitData, buffer.bytes());
 
-        assert_eq!(Ok(DataInstructions::ContactWaitData(Conversation::Data(data_object))), result);
+        assert_eq!(Ok(DataInstructions::ContactWaitData(Conversation::DataCashed(&mut data_object))), result);
     }
 
     #[test]
@@ -879,12 +847,11 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::FixData, buffer.bytes());
 
-        assert_eq!(Ok(DataInstructions::FixData(Conversation::Data(data_object))), result);
+        assert_eq!(Ok(DataInstructions::FixData(Conversation::DataCashed(&mut data_object))), result);
     }
 
     #[test]
@@ -900,30 +867,28 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::SwitchData, buffer.bytes());
 
-        assert_eq!(Ok(DataInstructions::SwitchData(Conversation::Data(data_object))), result);
+        assert_eq!(Ok(DataInstructions::SwitchData(Conversation::DataCashed(&mut data_object))), result);
     }
 
     #[test]
     fn test_response_body_parser_parse_on_cycle_statistics() {
-            let mut rng = thread_rng();
-            let data_object = CyclesStatistics::new(rng.gen_range(0..u16::MAX),
-                            rng.gen_range(0..u16::MAX), rng.gen_range(0..u16::MAX), rng.next_u64());
+        let mut rng = thread_rng();
+        let data_object = CyclesStatistics::new(rng.gen_range(0..u16::MAX),
+                                          rng.gen_range(0..u16::MAX), rng.gen_range(0..u16::MAX), rng.next_u64());
 
-            let mut buffer = unsafe { Buffer::new(&mut BUFFER_ARR) };
+        let mut buffer = unsafe { Buffer::new(&mut BUFFER_ARR) };
 
-            data_object.serialize(&mut buffer).unwrap();
+        data_object.serialize(&mut buffer).unwrap();
 
-            clear_static_buffer_index();
-            let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
-            let result = parser.parse(DataInstructionCodes::CyclesStatistics, buffer.bytes());
+        let result = parser.parse(DataInstructionCodes::CyclesStatistics, buffer.bytes());
 
-            assert_eq!(Ok(DataInstructions::CyclesStatistics(Conversation::Data(data_object))), result);
+        assert_eq!(Ok(DataInstructions::CyclesStatistics(Conversation::Data(data_object))), result);
     }
 
     #[test]
@@ -935,8 +900,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::SwitchCountingSettings, buffer.bytes());
 
@@ -953,8 +917,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::RelayDisabledTemp, buffer.bytes());
 
@@ -971,8 +934,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::RelaySwitchedOn, buffer.bytes());
 
@@ -989,8 +951,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::RelayMonitorOn, buffer.bytes());
 
@@ -1007,8 +968,7 @@ mod tests {
 
         data_object.serialize(&mut buffer).unwrap();
 
-        clear_static_buffer_index();
-        let parser = ResponseBodyParserImpl::create().unwrap();
+        let mut parser = ResponseBodyParserImpl::new();
 
         let result = parser.parse(DataInstructionCodes::RelayControlOn, buffer.bytes());
 
@@ -1032,23 +992,46 @@ mod tests {
             let mut buffer = unsafe { Buffer::new(&mut BUFFER_ARR) };
             AllData::serialize(&data_object, &mut buffer).unwrap();
 
-            clear_static_buffer_index();
-            let parser = ResponseBodyParserImpl::create().unwrap();
+            let mut parser = ResponseBodyParserImpl::new();
             let result = parser.parse(DataInstructionCodes::All, buffer.bytes());
 
-            assert_eq!(Ok(DataInstructions::All(Conversation::Data(data_object))), result);
+            assert_eq!(Ok(DataInstructions::All(Conversation::DataCashed(&mut data_object))), result);
         }
     }
 
+    #[test]
+    fn test_response_body_parser_reuses_buffer_for_cached_data() {
+        let mut rng = rand::thread_rng();
+        let mut parser = ResponseBodyParserImpl::new();
+        for _ in 0..3 {
+            let mut settings = RelaysSettings::new();
+            for _ in 0..rng.gen_range(0..MAX_RELAYS_COUNT) {
+                settings.add(rng.gen(), rng.gen(), rng.gen()).unwrap();
+            }
+            let mut buffer: Buffer<256> = Buffer::new(Box::leak(Box::new([0_u8; 256])));
+            settings.serialize(&mut buffer).unwrap();
 
+            let result = parser.parse(DataInstructionCodes::Settings, buffer.bytes());
 
-    fn clear_static_buffer_index() {
-        unsafe {
-            INSTANCES_COUNT = 0;
+            assert_eq!(Ok(DataInstructions::Settings(Conversation::DataCashed(&mut settings))), result);
+
+            let count = rng.gen_range(1..MAX_RELAYS_COUNT);
+            let mut all_data = AllData::new(rng.next_u32(), rng.gen_range(0..count));
+            for _ in 0..count {
+                all_data.add(rng.gen(), rng.gen(), rng.gen(), rng.gen_range(0..16)).unwrap();
+            }
+            let mut buffer: Buffer<256> = Buffer::new(Box::leak(Box::new([0_u8; 256])));
+            AllData::serialize(&all_data, &mut buffer).unwrap();
+
+            let result = parser.parse(DataInstructionCodes::All, buffer.bytes());
+
+            assert_eq!(Ok(DataInstructions::All(Conversation::DataCashed(&mut all_data))), result);
         }
     }
 
-    const ALL_INSTRUCTIONS: [DataInstructionCodes; 19] = [
+
+
+    const ALL_INSTRUCTIONS: [DataInstructionCodes; 20] = [
         DataInstructionCodes::Settings,
         DataInstructionCodes::State,
         DataInstructionCodes::Id,
@@ -1068,6 +1051,7 @@ mod tests {
         DataInstructionCodes::RelayMonitorOn,
         DataInstructionCodes::RelayControlOn,
         DataInstructionCodes::All,
+        DataInstructionCodes::ClearSwitchCount,
     ];
 
     const ALL_ERROR_CODES: [ErrorCode; 15] = [
@@ -1143,7 +1127,7 @@ mod tests {
             self.request_needs_cache_result
         }
 
-        fn parse(&self, _: DataInstructionCodes, data: &[u8]) -> Result<DataInstructions, Errors> {
+        fn parse(&mut self, _: DataInstructionCodes, data: &[u8]) -> Result<DataInstructions<'_>, Errors> {
             *self.parse_params.borrow_mut() = Some(data.to_vec());
             Err(Errors::DataCorrupted)
         }
@@ -0,0 +1,275 @@
+#![deny(unsafe_code)]
+
+use crate::errors::Errors;
+use crate::services::slave_controller_link::framing::{frame_size, FrameSize, MAX_FRAME_SIZE};
+
+/**
+Splits bytes of each idle line event into frames. Complete frames are passed on straight from the
+DMA buffer, the incomplete one at the end is kept and completed by the bytes of the next event.
+ */
+pub struct FrameReassembler {
+    framed: bool,
+    buffer: [u8; MAX_FRAME_SIZE],
+    size: usize,
+    frames: u32,
+}
+
+impl FrameReassembler {
+
+    pub const fn new(framed: bool) -> Self {
+        Self {
+            framed,
+            buffer: [0; MAX_FRAME_SIZE],
+            size: 0,
+            frames: 0,
+        }
+    }
+
+    /** Bytes of the incomplete frame waiting for the next chunk. */
+    #[inline(always)]
+    pub fn pending(&self) -> usize {
+        self.size
+    }
+
+    /** Frames passed on since the creation or the last `reset_frames`. */
+    #[inline(always)]
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    #[inline(always)]
+    pub fn reset_frames(&mut self) {
+        self.frames = 0;
+    }
+
+    /** Drops the incomplete frame, e.g. when the link is restarted. */
+    #[inline(always)]
+    pub fn reset(&mut self) {
+        self.size = 0;
+    }
+
+    /** Calls `on_frame` for every frame completed by the chunk, its errors are passed to `on_error`. */
+    pub fn push<F, E>(&mut self, data: &[u8], mut on_frame: F, mut on_error: E)
+        where
+            F: FnMut(&[u8]) -> Result<(), Errors>,
+            E: FnMut(Errors),
+    {
+        let mut rest = data;
+        if self.size > 0 {
+            rest = self.complete_pending(rest, &mut on_frame, &mut on_error);
+        }
+        while !rest.is_empty() {
+            let size = match frame_size(rest, self.framed) {
+                FrameSize::Known(size) if size <= rest.len() => size,
+                FrameSize::ToChunkEnd => rest.len(),
+                _ => {
+                    self.append(rest, &mut on_error);
+                    return;
+                }
+            };
+            self.frames = self.frames.saturating_add(1);
+            if let Err(error) = on_frame(&rest[..size]) {
+                on_error(error);
+            }
+            rest = &rest[size..];
+        }
+    }
+
+    fn complete_pending<'a, F, E>(&mut self, mut data: &'a [u8], on_frame: &mut F, on_error: &mut E) -> &'a [u8]
+        where
+            F: FnMut(&[u8]) -> Result<(), Errors>,
+            E: FnMut(Errors),
+    {
+        loop {
+            match frame_size(&self.buffer[..self.size], self.framed) {
+                FrameSize::Known(size) if size <= self.size => break,
+                FrameSize::Known(size) => {
+                    let count = (size - self.size).min(data.len());
+                    self.append(&data[..count], on_error);
+                    data = &data[count..];
+                    if data.is_empty() && self.size < size {
+                        return data;
+                    }
+                }
+                FrameSize::Unknown => {
+                    if data.is_empty() {
+                        return data;
+                    }
+                    self.append(&data[..1], on_error);
+                    data = &data[1..];
+                }
+                FrameSize::ToChunkEnd => {
+                    self.append(data, on_error);
+                    data = &data[data.len()..];
+                    break;
+                }
+            }
+        }
+        let result = on_frame(&self.buffer[..self.size]);
+        self.size = 0;
+        self.frames = self.frames.saturating_add(1);
+        if let Err(error) = result {
+            on_error(error);
+        }
+        data
+    }
+
+    fn append<E: FnMut(Errors)>(&mut self, data: &[u8], on_error: &mut E) {
+        let free = MAX_FRAME_SIZE - self.size;
+        if data.len() > free {
+            on_error(Errors::DataOverflow);
+        }
+        let count = data.len().min(free);
+        self.buffer[self.size..self.size + count].copy_from_slice(&data[..count]);
+        self.size += count;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::slave_controller_link::domain::{DataInstructionCodes, OperationCodes, Signals};
+
+    #[test]
+    fn test_splits_back_to_back_raw_frames() {
+        let mut rng = rand::thread_rng();
+        let time_request = [0, OperationCodes::Signal as u8, Signals::GetTimeStamp as u8];
+        let relay_signal = [0, OperationCodes::Signal as u8, Signals::RelayStateChanged as u8,
+            rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen()];
+        let response = [0, OperationCodes::Response as u8, DataInstructionCodes::Id as u8,
+            rng.gen(), rng.gen(), rng.gen(), rng.gen()];
+        let data = [time_request.as_slice(), relay_signal.as_slice(), response.as_slice()].concat();
+        let mut tested = FrameReassembler::new(false);
+
+        let (frames, errors) = push(&mut tested, &data, Ok(()));
+
+        assert_eq!(vec![time_request.to_vec(), relay_signal.to_vec(), response.to_vec()], frames);
+        assert!(errors.is_empty());
+        assert_eq!(0, tested.pending());
+        assert_eq!(3, tested.frames());
+    }
+
+    #[test]
+    fn test_carries_partial_frames_over() {
+        let mut rng = rand::thread_rng();
+        for framed in [false, true] {
+            let first = frame(framed, &mut rng);
+            let second = frame(framed, &mut rng);
+            let data = [first.as_slice(), second.as_slice()].concat();
+            for split in 1..data.len() {
+                let mut tested = FrameReassembler::new(framed);
+
+                let (mut frames, _) = push(&mut tested, &data[..split], Ok(()));
+                let (next_frames, errors) = push(&mut tested, &data[split..], Ok(()));
+                frames.extend(next_frames);
+
+                assert_eq!(vec![first.clone(), second.clone()], frames);
+                assert!(errors.is_empty());
+                assert_eq!(0, tested.pending());
+            }
+        }
+    }
+
+    #[test]
+    fn test_joins_frame_split_into_many_chunks() {
+        let mut rng = rand::thread_rng();
+        let body: Vec<u8> = (0..rng.gen_range(100..200)).map(|_| rng.gen()).collect();
+        let mut data = [body.len() as u8].to_vec();
+        data.extend_from_slice(&body);
+        data.extend_from_slice(&[rng.gen(), rng.gen()]);
+        let mut tested = FrameReassembler::new(true);
+
+        let mut frames = Vec::new();
+        for chunk in data.chunks(rng.gen_range(1..16)) {
+            frames.extend(push(&mut tested, chunk, Ok(())).0);
+        }
+
+        assert_eq!(vec![data], frames);
+    }
+
+    #[test]
+    fn test_raw_response_after_pending_bytes_ends_with_chunk() {
+        let mut rng = rand::thread_rng();
+        let mut tested = FrameReassembler::new(false);
+        let tail = [OperationCodes::Response as u8, DataInstructionCodes::Unknown as u8, rng.gen()];
+
+        push(&mut tested, &[0], Ok(()));
+        let (frames, _) = push(&mut tested, &tail, Ok(()));
+
+        assert_eq!(vec![[[0].as_slice(), tail.as_slice()].concat()], frames);
+    }
+
+    #[test]
+    fn test_joins_raw_all_data_response_split_by_idle_line() {
+        let mut rng = rand::thread_rng();
+        let count: u8 = rng.gen_range(1..=16);
+        let mut response = [0, OperationCodes::ResponseV2 as u8, DataInstructionCodes::All as u8].to_vec();
+        // request id, slave id and interrupt pin
+        response.extend((0..4 + 4 + 1).map(|_| rng.gen::<u8>()));
+        response.push(count);
+        response.extend((0..count as usize * 3 + (count as usize).div_ceil(2)).map(|_| rng.gen::<u8>()));
+        let signal = [0, OperationCodes::Signal as u8, Signals::GetTimeStamp as u8];
+        let data = [response.as_slice(), signal.as_slice()].concat();
+        let mut tested = FrameReassembler::new(false);
+
+        let mut frames = Vec::new();
+        for chunk in data.chunks(rng.gen_range(1..8)) {
+            frames.extend(push(&mut tested, chunk, Ok(())).0);
+        }
+
+        assert_eq!(vec![response, signal.to_vec()], frames);
+        assert_eq!(0, tested.pending());
+    }
+
+    #[test]
+    fn test_reports_error_of_each_frame() {
+        let mut rng = rand::thread_rng();
+        let first = frame(true, &mut rng);
+        let second = frame(true, &mut rng);
+        let data = [first.as_slice(), second.as_slice()].concat();
+        let mut tested = FrameReassembler::new(true);
+
+        let (frames, errors) = push(&mut tested, &data, Err(Errors::FrameCrcMismatch));
+
+        assert_eq!(2, frames.len());
+        assert_eq!(vec![Errors::FrameCrcMismatch, Errors::FrameCrcMismatch], errors);
+    }
+
+    #[test]
+    fn test_reset_drops_pending_bytes() {
+        let mut rng = rand::thread_rng();
+        let data = frame(true, &mut rng);
+        let mut tested = FrameReassembler::new(true);
+
+        push(&mut tested, &data[..data.len() - 1], Ok(()));
+        assert_eq!(data.len() - 1, tested.pending());
+        tested.reset();
+        let (frames, _) = push(&mut tested, &data, Ok(()));
+
+        assert_eq!(vec![data], frames);
+    }
+
+    fn frame(framed: bool, rng: &mut ThreadRng) -> Vec<u8> {
+        if framed {
+            let size = rng.gen_range(0..32);
+            let mut data = [size].to_vec();
+            data.extend((0..size + 2).map(|_| rng.gen::<u8>()));
+            data
+        } else {
+            [0, OperationCodes::SuccessV2 as u8, rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen()].to_vec()
+        }
+    }
+
+    fn push(tested: &mut FrameReassembler, data: &[u8], result: Result<(), Errors>) -> (Vec<Vec<u8>>, Vec<Errors>) {
+        let mut frames = Vec::new();
+        let mut errors = Vec::new();
+        tested.push(data, |frame| {
+            frames.push(frame.to_vec());
+            result
+        }, |error| errors.push(error));
+        (frames, errors)
+    }
+}
@@ -4,6 +4,7 @@ use crate::errors::Errors;
 use crate::hal_ext::rtc_wrapper::RelativeTimestampSource;
 use crate::hal_ext::serial_transfer::Receiver;
 use crate::services::slave_controller_link::parsers::{PayloadParser, ResponseParser, PayloadParserResult, SignalParser};
+use crate::services::slave_controller_link::reassembler::FrameReassembler;
 use crate::services::slave_controller_link::requests_controller::RequestsControllerRx;
 use crate::services::slave_controller_link::signals_controller::{ControlledRequestSender, SignalController};
 use crate::services::slave_controller_link::transmitter_to_slave::ErrorsSender;
@@ -19,29 +20,31 @@ pub trait ReceiverFromSlaveControllerAbstract<Rc, SC, RCR, EH, PP, SP, RP>
         RP: ResponseParser,
 {
 
-    fn slice(&mut self) -> (&mut Rc, &PP);
-    fn error_handler(&mut self) -> &mut EH;
+    fn slice(&mut self) -> (&mut Rc, &PP, &mut FrameReassembler, &mut EH);
 
     //(&mut self, payload: SP, data: &[u8])
     fn on_get_command<TS: RelativeTimestampSource, S: ControlledRequestSender + ErrorsSender + RequestsControllerSource<RCR, RP>>(
             &mut self, signal_controller: &mut SC, sender:  &mut S, time_source: &mut TS) {
 
-        let (rx, parser_factory) = self.slice();
+        let (rx, parser_factory, reassembler, error_handler) = self.slice();
         let res = rx.on_rx_transfer_interrupt(|data| {
-            let (parser, data) = parser_factory.parse(data)?;
-            match parser {
-                PayloadParserResult::ResponsePayload(response_parser) => {
-                    sender.requests_controller().process_response(response_parser, data);
-                    Ok(())
-                }
-                PayloadParserResult::SignalPayload(signal_parser) => {
-                    signal_controller.process_signal(signal_parser, data, time_source, sender);
-                    Ok(())
+            reassembler.push(data, |frame| {
+                let (parser, data) = parser_factory.parse(frame)?;
+                match parser {
+                    PayloadParserResult::ResponsePayload(response_parser) => {
+                        sender.requests_controller().process_response(response_parser, data);
+                        Ok(())
+                    }
+                    PayloadParserResult::SignalPayload(signal_parser) => {
+                        signal_controller.process_signal(signal_parser, data, time_source, sender);
+                        Ok(())
+                    }
                 }
-            }
+            }, |error| error_handler.on_error(error));
+            Ok(())
         });
-        if res.is_err() {
-            self.error_handler().on_error(res.err().unwrap());
+        if let Err(error) = res {
+            error_handler.on_error(error);
         }
     }
 }
@@ -65,6 +68,7 @@ pub struct ReceiverFromSlaveController<Rc, EH, PP, SP, RP>
     rx: Rc,
     error_handler: EH,
     payload_parser: PP,
+    reassembler: FrameReassembler,
     _signal_parser: core::marker::PhantomData<SP>,
     _response_parser: core::marker::PhantomData<RP>,
 }
@@ -77,11 +81,12 @@ impl < Rc, EH, PP, SP, RP> ReceiverFromSlaveController<Rc, EH, PP, SP, RP>
         SP: SignalParser,
         RP: ResponseParser,
 {
-    pub fn new(rx: Rc, error_handler: EH, payload_parser: PP) -> Self {
+    pub fn new(rx: Rc, error_handler: EH, payload_parser: PP, reassembler: FrameReassembler) -> Self {
         Self {
             rx,
             error_handler,
             payload_parser,
+            reassembler,
             _signal_parser: core::marker::PhantomData,
             _response_parser: core::marker::PhantomData,
         }
@@ -91,6 +96,16 @@ impl < Rc, EH, PP, SP, RP> ReceiverFromSlaveController<Rc, EH, PP, SP, RP>
     pub fn inner_rx(&mut self) -> &mut Rc {
         &mut self.rx
     }
+
+    #[inline(always)]
+    pub fn error_handler(&mut self) -> &mut EH {
+        &mut self.error_handler
+    }
+
+    #[inline(always)]
+    pub fn reassembler(&mut self) -> &mut FrameReassembler {
+        &mut self.reassembler
+    }
 }
 
 impl <Rc, RCR, SC, EH, PP, SP, RP> ReceiverFromSlaveControllerAbstract<Rc, SC, RCR, EH, PP, SP, RP>
@@ -105,13 +120,8 @@ impl <Rc, RCR, SC, EH, PP, SP, RP> ReceiverFromSlaveControllerAbstract<Rc, SC, R
         RP: ResponseParser,
 {
     #[inline(always)]
-    fn slice(&mut self) ->( &mut Rc, &PP) {
-        (&mut self.rx, &self.payload_parser)
-    }
-
-    #[inline(always)]
-    fn error_handler(&mut self) -> &mut EH {
-        &mut self.error_handler
+    fn slice(&mut self) -> (&mut Rc, &PP, &mut FrameReassembler, &mut EH) {
+        (&mut self.rx, &self.payload_parser, &mut self.reassembler, &mut self.error_handler)
     }
 
 }
@@ -143,7 +153,7 @@ mod tests {
         let mut signal_controller = MockSignalController::new();
         let request_controller_rx = MockRequestsControllerRx::new();
         let mut controller =
-            ReceiverFromSlaveController::new(mock_receiver, mock_error_handler,mock_parser);
+            ReceiverFromSlaveController::new(mock_receiver, mock_error_handler,mock_parser, FrameReassembler::new(false));
         let mut time_source = MockTimeSource::new(RelativeMillis::new(rng.gen_range(1..u32::MAX)));
         let mut mock_tx = MockSender::new(Ok(Some(rng.gen_range(1..u32::MAX))), Ok(()), request_controller_rx);
 
@@ -171,7 +181,7 @@ mod tests {
         let mut signal_controller = MockSignalController::new();
         let request_controller_rx = MockRequestsControllerRx::new();
         let mut controller =
-            ReceiverFromSlaveController::new(mock_receiver, mock_error_handler,mock_parser);
+            ReceiverFromSlaveController::new(mock_receiver, mock_error_handler,mock_parser, FrameReassembler::new(false));
         let mut time_source = MockTimeSource::new(RelativeMillis::new(rng.gen_range(1..u32::MAX)));
         let mut mock_tx = MockSender::new(Ok(Some(rng.gen_range(1..u32::MAX))), Ok(()), request_controller_rx);
 
@@ -200,7 +210,7 @@ mod tests {
         let mut signal_controller = MockSignalController::new();
         let request_controller_rx = MockRequestsControllerRx::new();
         let mut controller =
-            ReceiverFromSlaveController::new(mock_receiver, mock_error_handler,mock_parser);
+            ReceiverFromSlaveController::new(mock_receiver, mock_error_handler,mock_parser, FrameReassembler::new(false));
         let mut time_source = MockTimeSource::new(RelativeMillis::new(rng.gen_range(1..u32::MAX)));
         let mut mock_tx = MockSender::new(Ok(Some(rng.gen_range(1..u32::MAX))), Ok(()), request_controller_rx);
 
@@ -229,7 +239,7 @@ mod tests {
         let mut signal_controller = MockSignalController::new();
         let request_controller_rx = MockRequestsControllerRx::new();
         let mut controller =
-            ReceiverFromSlaveController::new(mock_receiver, mock_error_handler,mock_parser);
+            ReceiverFromSlaveController::new(mock_receiver, mock_error_handler,mock_parser, FrameReassembler::new(false));
         let mut time_source = MockTimeSource::new(RelativeMillis::new(rng.gen_range(1..u32::MAX)));
         let mut mock_tx = MockSender::new(Ok(Some(rng.gen_range(1..u32::MAX))), Ok(()), request_controller_rx);
 
@@ -354,7 +364,7 @@ mod tests {
         fn request_needs_cache(&self, _: DataInstructionCodes) -> bool {
             unimplemented!()
         }
-        fn parse(&self, _: DataInstructionCodes, _: &[u8]) -> Result<DataInstructions, Errors> {
+        fn parse(&mut self, _: DataInstructionCodes, _: &[u8]) -> Result<DataInstructions<'_>, Errors> {
             unimplemented!()
         }
     }
@@ -410,7 +420,7 @@ mod tests {
     }
 
     struct MockSender {
-        send_params: Option<(Operation, DataInstructions, RelativeMillis)>,
+        send_params: Option<(Operation, DataInstructions<'static>, RelativeMillis)>,
         send_result: Result<Option<u32>, Errors>,
         send_error_params: Option<(u8, ErrorCode)>,
         send_error_result: Result<(), Errors>,
@@ -432,7 +442,7 @@ mod tests {
 
     impl ControlledRequestSender for MockSender {
         fn send(&mut self, operation: Operation, instruction: DataInstructions, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
-            self.send_params = Some((operation, instruction, timestamp));
+            self.send_params = Some((operation, instruction.into_owned(), timestamp));
             self.send_result
         }
     }
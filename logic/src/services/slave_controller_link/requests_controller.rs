@@ -6,7 +6,9 @@ use crate::hal_ext::rtc_wrapper::{RelativeMillis };
 use crate::services::slave_controller_link::domain::{DataInstructionCodes, DataInstructions, ErrorCode, Operation, Version};
 use crate::services::slave_controller_link::parsers::{ResponseParser, ResponseBodyParser, ResponseData};
 
-const MAX_REQUESTS_COUNT: usize = 4;
+pub const MAX_REQUESTS_COUNT: usize = 4;
+const INSTRUCTIONS_COUNT: usize = DataInstructionCodes::Last as usize;
+pub const DEFAULT_REQUEST_TIMEOUT_MS: u32 = 1000;
 
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct SentRequest {
@@ -25,6 +27,63 @@ impl SentRequest {
             rel_timestamp
         }
     }
+
+    #[inline(always)]
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    #[inline(always)]
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    #[inline(always)]
+    pub fn instruction(&self) -> DataInstructionCodes {
+        self.instruction
+    }
+
+    #[inline(always)]
+    pub fn rel_timestamp(&self) -> RelativeMillis {
+        self.rel_timestamp
+    }
+}
+
+/** Time in milliseconds the slave has to answer a request, set per instruction. */
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct RequestTimeouts {
+    timeouts: [u32; INSTRUCTIONS_COUNT],
+    default_timeout: u32,
+}
+
+impl RequestTimeouts {
+    pub const fn new(default_timeout: u32) -> Self {
+        Self {
+            timeouts: [default_timeout; INSTRUCTIONS_COUNT],
+            default_timeout,
+        }
+    }
+
+    pub fn set(&mut self, instruction: DataInstructionCodes, timeout: u32) -> Result<(), Errors> {
+        let index = instruction as usize;
+        if index >= INSTRUCTIONS_COUNT {
+            return Err(Errors::InstructionNotRecognized(instruction as u8));
+        }
+        self.timeouts[index] = timeout;
+        Ok(())
+    }
+
+    pub fn get(&self, instruction: DataInstructionCodes) -> u32 {
+        self.timeouts.get(instruction as usize)
+            .copied()
+            .unwrap_or(self.default_timeout)
+    }
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        Self::new(DEFAULT_REQUEST_TIMEOUT_MS)
+    }
 }
 
 pub trait ResponseHandler {
@@ -33,11 +92,13 @@ pub trait ResponseHandler {
     fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode);
     fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]);
     fn on_request_search_error(&mut self, payload: ResponseData, error: Errors);
+    fn on_request_timeout(&mut self, request: SentRequest);
 }
 
 pub trait RequestsControllerTx {
     fn add_sent_request(&mut self, request: SentRequest);
     fn check_request(&mut self, instruction: DataInstructionCodes) -> Result<Option<u32>, Errors>;
+    fn check_resend(&mut self, instruction: DataInstructionCodes) -> Result<(), Errors>;
 }
 
 pub trait RequestsControllerRx<RP>
@@ -61,6 +122,7 @@ pub struct RequestsController<RH, RBP>
     response_body_parser: RBP,
     last_request_id: u32,
     slave_controller_version: Version,
+    timeouts: RequestTimeouts,
 }
 
 impl <RH, RBP> RequestsController<RH, RBP>
@@ -77,8 +139,72 @@ impl <RH, RBP> RequestsController<RH, RBP>
             response_body_parser,
             last_request_id: 0,
             slave_controller_version,
+            timeouts: RequestTimeouts::default(),
         }
     }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+
+    #[inline(always)]
+    pub fn version(&self) -> Version {
+        self.slave_controller_version
+    }
+
+    #[inline(always)]
+    pub fn set_version(&mut self, version: Version) {
+        self.slave_controller_version = version;
+    }
+
+    /** Some sent requests are waiting for the answer or the timeout. */
+    #[inline(always)]
+    pub fn has_sent_requests(&self) -> bool {
+        self.requests_count > 0
+    }
+
+    #[inline(always)]
+    pub fn timeouts(&self) -> &RequestTimeouts {
+        &self.timeouts
+    }
+
+    #[inline(always)]
+    pub fn timeouts_mut(&mut self) -> &mut RequestTimeouts {
+        &mut self.timeouts
+    }
+
+    /**
+    Drops every request the slave did not answer in time, reporting each one through
+    `ResponseHandler::on_request_timeout`. Elapsed time is counted with wrapping, so the
+    relative timer overflow does not expire requests early.
+     */
+    pub fn poll_timeouts(&mut self, now: RelativeMillis) {
+        for i in (0..self.requests_count).rev() {
+            if let Some(request) = self.sent_requests[i] {
+                let elapsed = now.value().wrapping_sub(request.rel_timestamp.value());
+                if elapsed >= self.timeouts.get(request.instruction) {
+                    self.remove_request(i);
+                    self.response_handler.on_request_timeout(request);
+                }
+            }
+        }
+    }
+
+    fn remove_request(&mut self, i: usize) {
+        if let Some(request) = self.sent_requests[i] {
+            if request.operation == Operation::Read && self.response_body_parser.request_needs_cache(request.instruction) {
+                self.request_needs_cache_send = false;
+            }
+        }
+        let mut next_pos = i + 1;
+        while next_pos < self.requests_count {
+            self.sent_requests.swap(next_pos - 1, next_pos);
+            next_pos += 1;
+        }
+        self.sent_requests[next_pos - 1] = None;
+        self.requests_count -= 1;
+    }
 }
 
 impl <RH, RBP> RequestsControllerTx for RequestsController<RH, RBP>
@@ -88,12 +214,7 @@ impl <RH, RBP> RequestsControllerTx for RequestsController<RH, RBP>
 {
 
     fn check_request(&mut self, instruction_code: DataInstructionCodes) -> Result<Option<u32>, Errors> {
-        if self.requests_count == MAX_REQUESTS_COUNT {
-            return Err(Errors::RequestsLimitReached);
-        }
-        if self.response_body_parser.request_needs_cache(instruction_code) && self.request_needs_cache_send {
-            return Err(Errors::RequestsNeedsCacheAlreadySent);
-        }
+        self.check_resend(instruction_code)?;
 
         if self.slave_controller_version == Version::V1 {
             Ok(None)
@@ -103,6 +224,16 @@ impl <RH, RBP> RequestsControllerTx for RequestsController<RH, RBP>
         }
     }
 
+    fn check_resend(&mut self, instruction_code: DataInstructionCodes) -> Result<(), Errors> {
+        if self.requests_count == MAX_REQUESTS_COUNT {
+            return Err(Errors::RequestsLimitReached);
+        }
+        if self.response_body_parser.request_needs_cache(instruction_code) && self.request_needs_cache_send {
+            return Err(Errors::RequestsNeedsCacheAlreadySent);
+        }
+        Ok(())
+    }
+
     fn add_sent_request(&mut self, request: SentRequest) {
         if request.operation == Operation::Read && self.response_body_parser.request_needs_cache(request.instruction) {
             self.request_needs_cache_send = true;
@@ -136,7 +267,7 @@ impl <RH, RP, RBP> RequestsControllerRx<RP> for RequestsController<RH, RBP>
                 if let Some(request) = self.sent_requests[i] {
                     if
                         response.request_id() == request.id && response.instruction() == request.instruction &&
-                            (response_operation == Operation::Error || response_operation == request.operation)
+                            answers(response_operation, request.operation)
                     {
                         if response_operation == Operation::Set {
                             self.response_handler.on_request_success(request);
@@ -153,16 +284,7 @@ impl <RH, RP, RBP> RequestsControllerRx<RP> for RequestsController<RH, RBP>
                                 }
                             }
                         }
-                        if request.operation == Operation::Read && self.response_body_parser.request_needs_cache(request.instruction) {
-                            self.request_needs_cache_send = false;
-                        }
-                        let mut next_pos = i + 1;
-                        while next_pos < self.requests_count {
-                            self.sent_requests.swap(next_pos - 1, next_pos);
-                            next_pos += 1;
-                        }
-                        self.sent_requests[next_pos - 1] = None;
-                        self.requests_count -= 1;
+                        self.remove_request(i);
 
                         return;
                     }
@@ -177,13 +299,20 @@ impl <RH, RP, RBP> RequestsControllerRx<RP> for RequestsController<RH, RBP>
 
 
 
+/** Commands are acknowledged by the slave the same way as `Set` requests. */
+fn answers(response_operation: Operation, request_operation: Operation) -> bool {
+    response_operation == Operation::Error || response_operation == request_operation ||
+        (response_operation == Operation::Set && request_operation == Operation::Command)
+}
+
+
 #[cfg(test)]
 mod tests {
     use alloc::vec::Vec;
     use core::cell::RefCell;
     use super::*;
     use rand::prelude::*;
-    use crate::services::slave_controller_link::domain::{Conversation, Operation};
+    use crate::services::slave_controller_link::domain::{Commands, Conversation, DataInstruction, Operation};
 
 
     #[test]
@@ -575,6 +704,39 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_process_response_should_inform_success_if_command_acknowledged() {
+        let mut rng = rand::thread_rng();
+        let instruction = Commands::ClearSwitchCount.code();
+        let id = rng.next_u32();
+
+        let mock_responses_parser = MockResponseBodyParser::new(
+            || Err(Errors::NotEnoughDataGot), false
+        );
+        let mut tested =
+            RequestsController::new(MockResponsesHandler::new(), mock_responses_parser, Version::V2);
+
+        let request = SentRequest::new(
+            Some(id), Operation::Command, instruction, RelativeMillis::new(rng.next_u32()));
+        tested.add_sent_request(request);
+
+        // a read response with the same code does not answer the command
+        let mrpp = ResponseData::new(Operation::Read, instruction, Some(id), ErrorCode::OK);
+        tested.process_response(MockResponseParser::new(Ok(mrpp)), &[]);
+        assert_eq!(1, tested.requests_count);
+        assert_eq!(Some((mrpp, Errors::NoRequestsFound)), tested.response_handler.on_request_search_error_params);
+
+        let mrpp = ResponseData::new(Operation::Set, instruction, Some(id), ErrorCode::OK);
+        tested.process_response(MockResponseParser::new(Ok(mrpp)), &[]);
+
+        assert_eq!(Some(request), tested.response_handler.on_request_success_params);
+        assert_eq!(0, tested.requests_count);
+        assert_eq!(None, tested.response_handler.on_request_response_params);
+        assert_eq!(None, tested.response_handler.on_request_error_params);
+        assert_eq!(None, *tested.response_body_parser.parse_response_params.borrow());
+    }
+
+
     #[test]
     fn test_process_response_should_proxy_response_if_read_request_found() {
         let mut rng = rand::thread_rng();
@@ -706,6 +868,136 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_poll_timeouts_should_expire_only_stale_requests() {
+        let mut rng = rand::thread_rng();
+        let mock_response_handler = MockResponsesHandler::new();
+        let mock_responses_parser = new_check_needs_cache(false);
+
+        let mut tested =
+            RequestsController::new(mock_response_handler, mock_responses_parser, Version::V2);
+
+        let timeout = rng.gen_range(10..10_000);
+        *tested.timeouts_mut() = RequestTimeouts::new(timeout);
+        // close to overflow to check elapsed time is counted with wrapping
+        let now = rng.gen_range(0..timeout / 2);
+        let start = now.wrapping_sub(timeout);
+
+        let requests = [
+            SentRequest::new(Some(1), Operation::Read, DataInstructionCodes::Id,
+                             RelativeMillis::new(start)),
+            SentRequest::new(Some(2), Operation::Set, DataInstructionCodes::Settings,
+                             RelativeMillis::new(start.wrapping_add(1))),
+            SentRequest::new(Some(3), Operation::Read, DataInstructionCodes::State,
+                             RelativeMillis::new(start.wrapping_sub(1))),
+        ];
+        for request in requests {
+            tested.add_sent_request(request);
+        }
+
+        tested.poll_timeouts(RelativeMillis::new(now));
+
+        assert_eq!(vec![requests[2], requests[0]], tested.response_handler.on_request_timeout_params);
+        assert_eq!(1, tested.requests_count);
+        assert_eq!([Some(requests[1]), None, None, None], tested.sent_requests);
+        //nothing else should be called
+        assert_eq!(None, tested.response_handler.on_request_success_params);
+        assert_eq!(None, tested.response_handler.on_request_response_params);
+        assert_eq!(None, tested.response_handler.on_request_error_params);
+        assert_eq!(None, tested.response_handler.on_request_parse_error_params);
+        assert_eq!(None, tested.response_handler.on_request_search_error_params);
+
+        tested.poll_timeouts(RelativeMillis::new(now.wrapping_add(1)));
+
+        assert_eq!(vec![requests[2], requests[0], requests[1]], tested.response_handler.on_request_timeout_params);
+        assert_eq!(0, tested.requests_count);
+        assert_eq!([None, None, None, None], tested.sent_requests);
+    }
+
+    #[test]
+    fn test_poll_timeouts_should_free_slots_for_new_requests() {
+        let mut rng = rand::thread_rng();
+        let mock_response_handler = MockResponsesHandler::new();
+        let mock_responses_parser = new_check_needs_cache(false);
+
+        let mut tested =
+            RequestsController::new(mock_response_handler, mock_responses_parser, Version::V1);
+
+        let start = rng.next_u32();
+        let request = SentRequest::new(
+            None, Operation::Read, DataInstructionCodes::Id, RelativeMillis::new(start));
+        for _ in 0..MAX_REQUESTS_COUNT {
+            tested.add_sent_request(request);
+        }
+        assert_eq!(Err(Errors::RequestsLimitReached), tested.check_request(DataInstructionCodes::Id));
+
+        tested.poll_timeouts(RelativeMillis::new(start.wrapping_add(DEFAULT_REQUEST_TIMEOUT_MS - 1)));
+        assert_eq!(Err(Errors::RequestsLimitReached), tested.check_request(DataInstructionCodes::Id));
+
+        tested.poll_timeouts(RelativeMillis::new(start.wrapping_add(DEFAULT_REQUEST_TIMEOUT_MS)));
+
+        assert_eq!(MAX_REQUESTS_COUNT, tested.response_handler.on_request_timeout_params.len());
+        assert_eq!(Ok(None), tested.check_request(DataInstructionCodes::Id));
+    }
+
+    #[test]
+    fn test_poll_timeouts_should_clear_cache_flag_on_cached_request_expiry() {
+        let mut rng = rand::thread_rng();
+        let mock_response_handler = MockResponsesHandler::new();
+        let mock_responses_parser = new_check_needs_cache(true);
+
+        let mut tested =
+            RequestsController::new(mock_response_handler, mock_responses_parser, Version::V1);
+
+        let start = rng.next_u32();
+        let instruction = DataInstructionCodes::All;
+        tested.add_sent_request(SentRequest::new(
+            None, Operation::Read, instruction, RelativeMillis::new(start)));
+        assert!(tested.request_needs_cache_send);
+        assert_eq!(Err(Errors::RequestsNeedsCacheAlreadySent), tested.check_request(instruction));
+
+        tested.poll_timeouts(RelativeMillis::new(start.wrapping_add(DEFAULT_REQUEST_TIMEOUT_MS)));
+
+        assert!(!tested.request_needs_cache_send);
+        assert_eq!(Ok(None), tested.check_request(instruction));
+    }
+
+    #[test]
+    fn test_poll_timeouts_should_use_per_instruction_timeout() {
+        let mut rng = rand::thread_rng();
+        let mock_response_handler = MockResponsesHandler::new();
+        let mock_responses_parser = new_check_needs_cache(false);
+
+        let mut tested =
+            RequestsController::new(mock_response_handler, mock_responses_parser, Version::V1);
+
+        let long_timeout = DEFAULT_REQUEST_TIMEOUT_MS * 5;
+        assert_eq!(Ok(()), tested.timeouts_mut().set(DataInstructionCodes::All, long_timeout));
+        assert_eq!(long_timeout, tested.timeouts().get(DataInstructionCodes::All));
+        assert_eq!(DEFAULT_REQUEST_TIMEOUT_MS, tested.timeouts().get(DataInstructionCodes::Id));
+        assert_eq!(DEFAULT_REQUEST_TIMEOUT_MS, tested.timeouts().get(DataInstructionCodes::Unknown));
+        assert_eq!(Err(Errors::InstructionNotRecognized(DataInstructionCodes::Unknown as u8)),
+                   tested.timeouts_mut().set(DataInstructionCodes::Unknown, long_timeout));
+
+        let start = rng.next_u32();
+        let long_request = SentRequest::new(
+            None, Operation::Read, DataInstructionCodes::All, RelativeMillis::new(start));
+        let short_request = SentRequest::new(
+            None, Operation::Read, DataInstructionCodes::Id, RelativeMillis::new(start));
+        tested.add_sent_request(long_request);
+        tested.add_sent_request(short_request);
+
+        tested.poll_timeouts(RelativeMillis::new(start.wrapping_add(long_timeout - 1)));
+
+        assert_eq!(vec![short_request], tested.response_handler.on_request_timeout_params);
+        assert_eq!([Some(long_request), None, None, None], tested.sent_requests);
+
+        tested.poll_timeouts(RelativeMillis::new(start.wrapping_add(long_timeout)));
+
+        assert_eq!(vec![short_request, long_request], tested.response_handler.on_request_timeout_params);
+        assert_eq!(0, tested.requests_count);
+    }
+
 
     const ALL_ERROR_CODES: [ErrorCode; 16] = [
         ErrorCode::OK,
@@ -765,8 +1057,9 @@ mod tests {
         on_request_success_params: Option<SentRequest>,
         on_request_error_params: Option<(SentRequest, ErrorCode)>,
         on_request_parse_error_params: Option<(Option<SentRequest>, Errors, Vec<u8>)>,
-        on_request_response_params: Option<(SentRequest, DataInstructions)>,
+        on_request_response_params: Option<(SentRequest, DataInstructions<'static>)>,
         on_request_search_error_params: Option<(ResponseData, Errors)>,
+        on_request_timeout_params: Vec<SentRequest>,
     }
 
     impl MockResponsesHandler {
@@ -777,6 +1070,7 @@ mod tests {
                 on_request_parse_error_params: None,
                 on_request_response_params: None,
                 on_request_search_error_params: None,
+                on_request_timeout_params: Vec::new(),
             }
         }
     }
@@ -791,7 +1085,7 @@ mod tests {
         }
 
         fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
-            self.on_request_response_params = Some((request, response));
+            self.on_request_response_params = Some((request, response.into_owned()));
         }
 
         fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
@@ -805,9 +1099,13 @@ mod tests {
         fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
             self.on_request_search_error_params = Some((payload, error));
         }
+
+        fn on_request_timeout(&mut self, request: SentRequest) {
+            self.on_request_timeout_params.push(request);
+        }
     }
 
-    type MyRbpCb = fn() -> Result<DataInstructions, Errors>;
+    type MyRbpCb = fn() -> Result<DataInstructions<'static>, Errors>;
 
     fn new_check_needs_cache(request_needs_cache_result: bool) -> MockResponseBodyParser<MyRbpCb> {
         let cb = || Err(Errors::NoRequestsFound);
@@ -819,7 +1117,7 @@ mod tests {
         MockResponseBodyParser::new(cb, false)
     }
 
-    struct MockResponseBodyParser<CB: Fn() -> Result<DataInstructions, Errors>> {
+    struct MockResponseBodyParser<CB: Fn() -> Result<DataInstructions<'static>, Errors>> {
         parse_response_params: RefCell<Option<(u8, Vec<u8>)>>,
         parse_response_result_producer: CB,
         request_needs_cache_param: RefCell<Option<DataInstructionCodes>>,
@@ -828,7 +1126,7 @@ mod tests {
         id: u32,
     }
 
-    impl <CB: Fn() -> Result<DataInstructions, Errors>> MockResponseBodyParser<CB> {
+    impl <CB: Fn() -> Result<DataInstructions<'static>, Errors>> MockResponseBodyParser<CB> {
         pub fn new(parse_response_result_producer: CB, request_needs_cache_result: bool) -> Self {
             let mut rng = rand::thread_rng();
             Self {
@@ -842,21 +1140,21 @@ mod tests {
         }
     }
 
-    impl <CB: Fn() -> Result<DataInstructions, Errors>> ResponseBodyParser for MockResponseBodyParser<CB> {
+    impl <CB: Fn() -> Result<DataInstructions<'static>, Errors>> ResponseBodyParser for MockResponseBodyParser<CB> {
 
         fn request_needs_cache(&self, instruction: DataInstructionCodes) -> bool {
             *self.request_needs_cache_param.borrow_mut() = Some(instruction);
             self.request_needs_cache_result
         }
 
-        fn parse(&self, instruction: DataInstructionCodes, data: &[u8]) -> Result<DataInstructions, Errors> {
+        fn parse(&mut self, instruction: DataInstructionCodes, data: &[u8]) -> Result<DataInstructions<'_>, Errors> {
             *self.parse_response_params.borrow_mut() = Some((instruction as u8, data.to_vec()));
             (self.parse_response_result_producer)()
         }
 
     }
 
-    impl <CB: Fn() -> Result<DataInstructions, Errors>> IdContainer for MockResponseBodyParser<CB> {
+    impl <CB: Fn() -> Result<DataInstructions<'static>, Errors>> IdContainer for MockResponseBodyParser<CB> {
         fn id(&self) -> u32 {
             self.id
         }
@@ -874,7 +1172,7 @@ mod tests {
         }
     }
 
-    type MockResponseBodyParserLight = MockResponseBodyParser<fn() -> Result<DataInstructions, Errors>>;
+    type MockResponseBodyParserLight = MockResponseBodyParser<fn() -> Result<DataInstructions<'static>, Errors>>;
 
     impl ResponseParser for MockResponseParserLight {
 
@@ -0,0 +1,665 @@
+#![deny(unsafe_code)]
+
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::RelativeMillis;
+use crate::services::slave_controller_link::domain::{DataInstruction, DataInstructionCodes, DataInstructions, ErrorCode};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest, MAX_REQUESTS_COUNT};
+use crate::utils::dma_read_buffer::BufferWriter;
+
+pub const MAX_RETRY_PAYLOAD_SIZE: usize = 64;
+pub const DEFAULT_MAX_ATTEMPTS: u8 = 3;
+pub const DEFAULT_RETRY_BACKOFF_MS: u32 = 100;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u8,
+    backoff: u32,
+    retryable_errors: u64,
+}
+
+impl RetryPolicy {
+
+    /**
+    `max_attempts` counts the first send too, so 1 disables retries. `backoff` is the delay in
+    milliseconds before the first resend, it doubles after every next failed attempt.
+     */
+    pub const fn new(max_attempts: u8, backoff: u32) -> Self {
+        Self {
+            max_attempts,
+            backoff,
+            retryable_errors: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn max_attempts(&self) -> u8 {
+        self.max_attempts
+    }
+
+    #[inline(always)]
+    pub fn backoff(&self) -> u32 {
+        self.backoff
+    }
+
+    pub fn set_retryable(&mut self, error_code: ErrorCode, retryable: bool) -> Result<(), Errors> {
+        let bit = Self::error_bit(error_code).ok_or(Errors::OutOfRange)?;
+        if retryable {
+            self.retryable_errors |= bit;
+        } else {
+            self.retryable_errors &= !bit;
+        }
+        Ok(())
+    }
+
+    pub fn is_retryable(&self, error_code: ErrorCode) -> bool {
+        Self::error_bit(error_code)
+            .map(|bit| self.retryable_errors & bit != 0)
+            .unwrap_or(false)
+    }
+
+    pub fn delay(&self, attempts: u8) -> u32 {
+        let exponent = attempts.saturating_sub(1) as u32;
+        self.backoff.saturating_mul(2_u32.saturating_pow(exponent))
+    }
+
+    fn error_bit(error_code: ErrorCode) -> Option<u64> {
+        match error_code {
+            ErrorCode::EUndefinedCode(_) => None,
+            _ => 1_u64.checked_shl(error_code.discriminant() as u32),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        let mut policy = Self::new(DEFAULT_MAX_ATTEMPTS, DEFAULT_RETRY_BACKOFF_MS);
+        // errors the slave reports when the command got corrupted on the wire
+        for error_code in [
+            ErrorCode::EInstructionUnrecognized,
+            ErrorCode::ECommandEmpty,
+            ErrorCode::ECommandSizeOverflow,
+            ErrorCode::EInstructionWrongStart,
+            ErrorCode::EWriteMaxAttemptsExceeded,
+            ErrorCode::EUndefinedOperation,
+        ] {
+            policy.set_retryable(error_code, true).ok();
+        }
+        policy
+    }
+}
+
+/** Serialized body of a sent instruction, kept to send exactly the same bytes again. */
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct RetryPayload {
+    code: DataInstructionCodes,
+    data: [u8; MAX_RETRY_PAYLOAD_SIZE],
+    size: usize,
+}
+
+impl RetryPayload {
+    pub fn create<I: DataInstruction>(instruction: &I) -> Result<Self, Errors> {
+        let mut payload = Self {
+            code: instruction.code(),
+            data: [0; MAX_RETRY_PAYLOAD_SIZE],
+            size: 0,
+        };
+        instruction.serialize(&mut payload)?;
+        Ok(payload)
+    }
+
+    #[inline(always)]
+    pub fn bytes(&self) -> &[u8] {
+        &self.data[..self.size]
+    }
+}
+
+impl BufferWriter for RetryPayload {
+
+    #[inline(always)]
+    fn add_str(&mut self, string: &str) -> Result<(), Errors> {
+        self.add(string.as_bytes())
+    }
+
+    fn add(&mut self, data: &[u8]) -> Result<(), Errors> {
+        if data.len() > MAX_RETRY_PAYLOAD_SIZE - self.size {
+            return Err(Errors::DataOverflow);
+        }
+        self.data[self.size..self.size + data.len()].copy_from_slice(data);
+        self.size += data.len();
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn add_u8(&mut self, byte: u8) -> Result<(), Errors> {
+        self.add(&[byte])
+    }
+
+    #[inline(always)]
+    fn add_u16(&mut self, value: u16) -> Result<(), Errors> {
+        self.add(&value.to_be_bytes())
+    }
+
+    #[inline(always)]
+    fn add_u32(&mut self, value: u32) -> Result<(), Errors> {
+        self.add(&value.to_be_bytes())
+    }
+
+    #[inline(always)]
+    fn add_u64(&mut self, value: u64) -> Result<(), Errors> {
+        self.add(&value.to_be_bytes())
+    }
+
+    fn clear(&mut self) {
+        self.size = 0;
+    }
+}
+
+impl DataInstruction for RetryPayload {
+
+    #[inline(always)]
+    fn code(&self) -> DataInstructionCodes {
+        self.code
+    }
+
+    #[inline(always)]
+    fn serialize<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors> {
+        buffer.add(self.bytes())
+    }
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum Failure {
+    Error(ErrorCode),
+    Timeout,
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum RetryState {
+    InFlight,
+    Failed(Failure),
+    Waiting(Failure, RelativeMillis, u32),
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct PendingRequest {
+    request: SentRequest,
+    payload: RetryPayload,
+    attempts: u8,
+    state: RetryState,
+}
+
+impl PendingRequest {
+
+    #[inline(always)]
+    pub fn request(&self) -> SentRequest {
+        self.request
+    }
+
+    #[inline(always)]
+    pub fn payload(&self) -> RetryPayload {
+        self.payload
+    }
+
+    #[inline(always)]
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+}
+
+/**
+Retry layer between `RequestsController` and the application `ResponseHandler`. Failed and timed out
+requests are kept back and handed out again by `take_due` once their backoff passes, so the
+wrapped handler gets only the final outcome of each request. Resent requests keep their V2 id, late
+responses to the earlier attempts are recognized by it and dropped.
+ */
+pub struct RetryController<RH: ResponseHandler> {
+    response_handler: RH,
+    policy: RetryPolicy,
+    pending: [Option<PendingRequest>; MAX_REQUESTS_COUNT],
+    retried_ids: [Option<u32>; MAX_REQUESTS_COUNT],
+    retried_ids_pos: usize,
+}
+
+impl <RH: ResponseHandler> RetryController<RH> {
+
+    pub fn new(response_handler: RH, policy: RetryPolicy) -> Self {
+        Self {
+            response_handler,
+            policy,
+            pending: [None; MAX_REQUESTS_COUNT],
+            retried_ids: [None; MAX_REQUESTS_COUNT],
+            retried_ids_pos: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+
+    #[inline(always)]
+    pub fn policy(&mut self) -> &mut RetryPolicy {
+        &mut self.policy
+    }
+
+    pub fn check_track(&self) -> Result<(), Errors> {
+        if self.pending.iter().any(|pending| pending.is_none()) {
+            Ok(())
+        } else {
+            Err(Errors::RequestsLimitReached)
+        }
+    }
+
+    pub fn track(&mut self, request: SentRequest, payload: RetryPayload) {
+        self.insert(PendingRequest {
+            request,
+            payload,
+            attempts: 1,
+            state: RetryState::InFlight,
+        });
+    }
+
+    /** Starts backoff of the requests failed since the last call. */
+    pub fn schedule(&mut self, now: RelativeMillis) {
+        for pending in self.pending.iter_mut().flatten() {
+            if let RetryState::Failed(failure) = pending.state {
+                pending.state = RetryState::Waiting(failure, now, self.policy.delay(pending.attempts));
+            }
+        }
+    }
+
+    /** Removes and returns a request which backoff has passed. It must be given back with `on_resent`. */
+    pub fn take_due(&mut self, now: RelativeMillis) -> Option<PendingRequest> {
+        for slot in self.pending.iter_mut() {
+            if let Some(PendingRequest { state: RetryState::Waiting(_, since, delay), .. }) = slot {
+                if now.value().wrapping_sub(since.value()) >= *delay {
+                    return slot.take();
+                }
+            }
+        }
+        None
+    }
+
+    pub fn on_resent(&mut self, mut pending: PendingRequest, timestamp: RelativeMillis, result: Result<(), Errors>) {
+        pending.attempts = pending.attempts.saturating_add(1);
+        match result {
+            Ok(()) => {
+                pending.request = SentRequest::new(pending.request.id(), pending.request.operation(),
+                                                   pending.request.instruction(), timestamp);
+                pending.state = RetryState::InFlight;
+                self.insert(pending);
+            }
+            Err(_) => {
+                let failure = match pending.state {
+                    RetryState::Waiting(failure, _, _) | RetryState::Failed(failure) => failure,
+                    RetryState::InFlight => Failure::Timeout,
+                };
+                if pending.attempts < self.policy.max_attempts {
+                    pending.state = RetryState::Failed(failure);
+                    self.insert(pending);
+                } else {
+                    self.report_failure(pending, failure);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, pending: PendingRequest) {
+        if let Some(slot) = self.pending.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(pending);
+        }
+    }
+
+    fn find(&self, request: &SentRequest) -> Option<usize> {
+        self.pending.iter().position(|pending| {
+            matches!(pending, Some(pending) if pending.state == RetryState::InFlight && pending.request == *request)
+        })
+    }
+
+    fn complete(&mut self, position: usize) {
+        if let Some(pending) = self.pending[position].take() {
+            self.remember_retried(&pending);
+        }
+    }
+
+    fn remember_retried(&mut self, pending: &PendingRequest) {
+        if let (Some(id), true) = (pending.request.id(), pending.attempts > 1) {
+            self.retried_ids[self.retried_ids_pos] = Some(id);
+            self.retried_ids_pos = (self.retried_ids_pos + 1) % MAX_REQUESTS_COUNT;
+        }
+    }
+
+    fn on_failure(&mut self, request: SentRequest, failure: Failure) {
+        if let Some(position) = self.find(&request) {
+            let retryable = match failure {
+                Failure::Error(error_code) => self.policy.is_retryable(error_code),
+                Failure::Timeout => true,
+            };
+            if let Some(pending) = self.pending[position].as_mut() {
+                if retryable && pending.attempts < self.policy.max_attempts {
+                    pending.state = RetryState::Failed(failure);
+                    return;
+                }
+            }
+            self.complete(position);
+        }
+        self.report(request, failure);
+    }
+
+    fn report_failure(&mut self, pending: PendingRequest, failure: Failure) {
+        self.remember_retried(&pending);
+        self.report(pending.request, failure);
+    }
+
+    fn report(&mut self, request: SentRequest, failure: Failure) {
+        match failure {
+            Failure::Error(error_code) => self.response_handler.on_request_error(request, error_code),
+            Failure::Timeout => self.response_handler.on_request_timeout(request),
+        }
+    }
+
+    fn is_duplicate(&self, response: &ResponseData) -> bool {
+        match response.request_id() {
+            Some(id) => {
+                self.retried_ids.contains(&Some(id)) ||
+                    self.pending.iter().flatten().any(|pending| {
+                        pending.request.id() == Some(id) && pending.request.instruction() == response.instruction()
+                    })
+            }
+            None => false,
+        }
+    }
+}
+
+impl <RH: ResponseHandler> ResponseHandler for RetryController<RH> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        if let Some(position) = self.find(&request) {
+            self.complete(position);
+        }
+        self.response_handler.on_request_success(request);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        if let Some(position) = self.find(&request) {
+            self.complete(position);
+        }
+        self.response_handler.on_request_response(request, response);
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        self.on_failure(request, Failure::Error(error_code));
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+        if let Some(position) = request.and_then(|request| self.find(&request)) {
+            self.complete(position);
+        }
+        self.response_handler.on_request_parse_error(request, error, data);
+    }
+
+    fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
+        if !self.is_duplicate(&payload) {
+            self.response_handler.on_request_search_error(payload, error);
+        }
+    }
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        self.on_failure(request, Failure::Timeout);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::slave_controller_link::domain::{Conversation, Operation, RelayIndexRequest};
+
+    #[test]
+    fn test_retry_policy_retryable_errors() {
+        let mut tested = RetryPolicy::new(DEFAULT_MAX_ATTEMPTS, DEFAULT_RETRY_BACKOFF_MS);
+
+        assert!(!tested.is_retryable(ErrorCode::ECommandEmpty));
+        assert_eq!(Ok(()), tested.set_retryable(ErrorCode::ECommandEmpty, true));
+        assert_eq!(Ok(()), tested.set_retryable(ErrorCode::ERelayNotAllowedPinUsed, true));
+        assert!(tested.is_retryable(ErrorCode::ECommandEmpty));
+        assert!(tested.is_retryable(ErrorCode::ERelayNotAllowedPinUsed));
+        assert!(!tested.is_retryable(ErrorCode::EInternalError));
+
+        assert_eq!(Ok(()), tested.set_retryable(ErrorCode::ECommandEmpty, false));
+        assert!(!tested.is_retryable(ErrorCode::ECommandEmpty));
+        assert!(tested.is_retryable(ErrorCode::ERelayNotAllowedPinUsed));
+
+        assert_eq!(Err(Errors::OutOfRange), tested.set_retryable(ErrorCode::EUndefinedCode(200), true));
+        assert!(!tested.is_retryable(ErrorCode::EUndefinedCode(200)));
+
+        let default = RetryPolicy::default();
+        assert!(default.is_retryable(ErrorCode::EInstructionWrongStart));
+        assert!(!default.is_retryable(ErrorCode::ERelayIndexOutOfRange));
+    }
+
+    #[test]
+    fn test_retry_policy_delay_doubles() {
+        let mut rng = rand::thread_rng();
+        let backoff = rng.gen_range(1..1000);
+        let tested = RetryPolicy::new(10, backoff);
+
+        assert_eq!(backoff, tested.delay(1));
+        assert_eq!(backoff * 2, tested.delay(2));
+        assert_eq!(backoff * 4, tested.delay(3));
+        assert_eq!(u32::MAX, tested.delay(u8::MAX));
+    }
+
+    #[test]
+    fn test_retry_payload_keeps_serialized_instruction() {
+        let mut rng = rand::thread_rng();
+        let relay_idx = rng.gen_range(0..16);
+        let instruction = DataInstructions::RelayState(Conversation::Request(RelayIndexRequest::new(relay_idx)));
+
+        let tested = RetryPayload::create(&instruction).unwrap();
+
+        assert_eq!(DataInstructionCodes::RelayState, tested.code());
+        assert_eq!(&[relay_idx], tested.bytes());
+
+        let mut copy = RetryPayload::create(&tested).unwrap();
+        assert_eq!(tested, copy);
+
+        copy.clear();
+        assert_eq!(Ok(()), copy.add(&[0; MAX_RETRY_PAYLOAD_SIZE]));
+        assert_eq!(Err(Errors::DataOverflow), copy.add_u8(0));
+    }
+
+    #[test]
+    fn test_untracked_requests_are_forwarded() {
+        let mut rng = rand::thread_rng();
+        let mut tested = RetryController::new(MockResponsesHandler::new(), RetryPolicy::default());
+        let request = random_request(&mut rng);
+
+        tested.on_request_timeout(request);
+        tested.on_request_error(request, ErrorCode::ECommandEmpty);
+        tested.on_request_success(request);
+
+        assert_eq!(vec![request], tested.response_handler.on_request_timeout_params);
+        assert_eq!(vec![(request, ErrorCode::ECommandEmpty)], tested.response_handler.on_request_error_params);
+        assert_eq!(vec![request], tested.response_handler.on_request_success_params);
+    }
+
+    #[test]
+    fn test_retryable_error_is_resent_and_success_reported_once() {
+        let mut rng = rand::thread_rng();
+        let backoff = rng.gen_range(1..1000);
+        let mut tested = RetryController::new(MockResponsesHandler::new(), RetryPolicy::new(DEFAULT_MAX_ATTEMPTS, backoff));
+        tested.policy().set_retryable(ErrorCode::ECommandEmpty, true).unwrap();
+        let request = random_request(&mut rng);
+        let payload = random_payload(&mut rng);
+        tested.track(request, payload);
+
+        tested.on_request_error(request, ErrorCode::ECommandEmpty);
+        assert!(tested.response_handler.on_request_error_params.is_empty());
+
+        let now = request.rel_timestamp().value().wrapping_add(10);
+        tested.schedule(RelativeMillis::new(now));
+        assert_eq!(None, tested.take_due(RelativeMillis::new(now.wrapping_add(backoff - 1))));
+
+        let resend_at = RelativeMillis::new(now.wrapping_add(backoff));
+        let pending = tested.take_due(resend_at).unwrap();
+        assert_eq!(request, pending.request());
+        assert_eq!(payload, pending.payload());
+        assert_eq!(1, pending.attempts());
+        tested.on_resent(pending, resend_at, Ok(()));
+
+        let resent = SentRequest::new(request.id(), request.operation(), request.instruction(), resend_at);
+        tested.on_request_success(resent);
+
+        assert_eq!(vec![resent], tested.response_handler.on_request_success_params);
+        assert!(tested.response_handler.on_request_error_params.is_empty());
+        assert!(tested.response_handler.on_request_timeout_params.is_empty());
+        assert_eq!([None; MAX_REQUESTS_COUNT], tested.pending);
+    }
+
+    #[test]
+    fn test_not_retryable_error_is_reported_at_once() {
+        let mut rng = rand::thread_rng();
+        let mut tested = RetryController::new(MockResponsesHandler::new(), RetryPolicy::default());
+        let request = random_request(&mut rng);
+        tested.track(request, random_payload(&mut rng));
+
+        tested.on_request_error(request, ErrorCode::ERelayIndexOutOfRange);
+        tested.schedule(request.rel_timestamp());
+
+        assert_eq!(vec![(request, ErrorCode::ERelayIndexOutOfRange)], tested.response_handler.on_request_error_params);
+        assert_eq!(None, tested.take_due(RelativeMillis::new(request.rel_timestamp().value().wrapping_add(u32::MAX / 2))));
+        assert_eq!([None; MAX_REQUESTS_COUNT], tested.pending);
+    }
+
+    #[test]
+    fn test_timeout_is_reported_once_after_max_attempts() {
+        let mut rng = rand::thread_rng();
+        let max_attempts = rng.gen_range(2..6);
+        let backoff = rng.gen_range(1..1000);
+        let mut tested = RetryController::new(MockResponsesHandler::new(), RetryPolicy::new(max_attempts, backoff));
+        let mut request = random_request(&mut rng);
+        tested.track(request, random_payload(&mut rng));
+
+        for attempt in 1..max_attempts {
+            tested.on_request_timeout(request);
+            assert!(tested.response_handler.on_request_timeout_params.is_empty());
+
+            let now = request.rel_timestamp();
+            tested.schedule(now);
+            let resend_at = RelativeMillis::new(now.value().wrapping_add(backoff * 2_u32.pow(attempt as u32 - 1)));
+            let pending = tested.take_due(resend_at).unwrap();
+            assert_eq!(attempt, pending.attempts());
+            tested.on_resent(pending, resend_at, Ok(()));
+            request = SentRequest::new(request.id(), request.operation(), request.instruction(), resend_at);
+        }
+        tested.on_request_timeout(request);
+
+        assert_eq!(vec![request], tested.response_handler.on_request_timeout_params);
+        assert_eq!([None; MAX_REQUESTS_COUNT], tested.pending);
+    }
+
+    #[test]
+    fn test_failed_resend_counts_as_attempt() {
+        let mut rng = rand::thread_rng();
+        let mut tested = RetryController::new(MockResponsesHandler::new(), RetryPolicy::new(2, 0));
+        let request = random_request(&mut rng);
+        tested.track(request, random_payload(&mut rng));
+
+        tested.on_request_timeout(request);
+        tested.schedule(request.rel_timestamp());
+        let pending = tested.take_due(request.rel_timestamp()).unwrap();
+        tested.on_resent(pending, request.rel_timestamp(), Err(Errors::TransferInProgress));
+
+        assert_eq!(vec![request], tested.response_handler.on_request_timeout_params);
+        assert_eq!([None; MAX_REQUESTS_COUNT], tested.pending);
+    }
+
+    #[test]
+    fn test_duplicate_responses_are_dropped() {
+        let mut rng = rand::thread_rng();
+        let mut tested = RetryController::new(MockResponsesHandler::new(), RetryPolicy::new(2, 0));
+        let request = random_request(&mut rng);
+        tested.track(request, random_payload(&mut rng));
+        let late_response = ResponseData::new(request.operation(), request.instruction(), request.id(), ErrorCode::OK);
+
+        tested.on_request_timeout(request);
+        // response to the first attempt came after it expired
+        tested.on_request_search_error(late_response, Errors::NoRequestsFound);
+        tested.schedule(request.rel_timestamp());
+        let pending = tested.take_due(request.rel_timestamp()).unwrap();
+        tested.on_resent(pending, request.rel_timestamp(), Ok(()));
+        tested.on_request_success(request);
+        // and once more for the resent one
+        tested.on_request_search_error(late_response, Errors::NoRequestsFound);
+
+        assert_eq!(vec![request], tested.response_handler.on_request_success_params);
+        assert!(tested.response_handler.on_request_search_error_params.is_empty());
+
+        let other_response = ResponseData::new(request.operation(), request.instruction(),
+                                               request.id().map(|id| id.wrapping_add(1)), ErrorCode::OK);
+        tested.on_request_search_error(other_response, Errors::NoRequestsFound);
+        assert_eq!(vec![(other_response, Errors::NoRequestsFound)], tested.response_handler.on_request_search_error_params);
+    }
+
+    fn random_request(rng: &mut ThreadRng) -> SentRequest {
+        SentRequest::new(Some(rng.gen_range(1..u32::MAX)), Operation::Set, DataInstructionCodes::RelaySwitchedOn,
+                         RelativeMillis::new(rng.next_u32()))
+    }
+
+    fn random_payload(rng: &mut ThreadRng) -> RetryPayload {
+        let relay_idx = rng.gen_range(0..16);
+        RetryPayload::create(&DataInstructions::RelayState(Conversation::Request(RelayIndexRequest::new(relay_idx)))).unwrap()
+    }
+
+    struct MockResponsesHandler {
+        on_request_success_params: Vec<SentRequest>,
+        on_request_error_params: Vec<(SentRequest, ErrorCode)>,
+        on_request_parse_error_params: Vec<(Option<SentRequest>, Errors, Vec<u8>)>,
+        on_request_response_params: Vec<(SentRequest, DataInstructions<'static>)>,
+        on_request_search_error_params: Vec<(ResponseData, Errors)>,
+        on_request_timeout_params: Vec<SentRequest>,
+    }
+
+    impl MockResponsesHandler {
+        pub fn new() -> Self {
+            Self {
+                on_request_success_params: Vec::new(),
+                on_request_error_params: Vec::new(),
+                on_request_parse_error_params: Vec::new(),
+                on_request_response_params: Vec::new(),
+                on_request_search_error_params: Vec::new(),
+                on_request_timeout_params: Vec::new(),
+            }
+        }
+    }
+
+    impl ResponseHandler for MockResponsesHandler {
+        fn on_request_success(&mut self, request: SentRequest) {
+            self.on_request_success_params.push(request);
+        }
+
+        fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+            self.on_request_response_params.push((request, response.into_owned()));
+        }
+
+        fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+            self.on_request_error_params.push((request, error_code));
+        }
+
+        fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+            self.on_request_parse_error_params.push((request, error, data.to_vec()));
+        }
+
+        fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
+            self.on_request_search_error_params.push((payload, error));
+        }
+
+        fn on_request_timeout(&mut self, request: SentRequest) {
+            self.on_request_timeout_params.push(request);
+        }
+    }
+}
@@ -44,11 +44,22 @@ impl <SP: SignalParser, SPH: SignalsPreHandler> SignalController<SP> for SPH {
 
 pub struct SignalControllerImpl<SH: SignalsHandler> {
     signal_handler: SH,
+    time_requested: Option<RelativeMillis>,
 }
 
 impl <SH: SignalsHandler> SignalControllerImpl<SH> {
     pub fn new(signal_handler: SH) -> Self {
-        Self { signal_handler }
+        Self { signal_handler, time_requested: None }
+    }
+
+    #[inline(always)]
+    pub fn signal_handler(&mut self) -> &mut SH {
+        &mut self.signal_handler
+    }
+
+    /** Slave asks for the timestamp when it (re)starts, returns the one sent to it, cleared on read. */
+    pub fn take_time_requested(&mut self) -> Option<RelativeMillis> {
+        self.time_requested.take()
     }
 }
 
@@ -57,6 +68,7 @@ impl <SH: SignalsHandler> SignalsPreHandler for SignalControllerImpl<SH> {
     fn on_signal<TS: RelativeTimestampSource, S: ControlledRequestSender + ErrorsSender>(&mut self, signal_data: SignalData, time_source: &mut TS, tx:  &mut S) {
         if signal_data.code() == Signals::GetTimeStamp {
             let timestamp = time_source.get();
+            self.time_requested = Some(timestamp);
             let res = tx.send(
                 Operation::Set,
                 DataInstructions::RemoteTimestamp(Conversation::Data(timestamp.seconds())),
@@ -185,6 +197,8 @@ mod tests {
                 timestamp)),
             mock_tx.send_params);
         assert_eq!(Some((SignalData::GetTimeStamp, true)), mock_signals_handler.borrow().on_signal_params);
+        assert_eq!(Some(timestamp), controller.take_time_requested());
+        assert_eq!(None, controller.take_time_requested());
         // should not call other methods
         assert_eq!(None, mock_signals_handler.borrow().on_signal_process_error_params);
         assert_eq!(None, mock_tx.send_error_params);
@@ -273,6 +287,7 @@ mod tests {
 
             assert_eq!(Some((data, false)), mock_signals_handler.borrow().on_signal_params);
             assert_eq!(false, time_source.time_source_called);
+            assert_eq!(None, controller.take_time_requested());
             // should not call other methods
             assert_eq!(None, mock_tx.send_params);
             assert_eq!(None, mock_signals_handler.borrow().on_signal_process_error_params);
@@ -513,7 +528,7 @@ mod tests {
     }
 
     struct MockControlledSender {
-        send_params: Option<(Operation, DataInstructions, RelativeMillis)>,
+        send_params: Option<(Operation, DataInstructions<'static>, RelativeMillis)>,
         send_result: Result<Option<u32>, Errors>,
         send_error_called: bool,
         send_error_params: Option<(u8, ErrorCode)>,
@@ -539,7 +554,7 @@ mod tests {
     }
     impl ControlledRequestSender for MockControlledSender {
         fn send(&mut self, operation: Operation, instruction: DataInstructions, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
-            self.send_params = Some((operation, instruction, timestamp));
+            self.send_params = Some((operation, instruction.into_owned(), timestamp));
             self.send_result
         }
     }
@@ -602,7 +617,7 @@ mod tests {
 
     #[derive(Debug, PartialEq)]
     struct MockSender {
-        send_params: Option<(Operation, DataInstructions, RelativeMillis)>,
+        send_params: Option<(Operation, DataInstructions<'static>, RelativeMillis)>,
         send_result: Result<Option<u32>, Errors>,
         send_error_params: Option<(u8, ErrorCode)>,
         send_error_result: Result<(), Errors>,
@@ -621,7 +636,7 @@ mod tests {
 
     impl ControlledRequestSender for MockSender {
         fn send(&mut self, operation: Operation, instruction: DataInstructions, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
-            self.send_params = Some((operation, instruction, timestamp));
+            self.send_params = Some((operation, instruction.into_owned(), timestamp));
             self.send_result
         }
     }
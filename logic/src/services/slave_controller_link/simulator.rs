@@ -0,0 +1,842 @@
+#![deny(unsafe_code)]
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use crate::errors::{DMAError, Errors};
+use crate::hal_ext::rtc_wrapper::RelativeSeconds;
+use crate::hal_ext::serial_transfer::{RxTransferProxy, SerialTransfer, TransferProxy, TxTransferProxy};
+use crate::services::slave_controller_link::domain::{AllData, ContactsWaitData, CyclesStatistics, DataInstructionCodes, ErrorCode, FixDataContainer, Operation, OperationCodes, Parser, RelaySignalData, RelaySignalDataExt, RelaySingleState, RelayState, RelaysSettings, Serializable, SignalData, Signals, State, StateFixSettings, StateSwitchDatas, SwitchCountingSettings, Version, MAX_RELAYS_COUNT};
+use crate::services::slave_controller_link::framing::{unframe, write_frame, FrameBody};
+use crate::utils::BitsU64;
+use crate::utils::dma_read_buffer::{Buffer, BufferWriter};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SimulatedRelay {
+    pub set_pin: u8,
+    pub monitor_pin: u8,
+    pub control_pin: u8,
+    pub on: bool,
+    pub disabled: bool,
+    pub monitoring: bool,
+    pub control: bool,
+    switches_count: u8,
+    switches_window_start: u32,
+}
+
+impl SimulatedRelay {
+    fn new(set_pin: u8, monitor_pin: u8, control_pin: u8) -> Self {
+        Self {
+            set_pin,
+            monitor_pin,
+            control_pin,
+            on: false,
+            disabled: false,
+            monitoring: false,
+            control: false,
+            switches_count: 0,
+            switches_window_start: 0,
+        }
+    }
+
+    /** State nibble of `State` and `All` responses. */
+    fn flags(&self) -> u8 {
+        self.on as u8 | (self.disabled as u8) << 1 | (self.monitoring as u8) << 2 | (self.control as u8) << 3
+    }
+}
+
+/** Misbehaviour of the slave on the next request with the instruction. */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Fault {
+    /** The request is rejected with the error code. */
+    Error(ErrorCode),
+    /** The request is processed, but the answer is lost. */
+    NoResponse,
+    /** The first byte of the answer is damaged on the wire. */
+    Corrupt,
+}
+
+/**
+In-process relay controller, which speaks the slave side of the link protocol. Requests come from
+`SimulatedTxTransfer` and the answers and signals go back through `SimulatedRxTransfer`, one frame per
+idle line event. V2 slave answers the id-less `Version` read in V1 format, so the master of any
+version could find out what it is talking to. The clock of the slave is moved by `advance` only.
+ */
+pub struct SimulatedSlave {
+    version: Version,
+    id: u32,
+    interrupt_pin: u8,
+    relays: Vec<SimulatedRelay>,
+    switch_limit_interval: u16,
+    max_switch_count: u8,
+    fix_settings: StateFixSettings,
+    contacts_wait_data: ContactsWaitData,
+    fix_data: FixDataContainer,
+    switch_data: StateSwitchDatas,
+    cycles_statistics: CyclesStatistics,
+    remote_timestamp: RelativeSeconds,
+    uptime: u32,
+    faults: Vec<(DataInstructionCodes, Fault)>,
+    requests: Vec<(Operation, DataInstructionCodes, Option<u32>)>,
+    master_errors: Vec<(u8, ErrorCode)>,
+    dropped_frames: u32,
+    output: VecDeque<Vec<u8>>,
+    connected: bool,
+}
+
+impl SimulatedSlave {
+
+    pub fn new(version: Version, id: u32, interrupt_pin: u8) -> Self {
+        Self {
+            version,
+            id,
+            interrupt_pin,
+            relays: Vec::new(),
+            switch_limit_interval: 0,
+            max_switch_count: 0,
+            fix_settings: StateFixSettings::default(),
+            contacts_wait_data: ContactsWaitData::new(),
+            fix_data: FixDataContainer::new(),
+            switch_data: StateSwitchDatas::new(),
+            cycles_statistics: CyclesStatistics::default(),
+            remote_timestamp: RelativeSeconds::new(0),
+            uptime: 0,
+            faults: Vec::new(),
+            requests: Vec::new(),
+            master_errors: Vec::new(),
+            dropped_frames: 0,
+            output: VecDeque::new(),
+            connected: true,
+        }
+    }
+
+    pub fn add_relay(&mut self, set_pin: u8, monitor_pin: u8, control_pin: u8) -> Result<(), Errors> {
+        if self.relays.len() >= MAX_RELAYS_COUNT as usize {
+            return Err(Errors::RelayCountOverflow);
+        }
+        self.relays.push(SimulatedRelay::new(set_pin, monitor_pin, control_pin));
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn relays(&self) -> &[SimulatedRelay] {
+        &self.relays
+    }
+
+    /** More than `max_switch_count` switches of a relay in the interval disable it, 0 turns the limit off. */
+    pub fn set_switch_counting(&mut self, switch_limit_interval_seconds: u16, max_switch_count: u8) {
+        self.switch_limit_interval = switch_limit_interval_seconds;
+        self.max_switch_count = max_switch_count;
+    }
+
+    pub fn set_fix_settings(&mut self, fix_settings: StateFixSettings) {
+        self.fix_settings = fix_settings;
+    }
+
+    pub fn set_contacts_wait_data(&mut self, contacts_wait_data: ContactsWaitData) {
+        self.contacts_wait_data = contacts_wait_data;
+    }
+
+    pub fn set_fix_data(&mut self, fix_data: FixDataContainer) {
+        self.fix_data = fix_data;
+    }
+
+    pub fn set_switch_data(&mut self, switch_data: StateSwitchDatas) {
+        self.switch_data = switch_data;
+    }
+
+    pub fn set_cycles_statistics(&mut self, cycles_statistics: CyclesStatistics) {
+        self.cycles_statistics = cycles_statistics;
+    }
+
+    pub fn advance(&mut self, seconds: u32) {
+        self.uptime = self.uptime.wrapping_add(seconds);
+    }
+
+    /** Timestamp the master has set, it is reset on restart. */
+    #[inline(always)]
+    pub fn remote_timestamp(&self) -> RelativeSeconds {
+        self.remote_timestamp
+    }
+
+    /** Faults are applied in the order they were injected, each one to a single request. */
+    pub fn inject(&mut self, instruction: DataInstructionCodes, fault: Fault) {
+        self.faults.push((instruction, fault));
+    }
+
+    /** Operation, instruction and id of every recognized request. */
+    #[inline(always)]
+    pub fn requests(&self) -> &[(Operation, DataInstructionCodes, Option<u32>)] {
+        &self.requests
+    }
+
+    /** Instruction codes and errors the master reported back. */
+    #[inline(always)]
+    pub fn master_errors(&self) -> &[(u8, ErrorCode)] {
+        &self.master_errors
+    }
+
+    /** V3 frames with a broken length or CRC, they are dropped without an answer. */
+    #[inline(always)]
+    pub fn dropped_frames(&self) -> u32 {
+        self.dropped_frames
+    }
+
+    #[inline(always)]
+    pub fn has_output(&self) -> bool {
+        !self.output.is_empty()
+    }
+
+    /**
+    Joins the unsent frames and splits them into chunks of the size, each one delivered with its own
+    idle line event. `usize::MAX` puts everything into one chunk.
+     */
+    pub fn rechunk_output(&mut self, chunk_size: usize) {
+        let bytes: Vec<u8> = self.output.drain(..).flatten().collect();
+        self.output.extend(bytes.chunks(chunk_size.max(1)).map(|chunk| chunk.to_vec()));
+    }
+
+    /** While the wire is cut, the master frames are lost. */
+    #[inline(always)]
+    pub fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+    }
+
+    /** Unsent frames are lost on the wire. */
+    #[inline(always)]
+    pub fn lose_output(&mut self) {
+        self.output.clear();
+    }
+
+    /** Relays are switched off, unsent frames are lost and the slave asks for the timestamp. */
+    pub fn restart(&mut self) {
+        for relay in self.relays.iter_mut() {
+            *relay = SimulatedRelay::new(relay.set_pin, relay.monitor_pin, relay.control_pin);
+        }
+        self.uptime = 0;
+        self.remote_timestamp = RelativeSeconds::new(0);
+        self.output.clear();
+        self.send_signal(SignalData::GetTimeStamp);
+    }
+
+    /** Switch made by the slave itself, it is reported with the `RelayStateChanged` signal. */
+    pub fn switch_relay(&mut self, relay_index: u8, on: bool) -> Result<(), Errors> {
+        self.relay_mut(relay_index)?;
+        if self.switch(relay_index as usize, on) {
+            let data = RelaySignalDataExt::new(RelativeSeconds::new(self.uptime), relay_index, on, true);
+            self.send_signal(SignalData::RelayStateChanged(data));
+        }
+        Ok(())
+    }
+
+    pub fn set_monitoring(&mut self, relay_index: u8, on: bool) -> Result<(), Errors> {
+        self.relay_mut(relay_index)?.monitoring = on;
+        let data = RelaySignalData::new(RelativeSeconds::new(self.uptime), relay_index, on);
+        self.send_signal(SignalData::MonitoringStateChanged(data));
+        Ok(())
+    }
+
+    pub fn set_control(&mut self, relay_index: u8, on: bool) -> Result<(), Errors> {
+        self.relay_mut(relay_index)?.control = on;
+        let data = RelaySignalData::new(RelativeSeconds::new(self.uptime), relay_index, on);
+        self.send_signal(SignalData::ControlStateChanged(data));
+        Ok(())
+    }
+
+    /** Reports an attempt to bring the relay back to its state. */
+    pub fn fix_try(&mut self, relay_index: u8) -> Result<(), Errors> {
+        let on = self.relay_mut(relay_index)?.on;
+        let data = RelaySignalData::new(RelativeSeconds::new(self.uptime), relay_index, on);
+        self.send_signal(SignalData::StateFixTry(data));
+        Ok(())
+    }
+
+    pub fn send_signal(&mut self, signal: SignalData) {
+        let mut body = FrameBytes::new();
+        let result = body.add_u8(OperationCodes::None as u8)
+            .and_then(|_| body.add_u8(OperationCodes::Signal as u8))
+            .and_then(|_| body.add_u8(signal.code() as u8))
+            .and_then(|_| match signal {
+                SignalData::GetTimeStamp => Ok(()),
+                SignalData::RelayStateChanged(data) => data.serialize(&mut body),
+                SignalData::MonitoringStateChanged(data) |
+                SignalData::ControlStateChanged(data) |
+                SignalData::StateFixTry(data) => data.serialize(&mut body),
+            });
+        if result.is_ok() {
+            self.emit(&body.0, None);
+        }
+    }
+
+    /** Processes one frame sent by the master. */
+    pub fn receive(&mut self, data: &[u8]) {
+        if !self.connected {
+            return;
+        }
+        let body = if self.version.framed() {
+            match unframe(data) {
+                Ok(body) => body,
+                Err(_) => {
+                    self.dropped_frames += 1;
+                    return;
+                }
+            }
+        } else {
+            data
+        };
+        if body.len() < 3 {
+            self.answer(Operation::Error, Signals::None as u8, None, Err(ErrorCode::ECommandEmpty), None);
+            return;
+        }
+        let instruction_code = body[2];
+        if body[0] != OperationCodes::None as u8 {
+            self.answer(Operation::Error, instruction_code, None, Err(ErrorCode::EInstructionWrongStart), None);
+            return;
+        }
+        let operation = if body[1] == OperationCodes::Read as u8 {
+            Operation::Read
+        } else if body[1] == OperationCodes::Set as u8 {
+            Operation::Set
+        } else if body[1] == OperationCodes::Command as u8 {
+            Operation::Command
+        } else if body[1] == OperationCodes::Error as u8 {
+            let error_code = body.get(3).copied().unwrap_or(ErrorCode::OK.discriminant());
+            self.master_errors.push((instruction_code, ErrorCode::for_code(error_code)));
+            return;
+        } else {
+            self.answer(Operation::Error, instruction_code, None, Err(ErrorCode::EUndefinedOperation), None);
+            return;
+        };
+        let handshake = body.len() == 3 && operation == Operation::Read &&
+            instruction_code == DataInstructionCodes::Version as u8;
+        let (id, payload) = if self.version == Version::V1 || handshake {
+            (None, &body[3..])
+        } else if body.len() >= 7 {
+            (Some(u32::from_be_bytes([body[3], body[4], body[5], body[6]])), &body[7..])
+        } else {
+            self.answer(operation, instruction_code, None, Err(ErrorCode::ERequestDataNoValue), None);
+            return;
+        };
+        let instruction = match DataInstructionCodes::get(instruction_code) {
+            Ok(instruction) => instruction,
+            Err(_) => {
+                self.answer(operation, instruction_code, id, Err(ErrorCode::EInstructionUnrecognized), None);
+                return;
+            }
+        };
+        self.requests.push((operation, instruction, id));
+        let fault = self.take_fault(instruction);
+        let result = match fault {
+            Some(Fault::Error(error_code)) => Err(error_code),
+            _ if operation == Operation::Read => self.read(instruction, payload),
+            _ if operation == Operation::Command => self.command(instruction).map(|_| Vec::new()),
+            _ => self.set(instruction, payload).map(|_| Vec::new()),
+        };
+        self.answer(operation, instruction_code, id, result, fault);
+    }
+
+    fn take_fault(&mut self, instruction: DataInstructionCodes) -> Option<Fault> {
+        let position = self.faults.iter().position(|(code, _)| *code == instruction)?;
+        Some(self.faults.remove(position).1)
+    }
+
+    fn relay_mut(&mut self, relay_index: u8) -> Result<&mut SimulatedRelay, Errors> {
+        self.relays.get_mut(relay_index as usize).ok_or(Errors::RelayIndexOutOfRange)
+    }
+
+    /** Returns `false` if the relay is disabled or already in the state. */
+    fn switch(&mut self, index: usize, on: bool) -> bool {
+        let now = self.uptime;
+        let interval = self.switch_limit_interval as u32;
+        let max_switch_count = self.max_switch_count;
+        let relay = &mut self.relays[index];
+        if relay.disabled || relay.on == on {
+            return false;
+        }
+        if now.wrapping_sub(relay.switches_window_start) >= interval {
+            relay.switches_count = 0;
+            relay.switches_window_start = now;
+        }
+        if max_switch_count > 0 && relay.switches_count >= max_switch_count {
+            relay.disabled = true;
+            return false;
+        }
+        relay.switches_count += 1;
+        relay.on = on;
+        true
+    }
+
+    fn single_state(&self, payload: &[u8]) -> Result<(usize, bool), ErrorCode> {
+        let state = RelaySingleState::parse(payload).map_err(ErrorCode::for_error)?;
+        let index = state.relay_index() as usize;
+        if index >= self.relays.len() {
+            return Err(ErrorCode::ERelayIndexOutOfRange);
+        }
+        Ok((index, state.is_set()))
+    }
+
+    fn state(&self) -> Result<State, Errors> {
+        let mut data = BitsU64::new(0);
+        for (i, relay) in self.relays.iter().enumerate() {
+            let from = i as u8 * 4;
+            data.set_byte(from, from + 3, relay.flags())?;
+        }
+        Ok(State { data, count: self.relays.len() as u8 })
+    }
+
+    fn settings(&self) -> Result<RelaysSettings, Errors> {
+        let mut settings = RelaysSettings::new();
+        for relay in self.relays.iter() {
+            settings.add(relay.set_pin, relay.monitor_pin, relay.control_pin)?;
+        }
+        Ok(settings)
+    }
+
+    fn all_data(&self) -> Result<AllData, Errors> {
+        let mut all_data = AllData::new(self.id, self.interrupt_pin);
+        for relay in self.relays.iter() {
+            all_data.add(relay.set_pin, relay.monitor_pin, relay.control_pin, relay.flags())?;
+        }
+        Ok(all_data)
+    }
+
+    fn read(&self, instruction: DataInstructionCodes, payload: &[u8]) -> Result<Vec<u8>, ErrorCode> {
+        let mut data = FrameBytes::new();
+        let result = match instruction {
+            DataInstructionCodes::Settings => self.settings().and_then(|settings| settings.serialize(&mut data)),
+            DataInstructionCodes::State => self.state().and_then(|state| state.serialize(&mut data)),
+            DataInstructionCodes::Id => self.id.serialize(&mut data),
+            DataInstructionCodes::InterruptPin => self.interrupt_pin.serialize(&mut data),
+            DataInstructionCodes::RemoteTimestamp => self.remote_timestamp.serialize(&mut data),
+            DataInstructionCodes::StateFixSettings => self.fix_settings.serialize(&mut data),
+            DataInstructionCodes::RelayState => {
+                let index = *payload.first().ok_or(ErrorCode::ERequestDataNoValue)?;
+                let relay = self.relays.get(index as usize).ok_or(ErrorCode::ERelayIndexOutOfRange)?;
+                RelayState::create(index, relay.on, relay.disabled)
+                    .and_then(|state| state.serialize(&mut data))
+            }
+            DataInstructionCodes::Version => self.version_code().serialize(&mut data),
+            DataInstructionCodes::CurrentTime => RelativeSeconds::new(self.uptime).serialize(&mut data),
+            DataInstructionCodes::ContactWaitData => self.contacts_wait_data.serialize(&mut data),
+            DataInstructionCodes::FixData => self.fix_data.serialize(&mut data),
+            DataInstructionCodes::SwitchData => self.switch_data.serialize(&mut data),
+            DataInstructionCodes::CyclesStatistics => self.cycles_statistics.serialize(&mut data),
+            DataInstructionCodes::SwitchCountingSettings =>
+                SwitchCountingSettings::new(self.switch_limit_interval, self.max_switch_count).serialize(&mut data),
+            DataInstructionCodes::All => self.all_data().and_then(|all_data| all_data.serialize(&mut data)),
+            _ => return Err(ErrorCode::EUndefinedOperation),
+        };
+        result.map_err(|_| ErrorCode::EInternalError)?;
+        Ok(data.0)
+    }
+
+    fn set(&mut self, instruction: DataInstructionCodes, payload: &[u8]) -> Result<(), ErrorCode> {
+        match instruction {
+            DataInstructionCodes::Settings => {
+                let settings = RelaysSettings::parse(payload).map_err(ErrorCode::for_error)?;
+                let relays = settings.get_relays().iter().enumerate().map(|(i, relay_settings)| {
+                    let mut relay = self.relays.get(i).copied()
+                        .unwrap_or_else(|| SimulatedRelay::new(0, 0, 0));
+                    relay.set_pin = relay_settings.set_pin().data();
+                    relay.monitor_pin = relay_settings.monitor_pin().data();
+                    relay.control_pin = relay_settings.control_pin().data();
+                    relay
+                }).collect();
+                self.relays = relays;
+            }
+            DataInstructionCodes::Id => {
+                self.id = u32::parse(payload).map_err(ErrorCode::for_error)?;
+            }
+            DataInstructionCodes::InterruptPin => {
+                self.interrupt_pin = u8::parse(payload).map_err(ErrorCode::for_error)?;
+            }
+            DataInstructionCodes::RemoteTimestamp => {
+                self.remote_timestamp = RelativeSeconds::parse(payload).map_err(ErrorCode::for_error)?;
+                // the slave clock goes on from the timestamp
+                self.uptime = self.remote_timestamp.value();
+            }
+            DataInstructionCodes::CurrentTime => {
+                self.uptime = RelativeSeconds::parse(payload).map_err(ErrorCode::for_error)?.value();
+            }
+            DataInstructionCodes::StateFixSettings => {
+                self.fix_settings = StateFixSettings::parse(payload).map_err(ErrorCode::for_error)?;
+            }
+            DataInstructionCodes::SwitchCountingSettings => {
+                SwitchCountingSettings::parse(payload).map_err(ErrorCode::for_error)?;
+                self.set_switch_counting(u16::from_be_bytes([payload[0], payload[1]]), payload[2]);
+            }
+            DataInstructionCodes::RelaySwitchedOn => {
+                let (index, on) = self.single_state(payload)?;
+                self.switch(index, on);
+            }
+            DataInstructionCodes::RelayDisabledTemp => {
+                let (index, disabled) = self.single_state(payload)?;
+                let relay = &mut self.relays[index];
+                relay.disabled = disabled;
+                if !disabled {
+                    relay.switches_count = 0;
+                }
+            }
+            DataInstructionCodes::RelayMonitorOn => {
+                let (index, on) = self.single_state(payload)?;
+                self.relays[index].monitoring = on;
+            }
+            DataInstructionCodes::RelayControlOn => {
+                let (index, on) = self.single_state(payload)?;
+                self.relays[index].control = on;
+            }
+            _ => return Err(ErrorCode::EUndefinedOperation),
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, instruction: DataInstructionCodes) -> Result<(), ErrorCode> {
+        match instruction {
+            DataInstructionCodes::ClearSwitchCount => {
+                for relay in self.relays.iter_mut() {
+                    relay.switches_count = 0;
+                }
+                Ok(())
+            }
+            _ => Err(ErrorCode::EUndefinedOperation),
+        }
+    }
+
+    fn version_code(&self) -> u8 {
+        match self.version {
+            Version::V1 => 1,
+            Version::V2 => 2,
+            Version::V3 => 3,
+        }
+    }
+
+    /** Answers without the request id use V1 operation codes. */
+    fn answer(&mut self, operation: Operation, instruction_code: u8, id: Option<u32>,
+              result: Result<Vec<u8>, ErrorCode>, fault: Option<Fault>) {
+        let mut body = FrameBytes::new();
+        body.0.push(OperationCodes::None as u8);
+        match &result {
+            Ok(_) => {
+                let operation_code = match (operation, id.is_some()) {
+                    (Operation::Read, false) => OperationCodes::Response,
+                    (Operation::Read, true) => OperationCodes::ResponseV2,
+                    (_, false) => OperationCodes::Success,
+                    (_, true) => OperationCodes::SuccessV2,
+                };
+                body.0.push(operation_code as u8);
+                body.0.push(instruction_code);
+            }
+            Err(error_code) => {
+                body.0.push(if id.is_some() { OperationCodes::ErrorV2 } else { OperationCodes::Error } as u8);
+                body.0.push(error_code.discriminant());
+                body.0.push(instruction_code);
+            }
+        }
+        if let Some(id) = id {
+            body.0.extend_from_slice(&id.to_be_bytes());
+        }
+        if let Ok(data) = result {
+            body.0.extend_from_slice(&data);
+        }
+        self.emit(&body.0, fault);
+    }
+
+    fn emit(&mut self, body: &[u8], fault: Option<Fault>) {
+        let mut frame = FrameBytes::new();
+        if write_frame(&mut frame, &RawBody(body), self.version.framed()).is_err() {
+            return;
+        }
+        match fault {
+            Some(Fault::NoResponse) => return,
+            Some(Fault::Corrupt) => frame.0[0] ^= 0xff,
+            _ => {}
+        }
+        self.output.push_back(frame.0);
+    }
+}
+
+struct RawBody<'a>(&'a [u8]);
+
+impl <'a> FrameBody for RawBody<'a> {
+    fn write<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors> {
+        buffer.add(self.0)
+    }
+}
+
+struct FrameBytes(Vec<u8>);
+
+impl FrameBytes {
+    fn new() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl BufferWriter for FrameBytes {
+
+    #[inline(always)]
+    fn add_str(&mut self, string: &str) -> Result<(), Errors> {
+        self.add(string.as_bytes())
+    }
+
+    fn add(&mut self, data: &[u8]) -> Result<(), Errors> {
+        self.0.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn add_u8(&mut self, byte: u8) -> Result<(), Errors> {
+        self.0.push(byte);
+        Ok(())
+    }
+
+    fn add_u16(&mut self, value: u16) -> Result<(), Errors> {
+        self.add(&value.to_be_bytes())
+    }
+
+    fn add_u32(&mut self, value: u32) -> Result<(), Errors> {
+        self.add(&value.to_be_bytes())
+    }
+
+    fn add_u64(&mut self, value: u64) -> Result<(), Errors> {
+        self.add(&value.to_be_bytes())
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/** TX DMA which hands every transfer to the slave at once and reports it complete. */
+pub struct SimulatedTxTransfer<const N: usize> {
+    slave: Rc<RefCell<SimulatedSlave>>,
+    buffer: Buffer<N>,
+    transfer_complete: bool,
+}
+
+impl <const N: usize> SimulatedTxTransfer<N> {
+    pub fn new(slave: Rc<RefCell<SimulatedSlave>>) -> Self {
+        Self {
+            slave,
+            buffer: new_buffer(),
+            transfer_complete: false,
+        }
+    }
+}
+
+impl <const N: usize> TransferProxy<Buffer<N>> for SimulatedTxTransfer<N> {
+
+    fn is_fifo_error(&self) -> bool {
+        false
+    }
+
+    fn is_transfer_complete(&self) -> bool {
+        self.transfer_complete
+    }
+
+    fn is_direct_mode_error(&self) -> bool {
+        false
+    }
+
+    fn is_half_transfer(&self) -> bool {
+        false
+    }
+
+    fn is_transfer_error(&self) -> bool {
+        false
+    }
+
+    fn clear_dma_interrupts(&mut self) {
+        self.transfer_complete = false;
+    }
+
+    fn clear_direct_mode_error(&mut self) {}
+
+    fn clear_fifo_error(&mut self) {}
+
+    fn clear_half_transfer(&mut self) {}
+
+    fn clear_transfer_complete(&mut self) {
+        self.transfer_complete = false;
+    }
+
+    fn clear_transfer_error(&mut self) {}
+}
+
+impl <const N: usize> TxTransferProxy<Buffer<N>> for SimulatedTxTransfer<N> {
+    fn next_transfer(&mut self, new_buf: Buffer<N>) -> Result<Buffer<N>, DMAError<Buffer<N>>> {
+        self.slave.borrow_mut().receive(new_buf.bytes());
+        self.transfer_complete = true;
+        Ok(core::mem::replace(&mut self.buffer, new_buf))
+    }
+}
+
+/** RX DMA, the line is idle while the slave has a frame to send and each transfer takes one. */
+pub struct SimulatedRxTransfer<const N: usize> {
+    slave: Rc<RefCell<SimulatedSlave>>,
+    buffer: Buffer<N>,
+}
+
+impl <const N: usize> SimulatedRxTransfer<N> {
+    pub fn new(slave: Rc<RefCell<SimulatedSlave>>) -> Self {
+        Self {
+            slave,
+            buffer: new_buffer(),
+        }
+    }
+}
+
+impl <const N: usize> TransferProxy<Buffer<N>> for SimulatedRxTransfer<N> {
+
+    fn is_fifo_error(&self) -> bool {
+        false
+    }
+
+    fn is_transfer_complete(&self) -> bool {
+        false
+    }
+
+    fn is_direct_mode_error(&self) -> bool {
+        false
+    }
+
+    fn is_half_transfer(&self) -> bool {
+        false
+    }
+
+    fn is_transfer_error(&self) -> bool {
+        false
+    }
+
+    fn clear_dma_interrupts(&mut self) {}
+
+    fn clear_direct_mode_error(&mut self) {}
+
+    fn clear_fifo_error(&mut self) {}
+
+    fn clear_half_transfer(&mut self) {}
+
+    fn clear_transfer_complete(&mut self) {}
+
+    fn clear_transfer_error(&mut self) {}
+}
+
+impl <const N: usize> RxTransferProxy<Buffer<N>> for SimulatedRxTransfer<N> {
+
+    fn get_read_bytes_count(&self) -> usize {
+        self.slave.borrow().output.front()
+            .map(|frame| frame.len().min(N))
+            .unwrap_or(0)
+    }
+
+    fn next_transfer(&mut self, new_buf: Buffer<N>) -> Result<Buffer<N>, DMAError<Buffer<N>>> {
+        let mut filled = core::mem::replace(&mut self.buffer, new_buf);
+        filled.clear();
+        if let Some(frame) = self.slave.borrow_mut().output.pop_front() {
+            // the rest of a too long frame is lost as with the real DMA
+            let _ = filled.add(&frame[..frame.len().min(N)]);
+        }
+        Ok(filled)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.slave.borrow().has_output()
+    }
+
+    fn is_rx_not_empty(&self) -> bool {
+        self.slave.borrow().has_output()
+    }
+
+    fn clear_idle_interrupt(&mut self) {}
+}
+
+pub type SimulatedSerialTransfer<const N: usize> = SerialTransfer<SimulatedTxTransfer<N>, SimulatedRxTransfer<N>, Buffer<N>, Buffer<N>>;
+
+/** Serial transfer of a link connected to the slave. */
+pub fn serial_transfer<const N: usize>(slave: &Rc<RefCell<SimulatedSlave>>) -> SimulatedSerialTransfer<N> {
+    SerialTransfer::new(SimulatedTxTransfer::new(slave.clone()), new_buffer(),
+                        SimulatedRxTransfer::new(slave.clone()), new_buffer())
+}
+
+fn new_buffer<const N: usize>() -> Buffer<N> {
+    Buffer::new(Box::leak(Box::new([0_u8; N])))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::slave_controller_link::framing::crc16;
+
+    #[test]
+    fn test_v1_answers_read_without_id() {
+        let id = rand::thread_rng().next_u32();
+        let mut tested = SimulatedSlave::new(Version::V1, id, 5);
+
+        tested.receive(&[OperationCodes::None as u8, OperationCodes::Read as u8, DataInstructionCodes::Id as u8]);
+
+        let mut expected = [OperationCodes::None as u8, OperationCodes::Response as u8, DataInstructionCodes::Id as u8].to_vec();
+        expected.extend_from_slice(&id.to_be_bytes());
+        assert_eq!(Some(expected), tested.output.pop_front());
+        assert_eq!(&[(Operation::Read, DataInstructionCodes::Id, None)], tested.requests());
+    }
+
+    #[test]
+    fn test_v2_answers_handshake_in_v1_format() {
+        let mut rng = rand::thread_rng();
+        let request_id: u32 = rng.gen();
+        let mut tested = SimulatedSlave::new(Version::V2, rng.gen(), 5);
+
+        tested.receive(&[OperationCodes::None as u8, OperationCodes::Read as u8, DataInstructionCodes::Version as u8]);
+        let mut request = [OperationCodes::None as u8, OperationCodes::Read as u8, DataInstructionCodes::Version as u8].to_vec();
+        request.extend_from_slice(&request_id.to_be_bytes());
+        tested.receive(&request);
+
+        assert_eq!(Some([0, OperationCodes::Response as u8, DataInstructionCodes::Version as u8, 2].to_vec()),
+                   tested.output.pop_front());
+        let mut expected = [0, OperationCodes::ResponseV2 as u8, DataInstructionCodes::Version as u8].to_vec();
+        expected.extend_from_slice(&request_id.to_be_bytes());
+        expected.push(2);
+        assert_eq!(Some(expected), tested.output.pop_front());
+    }
+
+    #[test]
+    fn test_v3_drops_damaged_frames_and_frames_answers() {
+        let mut tested = SimulatedSlave::new(Version::V3, 1, 5);
+        let body = [0, OperationCodes::Set as u8, DataInstructionCodes::InterruptPin as u8, 0, 0, 0, 7, 9];
+        let mut frame = [body.len() as u8].to_vec();
+        frame.extend_from_slice(&body);
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+
+        let mut damaged = frame.clone();
+        damaged[3] ^= 0x01;
+        tested.receive(&damaged);
+        assert_eq!(1, tested.dropped_frames());
+        assert!(!tested.has_output());
+
+        tested.receive(&frame);
+        let answer = tested.output.pop_front().unwrap();
+        assert_eq!(Ok([0, OperationCodes::SuccessV2 as u8, DataInstructionCodes::InterruptPin as u8, 0, 0, 0, 7].as_slice()),
+                   unframe(&answer));
+        assert_eq!(9, tested.interrupt_pin);
+    }
+
+    #[test]
+    fn test_switch_limit_disables_relay() {
+        let mut tested = SimulatedSlave::new(Version::V1, 1, 5);
+        tested.add_relay(1, 2, 3).unwrap();
+        tested.set_switch_counting(60, 2);
+
+        tested.switch_relay(0, true).unwrap();
+        tested.switch_relay(0, false).unwrap();
+        tested.switch_relay(0, true).unwrap();
+
+        assert!(!tested.relays()[0].on);
+        assert!(tested.relays()[0].disabled);
+        // only the accepted switches are reported
+        assert_eq!(2, tested.output.len());
+        assert_eq!(Err(Errors::RelayIndexOutOfRange), tested.switch_relay(1, true));
+    }
+}
@@ -4,7 +4,9 @@ use embedded_dma::ReadBuffer;
 use crate::errors::Errors;
 use crate::hal_ext::rtc_wrapper::RelativeMillis;
 use crate::hal_ext::serial_transfer::Sender;
-use crate::services::slave_controller_link::domain::{DataInstruction, ErrorCode, Operation, OperationCodes};
+use crate::hal_ext::tx_queue::TxPriority;
+use crate::services::slave_controller_link::domain::{DataInstruction, ErrorCode, Operation, OperationCodes, Version};
+use crate::services::slave_controller_link::framing::{write_frame, FrameBody};
 use crate::services::slave_controller_link::requests_controller::{RequestsControllerTx, SentRequest};
 use crate::utils::dma_read_buffer::BufferWriter;
 
@@ -13,6 +15,7 @@ pub trait RequestsSender<RCT>
         RCT: RequestsControllerTx,
 {
     fn send_request<I: DataInstruction>(&mut self, operation: Operation, instruction: I, timestamp: RelativeMillis, request_controller: &mut RCT) -> Result<Option<u32>, Errors>;
+    fn resend_request<I: DataInstruction>(&mut self, request: SentRequest, instruction: I, timestamp: RelativeMillis, request_controller: &mut RCT) -> Result<(), Errors>;
 }
 
 pub trait ErrorsSender {
@@ -25,17 +28,51 @@ pub struct TransmitterToSlaveController<TxBuff, S>
         S: Sender<TxBuff>,
 {
     tx: S,
+    version: Version,
     _phantom: core::marker::PhantomData<TxBuff>,
 }
 
+struct RequestFrame<'a, I: DataInstruction> {
+    operation: Operation,
+    id: Option<u32>,
+    instruction: &'a I,
+}
+
+impl <'a, I: DataInstruction> FrameBody for RequestFrame<'a, I> {
+    fn write<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors> {
+        buffer.add_u8(OperationCodes::None as u8)?;
+        buffer.add_u8(self.operation as u8)?;
+        buffer.add_u8(self.instruction.code() as u8)?;
+        if let Some(id) = self.id {
+            buffer.add_u32(id)?;
+        }
+        self.instruction.serialize(buffer)
+    }
+}
+
+struct ErrorFrame {
+    instruction_code: u8,
+    error_code: ErrorCode,
+}
+
+impl FrameBody for ErrorFrame {
+    fn write<B: BufferWriter>(&self, buffer: &mut B) -> Result<(), Errors> {
+        buffer.add_u8(OperationCodes::None as u8)?;
+        buffer.add_u8(OperationCodes::Error as u8)?;
+        buffer.add_u8(self.instruction_code)?;
+        buffer.add_u8(self.error_code.discriminant())
+    }
+}
+
 impl <TxBuff, S> TransmitterToSlaveController<TxBuff, S>
     where
         TxBuff: ReadBuffer + BufferWriter,
         S: Sender<TxBuff>,
 {
-    pub fn new (tx: S) -> Self {
+    pub fn new (tx: S, version: Version) -> Self {
         Self {
             tx,
+            version,
             _phantom: core::marker::PhantomData
         }
     }
@@ -45,6 +82,13 @@ impl <TxBuff, S> TransmitterToSlaveController<TxBuff, S>
         &mut self.tx
     }
 
+    fn transfer_request<I: DataInstruction>(&mut self, operation: Operation, id: Option<u32>, instruction: &I) -> Result<(), Errors> {
+        let framed = self.version.framed();
+        self.start_transfer(TxPriority::Request, |buffer| {
+            write_frame(buffer, &RequestFrame { operation, id, instruction }, framed)
+        })
+    }
+
 }
 
 impl <TxBuff, S> Sender<TxBuff> for TransmitterToSlaveController<TxBuff, S>
@@ -53,8 +97,8 @@ impl <TxBuff, S> Sender<TxBuff> for TransmitterToSlaveController<TxBuff, S>
         S: Sender<TxBuff>,
 {
     #[inline(always)]
-    fn start_transfer<F: FnOnce(&mut TxBuff) -> Result<(), Errors>>(&mut self, writter: F) -> Result<(), Errors> {
-        self.tx.start_transfer(writter)
+    fn start_transfer<F: FnOnce(&mut TxBuff) -> Result<(), Errors>>(&mut self, priority: TxPriority, writter: F) -> Result<(), Errors> {
+        self.tx.start_transfer(priority, writter)
     }
 }
 
@@ -64,12 +108,9 @@ impl <TxBuff, S> ErrorsSender for TransmitterToSlaveController<TxBuff, S>
         S: Sender<TxBuff>,
 {
     fn send_error(&mut self, instruction_code: u8, error_code: ErrorCode) -> Result<(), Errors> {
-        self.start_transfer(|buffer| {
-            buffer.clear();
-            buffer.add_u8(OperationCodes::None as u8)?;
-            buffer.add_u8(OperationCodes::Error as u8)?;
-            buffer.add_u8(instruction_code)?;
-            buffer.add_u8(error_code.discriminant())
+        let framed = self.version.framed();
+        self.start_transfer(TxPriority::Error, |buffer| {
+            write_frame(buffer, &ErrorFrame { instruction_code, error_code }, framed)
         })
     }
 }
@@ -84,21 +125,23 @@ impl <TxBuff, RCT, S> RequestsSender<RCT> for TransmitterToSlaveController<TxBuf
 
         let id = request_controller.check_request(instruction.code())?;
 
-        self.start_transfer(|buffer| {
-            buffer.clear();
-            buffer.add_u8(OperationCodes::None as u8)?;
-            buffer.add_u8(operation as u8)?;
-            buffer.add_u8(instruction.code() as u8)?;
-            if let Some(id) = id {
-                buffer.add_u32(id)?;
-            }
-            instruction.serialize(buffer)
-        })?;
+        self.transfer_request(operation, id, &instruction)?;
 
         request_controller.add_sent_request(SentRequest::new(
             id, operation, instruction.code(), timestamp));
         Ok(id)
     }
+
+    fn resend_request<I: DataInstruction>(&mut self, request: SentRequest, instruction: I, timestamp: RelativeMillis, request_controller: &mut RCT) -> Result<(), Errors> {
+
+        request_controller.check_resend(instruction.code())?;
+
+        self.transfer_request(request.operation(), request.id(), &instruction)?;
+
+        request_controller.add_sent_request(SentRequest::new(
+            request.id(), request.operation(), instruction.code(), timestamp));
+        Ok(())
+    }
 }
 
 
@@ -106,13 +149,16 @@ impl <TxBuff, RCT, S> RequestsSender<RCT> for TransmitterToSlaveController<TxBuf
 mod tests {
     #![allow(unsafe_code)]
 
+    use alloc::boxed::Box;
     use alloc::rc::Rc;
     use alloc::vec::Vec;
     use core::cell::RefCell;
     use super::*;
     use rand::prelude::*;
     use crate::errors::DMAError;
-    use crate::services::slave_controller_link::domain::{Conversation, DataInstructionCodes, DataInstructions, Signals};
+    use crate::services::slave_controller_link::domain::{Conversation, DataInstructionCodes, DataInstructions, EmptyRequest, RelayIndexRequest, Response, Signals};
+    use crate::services::slave_controller_link::framing::{crc16, unframe};
+    use crate::utils::dma_read_buffer::Buffer;
 
     #[test]
     fn test_send_error() {
@@ -122,11 +168,12 @@ mod tests {
 
         let mock = Rc::new(RefCell::new(MockSender::new(true, start_transfer_result)));
 
-        let mut tested = TransmitterToSlaveController::new(mock.clone());
+        let mut tested = TransmitterToSlaveController::new(mock.clone(), Version::V1);
 
         let result = tested.send_error(instruction_code, sending_error);
 
         assert_eq!(true, mock.borrow().start_transfer_called);
+        assert_eq!(Some(TxPriority::Error), mock.borrow().start_transfer_priority);
         assert_eq!(start_transfer_result, result);
         assert_eq!(true, mock.borrow().buffer.cleared);
         assert_eq!(4, mock.borrow().buffer.add_u8_arguments.len());
@@ -163,7 +210,7 @@ mod tests {
         ];
         let mock = Rc::new(RefCell::new(MockSender::new(true, start_transfer_result)));
 
-        let mut tested = TransmitterToSlaveController::new(mock.clone());
+        let mut tested = TransmitterToSlaveController::new(mock.clone(), Version::V1);
 
         for instruction_code in 0 .. 100 {
             for sending_error in sending_errors {
@@ -185,7 +232,7 @@ mod tests {
         let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
 
         let mock = Rc::new(RefCell::new(MockSender::new(true, start_transfer_result)));
-        let mut tested = TransmitterToSlaveController::new(mock.clone());
+        let mut tested = TransmitterToSlaveController::new(mock.clone(), Version::V1);
 
         let mut mock_request_controller = MockRequestsControllerTx::new(Ok(None));
 
@@ -196,6 +243,7 @@ mod tests {
         assert_eq!(Some(instruction.code), *mock_request_controller.check_request_parameter.borrow());
         assert_eq!(Some(SentRequest::new(None, operation, instruction.code(), timestamp)),
                    mock_request_controller.add_sent_request_parameter);
+        assert_eq!(Some(TxPriority::Request), mock.borrow().start_transfer_priority);
         //check buffer operations
         assert_eq!(true, mock.borrow().buffer.cleared);
         assert_eq!(3, mock.borrow().buffer.add_u8_arguments.len());
@@ -215,7 +263,7 @@ mod tests {
         let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
 
         let mock = Rc::new(RefCell::new(MockSender::new(true, start_transfer_result)));
-        let mut tested = TransmitterToSlaveController::new(mock.clone());
+        let mut tested = TransmitterToSlaveController::new(mock.clone(), Version::V1);
 
         let add_sent_request_result = rng.gen_range(1..u32::MAX);
         let mut mock_request_controller = MockRequestsControllerTx::new(Ok(Some(add_sent_request_result)));
@@ -246,7 +294,7 @@ mod tests {
         let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
 
         let mock = Rc::new(RefCell::new(MockSender::new(true, start_transfer_result)));
-        let mut tested = TransmitterToSlaveController::new(mock.clone());
+        let mut tested = TransmitterToSlaveController::new(mock.clone(), Version::V1);
 
         let errors = [
             Errors::RequestsLimitReached,
@@ -280,7 +328,7 @@ mod tests {
         let mock = Rc::new(RefCell::new(MockSender::new(true,
                                                         start_transfer_result)));
         let mut tested =
-            TransmitterToSlaveController::new(mock.clone());
+            TransmitterToSlaveController::new(mock.clone(), Version::V1);
 
         let errors = [
             Errors::TransferInProgress,
@@ -305,8 +353,205 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_send_request_serializes_read_requests_v1() {
+        let mut rng = rand::thread_rng();
+        let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
+        let mut tested = TransmitterToSlaveController::new(BufferSender::new(), Version::V1);
+
+        for relay_idx in [0, rng.gen_range(1..MAX_RELAY_IDX), MAX_RELAY_IDX] {
+            for (instruction, payload) in all_read_requests(relay_idx) {
+                let instruction_code = instruction.code();
+                let mut mock_request_controller = MockRequestsControllerTx::new(Ok(None));
+
+                let result = tested.send_request(Operation::Read, instruction, timestamp, &mut mock_request_controller);
+
+                assert_eq!(Ok(None), result);
+                let mut expected = [OperationCodes::None as u8, OperationCodes::Read as u8, instruction_code as u8].to_vec();
+                expected.extend_from_slice(payload.as_slice());
+                assert_eq!(expected.as_slice(), tested.inner_tx().buffer.bytes());
+                assert_eq!(Some(SentRequest::new(None, Operation::Read, instruction_code, timestamp)),
+                           mock_request_controller.add_sent_request_parameter);
+            }
+        }
+    }
+
+    #[test]
+    fn test_send_request_serializes_read_requests_v2() {
+        let mut rng = rand::thread_rng();
+        let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
+        let mut tested = TransmitterToSlaveController::new(BufferSender::new(), Version::V1);
+
+        for relay_idx in [0, rng.gen_range(1..MAX_RELAY_IDX), MAX_RELAY_IDX] {
+            for (instruction, payload) in all_read_requests(relay_idx) {
+                let instruction_code = instruction.code();
+                let id = rng.gen_range(1..u32::MAX);
+                let mut mock_request_controller = MockRequestsControllerTx::new(Ok(Some(id)));
+
+                let result = tested.send_request(Operation::Read, instruction, timestamp, &mut mock_request_controller);
+
+                assert_eq!(Ok(Some(id)), result);
+                let mut expected = [OperationCodes::None as u8, OperationCodes::Read as u8, instruction_code as u8].to_vec();
+                expected.extend_from_slice(&id.to_be_bytes());
+                expected.extend_from_slice(payload.as_slice());
+                assert_eq!(expected.as_slice(), tested.inner_tx().buffer.bytes());
+                assert_eq!(Some(SentRequest::new(Some(id), Operation::Read, instruction_code, timestamp)),
+                           mock_request_controller.add_sent_request_parameter);
+            }
+        }
+    }
+
+    #[test]
+    fn test_send_request_should_not_send_response_conversation() {
+        let mut rng = rand::thread_rng();
+        let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
+        let mut tested = TransmitterToSlaveController::new(BufferSender::new(), Version::V1);
+        let mut mock_request_controller = MockRequestsControllerTx::new(Ok(None));
+
+        let instruction = DataInstructions::RelayState(Conversation::Response(Response::Success));
+        let result = tested.send_request(Operation::Read, instruction, timestamp, &mut mock_request_controller);
+
+        assert_eq!(Err(Errors::InstructionNotSerializable), result);
+        assert_eq!(None, mock_request_controller.add_sent_request_parameter);
+    }
+
+    #[test]
+    fn test_resend_request_keeps_request_id() {
+        let mut rng = rand::thread_rng();
+        let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
+        let resend_timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
+        let mut tested = TransmitterToSlaveController::new(BufferSender::new(), Version::V1);
+
+        for id in [None, Some(rng.gen_range(1..u32::MAX))] {
+            let relay_idx = rng.gen_range(0..MAX_RELAY_IDX);
+            let request = SentRequest::new(id, Operation::Read, DataInstructionCodes::RelayState, timestamp);
+            // check_request would hand out a new id, resend should not ask for it
+            let mut mock_request_controller = MockRequestsControllerTx::new(Ok(Some(rng.gen_range(1..u32::MAX))));
+
+            let result = tested.resend_request(request,
+                                               DataInstructions::RelayState(Conversation::Request(RelayIndexRequest::new(relay_idx))),
+                                               resend_timestamp, &mut mock_request_controller);
+
+            assert_eq!(Ok(()), result);
+            let mut expected = [OperationCodes::None as u8, OperationCodes::Read as u8, DataInstructionCodes::RelayState as u8].to_vec();
+            if let Some(id) = id {
+                expected.extend_from_slice(&id.to_be_bytes());
+            }
+            expected.push(relay_idx);
+            assert_eq!(expected.as_slice(), tested.inner_tx().buffer.bytes());
+            assert_eq!(None, *mock_request_controller.check_request_parameter.borrow());
+            assert_eq!(Some(DataInstructionCodes::RelayState), mock_request_controller.check_resend_parameter);
+            assert_eq!(Some(SentRequest::new(id, Operation::Read, DataInstructionCodes::RelayState, resend_timestamp)),
+                       mock_request_controller.add_sent_request_parameter);
+        }
+    }
+
+    #[test]
+    fn test_resend_request_returns_check_resend_errors() {
+        let mut rng = rand::thread_rng();
+        let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
+        let mut tested = TransmitterToSlaveController::new(BufferSender::new(), Version::V1);
+
+        for error in [Errors::RequestsLimitReached, Errors::RequestsNeedsCacheAlreadySent] {
+            let request = SentRequest::new(Some(rng.gen_range(1..u32::MAX)), Operation::Read, DataInstructionCodes::Id, timestamp);
+            let mut mock_request_controller = MockRequestsControllerTx::new(Err(error));
+
+            let result = tested.resend_request(request,
+                                               DataInstructions::Id(Conversation::Request(EmptyRequest::new())),
+                                               timestamp, &mut mock_request_controller);
+
+            assert_eq!(Err(error), result);
+            assert_eq!(None, mock_request_controller.add_sent_request_parameter);
+        }
+    }
+
+    #[test]
+    fn test_send_request_v3_is_framed() {
+        let mut rng = rand::thread_rng();
+        let timestamp = RelativeMillis::new(rng.gen_range(1..u32::MAX));
+        let mut tested = TransmitterToSlaveController::new(BufferSender::new(), Version::V3);
+        let relay_idx = rng.gen_range(0..MAX_RELAY_IDX);
+        let id = rng.gen_range(1..u32::MAX);
+        let mut mock_request_controller = MockRequestsControllerTx::new(Ok(Some(id)));
+
+        let result = tested.send_request(Operation::Read,
+                                         DataInstructions::RelayState(Conversation::Request(RelayIndexRequest::new(relay_idx))),
+                                         timestamp, &mut mock_request_controller);
+
+        assert_eq!(Ok(Some(id)), result);
+        let mut body = [OperationCodes::None as u8, OperationCodes::Read as u8, DataInstructionCodes::RelayState as u8].to_vec();
+        body.extend_from_slice(&id.to_be_bytes());
+        body.push(relay_idx);
+        let mut expected = [body.len() as u8].to_vec();
+        expected.extend_from_slice(body.as_slice());
+        expected.extend_from_slice(&crc16(expected.as_slice()).to_be_bytes());
+        assert_eq!(expected.as_slice(), tested.inner_tx().buffer.bytes());
+    }
+
+    #[test]
+    fn test_send_error_v3_is_framed() {
+        let mut rng = rand::thread_rng();
+        let instruction_code = rng.gen();
+        let mut tested = TransmitterToSlaveController::new(BufferSender::new(), Version::V3);
+
+        let result = tested.send_error(instruction_code, ErrorCode::EInstructionUnrecognized);
+
+        assert_eq!(Ok(()), result);
+        let body = [OperationCodes::None as u8, OperationCodes::Error as u8, instruction_code,
+            ErrorCode::EInstructionUnrecognized.discriminant()];
+        assert_eq!(Ok(body.as_slice()), unframe(tested.inner_tx().buffer.bytes()));
+    }
+
+    const MAX_RELAY_IDX: u8 = 15;
+
+    fn all_read_requests(relay_idx: u8) -> [(DataInstructions<'static>, Vec<u8>); 19] {
+        [
+            (DataInstructions::Settings(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::State(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::Id(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::InterruptPin(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::RemoteTimestamp(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::StateFixSettings(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::RelayState(Conversation::Request(RelayIndexRequest::new(relay_idx))), [relay_idx].to_vec()),
+            (DataInstructions::Version(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::CurrentTime(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::ContactWaitData(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::FixData(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::SwitchData(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::CyclesStatistics(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::SwitchCountingSettings(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::RelayDisabledTemp(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::RelaySwitchedOn(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::RelayMonitorOn(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::RelayControlOn(Conversation::Request(EmptyRequest::new())), Vec::new()),
+            (DataInstructions::All(Conversation::Request(EmptyRequest::new())), Vec::new()),
+        ]
+    }
+
+    const FRAME_BUFFER_SIZE: usize = 32;
+
+    struct BufferSender {
+        buffer: Buffer<FRAME_BUFFER_SIZE>,
+    }
+
+    impl BufferSender {
+        fn new() -> Self {
+            Self {
+                buffer: Buffer::new(Box::leak(Box::new([0_u8; FRAME_BUFFER_SIZE]))),
+            }
+        }
+    }
+
+    impl Sender<Buffer<FRAME_BUFFER_SIZE>> for BufferSender {
+        fn start_transfer<F: FnOnce(&mut Buffer<FRAME_BUFFER_SIZE>) -> Result<(), Errors>>(&mut self, _: TxPriority, writter: F) -> Result<(), Errors> {
+            self.buffer.clear();
+            writter(&mut self.buffer)
+        }
+    }
+
     struct MockSender {
         start_transfer_called: bool,
+        start_transfer_priority: Option<TxPriority>,
         call_writer: bool,
         start_transfer_result: Result<(), Errors>,
         buffer: MockTxBuffer,
@@ -317,6 +562,7 @@ mod tests {
             let buffer = MockTxBuffer::new();
             Self {
                 start_transfer_called: false,
+                start_transfer_priority: None,
                 call_writer,
                 start_transfer_result,
                 buffer,
@@ -326,8 +572,9 @@ mod tests {
     }
 
     impl Sender<MockTxBuffer> for MockSender {
-        fn start_transfer<F: FnOnce(&mut MockTxBuffer) -> Result<(), Errors>>(&mut self, writter: F) -> Result<(), Errors> {
+        fn start_transfer<F: FnOnce(&mut MockTxBuffer) -> Result<(), Errors>>(&mut self, priority: TxPriority, writter: F) -> Result<(), Errors> {
             self.start_transfer_called = true;
+            self.start_transfer_priority = Some(priority);
             if self.call_writer {
                 writter(&mut self.buffer)?;
             }
@@ -336,8 +583,8 @@ mod tests {
     }
 
     impl Sender<MockTxBuffer> for Rc<RefCell<MockSender>> {
-        fn start_transfer<F: FnOnce(&mut MockTxBuffer) -> Result<(), Errors>>(&mut self, writter: F) -> Result<(), Errors> {
-            self.borrow_mut().start_transfer(writter)
+        fn start_transfer<F: FnOnce(&mut MockTxBuffer) -> Result<(), Errors>>(&mut self, priority: TxPriority, writter: F) -> Result<(), Errors> {
+            self.borrow_mut().start_transfer(priority, writter)
         }
     }
 
@@ -346,6 +593,7 @@ mod tests {
     struct MockRequestsControllerTx {
         check_request_result: Result<Option<u32>, Errors>,
         check_request_parameter: RefCell<Option<DataInstructionCodes>>,
+        check_resend_parameter: Option<DataInstructionCodes>,
         add_sent_request_parameter: Option<SentRequest>,
     }
 
@@ -354,6 +602,7 @@ mod tests {
             Self {
                 check_request_result,
                 check_request_parameter: RefCell::new(None),
+                check_resend_parameter: None,
                 add_sent_request_parameter: None,
             }
         }
@@ -366,6 +615,11 @@ mod tests {
             self.check_request_result
         }
 
+        fn check_resend(&mut self, value: DataInstructionCodes) -> Result<(), Errors> {
+            self.check_resend_parameter = Some(value);
+            self.check_request_result.map(|_| ())
+        }
+
         fn add_sent_request(&mut self, request: SentRequest) {
             self.add_sent_request_parameter = Some(request);
         }
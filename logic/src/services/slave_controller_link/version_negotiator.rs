@@ -0,0 +1,293 @@
+#![deny(unsafe_code)]
+
+use crate::errors::Errors;
+use crate::services::slave_controller_link::domain::{Conversation, DataInstructionCodes, DataInstructions, ErrorCode, Operation, Version};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum NegotiationState {
+    Required,
+    Requested,
+    Negotiated(Version),
+}
+
+/**
+Finds out the protocol version of the slave. The link sends a V1 `Version` read request while the
+state is `Required` and the answer switches it to `Negotiated`. Failed handshakes go back to
+`Required` to be tried again on the next poll. Framed (V3) links are configured explicitly and skip
+the handshake. Responses to the handshake requests are not passed to the wrapped handler.
+ */
+pub struct VersionNegotiator<RH: ResponseHandler> {
+    response_handler: RH,
+    state: NegotiationState,
+}
+
+impl <RH: ResponseHandler> VersionNegotiator<RH> {
+
+    pub fn new(response_handler: RH, api_version: Version) -> Self {
+        let state = if api_version.framed() {
+            NegotiationState::Negotiated(api_version)
+        } else {
+            NegotiationState::Required
+        };
+        Self {
+            response_handler,
+            state,
+        }
+    }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+
+    #[inline(always)]
+    pub fn state(&self) -> NegotiationState {
+        self.state
+    }
+
+    pub fn version(&self) -> Option<Version> {
+        match self.state {
+            NegotiationState::Negotiated(version) => Some(version),
+            _ => None,
+        }
+    }
+
+    /** Called when the slave restarts, as it could be flashed with another firmware. */
+    pub fn restart(&mut self) {
+        if let NegotiationState::Negotiated(version) = self.state {
+            if version.framed() {
+                return;
+            }
+        }
+        self.state = NegotiationState::Required;
+    }
+
+    #[inline(always)]
+    pub fn needs_request(&self) -> bool {
+        self.state == NegotiationState::Required
+    }
+
+    #[inline(always)]
+    pub fn on_requested(&mut self) {
+        self.state = NegotiationState::Requested;
+    }
+
+    fn is_handshake(&self, request: &SentRequest) -> bool {
+        self.state == NegotiationState::Requested &&
+            request.instruction() == DataInstructionCodes::Version && request.operation() == Operation::Read
+    }
+}
+
+impl <RH: ResponseHandler> ResponseHandler for VersionNegotiator<RH> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        self.response_handler.on_request_success(request);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        if !self.is_handshake(&request) {
+            self.response_handler.on_request_response(request, response);
+            return;
+        }
+        self.state = match response {
+            DataInstructions::Version(Conversation::Data(code)) => {
+                // an unframed link can not switch the framing, so V3 slave is used as V2
+                match Version::for_code(code) {
+                    Ok(Version::V1) => NegotiationState::Negotiated(Version::V1),
+                    Ok(_) => NegotiationState::Negotiated(Version::V2),
+                    Err(_) => NegotiationState::Required,
+                }
+            }
+            _ => NegotiationState::Required,
+        };
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        if self.is_handshake(&request) {
+            self.state = NegotiationState::Required;
+        } else {
+            self.response_handler.on_request_error(request, error_code);
+        }
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+        match request {
+            Some(request) if self.is_handshake(&request) => {
+                self.state = NegotiationState::Required;
+            }
+            _ => {
+                self.response_handler.on_request_parse_error(request, error, data);
+            }
+        }
+    }
+
+    fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
+        self.response_handler.on_request_search_error(payload, error);
+    }
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        if self.is_handshake(&request) {
+            self.state = NegotiationState::Required;
+        } else {
+            self.response_handler.on_request_timeout(request);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::hal_ext::rtc_wrapper::RelativeMillis;
+
+    #[test]
+    fn test_framed_version_skips_handshake() {
+        let mut tested = VersionNegotiator::new(MockResponsesHandler::new(), Version::V3);
+
+        assert!(!tested.needs_request());
+        assert_eq!(Some(Version::V3), tested.version());
+
+        tested.restart();
+
+        assert!(!tested.needs_request());
+        assert_eq!(Some(Version::V3), tested.version());
+    }
+
+    #[test]
+    fn test_negotiates_reported_version() {
+        let mut rng = rand::thread_rng();
+        for (code, expected) in [(1, Version::V1), (2, Version::V2), (3, Version::V2)] {
+            for api_version in [Version::V1, Version::V2] {
+                let mut tested = VersionNegotiator::new(MockResponsesHandler::new(), api_version);
+                assert!(tested.needs_request());
+                assert_eq!(None, tested.version());
+
+                tested.on_requested();
+                assert!(!tested.needs_request());
+                tested.on_request_response(version_request(&mut rng),
+                                           DataInstructions::Version(Conversation::Data(code)));
+
+                assert_eq!(Some(expected), tested.version());
+                assert!(tested.response_handler.on_request_response_params.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_restart_requires_new_handshake() {
+        let mut rng = rand::thread_rng();
+        let mut tested = VersionNegotiator::new(MockResponsesHandler::new(), Version::V1);
+        tested.on_requested();
+        tested.on_request_response(version_request(&mut rng), DataInstructions::Version(Conversation::Data(2)));
+
+        tested.restart();
+
+        assert!(tested.needs_request());
+        assert_eq!(None, tested.version());
+    }
+
+    #[test]
+    fn test_failed_handshake_is_required_again() {
+        let mut rng = rand::thread_rng();
+        let mut tested = VersionNegotiator::new(MockResponsesHandler::new(), Version::V1);
+
+        tested.on_requested();
+        tested.on_request_timeout(version_request(&mut rng));
+        assert!(tested.needs_request());
+
+        tested.on_requested();
+        tested.on_request_error(version_request(&mut rng), ErrorCode::EInstructionUnrecognized);
+        assert!(tested.needs_request());
+
+        tested.on_requested();
+        tested.on_request_parse_error(Some(version_request(&mut rng)), Errors::NotEnoughDataGot, &[]);
+        assert!(tested.needs_request());
+
+        tested.on_requested();
+        tested.on_request_response(version_request(&mut rng), DataInstructions::Version(Conversation::Data(0)));
+        assert!(tested.needs_request());
+
+        assert!(tested.response_handler.on_request_timeout_params.is_empty());
+        assert!(tested.response_handler.on_request_error_params.is_empty());
+        assert!(tested.response_handler.on_request_parse_error_params.is_empty());
+        assert!(tested.response_handler.on_request_response_params.is_empty());
+    }
+
+    #[test]
+    fn test_other_requests_are_forwarded() {
+        let mut rng = rand::thread_rng();
+        let mut tested = VersionNegotiator::new(MockResponsesHandler::new(), Version::V1);
+        let request = SentRequest::new(None, Operation::Read, DataInstructionCodes::Id,
+                                       RelativeMillis::new(rng.next_u32()));
+        // version read by the application after the handshake
+        let app_version_request = version_request(&mut rng);
+
+        tested.on_requested();
+        tested.on_request_response(request, DataInstructions::Id(Conversation::Data(rng.next_u32())));
+        tested.on_request_timeout(request);
+        tested.on_request_response(version_request(&mut rng), DataInstructions::Version(Conversation::Data(1)));
+        tested.on_request_response(app_version_request, DataInstructions::Version(Conversation::Data(1)));
+
+        assert_eq!(Some(Version::V1), tested.version());
+        assert_eq!(2, tested.response_handler.on_request_response_params.len());
+        assert_eq!(request, tested.response_handler.on_request_response_params[0].0);
+        assert_eq!(app_version_request, tested.response_handler.on_request_response_params[1].0);
+        assert_eq!(vec![request], tested.response_handler.on_request_timeout_params);
+    }
+
+    fn version_request(rng: &mut ThreadRng) -> SentRequest {
+        SentRequest::new(None, Operation::Read, DataInstructionCodes::Version, RelativeMillis::new(rng.next_u32()))
+    }
+
+    struct MockResponsesHandler {
+        on_request_success_params: Vec<SentRequest>,
+        on_request_error_params: Vec<(SentRequest, ErrorCode)>,
+        on_request_parse_error_params: Vec<(Option<SentRequest>, Errors, Vec<u8>)>,
+        on_request_response_params: Vec<(SentRequest, DataInstructions<'static>)>,
+        on_request_search_error_params: Vec<(ResponseData, Errors)>,
+        on_request_timeout_params: Vec<SentRequest>,
+    }
+
+    impl MockResponsesHandler {
+        pub fn new() -> Self {
+            Self {
+                on_request_success_params: Vec::new(),
+                on_request_error_params: Vec::new(),
+                on_request_parse_error_params: Vec::new(),
+                on_request_response_params: Vec::new(),
+                on_request_search_error_params: Vec::new(),
+                on_request_timeout_params: Vec::new(),
+            }
+        }
+    }
+
+    impl ResponseHandler for MockResponsesHandler {
+        fn on_request_success(&mut self, request: SentRequest) {
+            self.on_request_success_params.push(request);
+        }
+
+        fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+            self.on_request_response_params.push((request, response.into_owned()));
+        }
+
+        fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+            self.on_request_error_params.push((request, error_code));
+        }
+
+        fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+            self.on_request_parse_error_params.push((request, error, data.to_vec()));
+        }
+
+        fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
+            self.on_request_search_error_params.push((payload, error));
+        }
+
+        fn on_request_timeout(&mut self, request: SentRequest) {
+            self.on_request_timeout_params.push(request);
+        }
+    }
+}
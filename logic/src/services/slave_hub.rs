@@ -0,0 +1,269 @@
+#![deny(unsafe_code)]
+
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::{RelativeMillis, RelativeTimestampSource};
+use crate::services::slave_controller_link::domain::{Commands, Conversation, DataInstructions, EmptyRequest, Operation};
+use crate::services::slave_controller_link::connection_monitor::{ConnectionState, ConnectionTransition};
+use crate::services::slave_controller_link::SlaveLink;
+use crate::services::slave_state_mirror::SlaveStateMirror;
+
+/** Unanswered `Id` request is sent again after this period. */
+pub const ID_REQUEST_INTERVAL_MS: u32 = 5000;
+
+struct SlaveEntry<'a> {
+    link: &'a mut (dyn SlaveLink + Send),
+    handshaken: bool,
+    id_requested_at: Option<RelativeMillis>,
+}
+
+/**
+Owns the links of all the slaves and is the only entry point for the interrupt handlers. Interrupts
+are routed by the port - position of the link in the hub, the application addresses slaves by the
+id, which is read from each slave after the protocol version handshake. Signals, responses and
+errors of each slave go to the handlers of its link.
+ */
+pub struct SlaveHub<'a, const N: usize> {
+    slaves: [SlaveEntry<'a>; N],
+}
+
+impl <'a, const N: usize> SlaveHub<'a, N> {
+
+    pub fn new(links: [&'a mut (dyn SlaveLink + Send); N]) -> Self {
+        Self {
+            slaves: links.map(|link| SlaveEntry {
+                link,
+                handshaken: false,
+                id_requested_at: None,
+            }),
+        }
+    }
+
+    #[inline(always)]
+    pub fn ports_count(&self) -> usize {
+        N
+    }
+
+    pub fn on_get_command<TS: RelativeTimestampSource>(&mut self, port: usize, time_source: &mut TS) {
+        if let Some(slave) = self.slaves.get_mut(port) {
+            slave.link.on_get_command(time_source);
+        }
+    }
+
+    pub fn on_rx_dma_interrupts(&mut self, port: usize) {
+        if let Some(slave) = self.slaves.get_mut(port) {
+            slave.link.on_rx_dma_interrupts();
+        }
+    }
+
+    pub fn on_tx_dma_interrupts(&mut self, port: usize) {
+        if let Some(slave) = self.slaves.get_mut(port) {
+            slave.link.on_tx_dma_interrupts();
+        }
+    }
+
+    /** Polls timeouts of all the links and asks the slaves with unknown id for it. */
+    pub fn poll<TS: RelativeTimestampSource>(&mut self, time_source: &mut TS) {
+        for slave in self.slaves.iter_mut() {
+            slave.link.poll_timeouts(time_source);
+            let now = time_source.get();
+            if slave.link.version().is_none() {
+                // the slave restarted or is not answering yet, it could be replaced meanwhile
+                slave.handshaken = false;
+                continue;
+            }
+            let request_needed = if !slave.handshaken {
+                slave.handshaken = true;
+                true
+            } else if slave.link.state_mirror().id().is_none() {
+                match slave.id_requested_at {
+                    Some(requested_at) => now.value().wrapping_sub(requested_at.value()) >= ID_REQUEST_INTERVAL_MS,
+                    None => true,
+                }
+            } else {
+                false
+            };
+            if request_needed {
+                let result = slave.link.send_request(Operation::Read,
+                                                     DataInstructions::Id(Conversation::Request(EmptyRequest::new())), now);
+                slave.id_requested_at = if result.is_ok() { Some(now) } else { None };
+            }
+        }
+    }
+
+    /** Port of the slave with the id, `None` if it is not connected or its id was not read yet. */
+    pub fn port(&mut self, slave_id: u32) -> Option<usize> {
+        self.slaves.iter_mut()
+            .position(|slave| slave.link.state_mirror().id() == Some(slave_id))
+    }
+
+    pub fn slave_id(&mut self, port: usize) -> Option<u32> {
+        self.slaves.get_mut(port)
+            .and_then(|slave| slave.link.state_mirror().id())
+    }
+
+    pub fn send_request(&mut self, slave_id: u32, operation: Operation, instruction: DataInstructions,
+                        timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
+        let port = self.port(slave_id).ok_or(Errors::SlaveNotFound(slave_id))?;
+        self.slaves[port].link.send_request(operation, instruction, timestamp)
+    }
+
+    pub fn send_command(&mut self, slave_id: u32, command: Commands, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
+        let port = self.port(slave_id).ok_or(Errors::SlaveNotFound(slave_id))?;
+        self.slaves[port].link.send_command(command, timestamp)
+    }
+
+    pub fn state_mirror(&mut self, slave_id: u32) -> Option<&SlaveStateMirror> {
+        let port = self.port(slave_id)?;
+        Some(self.slaves[port].link.state_mirror())
+    }
+
+    /** `None` for a port out of the hub. */
+    pub fn connection_state(&mut self, port: usize) -> Option<ConnectionState> {
+        self.slaves.get_mut(port)
+            .map(|slave| slave.link.connection_state())
+    }
+
+    /** Next connection state change of the slaves with the port of its slave, by the order of the ports. */
+    pub fn take_connection_transition(&mut self) -> Option<(usize, ConnectionTransition)> {
+        self.slaves.iter_mut()
+            .enumerate()
+            .find_map(|(port, slave)| slave.link.take_connection_transition().map(|transition| (port, transition)))
+    }
+
+    #[inline(always)]
+    pub fn link(&mut self, port: usize) -> Option<&mut (dyn SlaveLink + Send + 'a)> {
+        self.slaves.get_mut(port).map(|slave| &mut *slave.link)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::services::slave_controller_link::connection_monitor::TransitionCause;
+    use crate::services::slave_controller_link::domain::{DataInstructionCodes, Version};
+    use crate::services::test_support::MockLink;
+
+    #[test]
+    fn test_routes_interrupts_by_port() {
+        let mut links = [MockLink::new(None), MockLink::new(None), MockLink::new(None)];
+        let [link0, link1, link2] = &mut links;
+        let mut tested = SlaveHub::new([link0, link1, link2]);
+        let mut time_source = MockTimeSource(RelativeMillis::new(0));
+
+        tested.on_get_command(1, &mut time_source);
+        tested.on_rx_dma_interrupts(2);
+        tested.on_tx_dma_interrupts(0);
+        tested.on_get_command(3, &mut time_source);
+
+        assert_eq!(3, tested.ports_count());
+        assert_eq!((0, 0, 1), links[0].interrupts_counts());
+        assert_eq!((1, 0, 0), links[1].interrupts_counts());
+        assert_eq!((0, 1, 0), links[2].interrupts_counts());
+    }
+
+    #[test]
+    fn test_requests_id_after_handshake() {
+        let mut links = [MockLink::new(None), MockLink::new(None).with_versions(&[Some(Version::V2)])];
+        let [link0, link1] = &mut links;
+        let mut tested = SlaveHub::new([link0, link1]);
+        let mut time_source = MockTimeSource(RelativeMillis::new(100));
+
+        tested.poll(&mut time_source);
+
+        assert_eq!(1, links[0].poll_timeouts_count);
+        assert!(links[0].sent_requests().is_empty());
+        assert_eq!(1, links[1].poll_timeouts_count);
+        assert_eq!(vec![(Operation::Read, DataInstructionCodes::Id, RelativeMillis::new(100))], links[1].sent_requests());
+    }
+
+    #[test]
+    fn test_repeats_id_request_after_interval() {
+        let mut links = [MockLink::new(None).with_versions(&[Some(Version::V1)])];
+        let [link0] = &mut links;
+        let mut tested = SlaveHub::new([link0]);
+        let start = rand::thread_rng().next_u32();
+        let mut time_source = MockTimeSource(RelativeMillis::new(start));
+
+        tested.poll(&mut time_source);
+        time_source.0 = RelativeMillis::new(start.wrapping_add(ID_REQUEST_INTERVAL_MS - 1));
+        tested.poll(&mut time_source);
+        time_source.0 = RelativeMillis::new(start.wrapping_add(ID_REQUEST_INTERVAL_MS));
+        tested.poll(&mut time_source);
+
+        assert_eq!(2, links[0].sent_requests().len());
+    }
+
+    #[test]
+    fn test_requests_id_again_after_restart() {
+        let mut links = [MockLink::new(None).with_versions(&[Some(Version::V1), Some(Version::V1), None, Some(Version::V2)])];
+        links[0].mirror.apply_response(&DataInstructions::Id(Conversation::Data(7)), RelativeMillis::new(0));
+        let [link0] = &mut links;
+        let mut tested = SlaveHub::new([link0]);
+        let mut time_source = MockTimeSource(RelativeMillis::new(0));
+
+        for _ in 0..4 {
+            tested.poll(&mut time_source);
+        }
+
+        assert_eq!(2, links[0].sent_requests().len());
+    }
+
+    #[test]
+    fn test_addresses_slaves_by_id() {
+        let mut rng = rand::thread_rng();
+        let id = rng.next_u32();
+        let mut links = [MockLink::new(None), MockLink::new(Some(id)).with_versions(&[Some(Version::V2)])];
+        let [link0, link1] = &mut links;
+        let mut tested = SlaveHub::new([link0, link1]);
+        let timestamp = RelativeMillis::new(rng.next_u32());
+
+        assert_eq!(Some(1), tested.port(id));
+        assert_eq!(None, tested.slave_id(0));
+        assert_eq!(Some(id), tested.slave_id(1));
+        assert_eq!(Some(id), tested.state_mirror(id).unwrap().id());
+        assert_eq!(Ok(Some(1)), tested.send_request(id, Operation::Read,
+                                DataInstructions::State(Conversation::Request(EmptyRequest::new())), timestamp));
+        assert_eq!(Err(Errors::SlaveNotFound(id.wrapping_add(1))), tested.send_request(id.wrapping_add(1),
+                   Operation::Read, DataInstructions::State(Conversation::Request(EmptyRequest::new())), timestamp));
+        assert!(tested.state_mirror(id.wrapping_add(1)).is_none());
+        assert_eq!(Ok(Some(2)), tested.send_command(id, Commands::ClearSwitchCount, timestamp));
+        assert_eq!(Err(Errors::SlaveNotFound(id.wrapping_add(1))),
+                   tested.send_command(id.wrapping_add(1), Commands::ClearSwitchCount, timestamp));
+
+        assert!(links[0].sent_requests().is_empty());
+        assert_eq!(vec![(Operation::Read, DataInstructionCodes::State, timestamp),
+                        (Operation::Command, DataInstructionCodes::ClearSwitchCount, timestamp)], links[1].sent_requests());
+    }
+
+    #[test]
+    fn test_takes_connection_transitions_of_all_ports() {
+        let mut links = [MockLink::new(None), MockLink::new(None)];
+        let at = RelativeMillis::new(rand::thread_rng().next_u32());
+        let online = ConnectionTransition::new(ConnectionState::Handshaking, ConnectionState::Online,
+                                               TransitionCause::Synced, at);
+        let offline = ConnectionTransition::new(ConnectionState::Online, ConnectionState::Offline,
+                                                TransitionCause::Silent, at);
+        links[0].connection = ConnectionState::Unknown;
+        links[1].transitions = vec![online, offline];
+        let [link0, link1] = &mut links;
+        let mut tested = SlaveHub::new([link0, link1]);
+
+        assert_eq!(Some(ConnectionState::Unknown), tested.connection_state(0));
+        assert_eq!(Some(ConnectionState::Offline), tested.connection_state(1));
+        assert_eq!(None, tested.connection_state(2));
+        assert_eq!(Some((1, online)), tested.take_connection_transition());
+        assert_eq!(Some((1, offline)), tested.take_connection_transition());
+        assert_eq!(None, tested.take_connection_transition());
+    }
+
+    struct MockTimeSource(RelativeMillis);
+
+    impl RelativeTimestampSource for MockTimeSource {
+        fn get(&mut self) -> RelativeMillis {
+            self.0
+        }
+    }
+}
@@ -0,0 +1,621 @@
+#![deny(unsafe_code)]
+
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::RelativeMillis;
+use crate::services::slave_controller_link::domain::{Conversation, DataInstructions, ErrorCode, MAX_RELAYS_COUNT, RelaySettings, RelaySignalDataGetter, SignalData, SwitchCountingSettings};
+use crate::services::slave_controller_link::parsers::ResponseData;
+use crate::services::slave_controller_link::requests_controller::{ResponseHandler, SentRequest};
+use crate::services::slave_controller_link::signals_controller::SignalsHandler;
+
+pub const PENDING_SIGNALS_COUNT: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum RelayFlag {
+    On,
+    Disabled,
+    Monitoring,
+    Control,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RelayMirror {
+    on: bool,
+    disabled: bool,
+    monitoring: bool,
+    control: bool,
+    settings: RelaySettings,
+    updated_at: Option<RelativeMillis>,
+    changed_at: Option<RelativeMillis>,
+}
+
+impl RelayMirror {
+
+    const fn new() -> Self {
+        Self {
+            on: false,
+            disabled: false,
+            monitoring: false,
+            control: false,
+            settings: RelaySettings::new(),
+            updated_at: None,
+            changed_at: None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    #[inline(always)]
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    #[inline(always)]
+    pub fn is_monitoring_on(&self) -> bool {
+        self.monitoring
+    }
+
+    #[inline(always)]
+    pub fn is_control_on(&self) -> bool {
+        self.control
+    }
+
+    #[inline(always)]
+    pub fn settings(&self) -> RelaySettings {
+        self.settings
+    }
+
+    /** Hub time of the last response or signal about the relay, `None` if nothing was got yet. */
+    #[inline(always)]
+    pub fn updated_at(&self) -> Option<RelativeMillis> {
+        self.updated_at
+    }
+
+    /** Hub time when any of the relay flags was seen changed last time. */
+    #[inline(always)]
+    pub fn changed_at(&self) -> Option<RelativeMillis> {
+        self.changed_at
+    }
+
+    fn flag(&mut self, flag: RelayFlag) -> &mut bool {
+        match flag {
+            RelayFlag::On => &mut self.on,
+            RelayFlag::Disabled => &mut self.disabled,
+            RelayFlag::Monitoring => &mut self.monitoring,
+            RelayFlag::Control => &mut self.control,
+        }
+    }
+
+    fn set_flag(&mut self, flag: RelayFlag, value: bool, now: RelativeMillis) {
+        let current = self.flag(flag);
+        if *current != value {
+            *current = value;
+            self.changed_at = Some(now);
+        }
+        self.updated_at = Some(now);
+    }
+}
+
+/**
+Last known state of one slave. Relay flags are updated from `State`, `All`, `RelayState` and single
+relay state responses and from the relay signals, pins - from `Settings` and `All` responses, the
+switch limit - from `SwitchCountingSettings` ones.
+Signals could be lost, so the snapshot is only fresh for a while after the last full (`State` or
+`All`) response, and it is invalidated when the slave restarts.
+ */
+pub struct SlaveStateMirror {
+    id: Option<u32>,
+    interrupt_pin: Option<u8>,
+    relays_count: u8,
+    relays: [RelayMirror; MAX_RELAYS_COUNT as usize],
+    switch_counting: Option<SwitchCountingSettings>,
+    synced_at: Option<RelativeMillis>,
+}
+
+impl SlaveStateMirror {
+
+    pub const fn new() -> Self {
+        Self {
+            id: None,
+            interrupt_pin: None,
+            relays_count: 0,
+            relays: [RelayMirror::new(); MAX_RELAYS_COUNT as usize],
+            switch_counting: None,
+            synced_at: None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    #[inline(always)]
+    pub fn interrupt_pin(&self) -> Option<u8> {
+        self.interrupt_pin
+    }
+
+    #[inline(always)]
+    pub fn relays_count(&self) -> u8 {
+        self.relays_count
+    }
+
+    pub fn relays(&self) -> &[RelayMirror] {
+        &self.relays[..self.relays_count as usize]
+    }
+
+    pub fn relay(&self, relay_index: u8) -> Option<&RelayMirror> {
+        self.relays().get(relay_index as usize)
+    }
+
+    /** Switch limit of the slave relays, `None` until it was read. */
+    #[inline(always)]
+    pub fn switch_counting_settings(&self) -> Option<&SwitchCountingSettings> {
+        self.switch_counting.as_ref()
+    }
+
+    /** Hub time of the last full state response, `None` if there was none since the start or restart. */
+    #[inline(always)]
+    pub fn synced_at(&self) -> Option<RelativeMillis> {
+        self.synced_at
+    }
+
+    pub fn is_stale(&self, now: RelativeMillis, max_age_ms: u32) -> bool {
+        match self.synced_at {
+            Some(synced_at) => now.value().wrapping_sub(synced_at.value()) > max_age_ms,
+            None => true,
+        }
+    }
+
+    /** Marks the snapshot stale, the relay data is kept until the next full response. */
+    #[inline(always)]
+    pub fn invalidate(&mut self) {
+        self.synced_at = None;
+    }
+
+    pub fn apply_response(&mut self, response: &DataInstructions, now: RelativeMillis) {
+        match response {
+            DataInstructions::State(Conversation::Data(state)) => {
+                self.relays_count = state.count;
+                for i in 0..state.count {
+                    let from = i * 4;
+                    if let Ok(flags) = state.data.bits_u8(from, from + 3) {
+                        self.apply_flags(i, flags, now);
+                    }
+                }
+                self.synced_at = Some(now);
+            }
+            // cached responses come as `DataCashed`, borrowing the parser buffer
+            DataInstructions::All(conversation) => {
+                if let Some(all_data) = conversation.data() {
+                    self.id = Some(all_data.id);
+                    self.interrupt_pin = Some(all_data.interrupt_pin);
+                    self.relays_count = all_data.relays_count;
+                    for i in 0..all_data.relays_count {
+                        self.relays[i as usize].settings = all_data.relays_settings[i as usize];
+                        let from = i * 4;
+                        if let Ok(flags) = all_data.state_data.bits_u8(from, from + 3) {
+                            self.apply_flags(i, flags, now);
+                        }
+                    }
+                    self.synced_at = Some(now);
+                }
+            }
+            DataInstructions::Settings(conversation) => {
+                if let Some(settings) = conversation.data() {
+                    self.relays_count = settings.relays_count;
+                    for (i, relay_settings) in settings.get_relays().iter().enumerate() {
+                        self.relays[i].settings = *relay_settings;
+                    }
+                }
+            }
+            DataInstructions::RelayState(Conversation::Data(state)) => {
+                let index = state.relay_index();
+                self.set_flag(index, RelayFlag::On, state.is_on(), now);
+                self.set_flag(index, RelayFlag::Disabled, state.is_disabled(), now);
+                self.set_flag(index, RelayFlag::Monitoring, state.is_monitoring_on(), now);
+                self.set_flag(index, RelayFlag::Control, state.is_control_on(), now);
+            }
+            DataInstructions::RelaySwitchedOn(Conversation::Data(state)) => {
+                self.set_flag(state.relay_index(), RelayFlag::On, state.is_set(), now);
+            }
+            DataInstructions::RelayDisabledTemp(Conversation::Data(state)) => {
+                self.set_flag(state.relay_index(), RelayFlag::Disabled, state.is_set(), now);
+            }
+            DataInstructions::RelayMonitorOn(Conversation::Data(state)) => {
+                self.set_flag(state.relay_index(), RelayFlag::Monitoring, state.is_set(), now);
+            }
+            DataInstructions::RelayControlOn(Conversation::Data(state)) => {
+                self.set_flag(state.relay_index(), RelayFlag::Control, state.is_set(), now);
+            }
+            DataInstructions::SwitchCountingSettings(conversation) => {
+                if let Some(settings) = conversation.data() {
+                    self.switch_counting = Some(settings.clone());
+                }
+            }
+            DataInstructions::Id(Conversation::Data(id)) => {
+                self.id = Some(*id);
+            }
+            DataInstructions::InterruptPin(Conversation::Data(pin)) => {
+                self.interrupt_pin = Some(*pin);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn apply_signal(&mut self, signal: &SignalData, now: RelativeMillis) {
+        match signal {
+            // the slave asks for the timestamp after its restart, so all its relays were reset
+            SignalData::GetTimeStamp => self.invalidate(),
+            SignalData::RelayStateChanged(data) => {
+                self.set_flag(data.get_relay_idx(), RelayFlag::On, data.is_on(), now);
+            }
+            SignalData::MonitoringStateChanged(data) => {
+                self.set_flag(data.get_relay_idx(), RelayFlag::Monitoring, data.is_on(), now);
+            }
+            SignalData::ControlStateChanged(data) => {
+                self.set_flag(data.get_relay_idx(), RelayFlag::Control, data.is_on(), now);
+            }
+            SignalData::StateFixTry(_) => {}
+        }
+    }
+
+    /** Relay state nibble of `State` and `All` data: on, disabled, monitoring and control bits. */
+    fn apply_flags(&mut self, relay_index: u8, flags: u8, now: RelativeMillis) {
+        self.set_flag(relay_index, RelayFlag::On, flags & 0x01 != 0, now);
+        self.set_flag(relay_index, RelayFlag::Disabled, flags & 0x02 != 0, now);
+        self.set_flag(relay_index, RelayFlag::Monitoring, flags & 0x04 != 0, now);
+        self.set_flag(relay_index, RelayFlag::Control, flags & 0x08 != 0, now);
+    }
+
+    fn set_flag(&mut self, relay_index: u8, flag: RelayFlag, value: bool, now: RelativeMillis) {
+        if let Some(relay) = self.relays.get_mut(relay_index as usize) {
+            relay.set_flag(flag, value, now);
+        }
+    }
+}
+
+impl Default for SlaveStateMirror {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+Keeps the mirror up to date with the responses. The request send time is used as the update time,
+as the response one is not known here.
+ */
+pub struct MirrorResponseHandler<RH: ResponseHandler> {
+    response_handler: RH,
+    mirror: SlaveStateMirror,
+}
+
+impl <RH: ResponseHandler> MirrorResponseHandler<RH> {
+
+    pub fn new(response_handler: RH) -> Self {
+        Self {
+            response_handler,
+            mirror: SlaveStateMirror::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn response_handler(&mut self) -> &mut RH {
+        &mut self.response_handler
+    }
+
+    #[inline(always)]
+    pub fn mirror(&self) -> &SlaveStateMirror {
+        &self.mirror
+    }
+
+    #[inline(always)]
+    pub fn mirror_mut(&mut self) -> &mut SlaveStateMirror {
+        &mut self.mirror
+    }
+}
+
+impl <RH: ResponseHandler> ResponseHandler for MirrorResponseHandler<RH> {
+
+    fn on_request_success(&mut self, request: SentRequest) {
+        self.response_handler.on_request_success(request);
+    }
+
+    fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+        self.mirror.apply_response(&response, request.rel_timestamp());
+        self.response_handler.on_request_response(request, response);
+    }
+
+    fn on_request_error(&mut self, request: SentRequest, error_code: ErrorCode) {
+        self.response_handler.on_request_error(request, error_code);
+    }
+
+    fn on_request_parse_error(&mut self, request: Option<SentRequest>, error: Errors, data: &[u8]) {
+        self.response_handler.on_request_parse_error(request, error, data);
+    }
+
+    fn on_request_search_error(&mut self, payload: ResponseData, error: Errors) {
+        self.response_handler.on_request_search_error(payload, error);
+    }
+
+    fn on_request_timeout(&mut self, request: SentRequest) {
+        self.response_handler.on_request_timeout(request);
+    }
+}
+
+/**
+Collects the signals for the mirror, which is owned by the responses side of the link, so they are
+applied with `apply_pending` after the command processing. Lost signals invalidate the mirror.
+ */
+pub struct MirrorSignalsHandler<SH: SignalsHandler> {
+    signals_handler: SH,
+    pending: [Option<SignalData>; PENDING_SIGNALS_COUNT],
+    pending_count: usize,
+    overflowed: bool,
+}
+
+impl <SH: SignalsHandler> MirrorSignalsHandler<SH> {
+
+    pub fn new(signals_handler: SH) -> Self {
+        Self {
+            signals_handler,
+            pending: [None; PENDING_SIGNALS_COUNT],
+            pending_count: 0,
+            overflowed: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn signals_handler(&mut self) -> &mut SH {
+        &mut self.signals_handler
+    }
+
+    pub fn apply_pending(&mut self, mirror: &mut SlaveStateMirror, now: RelativeMillis) {
+        for signal in self.pending[..self.pending_count].iter_mut() {
+            if let Some(signal) = signal.take() {
+                mirror.apply_signal(&signal, now);
+            }
+        }
+        self.pending_count = 0;
+        if core::mem::replace(&mut self.overflowed, false) {
+            mirror.invalidate();
+        }
+    }
+
+    fn push(&mut self, signal: SignalData) {
+        if self.pending_count < PENDING_SIGNALS_COUNT {
+            self.pending[self.pending_count] = Some(signal);
+            self.pending_count += 1;
+        } else {
+            self.overflowed = true;
+        }
+    }
+}
+
+impl <SH: SignalsHandler> SignalsHandler for MirrorSignalsHandler<SH> {
+
+    fn on_signal(&mut self, signal_data: SignalData, processed_successfully: bool) {
+        self.push(signal_data);
+        self.signals_handler.on_signal(signal_data, processed_successfully);
+    }
+
+    fn on_signal_parse_error(&mut self, error: Errors, sent_to_slave_success: bool, data: &[u8]) {
+        self.signals_handler.on_signal_parse_error(error, sent_to_slave_success, data);
+    }
+
+    fn on_signal_process_error(&mut self, error: Errors, sent_to_slave_success: bool, data: SignalData) {
+        self.push(data);
+        self.signals_handler.on_signal_process_error(error, sent_to_slave_success, data);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+    use rand::prelude::*;
+    use crate::hal_ext::rtc_wrapper::RelativeSeconds;
+    use crate::utils::BitsU64;
+    use crate::services::slave_controller_link::domain::{AllData, DataInstructionCodes, Operation, RelaySignalData, RelaySignalDataExt, RelaySingleState, RelayState, State};
+
+    #[test]
+    fn test_state_response_updates_relays() {
+        let mut tested = MirrorResponseHandler::new(MockResponsesHandler::new());
+        let now = RelativeMillis::new(1000);
+        // relay 0 - on, relay 1 - disabled and monitored, relay 2 - controlled
+        let state = State { data: BitsU64::new(0x0861), count: 3 };
+
+        tested.on_request_response(request(DataInstructionCodes::State, now),
+                                   DataInstructions::State(Conversation::Data(state)));
+
+        let mirror = tested.mirror();
+        assert_eq!(3, mirror.relays_count());
+        assert_eq!(Some(now), mirror.synced_at());
+        let relay = mirror.relay(0).unwrap();
+        assert!(relay.is_on() && !relay.is_disabled() && !relay.is_monitoring_on() && !relay.is_control_on());
+        let relay = mirror.relay(1).unwrap();
+        assert!(!relay.is_on() && relay.is_disabled() && relay.is_monitoring_on() && !relay.is_control_on());
+        let relay = mirror.relay(2).unwrap();
+        assert!(!relay.is_on() && !relay.is_disabled() && !relay.is_monitoring_on() && relay.is_control_on());
+        assert_eq!(Some(now), relay.changed_at());
+        assert_eq!(None, mirror.relay(3));
+        assert_eq!(1, tested.response_handler.on_request_response_params.len());
+    }
+
+    #[test]
+    fn test_all_data_response_updates_pins() {
+        let mut rng = rand::thread_rng();
+        let mut tested = SlaveStateMirror::new();
+        let now = RelativeMillis::new(rng.next_u32());
+        let mut all_data = AllData::new(rng.next_u32(), rng.gen());
+        let mut pins = Vec::new();
+        for _ in 0..rng.gen_range(1..MAX_RELAYS_COUNT) {
+            let relay_pins: (u8, u8, u8) = (rng.gen(), rng.gen(), rng.gen());
+            all_data.add(relay_pins.0, relay_pins.1, relay_pins.2, 0x01).unwrap();
+            pins.push(relay_pins);
+        }
+
+        tested.apply_response(&DataInstructions::All(Conversation::DataCashed(&mut all_data)), now);
+
+        assert_eq!(pins.len(), tested.relays().len());
+        for (relay, (set_pin, monitor_pin, control_pin)) in tested.relays().iter().zip(pins) {
+            assert_eq!(set_pin, relay.settings().set_pin().data());
+            assert_eq!(monitor_pin, relay.settings().monitor_pin().data());
+            assert_eq!(control_pin, relay.settings().control_pin().data());
+            assert!(relay.is_on());
+        }
+        assert!(tested.id().is_some());
+        assert!(tested.interrupt_pin().is_some());
+        assert!(!tested.is_stale(now, 0));
+    }
+
+    #[test]
+    fn test_single_relay_responses_keep_changed_at_of_unchanged_relays() {
+        let mut tested = SlaveStateMirror::new();
+        let synced = RelativeMillis::new(100);
+        let later = RelativeMillis::new(200);
+        tested.apply_response(&DataInstructions::State(Conversation::Data(State { data: BitsU64::new(0x11), count: 2 })), synced);
+
+        tested.apply_response(&DataInstructions::RelayState(Conversation::Data(
+            RelayState::create(0, true, false).unwrap())), later);
+        tested.apply_response(&DataInstructions::RelaySwitchedOn(Conversation::Data(
+            RelaySingleState::new(1, false))), later);
+
+        let relay = tested.relay(0).unwrap();
+        assert!(relay.is_on());
+        assert_eq!(Some(later), relay.updated_at());
+        assert_eq!(Some(synced), relay.changed_at());
+        let relay = tested.relay(1).unwrap();
+        assert!(!relay.is_on());
+        assert_eq!(Some(later), relay.changed_at());
+        assert_eq!(Some(synced), tested.synced_at());
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let mut tested = SlaveStateMirror::new();
+        assert!(tested.is_stale(RelativeMillis::new(0), u32::MAX));
+
+        tested.apply_response(&DataInstructions::State(Conversation::Data(State::create(1, 0).unwrap())),
+                              RelativeMillis::new(u32::MAX - 10));
+
+        assert!(!tested.is_stale(RelativeMillis::new(9), 20));
+        assert!(tested.is_stale(RelativeMillis::new(10), 20));
+
+        tested.invalidate();
+        assert!(tested.is_stale(RelativeMillis::new(u32::MAX - 10), 20));
+        assert_eq!(1, tested.relays_count());
+    }
+
+    #[test]
+    fn test_signals_are_applied_after_processing() {
+        let mut tested = MirrorSignalsHandler::new(MockSignalsHandler::new());
+        let mut mirror = SlaveStateMirror::new();
+        let now = RelativeMillis::new(500);
+        mirror.apply_response(&DataInstructions::State(Conversation::Data(State::create(2, 0).unwrap())),
+                              RelativeMillis::new(0));
+
+        tested.on_signal(SignalData::RelayStateChanged(RelaySignalDataExt::new(
+            RelativeSeconds::new(1), 0, true, false)), false);
+        tested.on_signal(SignalData::MonitoringStateChanged(RelaySignalData::new(
+            RelativeSeconds::new(1), 1, true)), false);
+        tested.on_signal_process_error(Errors::NotEnoughDataGot, false, SignalData::ControlStateChanged(
+            RelaySignalData::new(RelativeSeconds::new(1), 1, true)));
+        assert!(!mirror.relay(0).unwrap().is_on());
+
+        tested.apply_pending(&mut mirror, now);
+
+        assert!(mirror.relay(0).unwrap().is_on());
+        assert!(mirror.relay(1).unwrap().is_monitoring_on());
+        assert!(mirror.relay(1).unwrap().is_control_on());
+        assert_eq!(Some(now), mirror.relay(1).unwrap().changed_at());
+        assert!(!mirror.is_stale(now, 1000));
+        assert_eq!(2, tested.signals_handler.on_signal_params.len());
+        assert_eq!(1, tested.signals_handler.on_signal_process_error_params.len());
+    }
+
+    #[test]
+    fn test_restart_and_lost_signals_invalidate_mirror() {
+        let now = RelativeMillis::new(0);
+        for signals_lost in [false, true] {
+            let mut tested = MirrorSignalsHandler::new(MockSignalsHandler::new());
+            let mut mirror = SlaveStateMirror::new();
+            mirror.apply_response(&DataInstructions::State(Conversation::Data(State::create(1, 0).unwrap())), now);
+            if signals_lost {
+                for _ in 0..PENDING_SIGNALS_COUNT + 1 {
+                    tested.on_signal(SignalData::StateFixTry(RelaySignalData::new(RelativeSeconds::new(1), 0, true)), false);
+                }
+            } else {
+                tested.on_signal(SignalData::GetTimeStamp, true);
+            }
+
+            tested.apply_pending(&mut mirror, now);
+            assert!(mirror.is_stale(now, 1000));
+
+            mirror.apply_response(&DataInstructions::State(Conversation::Data(State::create(1, 0).unwrap())), now);
+            tested.apply_pending(&mut mirror, now);
+            assert!(!mirror.is_stale(now, 1000));
+        }
+    }
+
+    fn request(instruction: DataInstructionCodes, timestamp: RelativeMillis) -> SentRequest {
+        SentRequest::new(None, Operation::Read, instruction, timestamp)
+    }
+
+    struct MockResponsesHandler {
+        on_request_response_params: Vec<(SentRequest, DataInstructions<'static>)>,
+    }
+
+    impl MockResponsesHandler {
+        pub fn new() -> Self {
+            Self {
+                on_request_response_params: Vec::new(),
+            }
+        }
+    }
+
+    impl ResponseHandler for MockResponsesHandler {
+        fn on_request_success(&mut self, _: SentRequest) {}
+
+        fn on_request_response(&mut self, request: SentRequest, response: DataInstructions) {
+            self.on_request_response_params.push((request, response.into_owned()));
+        }
+
+        fn on_request_error(&mut self, _: SentRequest, _: ErrorCode) {}
+
+        fn on_request_parse_error(&mut self, _: Option<SentRequest>, _: Errors, _: &[u8]) {}
+
+        fn on_request_search_error(&mut self, _: ResponseData, _: Errors) {}
+
+        fn on_request_timeout(&mut self, _: SentRequest) {}
+    }
+
+    struct MockSignalsHandler {
+        on_signal_params: Vec<(SignalData, bool)>,
+        on_signal_process_error_params: Vec<(Errors, bool, SignalData)>,
+    }
+
+    impl MockSignalsHandler {
+        pub fn new() -> Self {
+            Self {
+                on_signal_params: Vec::new(),
+                on_signal_process_error_params: Vec::new(),
+            }
+        }
+    }
+
+    impl SignalsHandler for MockSignalsHandler {
+        fn on_signal(&mut self, signal_data: SignalData, processed_successfully: bool) {
+            self.on_signal_params.push((signal_data, processed_successfully));
+        }
+
+        fn on_signal_parse_error(&mut self, _: Errors, _: bool, _: &[u8]) {}
+
+        fn on_signal_process_error(&mut self, error: Errors, sent_to_slave_success: bool, data: SignalData) {
+            self.on_signal_process_error_params.push((error, sent_to_slave_success, data));
+        }
+    }
+}
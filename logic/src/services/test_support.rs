@@ -0,0 +1,242 @@
+#![deny(unsafe_code)]
+
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::{RelativeMillis, RelativeTimestampSource, Rtc};
+use crate::services::slave_clock::SlaveClock;
+use crate::services::slave_controller_link::SlaveLink;
+use crate::services::slave_controller_link::connection_monitor::{ConnectionState, ConnectionTransition};
+use crate::services::slave_controller_link::domain::{Commands, Conversation, DataInstruction, DataInstructionCodes,
+                                                     DataInstructions, Operation, State, SwitchCountingSettings,
+                                                     Version};
+use crate::services::slave_state_mirror::SlaveStateMirror;
+use crate::utils::BitsU64;
+
+/** Operation, instruction and the relay index with the flag value of the relay instructions. */
+pub type SentRelayRequest = (Operation, DataInstructionCodes, Option<(u8, bool)>);
+
+/** Request or command recorded by the `MockLink`. */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MockRequest {
+    pub operation: Operation,
+    pub instruction: DataInstructionCodes,
+    pub relay: Option<(u8, bool)>,
+    pub at: RelativeMillis,
+}
+
+/**
+Slave link of the service tests. The requests and commands are recorded, the ones sent while the link
+reports V2 or a later version get the ids counted from 1, as the real link gives them.
+ */
+pub struct MockLink {
+    pub mirror: SlaveStateMirror,
+    pub slave_clock: SlaveClock,
+    /** Reported one by one on each call, the last one is kept. */
+    pub versions: Vec<Option<Version>>,
+    pub requests: Vec<MockRequest>,
+    /** The state while there are no transitions, otherwise the one the last of them leads to. */
+    pub connection: ConnectionState,
+    pub transitions: Vec<ConnectionTransition>,
+    /** Flags nibbles of the slave relays, when set, the state reads are answered with them. */
+    pub actual: Option<u64>,
+    /** The switches change the actual flags. */
+    pub obeying: bool,
+    pub on_get_command_count: u32,
+    pub on_rx_dma_interrupts_count: u32,
+    pub on_tx_dma_interrupts_count: u32,
+    pub poll_timeouts_count: u32,
+}
+
+impl MockLink {
+
+    pub fn new(id: Option<u32>) -> Self {
+        let mut mirror = SlaveStateMirror::new();
+        if let Some(id) = id {
+            mirror.apply_response(&DataInstructions::Id(Conversation::Data(id)), RelativeMillis::new(0));
+        }
+        Self {
+            mirror,
+            slave_clock: SlaveClock::new(),
+            versions: vec![None],
+            requests: Vec::new(),
+            connection: ConnectionState::Online,
+            transitions: Vec::new(),
+            actual: None,
+            obeying: false,
+            on_get_command_count: 0,
+            on_rx_dma_interrupts_count: 0,
+            on_tx_dma_interrupts_count: 0,
+            poll_timeouts_count: 0,
+        }
+    }
+
+    pub fn with_versions(mut self, versions: &[Option<Version>]) -> Self {
+        self.versions = versions.to_vec();
+        self
+    }
+
+    /** The slave reports the relays all off. */
+    pub fn with_relays(mut self, relays_count: u8) -> Self {
+        let state = State::create(relays_count, 0).unwrap();
+        self.mirror.apply_response(&DataInstructions::State(Conversation::Data(state)), RelativeMillis::new(0));
+        self
+    }
+
+    pub fn with_slave(mut self, actual: u64, obeying: bool, switch_counting: SwitchCountingSettings) -> Self {
+        self.actual = Some(actual);
+        self.obeying = obeying;
+        self.mirror.apply_response(&DataInstructions::SwitchCountingSettings(Conversation::Data(switch_counting)),
+                                   RelativeMillis::new(0));
+        self
+    }
+
+    pub fn sent_requests(&self) -> Vec<(Operation, DataInstructionCodes, RelativeMillis)> {
+        self.requests.iter().map(|request| (request.operation, request.instruction, request.at)).collect()
+    }
+
+    pub fn sent_relay_requests(&self) -> Vec<SentRelayRequest> {
+        self.requests.iter().map(|request| (request.operation, request.instruction, request.relay)).collect()
+    }
+
+    /** Relay index and the state of the sent switchings. */
+    pub fn switches(&self) -> Vec<(u8, bool)> {
+        self.requests.iter()
+            .filter(|request| request.operation == Operation::Set && request.instruction == DataInstructionCodes::RelaySwitchedOn)
+            .filter_map(|request| request.relay)
+            .collect()
+    }
+
+    pub fn interrupts_counts(&self) -> (u32, u32, u32) {
+        (self.on_get_command_count, self.on_rx_dma_interrupts_count, self.on_tx_dma_interrupts_count)
+    }
+
+    fn record(&mut self, operation: Operation, instruction: DataInstructionCodes, relay: Option<(u8, bool)>,
+              at: RelativeMillis) -> Option<u32> {
+        self.requests.push(MockRequest { operation, instruction, relay, at });
+        match self.versions.last() {
+            Some(Some(Version::V2)) | Some(Some(Version::V3)) => Some(self.requests.len() as u32),
+            _ => None,
+        }
+    }
+
+    fn answer_slave(&mut self, instruction: &DataInstructions, now: RelativeMillis) {
+        let actual = match &mut self.actual {
+            Some(actual) => actual,
+            None => return,
+        };
+        match instruction {
+            DataInstructions::State(Conversation::Request(_)) => {
+                let state = State { data: BitsU64::new(*actual), count: self.mirror.relays_count() };
+                self.mirror.apply_response(&DataInstructions::State(Conversation::Data(state)), now);
+            }
+            DataInstructions::RelaySwitchedOn(Conversation::Data(state)) if self.obeying => {
+                let bit = 1 << (state.relay_index() * 4);
+                *actual = if state.is_set() { *actual | bit } else { *actual & !bit };
+            }
+            _ => {}
+        }
+    }
+}
+
+impl SlaveLink for MockLink {
+    fn on_get_command(&mut self, _: &mut dyn RelativeTimestampSource) {
+        self.on_get_command_count += 1;
+    }
+
+    fn on_rx_dma_interrupts(&mut self) {
+        self.on_rx_dma_interrupts_count += 1;
+    }
+
+    fn on_tx_dma_interrupts(&mut self) {
+        self.on_tx_dma_interrupts_count += 1;
+    }
+
+    fn poll_timeouts(&mut self, _: &mut dyn RelativeTimestampSource) {
+        self.poll_timeouts_count += 1;
+    }
+
+    fn send_request(&mut self, operation: Operation, instruction: DataInstructions, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
+        self.answer_slave(&instruction, timestamp);
+        let relay = match &instruction {
+            DataInstructions::RelaySwitchedOn(conversation) | DataInstructions::RelayDisabledTemp(conversation) |
+            DataInstructions::RelayMonitorOn(conversation) | DataInstructions::RelayControlOn(conversation) =>
+                conversation.data().map(|state| (state.relay_index(), state.is_set())),
+            _ => None,
+        };
+        Ok(self.record(operation, instruction.code(), relay, timestamp))
+    }
+
+    fn send_command(&mut self, command: Commands, timestamp: RelativeMillis) -> Result<Option<u32>, Errors> {
+        Ok(self.record(Operation::Command, command.code(), None, timestamp))
+    }
+
+    fn version(&mut self) -> Option<Version> {
+        if self.versions.len() > 1 {
+            self.versions.remove(0)
+        } else {
+            self.versions[0]
+        }
+    }
+
+    fn state_mirror(&mut self) -> &SlaveStateMirror {
+        &self.mirror
+    }
+
+    fn slave_clock(&mut self) -> &SlaveClock {
+        &self.slave_clock
+    }
+
+    fn connection_state(&mut self) -> ConnectionState {
+        self.transitions.last().map(|transition| transition.to()).unwrap_or(self.connection)
+    }
+
+    fn take_connection_transition(&mut self) -> Option<ConnectionTransition> {
+        if self.transitions.is_empty() {
+            None
+        } else {
+            Some(self.transitions.remove(0))
+        }
+    }
+}
+
+/** RTC of the tests, shared by `Rc` with the time source, which owns it. */
+pub struct MockRtc {
+    pub date_time: PrimitiveDateTime,
+}
+
+impl MockRtc {
+
+    pub fn new(date_time: PrimitiveDateTime) -> Self {
+        Self { date_time }
+    }
+
+    pub fn from_unix_timestamp(unix_seconds: i64) -> Self {
+        let date_time = OffsetDateTime::from_unix_timestamp(unix_seconds).unwrap();
+        Self::new(PrimitiveDateTime::new(date_time.date(), date_time.time()))
+    }
+
+    pub fn pass(&mut self, millis: u32) {
+        self.date_time += Duration::milliseconds(millis as i64);
+    }
+}
+
+impl Rtc for Rc<RefCell<MockRtc>> {
+    type Error = Errors;
+
+    fn get_datetime(&mut self) -> PrimitiveDateTime {
+        self.borrow().date_time
+    }
+
+    // as the real one, in the range of the two-digit years since 2000
+    fn set_datetime(&mut self, date: &PrimitiveDateTime) -> Result<(), Self::Error> {
+        if date.year() < 2000 || date.year() > 2099 {
+            return Err(Errors::OutOfRange);
+        }
+        self.borrow_mut().date_time = *date;
+        Ok(())
+    }
+}
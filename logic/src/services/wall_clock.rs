@@ -0,0 +1,330 @@
+#![deny(unsafe_code)]
+
+use serde_derive::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use crate::errors::Errors;
+use crate::hal_ext::rtc_wrapper::{DateTimeSource, RelativeMillis, RelativeTimestampSource, Rtc};
+use crate::services::slave_clock::HubTimestamp;
+
+/** The host syncs closer than this are not used for the drift estimate, their error would prevail. */
+pub const MIN_CALIBRATION_INTERVAL_MS: i64 = 3_600_000;
+/** A larger measured drift means the host clock was stepped, the measurement is dropped. */
+pub const MAX_DRIFT_PPM: i64 = 500;
+/** The sync point is moved forward after this period, to keep the local differences in the `i32` range. */
+const SYNC_POINT_PERIOD_MS: u32 = 86_400_000;
+/** Weight of the previous estimate against the new measurement. */
+const DRIFT_FILTER_WEIGHT: i32 = 4;
+const PPM: i64 = 1_000_000;
+const NANOS_PER_MILLI: i128 = 1_000_000;
+
+/** A moment of the UTC time, which is known to be within `uncertainty_ms` from `unix_millis`. */
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct UtcTimestamp {
+    unix_millis: i64,
+    uncertainty_ms: u32,
+}
+
+impl UtcTimestamp {
+
+    pub fn new(unix_millis: i64, uncertainty_ms: u32) -> Self {
+        Self { unix_millis, uncertainty_ms }
+    }
+
+    #[inline(always)]
+    pub fn unix_millis(&self) -> i64 {
+        self.unix_millis
+    }
+
+    #[inline(always)]
+    pub fn uncertainty_ms(&self) -> u32 {
+        self.uncertainty_ms
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct SyncPoint {
+    timestamp: RelativeMillis,
+    unix_millis: i64,
+}
+
+/**
+UTC time of the hub. The host syncs it with the absolute time, the RTC is set to the time then, and
+the drift of the RTC against the host is estimated from the syncs far enough apart. The relative
+timestamps go on without a gap over the syncs, so the ones of the sent requests and of the slave signals
+keep valid, and they are converted to the dates through the last sync, corrected by the drift. Until
+the first sync the dates are the ones of the RTC.
+ */
+pub struct WallClock<RTC: Rtc> {
+    source: DateTimeSource<RTC>,
+    last_sync: Option<SyncPoint>,
+    calibration_start: Option<SyncPoint>,
+    drift_ppm: Option<i32>,
+}
+
+impl <RTC: Rtc> WallClock<RTC> {
+
+    pub fn new(source: DateTimeSource<RTC>) -> Self {
+        Self {
+            source,
+            last_sync: None,
+            calibration_start: None,
+            drift_ppm: None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_synced(&self) -> bool {
+        self.last_sync.is_some()
+    }
+
+    /** The RTC runs faster than the host clock by the returned parts per million, `None` until it is known. */
+    #[inline(always)]
+    pub fn drift_ppm(&self) -> Option<i32> {
+        self.drift_ppm
+    }
+
+    /** Sets the RTC to a date, which is not known to be real, e.g. the configured one after the battery loss. */
+    pub fn preset(&mut self, unix_seconds: i64) -> Result<(), Errors> {
+        let date_time = OffsetDateTime::from_unix_timestamp(unix_seconds).map_err(|_| Errors::OutOfRange)?;
+        self.set_rtc(date_time)
+    }
+
+    /** Sets the time got from the host. Returns the time of the hub just before. */
+    pub fn sync(&mut self, unix_millis: i64) -> Result<i64, Errors> {
+        let date_time = OffsetDateTime::from_unix_timestamp_nanos(unix_millis as i128 * NANOS_PER_MILLI)
+            .map_err(|_| Errors::OutOfRange)?;
+        let timestamp = self.get();
+        let previous = self.unix_millis_at(timestamp).unwrap_or_default();
+        self.set_rtc(date_time)?;
+        let sync_point = SyncPoint { timestamp, unix_millis };
+        self.calibrate(sync_point);
+        self.last_sync = Some(sync_point);
+        Ok(previous)
+    }
+
+    pub fn now(&mut self) -> OffsetDateTime {
+        let timestamp = self.get();
+        self.to_date_time(timestamp).unwrap_or_else(|| self.source.get_datetime().assume_utc())
+    }
+
+    pub fn now_unix_millis(&mut self) -> i64 {
+        let timestamp = self.get();
+        // the base of the relative time is set by the get
+        self.unix_millis_at(timestamp).unwrap_or_default()
+    }
+
+    /** `None` if no relative timestamp was given yet, so the relative time has no base. */
+    pub fn unix_millis_at(&self, timestamp: RelativeMillis) -> Option<i64> {
+        match self.last_sync {
+            Some(sync) => {
+                let local_elapsed = timestamp.value().wrapping_sub(sync.timestamp.value()) as i32 as i64;
+                Some(sync.unix_millis + self.to_host_elapsed(local_elapsed))
+            }
+            None => self.source.relative_timestamp_to_date_time(timestamp)
+                .map(|date_time| (date_time.assume_utc().unix_timestamp_nanos() / NANOS_PER_MILLI) as i64),
+        }
+    }
+
+    pub fn to_date_time(&self, timestamp: RelativeMillis) -> Option<OffsetDateTime> {
+        self.unix_millis_at(timestamp)
+            .and_then(|unix_millis| OffsetDateTime::from_unix_timestamp_nanos(unix_millis as i128 * NANOS_PER_MILLI).ok())
+    }
+
+    /** The uncertainty of the hub time is kept, the one of the host sync is not known. */
+    pub fn to_utc(&self, timestamp: HubTimestamp) -> Option<UtcTimestamp> {
+        self.unix_millis_at(timestamp.timestamp())
+            .map(|unix_millis| UtcTimestamp::new(unix_millis, timestamp.uncertainty_ms()))
+    }
+
+    /** `None` if the date is too far from the last sync or before the base of the relative time. */
+    pub fn to_relative(&self, date_time: OffsetDateTime) -> Option<RelativeMillis> {
+        let unix_millis = (date_time.unix_timestamp_nanos() / NANOS_PER_MILLI) as i64;
+        match self.last_sync {
+            Some(sync) => {
+                let local_elapsed = self.to_local_elapsed(unix_millis - sync.unix_millis);
+                if local_elapsed < i32::MIN as i64 || local_elapsed > i32::MAX as i64 {
+                    return None;
+                }
+                Some(RelativeMillis::new(sync.timestamp.value().wrapping_add(local_elapsed as i32 as u32)))
+            }
+            None => {
+                let base = self.source.relative_timestamp_to_date_time(RelativeMillis::new(0))?;
+                let elapsed = (date_time - base.assume_utc()).whole_milliseconds();
+                if elapsed < 0 || elapsed > u32::MAX as i128 {
+                    return None;
+                }
+                Some(RelativeMillis::new(elapsed as u32))
+            }
+        }
+    }
+
+    fn set_rtc(&mut self, date_time: OffsetDateTime) -> Result<(), Errors> {
+        self.source.set_datetime(PrimitiveDateTime::new(date_time.date(), date_time.time()))
+            .map(|_| ())
+            .map_err(|_| Errors::OutOfRange)
+    }
+
+    fn calibrate(&mut self, sync_point: SyncPoint) {
+        let start = match self.calibration_start {
+            Some(start) => start,
+            None => {
+                self.calibration_start = Some(sync_point);
+                return;
+            }
+        };
+        let host_elapsed = sync_point.unix_millis - start.unix_millis;
+        if host_elapsed < MIN_CALIBRATION_INTERVAL_MS {
+            return;
+        }
+        self.calibration_start = Some(sync_point);
+        // the relative time could wrap since the start
+        if host_elapsed > i32::MAX as i64 {
+            return;
+        }
+        let local_elapsed = sync_point.timestamp.value().wrapping_sub(start.timestamp.value()) as i64;
+        let measured = (local_elapsed - host_elapsed) * PPM / host_elapsed;
+        if measured.abs() > MAX_DRIFT_PPM {
+            return;
+        }
+        let measured = measured as i32;
+        self.drift_ppm = Some(match self.drift_ppm {
+            Some(drift_ppm) => drift_ppm + (measured - drift_ppm) / DRIFT_FILTER_WEIGHT,
+            None => measured,
+        });
+    }
+
+    fn to_host_elapsed(&self, local_elapsed: i64) -> i64 {
+        match self.drift_ppm {
+            Some(drift_ppm) => local_elapsed * PPM / (PPM + drift_ppm as i64),
+            None => local_elapsed,
+        }
+    }
+
+    fn to_local_elapsed(&self, host_elapsed: i64) -> i64 {
+        match self.drift_ppm {
+            Some(drift_ppm) => host_elapsed * (PPM + drift_ppm as i64) / PPM,
+            None => host_elapsed,
+        }
+    }
+
+    fn move_sync_point(&mut self, timestamp: RelativeMillis) {
+        if let Some(sync) = self.last_sync {
+            if timestamp.value().wrapping_sub(sync.timestamp.value()) >= SYNC_POINT_PERIOD_MS {
+                if let Some(unix_millis) = self.unix_millis_at(timestamp) {
+                    self.last_sync = Some(SyncPoint { timestamp, unix_millis });
+                }
+            }
+        }
+    }
+
+}
+
+impl <RTC: Rtc> RelativeTimestampSource for WallClock<RTC> {
+    fn get(&mut self) -> RelativeMillis {
+        let timestamp = self.source.get_relative_timestamp();
+        self.move_sync_point(timestamp);
+        timestamp
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::services::test_support::MockRtc;
+
+    const START_UNIX_SECONDS: i64 = 1_686_644_790;
+
+    #[test]
+    fn test_dates_follow_rtc_until_synced() {
+        let rtc = Rc::new(RefCell::new(MockRtc::from_unix_timestamp(START_UNIX_SECONDS)));
+        let mut tested = WallClock::new(DateTimeSource::new(rtc.clone()));
+
+        assert_eq!(None, tested.to_date_time(RelativeMillis::new(0)));
+        assert_eq!(START_UNIX_SECONDS * 1000, tested.now_unix_millis());
+        rtc.borrow_mut().pass(10_500);
+
+        let now = tested.get();
+        assert_eq!(RelativeMillis::new(10_500), now);
+        assert_eq!(Some(UtcTimestamp::new(START_UNIX_SECONDS * 1000 + 10_500, 7)),
+                   tested.to_utc(HubTimestamp::new(now, 7)));
+        let date_time = tested.now();
+        assert_eq!(Some(now), tested.to_relative(date_time));
+        assert!(!tested.is_synced());
+    }
+
+    #[test]
+    fn test_sync_keeps_relative_time() {
+        let rtc = Rc::new(RefCell::new(MockRtc::from_unix_timestamp(START_UNIX_SECONDS)));
+        let mut tested = WallClock::new(DateTimeSource::new(rtc.clone()));
+        let sent_at = tested.get();
+        rtc.borrow_mut().pass(2_000);
+        let host_unix_millis = 1_700_000_000_123;
+
+        assert_eq!(Ok(START_UNIX_SECONDS * 1000 + 2_000), tested.sync(host_unix_millis));
+        rtc.borrow_mut().pass(3_000);
+
+        assert_eq!(RelativeMillis::new(5_000), tested.get());
+        assert_eq!(Some(host_unix_millis - 2_000), tested.unix_millis_at(sent_at));
+        assert_eq!(host_unix_millis + 3_000, tested.now_unix_millis());
+        assert_eq!(Some(RelativeMillis::new(2_000)),
+                   tested.to_relative(OffsetDateTime::from_unix_timestamp_nanos(host_unix_millis as i128 * 1_000_000).unwrap()));
+        assert!(tested.is_synced());
+        assert_eq!(None, tested.drift_ppm());
+    }
+
+    #[test]
+    fn test_drift_is_estimated_from_distant_syncs() {
+        let rtc = Rc::new(RefCell::new(MockRtc::from_unix_timestamp(START_UNIX_SECONDS)));
+        let mut tested = WallClock::new(DateTimeSource::new(rtc.clone()));
+        let mut host_unix_millis = 1_700_000_000_000;
+        tested.get();
+        tested.sync(host_unix_millis).unwrap();
+
+        // the RTC runs 100 ppm fast
+        for _ in 0..2 {
+            rtc.borrow_mut().pass(MIN_CALIBRATION_INTERVAL_MS as u32 + 360);
+            host_unix_millis += MIN_CALIBRATION_INTERVAL_MS;
+            tested.sync(host_unix_millis).unwrap();
+        }
+        assert_eq!(Some(100), tested.drift_ppm());
+        rtc.borrow_mut().pass(10_001);
+        assert_eq!(host_unix_millis + 10_000, tested.now_unix_millis());
+
+        // too short to measure
+        rtc.borrow_mut().pass(60_000);
+        host_unix_millis += 70_001;
+        tested.sync(host_unix_millis).unwrap();
+        // the host clock is stepped
+        rtc.borrow_mut().pass(MIN_CALIBRATION_INTERVAL_MS as u32);
+        host_unix_millis += 2 * MIN_CALIBRATION_INTERVAL_MS;
+        tested.sync(host_unix_millis).unwrap();
+        assert_eq!(Some(100), tested.drift_ppm());
+    }
+
+    #[test]
+    fn test_sync_point_moves_with_time() {
+        let rtc = Rc::new(RefCell::new(MockRtc::from_unix_timestamp(START_UNIX_SECONDS)));
+        let mut tested = WallClock::new(DateTimeSource::new(rtc.clone()));
+        let host_unix_millis = 1_700_000_000_000;
+        tested.get();
+        tested.sync(host_unix_millis).unwrap();
+
+        for day in 1..60_i64 {
+            rtc.borrow_mut().pass(SYNC_POINT_PERIOD_MS);
+            assert_eq!(host_unix_millis + day * SYNC_POINT_PERIOD_MS as i64, tested.now_unix_millis());
+        }
+    }
+
+    #[test]
+    fn test_rejects_time_out_of_rtc_range() {
+        let rtc = Rc::new(RefCell::new(MockRtc::from_unix_timestamp(START_UNIX_SECONDS)));
+        let mut tested = WallClock::new(DateTimeSource::new(rtc.clone()));
+
+        assert_eq!(Err(Errors::OutOfRange), tested.sync(i64::MAX));
+        assert_eq!(Err(Errors::OutOfRange), tested.preset(0));
+        assert!(!tested.is_synced());
+    }
+}
@@ -1,8 +1,12 @@
 #![deny(unsafe_code)]
 #![deny(warnings)]
 use crate::errors::Errors;
+use serde_derive::{Deserialize, Serialize};
 
 pub mod dma_read_buffer;
+pub mod flash_pages;
+pub mod logger;
+pub mod ram_flash;
 pub mod write_to;
 
 
@@ -67,7 +71,7 @@ impl BitsU8 {
 
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct BitsU64 {
     pub bits: u64,
 }
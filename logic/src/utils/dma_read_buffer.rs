@@ -2,6 +2,7 @@
 
 use embedded_dma::{ReadBuffer, WriteBuffer};
 use crate::errors::Errors;
+use crate::hal_ext::serial_transfer::ReadableBuffer;
 
 pub struct Buffer<const BUFFER_SIZE: usize> {
     buffer: &'static mut [u8; BUFFER_SIZE],
@@ -104,6 +105,13 @@ impl <const BUFFER_SIZE: usize> BufferWriter for Buffer<BUFFER_SIZE> {
     }
 }
 
+impl <const BUFFER_SIZE: usize> ReadableBuffer for Buffer<BUFFER_SIZE> {
+    /** Data written by DMA is not counted in the size, so it is sliced from the start of the array. */
+    fn slice_to(&self, to: usize) -> &[u8] {
+        &self.buffer[..to]
+    }
+}
+
 unsafe impl <const BUFFER_SIZE: usize> ReadBuffer for Buffer<BUFFER_SIZE> {
     type Word = u8;
 
@@ -0,0 +1,114 @@
+#![deny(unsafe_code)]
+
+use embedded_storage::nor_flash::{NorFlash, NorFlashError};
+use crate::errors::Errors;
+
+pub const PAGE_HEADER_SIZE: usize = 8;
+/** The header is written at once, padded to the write size, so the larger write sizes are not supported. */
+pub const MAX_WRITE_SIZE: usize = 16;
+
+/**
+Equal pages of a NOR flash, which are erased one at a time. The page starts with the header: magic,
+format version, reserved byte and sequence number (LE u32). The header of another magic or version
+is not valid, the page is treated as the erased one.
+ */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FlashPages {
+    offset: u32,
+    page_size: u32,
+    magic: [u8; 2],
+    format_version: u8,
+}
+
+impl FlashPages {
+
+    pub const fn new(offset: u32, page_size: u32, magic: [u8; 2], format_version: u8) -> Self {
+        Self {
+            offset,
+            page_size,
+            magic,
+            format_version,
+        }
+    }
+
+    #[inline(always)]
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    #[inline(always)]
+    pub fn page_address(&self, page: u32) -> u32 {
+        self.offset + page * self.page_size
+    }
+
+    /** The sequence number of the page, if it has a valid header. */
+    pub fn sequence<F: NorFlash>(&self, flash: &mut F, page: u32) -> Result<Option<u32>, Errors> {
+        let mut header = [0; PAGE_HEADER_SIZE];
+        flash.read(self.page_address(page), &mut header).map_err(flash_error)?;
+        if header[..2] != self.magic || header[2] != self.format_version {
+            return Ok(None);
+        }
+        Ok(Some(u32::from_le_bytes([header[4], header[5], header[6], header[7]])))
+    }
+
+    pub fn write_header<F: NorFlash>(&self, flash: &mut F, page: u32, sequence: u32) -> Result<(), Errors> {
+        let mut header = [0xff; MAX_WRITE_SIZE];
+        header[..2].copy_from_slice(&self.magic);
+        header[2] = self.format_version;
+        header[3] = 0;
+        header[4..PAGE_HEADER_SIZE].copy_from_slice(&sequence.to_le_bytes());
+        flash.write(self.page_address(page), &header[..aligned::<F>(PAGE_HEADER_SIZE)]).map_err(flash_error)
+    }
+
+    pub fn erase<F: NorFlash>(&self, flash: &mut F, page: u32) -> Result<(), Errors> {
+        let address = self.page_address(page);
+        flash.erase(address, address + self.page_size).map_err(flash_error)
+    }
+}
+
+/** The size rounded up to the write size of the flash. */
+pub fn aligned<F: NorFlash>(size: usize) -> usize {
+    size.div_ceil(F::WRITE_SIZE) * F::WRITE_SIZE
+}
+
+/** Whether the sequence number `a` is given after `b`, the numbers wrap around. */
+pub fn is_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+pub fn flash_error<E: NorFlashError>(error: E) -> Errors {
+    Errors::FlashError(error.kind())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ram_flash::RamFlash;
+
+    type TestFlash = RamFlash<512, 4, 128>;
+
+    #[test]
+    fn test_header_is_valid_for_same_magic_and_version() {
+        let mut flash = TestFlash::new();
+        let pages = FlashPages::new(256, 128, [0x41, 0x42], 3);
+
+        assert_eq!(Ok(None), pages.sequence(&mut flash, 1));
+        pages.write_header(&mut flash, 1, 0x01020304).unwrap();
+
+        assert_eq!(Ok(Some(0x01020304)), pages.sequence(&mut flash, 1));
+        assert_eq!(Ok(None), pages.sequence(&mut flash, 0));
+        assert_eq!(Ok(None), FlashPages::new(256, 128, [0x41, 0x43], 3).sequence(&mut flash, 1));
+        assert_eq!(Ok(None), FlashPages::new(256, 128, [0x41, 0x42], 4).sequence(&mut flash, 1));
+        pages.erase(&mut flash, 0).unwrap();
+        assert_eq!(Ok(Some(0x01020304)), pages.sequence(&mut flash, 1));
+    }
+
+    #[test]
+    fn test_sequence_wraps_around() {
+        assert!(is_after(1, 0));
+        assert!(is_after(0, u32::MAX));
+        assert!(!is_after(0, 0));
+        assert!(!is_after(u32::MAX, 0));
+    }
+}
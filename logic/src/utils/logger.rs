@@ -0,0 +1,237 @@
+#![allow(unsafe_code)]
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use crate::errors::Errors;
+use crate::hal_ext::tx_queue::TxPriority;
+use crate::services::slave_controller_link::domain::{DataInstructionCodes, ErrorCode};
+
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4,
+}
+
+impl Level {
+    fn for_code(code: u8) -> Level {
+        match code {
+            0 => Level::Trace,
+            1 => Level::Debug,
+            2 => Level::Info,
+            3 => Level::Warn,
+            _ => Level::Error,
+        }
+    }
+}
+
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Stream {
+    Rx,
+    Tx,
+}
+
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UsbEndpoint {
+    Serial,
+    Interrupt,
+}
+
+/** Mirrors `usb_device::UsbError`, which is not known here. */
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UsbErrorKind {
+    BufferOverflow,
+    EndpointMemoryOverflow,
+    InvalidEndpoint,
+    InvalidState,
+    WouldBlock,
+    ParseError,
+    EndpointOverflow,
+    Unsupported,
+}
+
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Event {
+    DmaFifoError(Stream),
+    DmaTransferComplete(Stream),
+    DmaTransferError(Stream),
+    DmaHalfTransfer(Stream),
+    DmaDirectModeError(Stream),
+    TxQueueOverflow(TxPriority),
+    QueuedFrameNotSent(Errors),
+    ReceiveError(Errors),
+    ResponseError(Errors),
+    RequestTimeout(DataInstructionCodes),
+    SlaveError(DataInstructionCodes, ErrorCode),
+    RuleActionFailed(Errors),
+    ScheduleActionFailed(Errors),
+    ReconcileActionFailed(Errors),
+    RelayDiverged { slave_id: u32, relay_index: u8 },
+    JournalSpillFailed(Errors),
+    UsbWritten(UsbEndpoint),
+    UsbWriteFailed(UsbEndpoint, UsbErrorKind),
+    UsbRead(usize),
+    UsbReadFailed(UsbErrorKind),
+    LedPeriodChanged(u16),
+    Measurement { temperature: f32, voltage: u16 },
+    UnexpectedInterrupt(i16),
+    HardFault { pc: u32, lr: u32 },
+}
+
+impl Event {
+    pub fn level(&self) -> Level {
+        match self {
+            Event::DmaTransferComplete(_) | Event::DmaHalfTransfer(_) => Level::Trace,
+            Event::UsbWritten(_) | Event::UsbRead(_) | Event::LedPeriodChanged(_) |
+            Event::Measurement { .. } => Level::Debug,
+            Event::RequestTimeout(_) | Event::SlaveError(_, _) => Level::Info,
+            Event::DmaFifoError(_) | Event::DmaTransferError(_) | Event::DmaDirectModeError(_) |
+            Event::TxQueueOverflow(_) | Event::QueuedFrameNotSent(_) | Event::ReceiveError(_) |
+            Event::ResponseError(_) | Event::UsbWriteFailed(_, _) | Event::UsbReadFailed(_) |
+            Event::RuleActionFailed(_) | Event::ScheduleActionFailed(_) | Event::ReconcileActionFailed(_) |
+            Event::RelayDiverged { .. } | Event::JournalSpillFailed(_) => Level::Warn,
+            Event::UnexpectedInterrupt(_) | Event::HardFault { .. } => Level::Error,
+        }
+    }
+}
+
+/** Logger backend. It is called from the interrupt handlers, so it should neither block nor halt. */
+pub trait Logger: Sync {
+    fn log(&self, level: Level, event: &Event);
+}
+
+pub struct NopLogger;
+
+impl Logger for NopLogger {
+    fn log(&self, _: Level, _: &Event) {}
+}
+
+/** Firmware backend, the events are encoded by `defmt` and formatted on the host, e.g. read over RTT. */
+#[cfg(feature = "defmt-log")]
+pub struct DefmtLogger;
+
+#[cfg(feature = "defmt-log")]
+impl Logger for DefmtLogger {
+    fn log(&self, level: Level, event: &Event) {
+        match level {
+            Level::Trace => defmt::trace!("{}", event),
+            Level::Debug => defmt::debug!("{}", event),
+            Level::Info => defmt::info!("{}", event),
+            Level::Warn => defmt::warn!("{}", event),
+            Level::Error => defmt::error!("{}", event),
+        }
+    }
+}
+
+/** Host tests backend, the records are printed to be seen with `--nocapture`. */
+#[cfg(test)]
+pub struct StdLogger;
+
+#[cfg(test)]
+impl Logger for StdLogger {
+    fn log(&self, level: Level, event: &Event) {
+        std::println!("[{:?}] {:?}", level, event);
+    }
+}
+
+#[cfg(test)]
+static DEFAULT_LOGGER: StdLogger = StdLogger;
+#[cfg(not(test))]
+static DEFAULT_LOGGER: NopLogger = NopLogger;
+
+const UNINITIALIZED: u8 = 0;
+const INITIALIZING: u8 = 1;
+const INITIALIZED: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(UNINITIALIZED);
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+// written only once, while STATE is INITIALIZING, and read only after it is INITIALIZED
+static mut LOGGER: &dyn Logger = &DEFAULT_LOGGER;
+
+/** Should be called once, before the interrupts using the log are enabled. */
+pub fn set_logger(logger: &'static dyn Logger) -> Result<(), Errors> {
+    match STATE.compare_exchange(UNINITIALIZED, INITIALIZING, Ordering::Acquire, Ordering::Relaxed) {
+        Ok(_) => {
+            unsafe { LOGGER = logger; }
+            STATE.store(INITIALIZED, Ordering::Release);
+            Ok(())
+        }
+        Err(_) => Err(Errors::LoggerAlreadySet),
+    }
+}
+
+/** Records below the level are dropped before reaching the logger. */
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn max_level() -> Level {
+    Level::for_code(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn log(event: Event) {
+    let level = event.level();
+    if level < max_level() {
+        return;
+    }
+    logger().log(level, &event);
+}
+
+fn logger() -> &'static dyn Logger {
+    if STATE.load(Ordering::Acquire) == INITIALIZED {
+        unsafe { LOGGER }
+    } else {
+        &DEFAULT_LOGGER
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::vec::Vec;
+    use super::*;
+
+    struct CapturingLogger {
+        records: Mutex<Vec<(Level, Event)>>,
+    }
+
+    impl Logger for CapturingLogger {
+        fn log(&self, level: Level, event: &Event) {
+            self.records.lock().unwrap().push((level, *event));
+        }
+    }
+
+    static CAPTURING_LOGGER: CapturingLogger = CapturingLogger { records: Mutex::new(Vec::new()) };
+
+    #[test]
+    fn test_records_reach_logger_by_level() {
+        assert_eq!(Ok(()), set_logger(&CAPTURING_LOGGER));
+        assert_eq!(Err(Errors::LoggerAlreadySet), set_logger(&NopLogger));
+
+        log(Event::DmaTransferComplete(Stream::Tx));
+        log(Event::DmaFifoError(Stream::Rx));
+        log(Event::RequestTimeout(DataInstructionCodes::Id));
+
+        // other tests log to the same logger at the same time
+        let records = CAPTURING_LOGGER.records.lock().unwrap();
+        assert!(records.contains(&(Level::Warn, Event::DmaFifoError(Stream::Rx))));
+        assert!(records.contains(&(Level::Info, Event::RequestTimeout(DataInstructionCodes::Id))));
+        assert!(!records.contains(&(Level::Trace, Event::DmaTransferComplete(Stream::Tx))));
+    }
+
+    #[test]
+    fn test_level_order() {
+        assert!(Level::Trace < Level::Debug);
+        assert!(Level::Warn < Level::Error);
+        for level in [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error] {
+            assert_eq!(level, Level::for_code(level as u8));
+        }
+    }
+}
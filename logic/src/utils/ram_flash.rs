@@ -0,0 +1,181 @@
+#![deny(unsafe_code)]
+
+use embedded_storage::nor_flash::{check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError,
+                                  NorFlashErrorKind, ReadNorFlash};
+
+pub const ERASED_BYTE: u8 = 0xff;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RamFlashError {
+    NotAligned,
+    OutOfBounds,
+    /** The write limit set by `power_off_after` is reached. */
+    PowerLost,
+}
+
+impl NorFlashError for RamFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            RamFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            RamFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            RamFlashError::PowerLost => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for RamFlashError {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => RamFlashError::NotAligned,
+            _ => RamFlashError::OutOfBounds,
+        }
+    }
+}
+
+/**
+NOR flash in RAM, for the host tests. As the real one, the writing only clears the bits, so a place
+written twice without the erase holds their AND. The power loss is simulated by limiting the count
+of the written bytes, the write crossing the limit is left torn.
+ */
+pub struct RamFlash<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> {
+    memory: [u8; SIZE],
+    erase_count: u32,
+    bytes_to_power_loss: Option<usize>,
+}
+
+impl <const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+
+    pub fn new() -> Self {
+        Self {
+            memory: [ERASED_BYTE; SIZE],
+            erase_count: 0,
+            bytes_to_power_loss: None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /** Count of the erased blocks since the creation. */
+    #[inline(always)]
+    pub fn erase_count(&self) -> u32 {
+        self.erase_count
+    }
+
+    /** The writes fail after the count of bytes is written, until `power_on` is called. */
+    pub fn power_off_after(&mut self, bytes_count: usize) {
+        self.bytes_to_power_loss = Some(bytes_count);
+    }
+
+    pub fn power_on(&mut self) {
+        self.bytes_to_power_loss = None;
+    }
+}
+
+impl <const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> Default for RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl <const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType for RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    type Error = RamFlashError;
+}
+
+impl <const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash for RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.memory[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl <const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash for RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        if self.bytes_to_power_loss == Some(0) {
+            return Err(RamFlashError::PowerLost);
+        }
+        self.memory[from as usize..to as usize].fill(ERASED_BYTE);
+        self.erase_count += (to - from) / ERASE_SIZE as u32;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let (count, result) = match self.bytes_to_power_loss {
+            Some(left) if left < bytes.len() => {
+                self.bytes_to_power_loss = Some(0);
+                (left, Err(RamFlashError::PowerLost))
+            }
+            Some(left) => {
+                self.bytes_to_power_loss = Some(left - bytes.len());
+                (bytes.len(), Ok(()))
+            }
+            None => (bytes.len(), Ok(())),
+        };
+        let offset = offset as usize;
+        for (cell, byte) in self.memory[offset..offset + count].iter_mut().zip(bytes) {
+            *cell &= *byte;
+        }
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_clears_bits_only() {
+        let mut tested = RamFlash::<16, 4, 8>::new();
+
+        tested.write(4, &[0x0f, 0xf0, 0x55, 0xff]).unwrap();
+        tested.write(4, &[0xff, 0x3c, 0xff, 0x00]).unwrap();
+
+        assert_eq!([0x0f, 0x30, 0x55, 0x00], tested.memory()[4..8]);
+        assert_eq!(Err(RamFlashError::NotAligned), tested.write(2, &[0; 4]));
+        assert_eq!(Err(RamFlashError::OutOfBounds), tested.write(16, &[0; 4]));
+    }
+
+    #[test]
+    fn test_erase_sets_whole_blocks() {
+        let mut tested = RamFlash::<16, 1, 8>::new();
+        tested.write(0, &[0; 16]).unwrap();
+
+        tested.erase(8, 16).unwrap();
+
+        assert_eq!([0; 8], tested.memory()[..8]);
+        assert_eq!([ERASED_BYTE; 8], tested.memory()[8..]);
+        assert_eq!(1, tested.erase_count());
+        assert_eq!(Err(RamFlashError::NotAligned), tested.erase(4, 8));
+    }
+
+    #[test]
+    fn test_power_loss_tears_write() {
+        let mut tested = RamFlash::<16, 1, 8>::new();
+
+        tested.power_off_after(3);
+        tested.write(0, &[1, 2]).unwrap();
+        assert_eq!(Err(RamFlashError::PowerLost), tested.write(2, &[3, 4, 5]));
+        assert_eq!(Err(RamFlashError::PowerLost), tested.write(5, &[6]));
+        assert_eq!(Err(RamFlashError::PowerLost), tested.erase(8, 16));
+        tested.power_on();
+        tested.write(5, &[6]).unwrap();
+
+        assert_eq!([1, 2, 3, ERASED_BYTE, ERASED_BYTE, 6], tested.memory()[..6]);
+    }
+}